@@ -0,0 +1,110 @@
+use crate::config::AdaptiveTelemetryConfig;
+use std::time::Instant;
+
+/// Telemetry limit for one connection, halved while its queue is backed up
+/// and raised again as it drains
+#[derive(Debug, Default)]
+pub struct AdaptiveRate {
+    /// Telemetry frames per second allowed, while limited
+    limit: Option<f64>,
+    tokens: f64,
+    refilled: Option<Instant>,
+    /// Telemetry frames let through since the last adjustment
+    sent: u32,
+}
+
+impl AdaptiveRate {
+    pub fn limit(&self) -> Option<f64> {
+        self.limit
+    }
+
+    /// Adjust the limit after a second in which the connection was or
+    /// wasn't congested. Returns whether it went from unlimited to limited
+    /// or back.
+    pub fn adjust(&mut self, congested: bool, config: &AdaptiveTelemetryConfig) -> bool {
+        let sent = std::mem::take(&mut self.sent) as f64;
+        let min = config.min_rate.max(1.0);
+        let was_limited = self.limit.is_some();
+
+        self.limit = match (self.limit, congested) {
+            // Start from half of what it was actually getting
+            (None, true) => Some((sent / 2.0).max(min)),
+            (Some(limit), true) => Some((limit / 2.0).max(min)),
+            (Some(limit), false) => {
+                Some(limit + config.increase).filter(|&limit| limit <= config.max_rate)
+            }
+            (None, false) => None,
+        };
+        match self.limit {
+            // A fresh limit starts with a second's worth
+            Some(limit) if !was_limited => self.tokens = limit,
+            Some(limit) => self.tokens = self.tokens.min(limit),
+            None => self.refilled = None,
+        }
+        was_limited != self.limit.is_some()
+    }
+
+    /// Whether a telemetry frame may be sent now
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let Some(limit) = self.limit else {
+            self.sent += 1;
+            return true;
+        };
+        if let Some(last) = self.refilled {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * limit).min(limit);
+        }
+        self.refilled = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.sent += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_aimd() {
+        let config = AdaptiveTelemetryConfig {
+            queued_bytes: 65536,
+            min_rate: 10.0,
+            max_rate: 100.0,
+            increase: 40.0,
+        };
+        let mut rate = AdaptiveRate::default();
+        let now = Instant::now();
+        for _ in 0..200 {
+            assert!(rate.allow(now));
+        }
+
+        // Congested: half of the 200 it was sent, then halving to the floor
+        assert!(rate.adjust(true, &config));
+        assert_eq!(rate.limit(), Some(100.0));
+        assert!(!rate.adjust(true, &config));
+        assert_eq!(rate.limit(), Some(50.0));
+        for _ in 0..3 {
+            rate.adjust(true, &config);
+        }
+        assert_eq!(rate.limit(), Some(10.0));
+
+        // The limit holds within a second
+        let later = now + Duration::from_secs(1);
+        assert_eq!((0..20).filter(|_| rate.allow(later)).count(), 10);
+
+        // Clear: back up additively, then unlimited
+        rate.adjust(false, &config);
+        assert_eq!(rate.limit(), Some(50.0));
+        rate.adjust(false, &config);
+        assert_eq!(rate.limit(), Some(90.0));
+        assert!(rate.adjust(false, &config));
+        assert_eq!(rate.limit(), None);
+    }
+}
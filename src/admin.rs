@@ -0,0 +1,612 @@
+use crate::config::Config;
+use crate::connection::tcp::RouterMessage;
+use crate::connection::{LineAction, ManualStarts, RouterSender, SerialControls, SerialSignal};
+use crate::link_test::LinkTestResult;
+use crate::mavlink::decode::{GpsRawInt, Heartbeat, SysStatus};
+use crate::mavlink::messages;
+use crate::metrics::Metrics;
+use crate::router::{InspectedFrame, QueueDepth, RouterQuery, StampedEvent};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot};
+use tracing::{debug, error, info};
+
+/// Config keys whose values are never exposed over the admin channel
+const SENSITIVE_KEYS: &[&str] = &["key", "psk", "secret", "password", "token"];
+
+/// Line-based admin channel: one command per line, plain-text responses
+pub struct AdminServer {
+    listener: TcpListener,
+    ctx: AdminContext,
+}
+
+/// State shared by all admin sessions
+#[derive(Clone)]
+struct AdminContext {
+    config: Arc<Config>,
+    router_tx: RouterSender,
+    metrics: Metrics,
+    manual_starts: ManualStarts,
+    serial_controls: SerialControls,
+    events: broadcast::Sender<StampedEvent>,
+}
+
+impl AdminServer {
+    pub async fn bind(
+        addr: &str,
+        config: Arc<Config>,
+        router_tx: RouterSender,
+        metrics: Metrics,
+        manual_starts: ManualStarts,
+        serial_controls: SerialControls,
+        events: broadcast::Sender<StampedEvent>,
+    ) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Admin channel listening on {}", addr);
+        Ok(Self {
+            listener,
+            ctx: AdminContext {
+                config,
+                router_tx,
+                metrics,
+                manual_starts,
+                serial_controls,
+                events,
+            },
+        })
+    }
+
+    pub async fn run(self) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, addr)) => {
+                    info!("Admin client connected from {}", addr);
+                    let ctx = self.ctx.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_admin_client(stream, ctx).await {
+                            debug!("Admin client {} error: {}", addr, e);
+                        }
+                        info!("Admin client {} disconnected", addr);
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to accept admin connection: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn handle_admin_client(stream: TcpStream, ctx: AdminContext) -> anyhow::Result<()> {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let mut args = line.split_whitespace();
+        let Some(command) = args.next() else {
+            continue;
+        };
+
+        let response = match command {
+            "help" => {
+                "commands: config, inspect <sysid>, test-link <sysid>, loss, clocks, connections, queues, open [name], \
+                 promote, break <uart>, dtr <on|off|pulse> <uart>, rts <on|off|pulse> <uart>, subscribe, help, quit\n"
+                    .to_string()
+            }
+            "config" => match redacted_config_toml(&ctx.config) {
+                Ok(toml) => toml,
+                Err(e) => format!("error: {}\n", e),
+            },
+            "inspect" => match args.next().map(str::parse::<u8>) {
+                Some(Ok(sysid)) => inspect(&ctx, sysid).await,
+                _ => "usage: inspect <sysid>\n".to_string(),
+            },
+            "test-link" => match args.next().map(str::parse::<u8>) {
+                Some(Ok(sysid)) => test_link(&ctx, sysid).await,
+                _ => "usage: test-link <sysid>\n".to_string(),
+            },
+            "loss" => packet_loss(&ctx.metrics),
+            "clocks" => clock_skew(&ctx.metrics),
+            "connections" => connection_traffic(&ctx.metrics),
+            "queues" => queues(&ctx).await,
+            "promote" => match query(&ctx, |reply| RouterQuery::Promote { reply }).await {
+                Ok(true) => "promoted, now routing\n".to_string(),
+                Ok(false) => "already active\n".to_string(),
+                Err(e) => format!("error: {}\n", e),
+            },
+            "open" => open_manual(&ctx.manual_starts, &args.collect::<Vec<_>>().join(" ")),
+            "break" => {
+                let uart = args.collect::<Vec<_>>().join(" ");
+                serial_signal(&ctx.serial_controls, &uart, SerialSignal::Break).await
+            }
+            "dtr" | "rts" => {
+                let signal = line_signal(command, args.next());
+                let uart = args.collect::<Vec<_>>().join(" ");
+                match signal {
+                    Some(signal) => serial_signal(&ctx.serial_controls, &uart, signal).await,
+                    None => format!("usage: {} <on|off|pulse> <uart>\n", command),
+                }
+            }
+            "subscribe" => {
+                stream_events(&ctx, &mut lines, &mut write_half).await?;
+                break;
+            }
+            "quit" => break,
+            other => format!("error: unknown command '{}'\n", other),
+        };
+
+        write_half.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Send router events as JSON lines until the client disconnects
+async fn stream_events(
+    ctx: &AdminContext,
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    write_half: &mut OwnedWriteHalf,
+) -> anyhow::Result<()> {
+    let mut events = ctx.events.subscribe();
+    loop {
+        let line = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => serde_json::to_string(&event)?,
+                Err(RecvError::Lagged(missed)) => {
+                    format!("{{\"event\":\"lagged\",\"missed\":{}}}", missed)
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            // Anything the client sends is ignored; stop once it hangs up
+            line = lines.next_line() => match line? {
+                Some(_) => continue,
+                None => return Ok(()),
+            },
+        };
+        write_half.write_all(line.as_bytes()).await?;
+        write_half.write_all(b"\n").await?;
+    }
+}
+
+/// Ask the router for state, mapping a stopped router to an error
+async fn query<T>(
+    ctx: &AdminContext,
+    make: impl FnOnce(oneshot::Sender<T>) -> RouterQuery,
+) -> anyhow::Result<T> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    ctx.router_tx.send(RouterMessage::Query(make(reply_tx)))?;
+    Ok(reply_rx.await?)
+}
+
+/// Compact vehicle status decoded from the last frames seen from `sysid`
+async fn inspect(ctx: &AdminContext, sysid: u8) -> String {
+    match query(ctx, |reply| RouterQuery::LastFrames { sysid, reply }).await {
+        Ok(frames) => inspect_report(sysid, &frames),
+        Err(e) => format!("error: {}\n", e),
+    }
+}
+
+fn inspect_report(sysid: u8, frames: &[InspectedFrame]) -> String {
+    if frames.is_empty() {
+        return format!("sysid {}: no frames seen\n", sysid);
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "sysid {}:", sysid);
+    for InspectedFrame { frame, source, age } in frames {
+        let summary = if let Some(hb) = Heartbeat::decode(frame) {
+            format!(
+                "{} type={} autopilot={} base_mode={:#04x} custom_mode={} status={}",
+                if hb.armed() { "ARMED" } else { "disarmed" },
+                hb.mav_type,
+                hb.autopilot,
+                hb.base_mode,
+                hb.custom_mode,
+                hb.system_status
+            )
+        } else if let Some(status) = SysStatus::decode(frame) {
+            format!(
+                "battery {:.2}V {:.2}A {}%",
+                status.voltage_battery as f64 / 1000.0,
+                status.current_battery as f64 / 100.0,
+                status.battery_remaining
+            )
+        } else if let Some(gps) = GpsRawInt::decode(frame) {
+            format!(
+                "fix {} sats={} lat={:.7} lon={:.7} alt={:.1}m",
+                gps.fix_name(),
+                gps.satellites_visible,
+                gps.lat as f64 / 1e7,
+                gps.lon as f64 / 1e7,
+                gps.alt as f64 / 1000.0
+            )
+        } else {
+            continue;
+        };
+
+        let name = messages::lookup(frame.msg_id()).map_or("?", |m| m.name);
+        let _ = writeln!(
+            out,
+            "  {:<12} {} ({:.1}s ago via {})",
+            name,
+            summary,
+            age.as_secs_f64(),
+            source
+        );
+    }
+
+    let _ = writeln!(out, "  {} message type(s) seen", frames.len());
+    out
+}
+
+/// Round trip of a request to `sysid` and its COMMAND_ACK back
+async fn test_link(ctx: &AdminContext, sysid: u8) -> String {
+    let announce = &ctx.config.discovery_announce;
+    let from = (announce.sysid, announce.compid);
+    let wait = Duration::from_millis(ctx.config.admin.test_link_timeout_ms);
+    let result = tokio::time::timeout(
+        wait,
+        query(ctx, |reply| RouterQuery::TestLink { sysid, from, reply }),
+    )
+    .await;
+
+    match result {
+        Ok(Ok(LinkTestResult::Answered {
+            ack,
+            via,
+            round_trip,
+        })) => format!(
+            "sysid {}: ok, COMMAND_ACK ({}) via {} in {:.1} ms\n",
+            sysid,
+            ack.result_name(),
+            via,
+            round_trip.as_secs_f64() * 1000.0
+        ),
+        Ok(Ok(LinkTestResult::NotSeen)) => format!("sysid {}: no link to it\n", sysid),
+        Ok(Err(e)) => format!("error: {}\n", e),
+        Err(_) => format!(
+            "sysid {}: FAILED, no answer within {} ms\n",
+            sysid,
+            wait.as_millis()
+        ),
+    }
+}
+
+/// Per-vehicle packet loss estimated from sequence gaps
+fn packet_loss(metrics: &Metrics) -> String {
+    let stats = metrics.sequence_stats();
+    if stats.is_empty() {
+        return "no vehicles seen\n".to_string();
+    }
+
+    let mut out = String::new();
+    for ((sysid, compid), stream) in stats {
+        let _ = writeln!(
+            out,
+            "{}/{}: {:.1}% this interval, {:.1}% total ({} lost, {} received)",
+            sysid,
+            compid,
+            stream.loss_percent(),
+            stream.total_loss_percent(),
+            stream.total_lost,
+            stream.total_received
+        );
+    }
+    out
+}
+
+/// Per-vehicle clock skew from SYSTEM_TIME
+fn clock_skew(metrics: &Metrics) -> String {
+    if metrics.clocks.is_none() {
+        return "clock skew not tracked (set clock_skew_warn_ms)\n".to_string();
+    }
+    let stats = metrics.clock_stats();
+    if stats.is_empty() {
+        return "no SYSTEM_TIME seen\n".to_string();
+    }
+
+    let mut out = String::new();
+    for (sysid, clock) in stats {
+        let age = clock.updated.map_or(0, |at| at.elapsed().as_secs());
+        let _ = match clock.skew_ms {
+            Some(skew) => writeln!(out, "{}: {:+} ms from the host ({}s ago)", sysid, skew, age),
+            None => writeln!(
+                out,
+                "{}: no wall-clock time, up {}s ({}s ago)",
+                sysid,
+                clock.time_boot_ms / 1000,
+                age
+            ),
+        };
+    }
+    out
+}
+
+/// Outbound queue depth per connection, now and at its highest
+async fn queues(ctx: &AdminContext) -> String {
+    let queues = match query(ctx, |reply| RouterQuery::Queues { reply }).await {
+        Ok(queues) => queues,
+        Err(e) => return format!("error: {}\n", e),
+    };
+    if queues.is_empty() {
+        return "no connections\n".to_string();
+    }
+
+    let kb = |bytes: usize| bytes as f64 / 1024.0;
+    let mut out = String::new();
+    for QueueDepth {
+        connection,
+        queued_bytes,
+        peak_bytes,
+        congested_above,
+    } in &queues
+    {
+        let limit = match congested_above {
+            Some(limit) => format!("unbounded, congested above {:.1} KB", kb(*limit)),
+            None => "unbounded".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "{}: {:.1} KB queued, peak {:.1} KB ({})",
+            connection,
+            kb(*queued_bytes),
+            kb(*peak_bytes),
+            limit
+        );
+    }
+    let total: usize = queues.iter().map(|queue| queue.queued_bytes).sum();
+    let cap = match ctx.config.routing.max_queued_bytes {
+        0 => "no cap".to_string(),
+        cap => format!("cap {:.1} KB", kb(cap)),
+    };
+    let _ = writeln!(out, "total: {:.1} KB queued ({})", kb(total), cap);
+    out
+}
+
+fn connection_traffic(metrics: &Metrics) -> String {
+    if metrics.connections.is_none() {
+        return "connection_stats is off\n".to_string();
+    }
+    let stats = metrics.connection_stats();
+    if stats.is_empty() {
+        return "no connections seen\n".to_string();
+    }
+
+    let mut out = String::new();
+    for (name, conn) in stats {
+        let _ = writeln!(
+            out,
+            "{}: session {} up {}s: {} in / {} out bytes ({} / {} frames); \
+             total: {} in / {} out bytes ({} / {} frames)",
+            name,
+            conn.sessions,
+            conn.session_age().as_secs(),
+            conn.session.bytes_rx,
+            conn.session.bytes_tx,
+            conn.session.frames_rx,
+            conn.session.frames_tx,
+            conn.total.bytes_rx,
+            conn.total.bytes_tx,
+            conn.total.frames_rx,
+            conn.total.frames_tx
+        );
+    }
+    out
+}
+
+/// Open a `start = "manual"` connection, or list those still closed
+fn open_manual(manual_starts: &ManualStarts, name: &str) -> String {
+    if name.is_empty() {
+        let waiting = manual_starts.waiting();
+        if waiting.is_empty() {
+            return "no connections waiting to be opened\n".to_string();
+        }
+        return format!("waiting to be opened: {}\n", waiting.join(", "));
+    }
+
+    if manual_starts.open(name) {
+        info!("Admin opened connection {}", name);
+        format!("opening {}\n", name)
+    } else {
+        format!("error: no connection '{}' waiting to be opened\n", name)
+    }
+}
+
+/// The control line change a `dtr` or `rts` command asks for
+fn line_signal(command: &str, action: Option<&str>) -> Option<SerialSignal> {
+    let action = match action? {
+        "on" => LineAction::On,
+        "off" => LineAction::Off,
+        "pulse" => LineAction::Pulse,
+        _ => return None,
+    };
+    match command {
+        "dtr" => Some(SerialSignal::Dtr(action)),
+        "rts" => Some(SerialSignal::Rts(action)),
+        _ => None,
+    }
+}
+
+/// Send a break or control line change on an open UART, given by path or
+/// name; with no UART, list the open ones
+async fn serial_signal(controls: &SerialControls, uart: &str, signal: SerialSignal) -> String {
+    if uart.is_empty() {
+        let open = controls.open_ports();
+        if open.is_empty() {
+            return "no UARTs open\n".to_string();
+        }
+        return format!("open UARTs: {}\n", open.join(", "));
+    }
+
+    match controls.signal(uart, signal).await {
+        Ok(()) => {
+            info!("Admin sent {} on UART {}", signal, uart);
+            format!("sent {} on {}\n", signal, uart)
+        }
+        Err(e) => format!("error: {}\n", e),
+    }
+}
+
+/// Effective config as TOML, with sensitive values masked
+fn redacted_config_toml(config: &Config) -> anyhow::Result<String> {
+    let mut value = toml::Value::try_from(config)?;
+    redact(&mut value);
+    Ok(toml::to_string_pretty(&value)?)
+}
+
+fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                if is_sensitive(key) {
+                    *value = toml::Value::String("<redacted>".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        toml::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn is_sensitive(key: &str) -> bool {
+    key.split('_').any(|part| SENSITIVE_KEYS.contains(&part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionId;
+    use crate::mavlink::MavFrame;
+
+    #[test]
+    fn test_redact_nested_keys() {
+        let mut value: toml::Value = toml::from_str(
+            "[signing]\nsecret_key = \"abc\"\nenabled = true\n[[udp]]\npsk = \"xyz\"\n",
+        )
+        .unwrap();
+        redact(&mut value);
+
+        assert_eq!(value["signing"]["secret_key"].as_str(), Some("<redacted>"));
+        assert_eq!(value["signing"]["enabled"].as_bool(), Some(true));
+        assert_eq!(value["udp"][0]["psk"].as_str(), Some("<redacted>"));
+    }
+
+    #[test]
+    fn test_inspect_report() {
+        let source = ConnectionId::new_uart(0);
+        let inspected = |frame, secs| InspectedFrame {
+            frame,
+            source,
+            age: Duration::from_secs(secs),
+        };
+        let heartbeat = Heartbeat {
+            custom_mode: 4,
+            mav_type: 2,
+            autopilot: 3,
+            base_mode: 0x81,
+            system_status: 4,
+        };
+        let mut sys_status = [0u8; 31];
+        sys_status[14..16].copy_from_slice(&12600u16.to_le_bytes());
+        sys_status[16..18].copy_from_slice(&1550i16.to_le_bytes());
+        sys_status[30] = 87;
+        let mut gps = [0u8; 30];
+        gps[8..12].copy_from_slice(&473977418i32.to_le_bytes());
+        gps[12..16].copy_from_slice(&85455939i32.to_le_bytes());
+        gps[16..20].copy_from_slice(&488150i32.to_le_bytes());
+        gps[28] = 3;
+        gps[29] = 12;
+        let frames = [
+            inspected(heartbeat.to_frame(0, 1, 1), 1),
+            inspected(
+                MavFrame::new_v2(0, 1, 1, messages::SYS_STATUS, &sys_status).unwrap(),
+                2,
+            ),
+            inspected(
+                MavFrame::new_v2(0, 1, 1, messages::GPS_RAW_INT, &gps).unwrap(),
+                3,
+            ),
+            // Not summarized, but counted
+            inspected(
+                MavFrame::new_v2(0, 1, 1, messages::ATTITUDE, &[0; 28]).unwrap(),
+                4,
+            ),
+        ];
+
+        assert_eq!(
+            inspect_report(1, &frames),
+            "sysid 1:\n  \
+             HEARTBEAT    ARMED type=2 autopilot=3 base_mode=0x81 custom_mode=4 status=4 \
+             (1.0s ago via UART-0)\n  \
+             SYS_STATUS   battery 12.60V 15.50A 87% (2.0s ago via UART-0)\n  \
+             GPS_RAW_INT  fix 3D sats=12 lat=47.3977418 lon=8.5455939 alt=488.1m \
+             (3.0s ago via UART-0)\n  \
+             4 message type(s) seen\n"
+        );
+        assert_eq!(inspect_report(2, &[]), "sysid 2: no frames seen\n");
+    }
+
+    #[test]
+    fn test_line_signal() {
+        assert_eq!(
+            line_signal("dtr", Some("on")),
+            Some(SerialSignal::Dtr(LineAction::On))
+        );
+        assert_eq!(
+            line_signal("dtr", Some("off")),
+            Some(SerialSignal::Dtr(LineAction::Off))
+        );
+        assert_eq!(
+            line_signal("rts", Some("pulse")),
+            Some(SerialSignal::Rts(LineAction::Pulse))
+        );
+        assert_eq!(line_signal("rts", Some("toggle")), None);
+        assert_eq!(line_signal("rts", None), None);
+        assert_eq!(
+            SerialSignal::Rts(LineAction::Pulse).to_string(),
+            "RTS pulse"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serial_signal() {
+        let controls = SerialControls::default();
+        assert_eq!(
+            serial_signal(&controls, "", SerialSignal::Break).await,
+            "no UARTs open\n"
+        );
+
+        // Found by name as well as path, and the port's task replies
+        let mut rx = controls.register("/dev/ttyACM0", Some("fc"));
+        tokio::spawn(async move {
+            while let Some(control) = rx.recv().await {
+                assert_eq!(control.signal, SerialSignal::Dtr(LineAction::Off));
+                let _ = control.reply.send(Ok(()));
+            }
+        });
+        let signal = SerialSignal::Dtr(LineAction::Off);
+        assert_eq!(
+            serial_signal(&controls, "fc", signal).await,
+            "sent DTR off on fc\n"
+        );
+        assert_eq!(
+            serial_signal(&controls, "/dev/ttyACM0", signal).await,
+            "sent DTR off on /dev/ttyACM0\n"
+        );
+        assert!(serial_signal(&controls, "/dev/ttyUSB0", signal)
+            .await
+            .starts_with("error"));
+
+        controls.unregister("/dev/ttyACM0");
+        assert!(serial_signal(&controls, "fc", signal)
+            .await
+            .starts_with("error"));
+    }
+}
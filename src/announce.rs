@@ -0,0 +1,322 @@
+use crate::config::{DiscoveryAnnounceConfig, HealthMessage};
+use crate::mavlink::decode::{
+    named_value_float, status_text, Heartbeat, MAV_AUTOPILOT_INVALID, MAV_SEVERITY_INFO,
+    MAV_STATE_ACTIVE, MAV_TYPE_ONBOARD_CONTROLLER,
+};
+use crate::mavlink::MavFrame;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+/// CRC_EXTRA of MAV_LITE_STATS
+const STATS_CRC_EXTRA: u8 = 43;
+
+/// Builds the frames the router identifies itself with
+#[derive(Debug)]
+pub struct Announcer {
+    sysid: u8,
+    compid: u8,
+    seq: u8,
+    pub interval: Duration,
+    health: Option<HealthMessage>,
+    pub health_interval: Duration,
+    health_msgid: u32,
+    started: Instant,
+    /// Frames received and dropped as of the last health report, and when
+    /// it was
+    last_counts: (u64, u64),
+    last_report: Instant,
+}
+
+/// The router's state, as reported with `health`
+#[derive(Debug, Default)]
+pub struct Health {
+    pub connections: usize,
+    pub vehicles: usize,
+    /// Frames received and dropped since startup
+    pub received: u64,
+    pub dropped: u64,
+    /// Each vehicle's packet loss in percent, by sysid
+    pub loss: Vec<(u8, f64)>,
+}
+
+impl Announcer {
+    /// `None` unless `[discovery_announce]` is enabled
+    pub fn new(config: &DiscoveryAnnounceConfig) -> Option<Self> {
+        config.enabled.then(|| Self {
+            sysid: config.sysid,
+            compid: config.compid,
+            seq: 0,
+            interval: Duration::from_secs(config.interval_secs.max(1)),
+            health: config.health,
+            health_interval: Duration::from_secs(config.health_interval_secs.max(1)),
+            health_msgid: config.health_msgid,
+            started: Instant::now(),
+            last_counts: (0, 0),
+            last_report: Instant::now(),
+        })
+    }
+
+    pub fn reports_health(&self) -> bool {
+        self.health.is_some()
+    }
+
+    fn next_seq(&mut self) -> u8 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+
+    pub fn heartbeat(&mut self) -> MavFrame {
+        let heartbeat = Heartbeat {
+            custom_mode: 0,
+            mav_type: MAV_TYPE_ONBOARD_CONTROLLER,
+            autopilot: MAV_AUTOPILOT_INVALID,
+            base_mode: 0,
+            system_status: MAV_STATE_ACTIVE,
+        };
+        let seq = self.next_seq();
+        heartbeat.to_frame(seq, self.sysid, self.compid)
+    }
+
+    /// STATUSTEXT naming the router, for peers that have just connected
+    pub fn identity(&mut self) -> MavFrame {
+        let text = format!("mav-lite {} MAVLink router", env!("CARGO_PKG_VERSION"));
+        let seq = self.next_seq();
+        status_text(seq, self.sysid, self.compid, MAV_SEVERITY_INFO, &text)
+    }
+
+    /// Frames reporting `health`; the drop and receive rates are over the
+    /// frames received since the last report
+    pub fn health(&mut self, health: &Health) -> Vec<MavFrame> {
+        let (received, dropped) = self.last_counts;
+        self.last_counts = (health.received, health.dropped);
+        let since = std::mem::replace(&mut self.last_report, Instant::now());
+        let received = health.received.saturating_sub(received);
+        let dropped = health.dropped.saturating_sub(dropped);
+        let drop_percent = if received > 0 {
+            dropped as f64 * 100.0 / received as f64
+        } else {
+            0.0
+        };
+
+        match self.health {
+            None => Vec::new(),
+            Some(HealthMessage::NamedValue) => {
+                let time_boot_ms = self.started.elapsed().as_millis() as u32;
+                let mut values = vec![
+                    ("conns".to_string(), health.connections as f64),
+                    ("vehicles".to_string(), health.vehicles as f64),
+                    ("drop_pct".to_string(), drop_percent),
+                ];
+                for &(sysid, loss) in &health.loss {
+                    values.push((format!("loss_{}", sysid), loss));
+                }
+                values
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let seq = self.next_seq();
+                        named_value_float(
+                            seq,
+                            self.sysid,
+                            self.compid,
+                            time_boot_ms,
+                            &name,
+                            value as f32,
+                        )
+                    })
+                    .collect()
+            }
+            Some(HealthMessage::Statustext) => {
+                let mut text = format!(
+                    "relay {}c {}v drop {:.1}%",
+                    health.connections, health.vehicles, drop_percent
+                );
+                for &(sysid, loss) in &health.loss {
+                    let _ = write!(text, " {}:{:.0}%", sysid, loss);
+                }
+                let seq = self.next_seq();
+                vec![status_text(
+                    seq,
+                    self.sysid,
+                    self.compid,
+                    MAV_SEVERITY_INFO,
+                    &text,
+                )]
+            }
+            Some(HealthMessage::MavLiteStats) => {
+                let rx_rate = received as f64 / since.elapsed().as_secs_f64().max(0.001);
+                let worst_loss = health
+                    .loss
+                    .iter()
+                    .map(|&(_, loss)| loss)
+                    .fold(0.0, f64::max);
+                let mut payload = (self.started.elapsed().as_millis() as u32)
+                    .to_le_bytes()
+                    .to_vec();
+                payload.extend_from_slice(&(health.received as u32).to_le_bytes());
+                payload.extend_from_slice(&(health.dropped as u32).to_le_bytes());
+                payload.extend_from_slice(&(rx_rate as f32).to_le_bytes());
+                payload.extend_from_slice(&(drop_percent as f32).to_le_bytes());
+                payload.extend_from_slice(&(worst_loss as f32).to_le_bytes());
+                payload.extend_from_slice(&(health.connections as u16).to_le_bytes());
+                payload.extend_from_slice(&(health.vehicles as u16).to_le_bytes());
+                let seq = self.next_seq();
+                vec![MavFrame::new_v2_with_crc_extra(
+                    seq,
+                    self.sysid,
+                    self.compid,
+                    self.health_msgid,
+                    STATS_CRC_EXTRA,
+                    &payload,
+                )]
+            }
+        }
+    }
+}
+
+/// Send the announcement as UDP datagrams to `broadcast_addr`, if set, for
+/// tools listening on the network rather than connected to the router
+pub fn start_broadcast(config: &DiscoveryAnnounceConfig) {
+    let Some(addr) = config.broadcast_addr.clone() else {
+        return;
+    };
+    let Some(mut announcer) = Announcer::new(config) else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let socket = match UdpSocket::bind("0.0.0.0:0").await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Discovery announce: can't open a UDP socket: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = socket.set_broadcast(true) {
+            warn!("Discovery announce: can't enable broadcast: {}", e);
+        }
+        info!(
+            "Discovery announce: broadcasting to {} every {}s",
+            addr,
+            announcer.interval.as_secs()
+        );
+
+        let mut tick = interval(announcer.interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut rounds = 0u64;
+        loop {
+            tick.tick().await;
+            let mut frames = vec![announcer.heartbeat()];
+            // Name the router now and then, for tools that only listen
+            if rounds.is_multiple_of(10) {
+                frames.push(announcer.identity());
+            }
+            for frame in frames {
+                if let Err(e) = socket.send_to(frame.as_bytes(), &addr).await {
+                    debug!("Discovery announce: send to {} failed: {}", addr, e);
+                }
+            }
+            rounds += 1;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    #[test]
+    fn test_frames() {
+        let config = DiscoveryAnnounceConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let mut announcer = Announcer::new(&config).unwrap();
+
+        let heartbeat = announcer.heartbeat();
+        assert_eq!(heartbeat.sys_id(), config.sysid);
+        assert_eq!(heartbeat.comp_id(), config.compid);
+        let decoded = Heartbeat::decode(&heartbeat).unwrap();
+        assert_eq!(decoded.mav_type, MAV_TYPE_ONBOARD_CONTROLLER);
+
+        let identity = announcer.identity();
+        assert_eq!(identity.msg_id(), messages::STATUSTEXT);
+        assert_eq!(identity.sequence(), 1);
+        assert_eq!(identity.length_matches(), Some(true));
+        assert!(identity.payload()[1..].starts_with(b"mav-lite "));
+
+        assert!(Announcer::new(&DiscoveryAnnounceConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_health() {
+        let config = DiscoveryAnnounceConfig {
+            enabled: true,
+            health: Some(HealthMessage::NamedValue),
+            ..Default::default()
+        };
+        let mut announcer = Announcer::new(&config).unwrap();
+        let mut health = Health {
+            connections: 3,
+            vehicles: 1,
+            received: 1000,
+            dropped: 10,
+            loss: vec![(1, 2.25)],
+        };
+
+        let frames = announcer.health(&health);
+        let named: Vec<(String, f32)> = frames
+            .iter()
+            .map(|frame| {
+                let payload = frame.payload();
+                let name = payload[8..]
+                    .iter()
+                    .take_while(|&&b| b != 0)
+                    .map(|&b| b as char);
+                let value = f32::from_le_bytes(payload[4..8].try_into().unwrap());
+                (name.collect(), value)
+            })
+            .collect();
+        assert_eq!(
+            named,
+            [
+                ("conns".to_string(), 3.0),
+                ("vehicles".to_string(), 1.0),
+                ("drop_pct".to_string(), 1.0),
+                ("loss_1".to_string(), 2.25),
+            ]
+        );
+        assert!(frames
+            .iter()
+            .all(|f| f.msg_id() == messages::NAMED_VALUE_FLOAT));
+
+        // The drop rate is since the last report
+        health.received = 1100;
+        health.dropped = 30;
+        announcer.health = Some(HealthMessage::Statustext);
+        let frames = announcer.health(&health);
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].payload()[1..].starts_with(b"relay 3c 1v drop 20.0% 1:2%"));
+
+        health.received = 1200;
+        health.dropped = 35;
+        health.loss.push((2, 7.5));
+        announcer.health = Some(HealthMessage::MavLiteStats);
+        let frames = announcer.health(&health);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].msg_id(), config.health_msgid);
+        assert_eq!(frames[0].crc_extra(), Some(STATS_CRC_EXTRA));
+        // Trailing zeros are cut from MAVLink 2 payloads
+        let mut payload = [0u8; 28];
+        payload[..frames[0].payload().len()].copy_from_slice(frames[0].payload());
+        let u32_at = |at: usize| u32::from_le_bytes(payload[at..at + 4].try_into().unwrap());
+        let f32_at = |at: usize| f32::from_le_bytes(payload[at..at + 4].try_into().unwrap());
+        assert_eq!((u32_at(4), u32_at(8)), (1200, 35));
+        assert_eq!((f32_at(16), f32_at(20)), (5.0, 7.5));
+        assert_eq!(&payload[24..28], &[3, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config::AuditLogConfig;
+use crate::connection::ConnectionId;
+use crate::mavlink::messages::{self, MessageClass};
+use crate::mavlink::MavFrame;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// What the router did with a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// Sent on to the listed connections
+    Allowed,
+    /// Kept for a vehicle whose link dropped, to send if it comes back
+    Held,
+    /// Answered by the router itself, from its parameter cache
+    Answered,
+    /// Stopped by the router, for the given reason
+    Denied,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    time: f64,
+    source: String,
+    sysid: u8,
+    compid: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_system: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_component: Option<u8>,
+    msgid: u32,
+    message: &'static str,
+    decision: Decision,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    destinations: Vec<String>,
+}
+
+impl Entry {
+    fn new(
+        time: f64,
+        source: ConnectionId,
+        frame: &MavFrame,
+        decision: Decision,
+        reason: Option<&'static str>,
+        destinations: &[ConnectionId],
+    ) -> Self {
+        Self {
+            time,
+            source: source.to_string(),
+            sysid: frame.sys_id(),
+            compid: frame.comp_id(),
+            target_system: frame.target_system(),
+            target_component: frame.target_component(),
+            msgid: frame.msg_id(),
+            message: messages::lookup(frame.msg_id()).map_or("?", |m| m.name),
+            decision,
+            reason,
+            destinations: destinations.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+/// Router-side handle to the audit file's writer
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    tx: mpsc::UnboundedSender<Entry>,
+}
+
+impl AuditLog {
+    /// Open the file for `[audit_log]`. Returns `None` when disabled, and an
+    /// error if it can't be opened: an audit trail that silently isn't
+    /// written is worse than not starting.
+    pub fn start(config: &AuditLogConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)
+            .map_err(|e| anyhow::anyhow!("audit_log: can't open {}: {}", config.path, e))?;
+        info!("Audit log: commands to {}", config.path);
+
+        let path = config.path.clone();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Entry>();
+        let mut file = BufWriter::new(file);
+        tokio::task::spawn_blocking(move || {
+            while let Some(entry) = rx.blocking_recv() {
+                let line = serde_json::to_string(&entry).expect("entries serialize");
+                if let Err(e) = writeln!(file, "{}", line) {
+                    error!("Audit log: write to {} failed: {}", path, e);
+                }
+                if rx.is_empty() {
+                    if let Err(e) = file.flush() {
+                        error!("Audit log: write to {} failed: {}", path, e);
+                    }
+                }
+            }
+        });
+
+        Ok(Some(Self { tx }))
+    }
+
+    /// Record what was done with a frame, if it is a command
+    pub fn record(
+        &self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        decision: Decision,
+        reason: Option<&'static str>,
+        destinations: &[ConnectionId],
+    ) {
+        if frame.message_class() != MessageClass::Command {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let _ = self.tx.send(Entry::new(
+            time,
+            source,
+            frame,
+            decision,
+            reason,
+            destinations,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let mut payload = [0u8; 33];
+        payload[30] = 1;
+        payload[31] = 1;
+        let frame = MavFrame::new_v2(0, 255, 190, messages::COMMAND_LONG, &payload).unwrap();
+
+        let entry = Entry::new(
+            12.5,
+            ConnectionId::new_tcp(0),
+            &frame,
+            Decision::Allowed,
+            None,
+            &[ConnectionId::new_uart(0)],
+        );
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            "{\"time\":12.5,\"source\":\"TCP-0\",\"sysid\":255,\"compid\":190,\
+             \"target_system\":1,\"target_component\":1,\"msgid\":76,\
+             \"message\":\"COMMAND_LONG\",\"decision\":\"allowed\",\
+             \"destinations\":[\"UART-0\"]}"
+        );
+
+        let entry = Entry::new(
+            12.5,
+            ConnectionId::new_tcp(0),
+            &frame,
+            Decision::Denied,
+            Some("no_heartbeat"),
+            &[],
+        );
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.ends_with("\"decision\":\"denied\",\"reason\":\"no_heartbeat\"}"));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::config::BootSuppressionConfig;
+use crate::mavlink::MavFrame;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Boot-burst messages let through to one link
+#[derive(Debug, Default)]
+pub struct BootSuppression {
+    /// When each (sysid, msgid) was last sent during its vehicle's window
+    last_sent: HashMap<(u8, u32), Instant>,
+}
+
+impl BootSuppression {
+    /// Whether a frame may be sent now, given when its vehicle's first frame
+    /// arrived
+    pub fn allow(
+        &mut self,
+        config: &BootSuppressionConfig,
+        first_frame: Option<Instant>,
+        frame: &MavFrame,
+        now: Instant,
+    ) -> bool {
+        let key = (frame.sys_id(), frame.msg_id());
+        let booting = first_frame.is_some_and(|first| {
+            now.duration_since(first) < Duration::from_secs(config.window_secs)
+        });
+        if !booting || !config.msgids.contains(&key.1) {
+            return true;
+        }
+        if config.rate_hz <= 0.0 {
+            return false;
+        }
+
+        let min_gap = Duration::from_secs_f64(1.0 / config.rate_hz);
+        match self.last_sent.get(&key) {
+            Some(last) if now.duration_since(*last) < min_gap => false,
+            _ => {
+                self.last_sent.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    #[test]
+    fn test_window() {
+        let mut config = BootSuppressionConfig {
+            window_secs: 10,
+            msgids: vec![messages::STATUSTEXT],
+            rate_hz: 0.0,
+        };
+        let banner = MavFrame::new_v2(0, 1, 1, messages::STATUSTEXT, &[6; 51]).unwrap();
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let first = Instant::now();
+        let mut boot = BootSuppression::default();
+
+        assert!(!boot.allow(&config, Some(first), &banner, first));
+        assert!(boot.allow(&config, Some(first), &heartbeat, first));
+        assert!(boot.allow(
+            &config,
+            Some(first),
+            &banner,
+            first + Duration::from_secs(10)
+        ));
+
+        // Rate-limited rather than dropped
+        config.rate_hz = 1.0;
+        assert!(boot.allow(&config, Some(first), &banner, first));
+        assert!(!boot.allow(
+            &config,
+            Some(first),
+            &banner,
+            first + Duration::from_millis(500)
+        ));
+        assert!(boot.allow(
+            &config,
+            Some(first),
+            &banner,
+            first + Duration::from_secs(1)
+        ));
+    }
+}
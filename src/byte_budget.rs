@@ -0,0 +1,114 @@
+use crate::config::ByteBudgetConfig;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Usage {
+    total: u64,
+    /// Bytes of each message id that has a share
+    by_msgid: HashMap<u32, u64>,
+}
+
+/// Bytes sent over one link, for enforcing its shares
+#[derive(Debug, Default)]
+pub struct ByteBudget {
+    /// When the current window started
+    started: Option<Instant>,
+    previous: Usage,
+    current: Usage,
+}
+
+impl ByteBudget {
+    /// Whether a frame of `len` bytes may be sent now
+    pub fn allow(
+        &mut self,
+        config: &ByteBudgetConfig,
+        msgid: u32,
+        len: usize,
+        now: Instant,
+    ) -> bool {
+        let Some(share) = config.share(msgid) else {
+            return true;
+        };
+        self.roll(config, now);
+
+        let len = len as u64;
+        let sent = |usage: &Usage| usage.by_msgid.get(&msgid).copied().unwrap_or(0);
+        let of_type = sent(&self.previous) + sent(&self.current) + len;
+        let total = self.previous.total + self.current.total + len;
+        of_type as f64 <= total as f64 * share / 100.0
+    }
+
+    /// Count a frame that was sent
+    pub fn record(&mut self, config: &ByteBudgetConfig, msgid: u32, len: usize, now: Instant) {
+        self.roll(config, now);
+        let len = len as u64;
+        self.current.total += len;
+        if config.share(msgid).is_some() {
+            *self.current.by_msgid.entry(msgid).or_default() += len;
+        }
+    }
+
+    fn roll(&mut self, config: &ByteBudgetConfig, now: Instant) {
+        let window = Duration::from_millis(config.window_ms.max(1));
+        let Some(started) = self.started else {
+            self.started = Some(now);
+            return;
+        };
+        let elapsed = now.duration_since(started);
+        if elapsed < window {
+            return;
+        }
+        // After a quiet spell the last window says nothing about now
+        self.previous = if elapsed < window * 2 {
+            std::mem::take(&mut self.current)
+        } else {
+            self.current = Usage::default();
+            Usage::default()
+        };
+        self.started = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ByteShare;
+    use crate::mavlink::messages;
+
+    #[test]
+    fn test_shares() {
+        let config = ByteBudgetConfig {
+            window_ms: 1000,
+            shares: vec![ByteShare {
+                msgid: messages::ATTITUDE,
+                percent: 20.0,
+            }],
+        };
+        let mut budget = ByteBudget::default();
+        let now = Instant::now();
+
+        // Nothing else sent yet, so even one frame is over its share
+        assert!(!budget.allow(&config, messages::ATTITUDE, 40, now));
+        assert!(budget.allow(&config, messages::HEARTBEAT, 100, now));
+        budget.record(&config, messages::HEARTBEAT, 100, now);
+        budget.record(&config, messages::HEARTBEAT, 100, now);
+
+        // 40 of 240 bytes is within 20%, a second 40 of 280 isn't
+        assert!(budget.allow(&config, messages::ATTITUDE, 40, now));
+        budget.record(&config, messages::ATTITUDE, 40, now);
+        assert!(!budget.allow(&config, messages::ATTITUDE, 40, now));
+
+        // The previous window still counts, then it is forgotten
+        let later = now + Duration::from_millis(1500);
+        assert!(!budget.allow(&config, messages::ATTITUDE, 40, later));
+        budget.record(&config, messages::HEARTBEAT, 200, later);
+        assert!(budget.allow(&config, messages::ATTITUDE, 40, later));
+        assert!(!budget.allow(
+            &config,
+            messages::ATTITUDE,
+            40,
+            later + Duration::from_secs(5)
+        ));
+    }
+}
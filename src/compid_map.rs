@@ -0,0 +1,80 @@
+use crate::config::GcsCompidConfig;
+use crate::connection::ConnectionId;
+use std::collections::HashMap;
+
+/// A component id of our own for each component heard on a GCS connection,
+/// so replies go back to the one that asked
+#[derive(Debug)]
+pub struct CompidMap {
+    first: u8,
+    last: u8,
+    /// Assigned id for each (connection, original compid)
+    assigned: HashMap<(ConnectionId, u8), u8>,
+    /// And the way back
+    owners: HashMap<u8, (ConnectionId, u8)>,
+}
+
+impl CompidMap {
+    pub fn new(config: &GcsCompidConfig) -> Self {
+        Self {
+            // 0 addresses every component, so it's never handed out
+            first: config.first.max(1),
+            last: config.last,
+            assigned: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    /// The id a GCS component's frames go out with, if it has one
+    pub fn get(&self, conn_id: ConnectionId, compid: u8) -> Option<u8> {
+        self.assigned.get(&(conn_id, compid)).copied()
+    }
+
+    /// Give a GCS component an id. Returns `None` once the range is used up.
+    pub fn assign(&mut self, conn_id: ConnectionId, compid: u8) -> Option<u8> {
+        if let Some(assigned) = self.get(conn_id, compid) {
+            return Some(assigned);
+        }
+        let free = (self.first..=self.last).find(|id| !self.owners.contains_key(id))?;
+        self.assigned.insert((conn_id, compid), free);
+        self.owners.insert(free, (conn_id, compid));
+        Some(free)
+    }
+
+    /// The connection an assigned id belongs to, and the compid it stands for
+    pub fn owner(&self, assigned: u8) -> Option<(ConnectionId, u8)> {
+        self.owners.get(&assigned).copied()
+    }
+
+    /// Free the ids of a connection that has gone
+    pub fn release(&mut self, conn_id: ConnectionId) {
+        self.assigned.retain(|&(id, _), _| id != conn_id);
+        self.owners.retain(|_, &mut (id, _)| id != conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_and_release() {
+        let mut map = CompidMap::new(&GcsCompidConfig { first: 0, last: 2 });
+        let (a, b, c) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_tcp(1),
+            ConnectionId::new_tcp(2),
+        );
+
+        // Same identity on two connections, told apart
+        assert_eq!(map.assign(a, 190), Some(1));
+        assert_eq!(map.assign(b, 190), Some(2));
+        assert_eq!(map.get(a, 190), Some(1));
+        assert_eq!(map.owner(2), Some((b, 190)));
+        assert_eq!(map.assign(c, 190), None);
+
+        map.release(a);
+        assert_eq!(map.owner(1), None);
+        assert_eq!(map.assign(c, 190), Some(1));
+    }
+}
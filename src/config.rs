@@ -1,3 +1,5 @@
+use crate::connection::ConnectionType;
+use crate::mavlink::messages;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -10,54 +12,916 @@ pub struct Config {
     #[serde(default)]
     pub uart: Vec<UartConfig>,
 
+    /// SocketCAN endpoints for drone connections (Linux only)
+    #[serde(default)]
+    pub can: Vec<CanConfig>,
+
+    /// UDP endpoints for drone connections (e.g., SITL)
+    #[serde(default)]
+    pub udp: Vec<UdpConfig>,
+
+    /// HTTP services streaming MAVLink (receive-only)
+    #[serde(default)]
+    pub http_source: Vec<HttpSourceConfig>,
+
+    /// Outbound TCP connections (e.g., to a relay on another machine)
+    #[serde(default)]
+    pub tcp_client: Vec<TcpClientConfig>,
+
     /// Dynamic UART discovery settings
     #[serde(default)]
-    pub uart_discovery: UartDiscoveryConfig,
+    pub uart_discovery: UartDiscoveryConfig,
+
+    /// Routing rules
+    #[serde(default)]
+    pub routing: RoutingConfig,
+
+    /// Admin channel for remote management
+    #[serde(default)]
+    pub admin: AdminConfig,
+
+    /// Retention of recorded log files
+    #[serde(default)]
+    pub logging: LoggingConfig,
+
+    /// Decoded log of chosen messages
+    #[serde(default)]
+    pub decoded_log: DecodedLogConfig,
+
+    /// Audit trail of routed commands
+    #[serde(default)]
+    pub audit_log: AuditLogConfig,
+
+    /// Export of decoded telemetry to a time-series database
+    #[serde(default)]
+    pub tsdb: TsdbConfig,
+
+    /// Log of every frame the router drops, and why
+    #[serde(default)]
+    pub dead_letter_log: DeadLetterConfig,
+
+    /// Announcing the router itself, for network discovery tools
+    #[serde(default)]
+    pub discovery_announce: DiscoveryAnnounceConfig,
+
+    /// Log level (trace, debug, info, warn, error)
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+
+    /// Performance stats logging interval in seconds (0 = disabled)
+    #[serde(default = "default_stats_interval")]
+    pub stats_interval_secs: u64,
+
+    /// Count traffic per connection, both in total and since its link last
+    /// (re)opened, for the stats log and the admin channel
+    #[serde(default)]
+    pub connection_stats: bool,
+
+    /// Per-vehicle packet loss (from sequence gaps) at or above this
+    /// percentage is logged as a warning in the stats log
+    #[serde(default = "default_loss_warn_percent")]
+    pub loss_warn_percent: f64,
+
+    /// Compare each vehicle's SYSTEM_TIME with the host clock, warning when
+    /// they differ by more than this many milliseconds (unset = not tracked)
+    #[serde(default)]
+    pub clock_skew_warn_ms: Option<u64>,
+
+    /// Directory dialects named by a link's `dialect` are read from
+    #[serde(default = "default_dialect_dir")]
+    pub dialect_dir: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TcpConfig {
+    /// Port to listen on for incoming GCS connections
+    #[serde(default = "default_tcp_port")]
+    pub listen_port: u16,
+
+    /// Bind address
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: String,
+
+    /// Stream compression for every accepted client. The peer must be
+    /// configured the same way, since nothing is negotiated.
+    #[serde(default)]
+    pub compression: Compression,
+
+    /// Gather frames queued for a client into writes of up to this many
+    /// bytes (0 = one write per frame)
+    #[serde(default)]
+    pub max_write_bytes: usize,
+
+    /// Close clients connected for longer than this many seconds, so they
+    /// reconnect, e.g. to a rebalanced instance (0 = never)
+    #[serde(default)]
+    pub max_connection_lifetime_secs: u64,
+
+    /// Link options applied to every accepted client
+    #[serde(flatten)]
+    pub link: LinkConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            listen_port: default_tcp_port(),
+            bind_addr: default_bind_addr(),
+            compression: Compression::None,
+            max_write_bytes: 0,
+            max_connection_lifetime_secs: 0,
+            link: LinkConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminConfig {
+    /// Enable the admin channel
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Port to listen on for admin clients
+    #[serde(default = "default_admin_port")]
+    pub listen_port: u16,
+
+    /// Bind address (loopback by default; the channel is unauthenticated)
+    #[serde(default = "default_admin_bind_addr")]
+    pub bind_addr: String,
+
+    /// How long `test-link` waits for the vehicle to answer
+    #[serde(default = "default_test_link_timeout")]
+    pub test_link_timeout_ms: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_port: default_admin_port(),
+            bind_addr: default_admin_bind_addr(),
+            test_link_timeout_ms: default_test_link_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LoggingConfig {
+    /// Directory of recorded logs, such as tlogs, to keep within the
+    /// limits below (unset = none)
+    #[serde(default)]
+    pub dir: Option<String>,
+
+    /// Delete the oldest log files once together they take more than this
+    /// (0 = no limit)
+    #[serde(default)]
+    pub max_total_size_mb: u64,
+
+    /// Delete log files last written longer ago than this (0 = no limit)
+    #[serde(default)]
+    pub max_age_days: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DecodedLogConfig {
+    /// Enable the decoded log
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Message ids to decode and log
+    #[serde(default)]
+    pub messages: Vec<u32>,
+
+    /// Output format
+    #[serde(default)]
+    pub format: DecodedLogFormat,
+
+    /// File to append to; for CSV, each message gets its own file named
+    /// after this one
+    #[serde(default = "default_decoded_log_path")]
+    pub path: String,
+}
+
+impl Default for DecodedLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            messages: Vec::new(),
+            format: DecodedLogFormat::Jsonl,
+            path: default_decoded_log_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeadLetterConfig {
+    /// Enable the dead-letter log
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// File to append to
+    #[serde(default = "default_dead_letter_path")]
+    pub path: String,
+
+    /// Size at which the file is rotated
+    #[serde(default = "default_dead_letter_max_size_mb")]
+    pub max_size_mb: u64,
+
+    /// Rotated files kept, as `<path>.1` (newest) to `<path>.<max_files>`
+    #[serde(default = "default_dead_letter_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_dead_letter_path(),
+            max_size_mb: default_dead_letter_max_size_mb(),
+            max_files: default_dead_letter_max_files(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TsdbConfig {
+    /// Enable the export
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// `udp://host:port` for line protocol over UDP, or an `http(s)://`
+    /// write URL to POST it to
+    #[serde(default)]
+    pub endpoint: String,
+
+    /// Message ids to decode and export
+    #[serde(default)]
+    pub messages: Vec<u32>,
+
+    /// API token sent as `Authorization: Token <token>` over HTTP
+    #[serde(default)]
+    pub token: Option<String>,
+
+    /// How often batched points are written
+    #[serde(default = "default_tsdb_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+}
+
+impl Default for TsdbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: String::new(),
+            messages: Vec::new(),
+            token: None,
+            flush_interval_ms: default_tsdb_flush_interval_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditLogConfig {
+    /// Record what happened to every command-class frame
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// JSON-lines file to append to
+    #[serde(default = "default_audit_log_path")]
+    pub path: String,
+}
+
+impl Default for AuditLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_audit_log_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DiscoveryAnnounceConfig {
+    /// Send the router's own HEARTBEAT to every connection
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Seconds between HEARTBEATs
+    #[serde(default = "default_announce_interval")]
+    pub interval_secs: u64,
+
+    /// System id the router announces itself as
+    #[serde(default = "default_announce_sysid")]
+    pub sysid: u8,
+
+    /// Component id the router announces itself as
+    #[serde(default = "default_announce_compid")]
+    pub compid: u8,
+
+    /// Also send the announcement as UDP datagrams to this address, e.g. a
+    /// subnet broadcast address such as "192.168.1.255:14550"
+    #[serde(default)]
+    pub broadcast_addr: Option<String>,
+
+    /// Also report the router's health to every connection, as this message
+    #[serde(default)]
+    pub health: Option<HealthMessage>,
+
+    /// Seconds between health reports
+    #[serde(default = "default_health_interval")]
+    pub health_interval_secs: u64,
+
+    /// Message id of MAV_LITE_STATS, with `health = "mav_lite_stats"`
+    #[serde(default = "default_health_msgid")]
+    pub health_msgid: u32,
+}
+
+/// How the router reports its health
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthMessage {
+    /// A NAMED_VALUE_FLOAT per figure, for tools that plot them
+    NamedValue,
+    /// One line of text, for reading in a GCS message log
+    Statustext,
+    /// mav-lite's own MAV_LITE_STATS message, for tools given its definition
+    MavLiteStats,
+}
+
+impl Default for DiscoveryAnnounceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_announce_interval(),
+            sysid: default_announce_sysid(),
+            compid: default_announce_compid(),
+            broadcast_addr: None,
+            health: None,
+            health_interval_secs: default_health_interval(),
+            health_msgid: default_health_msgid(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodedLogFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// A CSV file per message, with a header row
+    Csv,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UartConfig {
+    /// Path to the serial device (e.g., /dev/ttyUSB0)
+    pub path: String,
+
+    /// Baud rate
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+
+    /// Write to this device instead of `path`, which is then only read
+    /// (separate up- and downlink radios, or a one-way isolated pair)
+    #[serde(default)]
+    pub tx_path: Option<String>,
+
+    /// Optional friendly name for logging
+    pub name: Option<String>,
+
+    /// Fixed connection id, so the port is always `UART-<id>`; without one
+    /// ids are handed out in order
+    pub id: Option<usize>,
+
+    /// When the port is opened
+    #[serde(default)]
+    pub start: StartMode,
+
+    /// On a likely baud rate mismatch (bytes arriving but no frames parsed
+    /// soon after opening), reopen at the next common rate instead of only
+    /// warning
+    #[serde(default)]
+    pub auto_baud: bool,
+
+    /// Riding out outages
+    #[serde(flatten)]
+    pub reconnect: UartReconnectConfig,
+
+    /// Link options
+    #[serde(flatten)]
+    pub link: LinkConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UartReconnectConfig {
+    /// How long a UART must stay down before the router is told it has
+    /// disconnected; shorter outages are ridden out with quick reopens
+    #[serde(default = "default_disconnect_debounce")]
+    pub disconnect_debounce_ms: u64,
+
+    /// Outbound frames to hold while a UART is reopening inside the
+    /// disconnect debounce, flushed once it is back (0 = drop them)
+    #[serde(default)]
+    pub reconnect_buffer_frames: usize,
+
+    /// Buffered frames older than this when the port reopens are discarded
+    /// rather than sent late
+    #[serde(default = "default_reconnect_buffer_max_age")]
+    pub reconnect_buffer_max_age_ms: u64,
+}
+
+impl Default for UartReconnectConfig {
+    fn default() -> Self {
+        Self {
+            disconnect_debounce_ms: default_disconnect_debounce(),
+            reconnect_buffer_frames: 0,
+            reconnect_buffer_max_age_ms: default_reconnect_buffer_max_age(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartMode {
+    /// At startup
+    #[default]
+    Auto,
+    /// Only once the admin channel asks for it (`open <name>`)
+    Manual,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CanConfig {
+    /// SocketCAN interface (e.g., can0)
+    pub interface: String,
+
+    /// CAN id our frames are sent with (ids above 0x7FF are sent extended)
+    pub tx_id: u32,
+
+    /// CAN id of the peer's frames; everything else on the bus is ignored
+    pub rx_id: u32,
+
+    /// Optional friendly name for logging
+    pub name: Option<String>,
+
+    /// Link options
+    #[serde(flatten)]
+    pub link: LinkConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UdpConfig {
+    /// Port conventions of a common simulator, used for any address not set
+    pub preset: Option<UdpPreset>,
+
+    /// Local address to bind (e.g., "0.0.0.0:14550")
+    pub bind_addr: Option<String>,
+
+    /// Peer to send to. Without one, replies go to whoever sent the most
+    /// recent datagram.
+    pub remote_addr: Option<String>,
+
+    /// Optional friendly name for logging
+    pub name: Option<String>,
+
+    /// Send frames already queued together in one datagram, up to `mtu`
+    /// bytes, instead of one datagram per frame
+    #[serde(default)]
+    pub pack_frames: bool,
+
+    /// Largest datagram sent when packing frames
+    #[serde(default = "default_udp_mtu")]
+    pub mtu: usize,
+
+    /// Encrypt datagrams with this pre-shared key; the remote must use the
+    /// same one
+    pub psk: Option<String>,
+
+    /// Link options
+    #[serde(flatten)]
+    pub link: LinkConfig,
+}
+
+impl UdpConfig {
+    /// Bind address after applying the preset
+    pub fn bind_addr(&self) -> String {
+        self.bind_addr
+            .clone()
+            .or_else(|| self.preset.map(|p| p.bind_addr().to_string()))
+            .unwrap_or_else(|| "0.0.0.0:0".to_string())
+    }
+
+    /// Remote address after applying the preset
+    pub fn remote_addr(&self) -> Option<String> {
+        self.remote_addr.clone().or_else(|| {
+            self.preset
+                .and_then(|p| p.remote_addr().map(str::to_string))
+        })
+    }
+}
+
+/// UDP port conventions of the common SITL setups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UdpPreset {
+    /// PX4 SITL offboard link: PX4 listens on 14580 and sends to 14540
+    Px4Sitl,
+    /// ArduPilot SITL (sim_vehicle.py): sends to 14550 and replies go back
+    /// to where it sent from
+    ArdupilotSitl,
+}
+
+impl UdpPreset {
+    fn bind_addr(self) -> &'static str {
+        match self {
+            UdpPreset::Px4Sitl => "0.0.0.0:14540",
+            UdpPreset::ArdupilotSitl => "0.0.0.0:14550",
+        }
+    }
+
+    fn remote_addr(self) -> Option<&'static str> {
+        match self {
+            UdpPreset::Px4Sitl => Some("127.0.0.1:14580"),
+            UdpPreset::ArdupilotSitl => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpSourceConfig {
+    /// Endpoint to subscribe to (http:// or https://)
+    pub url: String,
+
+    /// How the endpoint delivers MAVLink
+    #[serde(default)]
+    pub mode: HttpSourceMode,
+
+    /// Optional friendly name for logging
+    pub name: Option<String>,
+
+    /// Link options
+    #[serde(flatten)]
+    pub link: LinkConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpSourceMode {
+    /// Server-Sent Events, each event's data base64-encoded MAVLink
+    #[default]
+    Sse,
+    /// Repeated requests, each response body raw MAVLink
+    LongPoll,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TcpClientConfig {
+    /// Remote address to connect to (e.g., "relay.example.com:5760")
+    pub addr: String,
+
+    /// Optional friendly name for logging
+    pub name: Option<String>,
+
+    /// Wait before the first reconnect attempt; it doubles with each
+    /// failure, up to `max_reconnect_delay_ms`
+    #[serde(default = "default_min_reconnect_delay")]
+    pub min_reconnect_delay_ms: u64,
+
+    /// Longest wait between reconnect attempts
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_reconnect_delay_ms: u64,
+
+    /// Stream compression, as the remote's `[tcp] compression` is set
+    #[serde(default)]
+    pub compression: Compression,
+
+    /// Link options
+    #[serde(flatten)]
+    pub link: LinkConfig,
+}
+
+/// Per-connection link options, shared by every connection type
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LinkConfig {
+    /// Treat bytes that aren't a MAVLink frame start as a framing error
+    /// instead of silently skipping them (for known-clean links)
+    #[serde(default)]
+    pub strict_framing: bool,
+
+    /// With `strict_framing`, drop the link on a framing error so it
+    /// re-syncs on reconnect
+    #[serde(default)]
+    pub strict_framing_disconnect: bool,
+
+    /// When the stream stops parsing, log a guess at why (byte swapping,
+    /// wrong baud rate, not a MAVLink device) instead of every skipped byte
+    #[serde(default = "default_true")]
+    pub diagnose_framing: bool,
+
+    /// Log a hex dump of the bytes skipped as unparseable, at most once a
+    /// second
+    #[serde(default)]
+    pub log_unparseable_bytes: bool,
+
+    /// Drop frames read from this link beyond this many per second, before
+    /// they reach the router (0 = unlimited)
+    #[serde(default)]
+    pub max_ingress_frames_per_sec: u32,
+
+    /// Rewrite the source sysid of commands sent out this vehicle link to
+    /// this GCS sysid, for autopilots that only accept commands from a known
+    /// GCS
+    #[serde(default)]
+    pub gcs_sysid: Option<u8>,
+
+    /// Have chosen messages acknowledged by the mav-lite at the other end
+    /// and resend them until they are (unset = send once)
+    #[serde(default)]
+    pub reliable: Option<ReliableConfig>,
+
+    /// Which side wins when reads and writes are ready at the same time
+    #[serde(default)]
+    pub io_priority: IoPriority,
+
+    /// Vehicle links sharing a failover group reach the same vehicle: all of
+    /// them feed telemetry, but only the active one carries traffic to it
+    #[serde(default)]
+    pub failover_group: Option<String>,
+
+    /// Preference within the failover group; the lowest that is alive is
+    /// the active link
+    #[serde(default)]
+    pub failover_priority: u8,
+
+    /// Drop frames identical to one already sent to this link within this
+    /// many milliseconds, e.g. copies arriving over redundant links (0 = off)
+    #[serde(default)]
+    pub dedup_window_ms: u64,
+
+    /// What makes two frames copies of each other for `dedup_window_ms`
+    #[serde(default)]
+    pub dedup_match: DedupMatch,
+
+    /// Let a frame through again when it comes from the same connection as
+    /// the copy already sent, as a retransmission rather than a redundant copy
+    #[serde(default)]
+    pub dedup_allow_retransmit: bool,
+
+    /// Reject frames received on this link whose message id isn't defined by
+    /// this dialect (a name in `dialect_dir`, or a path to its XML file)
+    #[serde(default)]
+    pub dialect: Option<String>,
+
+    /// What to do with a frame whose checksum is valid for its message but
+    /// whose payload length doesn't fit the message's definition
+    #[serde(default)]
+    pub length_check: LengthCheck,
+
+    /// Only send STATUSTEXT this severe or worse over the link
+    #[serde(default)]
+    pub min_statustext_severity: Option<Severity>,
+
+    /// Hold back the burst of messages a vehicle sends as it boots
+    #[serde(default)]
+    pub boot_suppression: Option<BootSuppressionConfig>,
+
+    /// Cap chosen message types at a share of the bytes sent over the link
+    #[serde(default)]
+    pub byte_budget: Option<ByteBudgetConfig>,
+
+    /// Send only the latest of chosen state messages, at a fixed rate
+    #[serde(default)]
+    pub conflate: Option<ConflateConfig>,
+
+    /// MAVLink 2 signing of the link's traffic, with keys rotated over time
+    #[serde(default)]
+    pub signing: Option<SigningConfig>,
+
+    /// Only send MAVLink 2 frames once the peer has sent one itself
+    #[serde(default)]
+    pub await_peer_v2: Option<AwaitPeerV2Config>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BootSuppressionConfig {
+    /// How long after a vehicle's first frame on its link the burst lasts
+    #[serde(default = "default_boot_window")]
+    pub window_secs: u64,
+
+    /// Message ids held back meanwhile
+    #[serde(default = "default_boot_msgids")]
+    pub msgids: Vec<u32>,
+
+    /// Each is still sent this often per vehicle (0 = not at all)
+    #[serde(default)]
+    pub rate_hz: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ByteBudgetConfig {
+    /// Bytes are counted over this window and the one before it
+    #[serde(default = "default_budget_window")]
+    pub window_ms: u64,
+
+    /// The message types that are capped
+    pub shares: Vec<ByteShare>,
+}
+
+impl ByteBudgetConfig {
+    /// The percentage of the link a message id may use, if it is capped
+    pub fn share(&self, msgid: u32) -> Option<f64> {
+        self.shares
+            .iter()
+            .find(|share| share.msgid == msgid)
+            .map(|share| share.percent)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ByteShare {
+    pub msgid: u32,
+    /// Most of the link's bytes it may take, in percent
+    pub percent: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConflateConfig {
+    /// Message ids of which only the latest per vehicle is sent
+    #[serde(default = "default_conflate_msgids")]
+    pub msgids: Vec<u32>,
+
+    /// Most of each, per vehicle, sent per second
+    #[serde(default = "default_conflate_rate")]
+    pub rate_hz: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AwaitPeerV2Config {
+    /// What becomes of MAVLink 2 frames for the link until then
+    #[serde(default)]
+    pub fallback: PeerV2Fallback,
+
+    /// Most frames held, with `fallback = "hold"`; the oldest go first
+    #[serde(default = "default_peer_v2_hold_frames")]
+    pub hold_frames: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerV2Fallback {
+    /// Send them as MAVLink 1 where the message allows, drop the rest
+    #[default]
+    Downgrade,
+    /// Keep them, and send them once the peer has shown it speaks MAVLink 2
+    Hold,
+    /// Drop them
+    Drop,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SigningConfig {
+    /// Keys frames may be signed with, each valid for a span of time
+    pub keys: Vec<SigningKeyConfig>,
+
+    /// Drop frames from the link that aren't signed with a valid key
+    #[serde(default = "default_true")]
+    pub require: bool,
+
+    /// Sign frames sent over the link with the active key
+    #[serde(default = "default_true")]
+    pub sign_outgoing: bool,
+
+    /// Link id carried in the signatures of frames we sign
+    #[serde(default)]
+    pub link_id: u8,
+}
 
-    /// Routing rules
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SigningKeyConfig {
+    /// Name for the key in logs
+    pub id: String,
+
+    /// The 32-byte key as 64 hex digits, or a passphrase whose SHA-256 is
+    /// the key
+    pub secret: String,
+
+    /// Unix time (seconds) the key becomes valid, if not always
     #[serde(default)]
-    pub routing: RoutingConfig,
+    pub valid_from: Option<u64>,
 
-    /// Log level (trace, debug, info, warn, error)
-    #[serde(default = "default_log_level")]
-    pub log_level: String,
+    /// Unix time (seconds) the key stops being valid, if ever
+    #[serde(default)]
+    pub valid_until: Option<u64>,
+}
 
-    /// Performance stats logging interval in seconds (0 = disabled)
-    #[serde(default = "default_stats_interval")]
-    pub stats_interval_secs: u64,
+/// MAV_SEVERITY, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Emergency,
+    Alert,
+    Critical,
+    Error,
+    Warning,
+    Notice,
+    Info,
+    Debug,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct TcpConfig {
-    /// Port to listen on for incoming GCS connections
-    #[serde(default = "default_tcp_port")]
-    pub listen_port: u16,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMatch {
+    /// Same sysid, compid, sequence, message id and checksum
+    #[default]
+    Wire,
+    /// Same sysid, compid, sequence, message id and payload, whatever the
+    /// MAVLink version or trailing-zero truncation of each copy
+    Content,
+}
 
-    /// Bind address
-    #[serde(default = "default_bind_addr")]
-    pub bind_addr: String,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthCheck {
+    /// Trust the checksum and route it
+    #[default]
+    Accept,
+    /// Route it, with a warning
+    Warn,
+    /// Discard it
+    Drop,
 }
 
-impl Default for TcpConfig {
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoPriority {
+    /// Pick randomly among ready operations
+    #[default]
+    Fair,
+    /// Drain incoming data before sending
+    ReadFirst,
+    /// Send queued frames (e.g. commands to a vehicle) before reading
+    WriteFirst,
+}
+
+impl Default for LinkConfig {
     fn default() -> Self {
         Self {
-            listen_port: default_tcp_port(),
-            bind_addr: default_bind_addr(),
+            strict_framing: false,
+            strict_framing_disconnect: false,
+            diagnose_framing: true,
+            log_unparseable_bytes: false,
+            max_ingress_frames_per_sec: 0,
+            gcs_sysid: None,
+            reliable: None,
+            io_priority: IoPriority::Fair,
+            failover_group: None,
+            failover_priority: 0,
+            dedup_window_ms: 0,
+            dedup_match: DedupMatch::Wire,
+            dedup_allow_retransmit: false,
+            dialect: None,
+            length_check: LengthCheck::Accept,
+            min_statustext_severity: None,
+            boot_suppression: None,
+            byte_budget: None,
+            conflate: None,
+            signing: None,
+            await_peer_v2: None,
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct UartConfig {
-    /// Path to the serial device (e.g., /dev/ttyUSB0)
-    pub path: String,
+pub struct ReliableConfig {
+    /// Message ids sent with acknowledgement
+    #[serde(default = "default_reliable_messages")]
+    pub messages: Vec<u32>,
 
-    /// Baud rate
-    #[serde(default = "default_baud_rate")]
-    pub baud_rate: u32,
+    /// Milliseconds to wait for an ack before sending again
+    #[serde(default = "default_retransmit_ms")]
+    pub retransmit_ms: u64,
 
-    /// Optional friendly name for logging
-    pub name: Option<String>,
+    /// Resends before a frame is given up on
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        Self {
+            messages: default_reliable_messages(),
+            retransmit_ms: default_retransmit_ms(),
+            max_retries: default_max_retries(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -70,17 +934,69 @@ pub struct UartDiscoveryConfig {
     #[serde(default = "default_device_pattern")]
     pub device_pattern: String,
 
+    /// Glob patterns for devices matching `device_pattern` that are never
+    /// probed (e.g., "/dev/ttyACM3" for a known non-MAVLink device)
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+
     /// Baud rate for discovered devices
     #[serde(default = "default_baud_rate")]
     pub baud_rate: u32,
 
+    /// Frames that must parse within the detection timeout before a port
+    /// is taken to be MAVLink
+    #[serde(default = "default_min_frames_to_confirm")]
+    pub min_frames_to_confirm: u32,
+
+    /// Only count frames whose checksum is right, which needs a message
+    /// the router knows the definition of (HEARTBEAT is enough)
+    #[serde(default)]
+    pub require_valid_crc: bool,
+
     /// Timeout in seconds to detect MAVLink traffic on a port
     #[serde(default = "default_detection_timeout")]
     pub detection_timeout_secs: u64,
 
+    /// Total seconds one scan may spend probing devices; any left over are
+    /// probed first on the next scan (0 = no limit)
+    #[serde(default)]
+    pub max_scan_duration_secs: u64,
+
     /// Interval in seconds to rescan for new devices
     #[serde(default = "default_rescan_interval")]
     pub rescan_interval_secs: u64,
+
+    /// Warn at most this often, in seconds, while scans find no devices or
+    /// none sending MAVLink (0 = only log it at debug level)
+    #[serde(default = "default_idle_warn_interval")]
+    pub idle_warn_interval_secs: u64,
+
+    /// Remember devices that stay connected, and connect to them directly on
+    /// the next start instead of probing
+    #[serde(default)]
+    pub persist_discovered: bool,
+
+    /// Sidecar file that remembered devices are written to
+    #[serde(default = "default_persist_file")]
+    pub persist_file: String,
+
+    /// How long a discovered device must stay present before it is remembered
+    #[serde(default = "default_persist_after")]
+    pub persist_after_secs: u64,
+
+    /// Derive each discovered device's connection id from its stable path,
+    /// so it keeps the same id across restarts whatever order devices
+    /// enumerate in
+    #[serde(default)]
+    pub stable_ids: bool,
+
+    /// Riding out outages of discovered devices
+    #[serde(flatten)]
+    pub reconnect: UartReconnectConfig,
+
+    /// Link options for discovered devices
+    #[serde(flatten)]
+    pub link: LinkConfig,
 }
 
 impl Default for UartDiscoveryConfig {
@@ -88,9 +1004,20 @@ impl Default for UartDiscoveryConfig {
         Self {
             enabled: false,
             device_pattern: default_device_pattern(),
+            exclude_patterns: Vec::new(),
             baud_rate: default_baud_rate(),
             detection_timeout_secs: default_detection_timeout(),
+            max_scan_duration_secs: 0,
             rescan_interval_secs: default_rescan_interval(),
+            idle_warn_interval_secs: default_idle_warn_interval(),
+            persist_discovered: false,
+            persist_file: default_persist_file(),
+            persist_after_secs: default_persist_after(),
+            min_frames_to_confirm: default_min_frames_to_confirm(),
+            require_valid_crc: false,
+            stable_ids: false,
+            reconnect: UartReconnectConfig::default(),
+            link: LinkConfig::default(),
         }
     }
 }
@@ -112,6 +1039,133 @@ pub struct RoutingConfig {
     /// Allow TCP-to-UART routing (GCS-to-drone)
     #[serde(default = "default_true")]
     pub allow_tcp_to_uart: bool,
+
+    /// Only route between vehicles and ground stations, never vehicle to
+    /// vehicle or GCS to GCS, whatever the rules above say
+    #[serde(default)]
+    pub strict_role_separation: bool,
+
+    /// Further restrictions on command-class messages
+    #[serde(default)]
+    pub commands: ClassRoutingConfig,
+
+    /// Further restrictions on response-class messages
+    #[serde(default)]
+    pub responses: ClassRoutingConfig,
+
+    /// Further restrictions on telemetry-class messages
+    #[serde(default)]
+    pub telemetry: ClassRoutingConfig,
+
+    /// Sysid namespacing applied to frames crossing a routing edge
+    #[serde(default)]
+    pub sysid_namespace: Vec<SysidNamespaceConfig>,
+
+    /// Commands sent to every vehicle (target_system 0) are rewritten to
+    /// target this one on their way to vehicle links
+    #[serde(default)]
+    pub broadcast_command_target: Option<u8>,
+
+    /// Ceiling on bytes queued across all connections; above it, untargeted
+    /// telemetry is shed (0 = unlimited)
+    #[serde(default)]
+    pub max_queued_bytes: usize,
+
+    /// Sysid of the operator's GCS. The connection it is heard on isn't
+    /// throttled, and still gets telemetry shed from the other (observer)
+    /// GCSs, which starts early under `max_queued_bytes` while it is there
+    #[serde(default)]
+    pub primary_gcs_sysid: Option<u8>,
+
+    /// Percentage of `max_queued_bytes` at which shedding starts while the
+    /// primary GCS is connected, so observers give way before the operator
+    /// has to (100 = at the cap itself)
+    #[serde(default = "default_primary_gcs_shed_percent")]
+    pub primary_gcs_shed_percent: u8,
+
+    /// When a vehicle's HEARTBEAT stops, keep re-sending its last one to
+    /// ground stations for this many seconds, marked critical, so the GCS
+    /// shows a lost link rather than dropping the vehicle (0 = disabled)
+    #[serde(default)]
+    pub synthetic_heartbeat_secs: u64,
+
+    /// Keep a vehicle's sysid reserved this long after its link drops, so
+    /// commands for it aren't broadcast to every other vehicle in the
+    /// meantime (0 = forget it immediately)
+    #[serde(default)]
+    pub sysid_grace_secs: u64,
+
+    /// What happens to commands for a vehicle inside its grace period
+    #[serde(default)]
+    pub lost_sysid_policy: LostSysidPolicy,
+
+    /// Throttle telemetry sent over links whose measured quality is poor
+    #[serde(default)]
+    pub link_quality: Option<LinkQualityConfig>,
+
+    /// Cut telemetry to connections that can't keep up with it, and restore
+    /// it as they recover
+    #[serde(default)]
+    pub adaptive_telemetry: Option<AdaptiveTelemetryConfig>,
+
+    /// A failover group member that has received nothing for this long is
+    /// considered dead, and traffic moves to the next one
+    #[serde(default = "default_failover_timeout")]
+    pub failover_timeout_ms: u64,
+
+    /// Frames that waited longer than this between being read and being
+    /// routed, because the router is behind, are dropped rather than
+    /// delivered late (0 = no limit)
+    #[serde(default)]
+    pub max_router_age_ms: u64,
+
+    /// Most frames waiting for the router at once; connections drop frames
+    /// beyond it instead of queueing them (0 = no limit)
+    #[serde(default)]
+    pub input_queue_frames: usize,
+
+    /// Only forward commands to a vehicle that has sent a HEARTBEAT within
+    /// this many seconds; commands to silent vehicles are dropped
+    /// (0 = disabled)
+    #[serde(default)]
+    pub command_heartbeat_timeout_secs: u64,
+
+    /// Keep each vehicle's PARAM_VALUEs and answer GCS parameter requests
+    /// from them once the full set has been seen, instead of asking the
+    /// vehicle again
+    #[serde(default)]
+    pub param_cache: bool,
+
+    /// How long a cached parameter value is trusted; older ones are asked
+    /// of the vehicle again (0 = until it changes)
+    #[serde(default)]
+    pub param_cache_ttl_secs: u64,
+
+    /// Answer PARAM_REQUEST_READ from the cache as well as
+    /// PARAM_REQUEST_LIST; when off, reads always go to the vehicle
+    #[serde(default = "default_true")]
+    pub param_cache_reads: bool,
+
+    /// Give each GCS connection a component id of its own, so replies can
+    /// be routed to the one that asked even when every GCS uses the same
+    /// sysid/compid
+    #[serde(default)]
+    pub gcs_compids: Option<GcsCompidConfig>,
+
+    /// Start as a warm standby: track vehicles and caches from all traffic,
+    /// but send nothing until promoted
+    #[serde(default)]
+    pub standby: Option<StandbyConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LostSysidPolicy {
+    /// Discard them
+    #[default]
+    Drop,
+    /// Hold a bounded number and deliver them if the vehicle comes back
+    Queue,
 }
 
 impl Default for RoutingConfig {
@@ -121,10 +1175,136 @@ impl Default for RoutingConfig {
             allow_tcp_to_tcp: true,
             allow_uart_to_tcp: true,
             allow_tcp_to_uart: true,
+            strict_role_separation: false,
+            commands: ClassRoutingConfig::default(),
+            responses: ClassRoutingConfig::default(),
+            telemetry: ClassRoutingConfig::default(),
+            sysid_namespace: Vec::new(),
+            broadcast_command_target: None,
+            max_queued_bytes: 0,
+            primary_gcs_sysid: None,
+            primary_gcs_shed_percent: default_primary_gcs_shed_percent(),
+            synthetic_heartbeat_secs: 0,
+            sysid_grace_secs: 0,
+            lost_sysid_policy: LostSysidPolicy::Drop,
+            link_quality: None,
+            adaptive_telemetry: None,
+            failover_timeout_ms: default_failover_timeout(),
+            max_router_age_ms: 0,
+            input_queue_frames: 0,
+            command_heartbeat_timeout_secs: 0,
+            param_cache: false,
+            param_cache_ttl_secs: 0,
+            param_cache_reads: true,
+            gcs_compids: None,
+            standby: None,
+        }
+    }
+}
+
+/// Routing rules for one class of message. A frame must be allowed both by
+/// these and by the general rules, so they can only narrow what is routed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClassRoutingConfig {
+    #[serde(default = "default_true")]
+    pub allow_uart_to_uart: bool,
+
+    #[serde(default = "default_true")]
+    pub allow_tcp_to_tcp: bool,
+
+    #[serde(default = "default_true")]
+    pub allow_uart_to_tcp: bool,
+
+    #[serde(default = "default_true")]
+    pub allow_tcp_to_uart: bool,
+}
+
+impl Default for ClassRoutingConfig {
+    fn default() -> Self {
+        Self {
+            allow_uart_to_uart: true,
+            allow_tcp_to_tcp: true,
+            allow_uart_to_tcp: true,
+            allow_tcp_to_uart: true,
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SysidNamespaceConfig {
+    /// Side of the edge whose sysids get shifted ("uart" or "tcp")
+    pub from: ConnectionType,
+
+    /// Side of the edge that sees the shifted sysids
+    pub to: ConnectionType,
+
+    /// Added to the source sysid going `from` -> `to`, and subtracted from
+    /// `target_system` coming back
+    pub offset: u8,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LinkQualityConfig {
+    /// A link is degraded while RADIO_STATUS received on it reports an RSSI
+    /// (the weaker of local and remote) below this
+    pub min_rssi: Option<u8>,
+
+    /// A link is degraded while loss of the frames received on it exceeds
+    /// this percentage
+    pub max_loss_percent: Option<f64>,
+
+    /// Message ids rate-limited on a degraded link
+    #[serde(default)]
+    pub throttle_msgids: Vec<u32>,
+
+    /// Rate each throttled message id is held to, per sysid
+    #[serde(default = "default_throttled_rate_hz")]
+    pub throttled_rate_hz: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdaptiveTelemetryConfig {
+    /// A connection with more than this queued for it is congested
+    #[serde(default = "default_adaptive_queued_bytes")]
+    pub queued_bytes: usize,
+
+    /// Telemetry frames per second a congested connection is cut back to
+    /// at the lowest
+    #[serde(default = "default_adaptive_min_rate")]
+    pub min_rate: f64,
+
+    /// Once recovered past this many frames per second, the limit is lifted
+    #[serde(default = "default_adaptive_max_rate")]
+    pub max_rate: f64,
+
+    /// Frames per second added back each second without congestion
+    #[serde(default = "default_adaptive_increase")]
+    pub increase: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GcsCompidConfig {
+    /// Lowest component id handed out
+    #[serde(default = "default_gcs_compid_first")]
+    pub first: u8,
+
+    /// Highest component id handed out
+    #[serde(default = "default_gcs_compid_last")]
+    pub last: u8,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StandbyConfig {
+    /// Sysid the primary router announces itself as; without one, only the
+    /// admin channel's `promote` makes this router active
+    #[serde(default)]
+    pub primary_sysid: Option<u8>,
+
+    /// Take over once the primary's HEARTBEAT has been missing this long
+    #[serde(default = "default_primary_timeout")]
+    pub primary_timeout_secs: u64,
+}
+
 fn default_tcp_port() -> u16 {
     5760
 }
@@ -133,10 +1313,78 @@ fn default_bind_addr() -> String {
     "0.0.0.0".to_string()
 }
 
+fn default_primary_timeout() -> u64 {
+    3
+}
+
+fn default_admin_port() -> u16 {
+    5770
+}
+
+fn default_admin_bind_addr() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_test_link_timeout() -> u64 {
+    3000
+}
+
 fn default_baud_rate() -> u32 {
     57600
 }
 
+fn default_disconnect_debounce() -> u64 {
+    2000
+}
+
+fn default_reconnect_buffer_max_age() -> u64 {
+    1000
+}
+
+fn default_reliable_messages() -> Vec<u32> {
+    vec![
+        messages::COMMAND_INT,
+        messages::COMMAND_LONG,
+        messages::COMMAND_ACK,
+    ]
+}
+
+fn default_boot_window() -> u64 {
+    10
+}
+
+fn default_budget_window() -> u64 {
+    1000
+}
+
+fn default_peer_v2_hold_frames() -> usize {
+    64
+}
+
+fn default_conflate_msgids() -> Vec<u32> {
+    vec![messages::ATTITUDE, messages::GLOBAL_POSITION_INT]
+}
+
+fn default_conflate_rate() -> f64 {
+    2.0
+}
+
+fn default_boot_msgids() -> Vec<u32> {
+    vec![
+        messages::PARAM_VALUE,
+        messages::AUTOPILOT_VERSION,
+        messages::STATUSTEXT,
+    ]
+}
+
+fn default_retransmit_ms() -> u64 {
+    250
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
 fn default_true() -> bool {
     true
 }
@@ -149,6 +1397,110 @@ fn default_stats_interval() -> u64 {
     30 // Log stats every 30 seconds by default
 }
 
+fn default_loss_warn_percent() -> f64 {
+    5.0
+}
+
+fn default_min_reconnect_delay() -> u64 {
+    500
+}
+
+fn default_max_reconnect_delay() -> u64 {
+    30_000
+}
+
+/// A 1500-byte Ethernet MTU less the IPv4 and UDP headers
+fn default_udp_mtu() -> usize {
+    1472
+}
+
+fn default_announce_interval() -> u64 {
+    1
+}
+
+fn default_announce_sysid() -> u8 {
+    250
+}
+
+fn default_health_interval() -> u64 {
+    5
+}
+
+fn default_health_msgid() -> u32 {
+    59101
+}
+
+/// MAV_COMP_ID_UDP_BRIDGE
+fn default_announce_compid() -> u8 {
+    240
+}
+
+fn default_primary_gcs_shed_percent() -> u8 {
+    75
+}
+
+fn default_failover_timeout() -> u64 {
+    3000
+}
+
+fn default_adaptive_queued_bytes() -> usize {
+    65536
+}
+
+fn default_adaptive_min_rate() -> f64 {
+    10.0
+}
+
+fn default_adaptive_max_rate() -> f64 {
+    500.0
+}
+
+fn default_adaptive_increase() -> f64 {
+    20.0
+}
+
+/// MAV_COMP_ID_USER1
+fn default_gcs_compid_first() -> u8 {
+    25
+}
+
+/// MAV_COMP_ID_USER75
+fn default_gcs_compid_last() -> u8 {
+    99
+}
+
+fn default_throttled_rate_hz() -> f64 {
+    1.0
+}
+
+fn default_audit_log_path() -> String {
+    "audit.jsonl".to_string()
+}
+
+fn default_dead_letter_path() -> String {
+    "dead_letters.jsonl".to_string()
+}
+
+fn default_dead_letter_max_size_mb() -> u64 {
+    10
+}
+
+fn default_dead_letter_max_files() -> usize {
+    5
+}
+
+fn default_tsdb_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn default_decoded_log_path() -> String {
+    "decoded.jsonl".to_string()
+}
+
+fn default_dialect_dir() -> String {
+    "/usr/share/mavlink/message_definitions/v1.0".to_string()
+}
+
 fn default_device_pattern() -> String {
     "/dev/ttyACM*".to_string()
 }
@@ -157,10 +1509,26 @@ fn default_detection_timeout() -> u64 {
     5 // Wait up to 5 seconds for MAVLink traffic
 }
 
+fn default_min_frames_to_confirm() -> u32 {
+    1
+}
+
 fn default_rescan_interval() -> u64 {
     30 // Rescan for new devices every 30 seconds
 }
 
+fn default_idle_warn_interval() -> u64 {
+    300
+}
+
+fn default_persist_file() -> String {
+    "discovered_devices.toml".to_string()
+}
+
+fn default_persist_after() -> u64 {
+    60 // Remember a device once it has been up for a minute
+}
+
 impl Config {
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
@@ -176,17 +1544,55 @@ impl Config {
                     path: "/dev/ttyUSB0".to_string(),
                     baud_rate: 57600,
                     name: Some("Drone 1".to_string()),
+                    id: None,
+                    start: StartMode::Auto,
+                    auto_baud: false,
+                    tx_path: None,
+                    reconnect: UartReconnectConfig::default(),
+                    link: LinkConfig::default(),
                 },
                 UartConfig {
                     path: "/dev/ttyUSB1".to_string(),
                     baud_rate: 57600,
                     name: Some("Drone 2".to_string()),
+                    id: None,
+                    start: StartMode::Auto,
+                    auto_baud: false,
+                    tx_path: None,
+                    reconnect: UartReconnectConfig::default(),
+                    link: LinkConfig::default(),
                 },
             ],
+            can: Vec::new(),
+            udp: Vec::new(),
+            http_source: Vec::new(),
+            tcp_client: Vec::new(),
             uart_discovery: UartDiscoveryConfig::default(),
             routing: RoutingConfig::default(),
+            admin: AdminConfig::default(),
+            logging: LoggingConfig::default(),
+            decoded_log: DecodedLogConfig::default(),
+            audit_log: AuditLogConfig::default(),
+            tsdb: TsdbConfig::default(),
+            dead_letter_log: DeadLetterConfig::default(),
+            discovery_announce: DiscoveryAnnounceConfig::default(),
             log_level: default_log_level(),
             stats_interval_secs: default_stats_interval(),
+            connection_stats: false,
+            loss_warn_percent: default_loss_warn_percent(),
+            clock_skew_warn_ms: None,
+            dialect_dir: default_dialect_dir(),
         }
     }
+
+    /// Link options of every configured connection
+    pub fn links(&self) -> impl Iterator<Item = &LinkConfig> {
+        std::iter::once(&self.tcp.link)
+            .chain(std::iter::once(&self.uart_discovery.link))
+            .chain(self.uart.iter().map(|c| &c.link))
+            .chain(self.can.iter().map(|c| &c.link))
+            .chain(self.udp.iter().map(|c| &c.link))
+            .chain(self.http_source.iter().map(|c| &c.link))
+            .chain(self.tcp_client.iter().map(|c| &c.link))
+    }
 }
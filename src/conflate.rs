@@ -0,0 +1,138 @@
+use crate::config::ConflateConfig;
+use crate::connection::ConnectionId;
+use crate::mavlink::MavFrame;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What became of a frame offered for a link
+#[derive(Debug)]
+pub enum Offer {
+    /// Not conflated, or its interval is up: send it now
+    Send,
+    /// Held until its interval is up
+    Held,
+    /// Held, in place of this older frame, which is now never sent
+    Replaced(ConnectionId, MavFrame),
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    /// When one of these was last sent
+    sent: Option<Instant>,
+    /// The newest frame waiting, and where it came from
+    held: Option<(ConnectionId, MavFrame)>,
+}
+
+/// Conflated message types on one link
+#[derive(Debug, Default)]
+pub struct Conflation {
+    /// By (sysid, compid, msgid)
+    slots: HashMap<(u8, u8, u32), Slot>,
+}
+
+impl Conflation {
+    pub fn offer(
+        &mut self,
+        config: &ConflateConfig,
+        source: ConnectionId,
+        frame: &MavFrame,
+        now: Instant,
+    ) -> Offer {
+        if !config.msgids.contains(&frame.msg_id()) {
+            return Offer::Send;
+        }
+        let slot = self
+            .slots
+            .entry((frame.sys_id(), frame.comp_id(), frame.msg_id()))
+            .or_default();
+        if slot.held.is_none()
+            && slot
+                .sent
+                .is_none_or(|sent| now.duration_since(sent) >= interval(config))
+        {
+            slot.sent = Some(now);
+            return Offer::Send;
+        }
+        match slot.held.replace((source, frame.clone())) {
+            Some((source, frame)) => Offer::Replaced(source, frame),
+            None => Offer::Held,
+        }
+    }
+
+    /// Held frames whose interval is up, to send now
+    pub fn due(&mut self, config: &ConflateConfig, now: Instant) -> Vec<(ConnectionId, MavFrame)> {
+        let interval = interval(config);
+        let mut due = Vec::new();
+        for slot in self.slots.values_mut() {
+            if slot.held.is_some()
+                && slot
+                    .sent
+                    .is_none_or(|sent| now.duration_since(sent) >= interval)
+            {
+                due.extend(slot.held.take());
+                slot.sent = Some(now);
+            }
+        }
+        due
+    }
+}
+
+fn interval(config: &ConflateConfig) -> Duration {
+    Duration::from_secs_f64(1.0 / config.rate_hz.max(0.01))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    fn attitude(roll: u8) -> MavFrame {
+        let mut payload = [0u8; 28];
+        payload[4] = roll;
+        MavFrame::new_v2(0, 1, 1, messages::ATTITUDE, &payload).unwrap()
+    }
+
+    #[test]
+    fn test_keeps_latest() {
+        let config = ConflateConfig {
+            msgids: vec![messages::ATTITUDE],
+            rate_hz: 2.0,
+        };
+        let source = ConnectionId::new_uart(0);
+        let mut conflation = Conflation::default();
+        let now = Instant::now();
+
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        assert!(matches!(
+            conflation.offer(&config, source, &heartbeat, now),
+            Offer::Send
+        ));
+
+        // The first goes straight out, the next two wait and the newer wins
+        assert!(matches!(
+            conflation.offer(&config, source, &attitude(1), now),
+            Offer::Send
+        ));
+        let later = now + Duration::from_millis(100);
+        assert!(matches!(
+            conflation.offer(&config, source, &attitude(2), later),
+            Offer::Held
+        ));
+        match conflation.offer(&config, source, &attitude(3), later) {
+            Offer::Replaced(_, replaced) => assert_eq!(replaced.payload()[4], 2),
+            offer => panic!("expected a replacement, got {:?}", offer),
+        }
+        assert!(conflation.due(&config, later).is_empty());
+
+        let due = conflation.due(&config, now + Duration::from_millis(500));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.payload()[4], 3);
+
+        // The interval starts again from that release
+        let soon = now + Duration::from_millis(700);
+        assert!(matches!(
+            conflation.offer(&config, source, &attitude(4), soon),
+            Offer::Held
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Reconnect delays doubling from a minimum to a maximum, with jitter
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            failures: 0,
+        }
+    }
+
+    /// Wait before the next attempt: between half and all of `min` doubled
+    /// once per failure so far, capped at `max`
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.ceiling();
+        self.failures = self.failures.saturating_add(1);
+        delay.mul_f64(0.5 + 0.5 * jitter())
+    }
+
+    /// Start again from `min`, after a successful connection
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    fn ceiling(&self) -> Duration {
+        self.min
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(self.max)
+    }
+}
+
+/// A random fraction in [0, 1), from std's randomly keyed hasher
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doubles_up_to_max() {
+        let min = Duration::from_millis(500);
+        let max = Duration::from_secs(5);
+        let mut backoff = Backoff::new(min, max);
+
+        for ceiling in [500, 1000, 2000, 4000, 5000, 5000] {
+            let ceiling = Duration::from_millis(ceiling);
+            let delay = backoff.next_delay();
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?}", delay);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= min);
+    }
+}
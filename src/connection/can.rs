@@ -0,0 +1,371 @@
+use crate::config::CanConfig;
+use crate::connection::framing::FrameDecoder;
+use crate::connection::tcp::RouterMessage;
+use crate::connection::{
+    message_channel, next_io, ConnectionId, IoEvent, MessageReceiver, RouterSender,
+};
+use bytes::BytesMut;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Instant;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, info, warn};
+
+/// Payload bytes in a classic CAN frame
+const CAN_MAX_DLEN: usize = 8;
+
+/// `struct can_frame` from linux/can.h
+#[repr(C)]
+#[derive(Default)]
+struct RawCanFrame {
+    can_id: u32,
+    len: u8,
+    _pad: u8,
+    _res0: u8,
+    _len8_dlc: u8,
+    data: [u8; CAN_MAX_DLEN],
+}
+
+impl RawCanFrame {
+    /// A frame with `can_id` carrying `chunk`, at most 8 bytes of the stream
+    fn new(can_id: u32, chunk: &[u8]) -> Self {
+        let mut frame = Self {
+            can_id,
+            len: chunk.len() as u8,
+            ..Default::default()
+        };
+        frame.data[..chunk.len()].copy_from_slice(chunk);
+        frame
+    }
+
+    /// The bytes of the stream it carries
+    fn payload(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(CAN_MAX_DLEN)]
+    }
+}
+
+/// `struct can_filter` from linux/can.h
+#[repr(C)]
+struct RawCanFilter {
+    can_id: u32,
+    can_mask: u32,
+}
+
+/// Non-blocking raw CAN socket bound to one interface
+struct CanSocket {
+    fd: AsyncFd<OwnedFd>,
+    tx_id: u32,
+}
+
+impl CanSocket {
+    fn open(interface: &str, tx_id: u32, rx_id: u32) -> io::Result<Self> {
+        let ifname = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad interface name"))?;
+
+        // SAFETY: plain libc calls; every return value is checked, and the fd
+        // is owned by `OwnedFd` as soon as it exists.
+        unsafe {
+            let ifindex = libc::if_nametoindex(ifname.as_ptr());
+            if ifindex == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let raw = libc::socket(
+                libc::AF_CAN,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            );
+            if raw < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(raw);
+
+            // Only receive our peer's id
+            let filter = RawCanFilter {
+                can_id: can_id(rx_id),
+                can_mask: libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG | libc::CAN_EFF_MASK,
+            };
+            if libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_CAN_RAW,
+                libc::CAN_RAW_FILTER,
+                &filter as *const RawCanFilter as *const libc::c_void,
+                mem::size_of::<RawCanFilter>() as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut addr: libc::sockaddr_can = mem::zeroed();
+            addr.can_family = libc::AF_CAN as libc::sa_family_t;
+            addr.can_ifindex = ifindex as libc::c_int;
+            if libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self {
+                fd: AsyncFd::new(fd)?,
+                tx_id: can_id(tx_id),
+            })
+        }
+    }
+
+    /// Receive one frame's payload into `buf`
+    async fn recv(&self, buf: &mut BytesMut) -> io::Result<usize> {
+        let mut frame = RawCanFrame::default();
+        let n = self
+            .fd
+            .async_io(Interest::READABLE, |fd| {
+                // SAFETY: reading at most size_of::<RawCanFrame>() into a
+                // repr(C) struct that matches the kernel layout
+                let n = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        &mut frame as *mut RawCanFrame as *mut libc::c_void,
+                        mem::size_of::<RawCanFrame>(),
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            })
+            .await?;
+
+        if n < mem::size_of::<RawCanFrame>() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short CAN read",
+            ));
+        }
+
+        buf.extend_from_slice(frame.payload());
+        Ok(frame.payload().len())
+    }
+
+    /// Send a byte stream, split across as many CAN frames as needed
+    async fn send(&self, data: &[u8]) -> io::Result<()> {
+        for chunk in data.chunks(CAN_MAX_DLEN) {
+            let frame = RawCanFrame::new(self.tx_id, chunk);
+
+            self.fd
+                .async_io(Interest::WRITABLE, |fd| {
+                    // SAFETY: writing a repr(C) struct that matches the kernel layout
+                    let n = unsafe {
+                        libc::write(
+                            fd.as_raw_fd(),
+                            &frame as *const RawCanFrame as *const libc::c_void,
+                            mem::size_of::<RawCanFrame>(),
+                        )
+                    };
+                    if n < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(())
+                    }
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Ids above the 11-bit range go out as extended (29-bit) frames
+fn can_id(id: u32) -> u32 {
+    if id > libc::CAN_SFF_MASK {
+        (id & libc::CAN_EFF_MASK) | libc::CAN_EFF_FLAG
+    } else {
+        id
+    }
+}
+
+/// MAVLink byte stream cut into classic CAN frames, and reassembled
+pub struct CanConnection {
+    conn_id: ConnectionId,
+    config: CanConfig,
+}
+
+impl CanConnection {
+    pub fn new(id: usize, config: CanConfig) -> Self {
+        Self {
+            conn_id: ConnectionId::new_can(id),
+            config,
+        }
+    }
+
+    pub async fn start(self, router_tx: RouterSender) {
+        let (tx, rx) = message_channel();
+
+        // Notify router of new connection
+        let _ = router_tx.send(RouterMessage::NewConnection {
+            conn_id: self.conn_id,
+            tx,
+            link: Box::new(self.config.link.clone()),
+        });
+
+        tokio::spawn(async move {
+            self.run_with_reconnect(rx, router_tx).await;
+        });
+    }
+
+    async fn run_with_reconnect(&self, mut rx: MessageReceiver, router_tx: RouterSender) {
+        let display_name = self
+            .config
+            .name
+            .as_deref()
+            .unwrap_or(&self.config.interface);
+
+        loop {
+            info!(
+                "CAN connection {} ({}) attempting to open {}",
+                self.conn_id, display_name, self.config.interface
+            );
+
+            match CanSocket::open(&self.config.interface, self.config.tx_id, self.config.rx_id) {
+                Ok(socket) => {
+                    let _ = router_tx.send(RouterMessage::LinkUp {
+                        conn_id: self.conn_id,
+                    });
+                    info!(
+                        "CAN connection {} ({}) opened (tx {:#x}, rx {:#x})",
+                        self.conn_id, display_name, self.config.tx_id, self.config.rx_id
+                    );
+
+                    let result = self
+                        .handle_connection(&socket, &mut rx, router_tx.clone())
+                        .await;
+                    if router_tx.is_closed() {
+                        debug!(
+                            "CAN connection {} ({}) stopping, the router has shut down",
+                            self.conn_id, display_name
+                        );
+                        return;
+                    }
+                    if let Err(e) = result {
+                        error!(
+                            "CAN connection {} ({}) error: {}",
+                            self.conn_id, display_name, e
+                        );
+                    }
+
+                    info!(
+                        "CAN connection {} ({}) disconnected, will retry in 5s",
+                        self.conn_id, display_name
+                    );
+                }
+                Err(e) => {
+                    warn!(
+                        "CAN connection {} ({}) failed to open: {}, retrying in 5s",
+                        self.conn_id, display_name, e
+                    );
+                }
+            }
+
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    async fn handle_connection(
+        &self,
+        socket: &CanSocket,
+        rx: &mut MessageReceiver,
+        router_tx: RouterSender,
+    ) -> anyhow::Result<()> {
+        let mut read_buf = BytesMut::with_capacity(4096);
+        let mut decoder = FrameDecoder::new(self.conn_id, &self.config.link)
+            .with_dead_letters(router_tx.dead_letters());
+
+        loop {
+            match next_io(self.config.link.io_priority, socket.recv(&mut read_buf), rx).await {
+                // Read from CAN
+                IoEvent::Read(result) => {
+                    let n = result?;
+                    debug!("CAN connection {} read {} bytes", self.conn_id, n);
+
+                    // Parse MAVLink frames
+                    while let Some(frame) = decoder.next_frame(&mut read_buf)? {
+                        debug!(
+                            "CAN {} received MAVLink msg: sysid={} compid={} msgid={}",
+                            self.conn_id,
+                            frame.sys_id(),
+                            frame.comp_id(),
+                            frame.msg_id()
+                        );
+
+                        // Send to router
+                        router_tx.send(RouterMessage::Frame {
+                            source: self.conn_id,
+                            frame,
+                            received: Instant::now(),
+                        })?;
+                    }
+                }
+
+                // Write to CAN
+                IoEvent::Write(data) => {
+                    socket.send(&data).await?;
+                    debug!("CAN connection {} wrote {} bytes", self.conn_id, data.len());
+                }
+
+                // The router is gone and everything it queued is written
+                IoEvent::Closed => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LinkConfig;
+    use crate::mavlink::{messages, MavFrame};
+
+    #[test]
+    fn test_segmentation() {
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[7; 9]).unwrap();
+        let command = MavFrame::new_v2(1, 255, 190, messages::COMMAND_LONG, &[1; 33]).unwrap();
+        let mut stream = heartbeat.as_bytes().to_vec();
+        stream.extend_from_slice(command.as_bytes());
+
+        // Full frames but the last, all with our id
+        let can_frames: Vec<RawCanFrame> = stream
+            .chunks(CAN_MAX_DLEN)
+            .map(|chunk| RawCanFrame::new(can_id(0x300), chunk))
+            .collect();
+        assert_eq!(can_frames.len(), stream.len().div_ceil(CAN_MAX_DLEN));
+        assert!(can_frames.iter().all(|frame| frame.can_id == 0x300));
+        let lens: Vec<u8> = can_frames.iter().map(|frame| frame.len).collect();
+        assert!(lens[..lens.len() - 1].iter().all(|&len| len == 8));
+        assert_eq!(*lens.last().unwrap() as usize, stream.len() % CAN_MAX_DLEN);
+
+        // Reassembled, it is the same MAVLink, however the reads fall
+        let link = LinkConfig::default();
+        let mut decoder = FrameDecoder::new(ConnectionId::new_can(0), &link);
+        let mut read_buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for frame in &can_frames {
+            read_buf.extend_from_slice(frame.payload());
+            while let Some(frame) = decoder.next_frame(&mut read_buf).unwrap() {
+                decoded.push(frame.as_bytes().to_vec());
+            }
+        }
+        assert_eq!(decoded, [heartbeat.as_bytes(), command.as_bytes()]);
+    }
+
+    #[test]
+    fn test_can_id() {
+        assert_eq!(can_id(0x7FF), 0x7FF);
+        assert_eq!(can_id(0x800), 0x800 | libc::CAN_EFF_FLAG);
+        assert_eq!(can_id(0x1FFF_FFFF), 0x1FFF_FFFF | libc::CAN_EFF_FLAG);
+    }
+}
@@ -0,0 +1,87 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+
+/// Length prefix on each compressed block
+const BLOCK_HEADER_LEN: usize = 4;
+
+/// Largest block (compressed or not) we accept, to bound memory on a
+/// corrupt stream
+const MAX_BLOCK_LEN: usize = 1024 * 1024;
+
+/// Compress one batch of outbound bytes into a length-prefixed block
+pub fn compress_block(data: &[u8]) -> Bytes {
+    let block = lz4_flex::block::compress_prepend_size(data);
+    let mut out = BytesMut::with_capacity(BLOCK_HEADER_LEN + block.len());
+    out.put_u32_le(block.len() as u32);
+    out.extend_from_slice(&block);
+    out.freeze()
+}
+
+/// Decompress every complete block in `wire` into `out`, leaving any partial
+/// block buffered for the next read
+pub fn decompress_blocks(wire: &mut BytesMut, out: &mut BytesMut) -> io::Result<()> {
+    while wire.len() >= BLOCK_HEADER_LEN {
+        let block_len = u32::from_le_bytes([wire[0], wire[1], wire[2], wire[3]]) as usize;
+        if block_len > MAX_BLOCK_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("compressed block of {} bytes exceeds limit", block_len),
+            ));
+        }
+        if wire.len() < BLOCK_HEADER_LEN + block_len {
+            break;
+        }
+
+        wire.advance(BLOCK_HEADER_LEN);
+        let mut block = wire.split_to(block_len);
+        if block.len() < 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated block",
+            ));
+        }
+        let data_len = block.get_u32_le() as usize;
+        if data_len > MAX_BLOCK_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block expands to {} bytes, exceeds limit", data_len),
+            ));
+        }
+        let data = lz4_flex::block::decompress(&block, data_len)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        out.extend_from_slice(&data);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_split_reads() {
+        let data: Vec<u8> = (0..600u32).map(|i| (i % 7) as u8).collect();
+        let mut stream = compress_block(&data).to_vec();
+        stream.extend_from_slice(&compress_block(b"second"));
+        assert!(stream.len() < data.len());
+
+        // Feed the stream a few bytes at a time, as reads might deliver it
+        let mut wire = BytesMut::new();
+        let mut out = BytesMut::new();
+        for chunk in stream.chunks(5) {
+            wire.extend_from_slice(chunk);
+            decompress_blocks(&mut wire, &mut out).unwrap();
+        }
+
+        assert!(wire.is_empty());
+        assert_eq!(&out[..data.len()], &data[..]);
+        assert_eq!(&out[data.len()..], b"second");
+    }
+
+    #[test]
+    fn test_oversized_block_rejected() {
+        let mut wire = BytesMut::new();
+        wire.put_u32_le(MAX_BLOCK_LEN as u32 + 1);
+        assert!(decompress_blocks(&mut wire, &mut BytesMut::new()).is_err());
+    }
+}
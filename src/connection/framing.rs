@@ -0,0 +1,518 @@
+use crate::config::LinkConfig;
+use crate::connection::ConnectionId;
+use crate::dead_letter::DeadLetters;
+use crate::mavlink::{MavFrame, ParseError};
+use bytes::{Buf, BytesMut};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Recent unparseable bytes kept for diagnosing a broken stream
+const DIAGNOSIS_SAMPLE_BYTES: usize = 512;
+
+/// Garbage bytes, without a verified frame, before the stream is diagnosed
+const DIAGNOSIS_THRESHOLD_BYTES: usize = 256;
+
+/// Largest MAVLink frame; a healthy stream has a start byte at least this often
+const MAX_FRAME_LEN: usize = 280;
+
+/// Minimum time between warnings about frames over the ingress limit
+const INGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Minimum time between dumps of unparseable bytes
+const DUMP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most unparseable bytes shown in one dump
+const DUMP_MAX_BYTES: usize = 256;
+
+/// Splits a connection's byte stream into MAVLink frames
+pub struct FrameDecoder {
+    conn_id: ConnectionId,
+    strict: bool,
+    disconnect_on_error: bool,
+    diagnosis: Option<StreamDiagnosis>,
+    ingress_limit: Option<IngressLimit>,
+    dump: Option<ByteDump>,
+    dead_letters: Option<DeadLetters>,
+    /// Frames parsed, and bytes skipped as garbage, since the decoder was made
+    frames: u64,
+    skipped: u64,
+}
+
+impl FrameDecoder {
+    pub fn new(conn_id: ConnectionId, link: &LinkConfig) -> Self {
+        Self {
+            conn_id,
+            strict: link.strict_framing,
+            disconnect_on_error: link.strict_framing && link.strict_framing_disconnect,
+            diagnosis: link.diagnose_framing.then(StreamDiagnosis::default),
+            ingress_limit: (link.max_ingress_frames_per_sec > 0)
+                .then(|| IngressLimit::new(link.max_ingress_frames_per_sec)),
+            dump: link.log_unparseable_bytes.then(ByteDump::default),
+            dead_letters: None,
+            frames: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Log frames dropped over the ingress limit
+    pub fn with_dead_letters(mut self, dead_letters: Option<DeadLetters>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    pub fn frames_parsed(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn bytes_skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Take the next complete frame off the front of `buf`.
+    ///
+    /// Returns `Ok(None)` when more data is needed. Garbage is skipped a byte
+    /// at a time; on a strict link that is logged as an error, and if the link
+    /// is set to disconnect an `Err` is returned instead. Frames over the
+    /// link's ingress limit are dropped here.
+    pub fn next_frame(&mut self, buf: &mut BytesMut) -> anyhow::Result<Option<MavFrame>> {
+        while !buf.is_empty() {
+            match MavFrame::parse(buf) {
+                Ok((frame, consumed)) => {
+                    self.dump_unparseable(Instant::now());
+                    buf.advance(consumed);
+                    self.frames += 1;
+                    self.observe_frame(&frame);
+                    if !self.admit(Instant::now()) {
+                        if let Some(log) = &self.dead_letters {
+                            log.record(self.conn_id, None, &frame, "ingress_limit");
+                        }
+                        continue;
+                    }
+                    return Ok(Some(frame));
+                }
+                Err(ParseError::Incomplete(_, _)) => {
+                    // Need more data
+                    self.dump_unparseable(Instant::now());
+                    return Ok(None);
+                }
+                Err(e) if self.strict => {
+                    error!(
+                        "{} FRAMING ERROR on strict link: {} ({} bytes buffered)",
+                        self.conn_id,
+                        e,
+                        buf.len()
+                    );
+                    if self.disconnect_on_error {
+                        self.observe_garbage(buf[0]);
+                        self.dump_unparseable(Instant::now());
+                        anyhow::bail!("framing error on strict link: {}", e);
+                    }
+                    self.observe_garbage(buf[0]);
+                    buf.advance(1);
+                }
+                Err(e) => {
+                    // With diagnosis on, a sustained problem gets one
+                    // explanation instead of a warning per byte
+                    if self.diagnosis.is_some() {
+                        debug!("{} parse error: {}, skipping byte", self.conn_id, e);
+                    } else {
+                        warn!("{} parse error: {}, skipping byte", self.conn_id, e);
+                    }
+                    self.observe_garbage(buf[0]);
+                    buf.advance(1);
+                }
+            }
+        }
+
+        self.dump_unparseable(Instant::now());
+        Ok(None)
+    }
+
+    /// Log the bytes skipped since the last dump, unless one was logged
+    /// within the interval; they are kept for the next one if so
+    fn dump_unparseable(&mut self, now: Instant) {
+        let Some(dump) = &mut self.dump else {
+            return;
+        };
+        if dump.bytes.is_empty()
+            || dump
+                .logged
+                .is_some_and(|at| now.duration_since(at) < DUMP_INTERVAL)
+        {
+            return;
+        }
+
+        let omitted = if dump.omitted > 0 {
+            format!(", {} more not shown", dump.omitted)
+        } else {
+            String::new()
+        };
+        info!(
+            "{} skipped {} unparseable bytes{}:\n{}",
+            self.conn_id,
+            dump.bytes.len(),
+            omitted,
+            hex_dump(&dump.bytes)
+        );
+        *dump = ByteDump {
+            logged: Some(now),
+            ..Default::default()
+        };
+    }
+
+    /// Whether a frame is within the ingress limit, warning now and then
+    /// about those that aren't
+    fn admit(&mut self, now: Instant) -> bool {
+        let Some(limit) = &mut self.ingress_limit else {
+            return true;
+        };
+        if limit.admit(now) {
+            return true;
+        }
+
+        limit.dropped += 1;
+        if limit
+            .logged
+            .is_none_or(|at| now.duration_since(at) >= INGRESS_LOG_INTERVAL)
+        {
+            warn!(
+                "{} is sending more than {} frames/s, {} dropped since the last report",
+                self.conn_id, limit.rate, limit.dropped
+            );
+            limit.logged = Some(now);
+            limit.dropped = 0;
+        }
+        false
+    }
+
+    fn observe_frame(&mut self, frame: &MavFrame) {
+        let Some(diagnosis) = &mut self.diagnosis else {
+            return;
+        };
+        match frame.crc_valid() {
+            Some(true) => diagnosis.reset(),
+            // A known message with a bad checksum is garbage too
+            Some(false) => diagnosis.add(frame.as_bytes(), true),
+            // Unknown messages can't be checked, so only keep them for context
+            None => diagnosis.add(frame.as_bytes(), false),
+        }
+        self.report();
+    }
+
+    fn observe_garbage(&mut self, byte: u8) {
+        self.skipped += 1;
+        if let Some(diagnosis) = &mut self.diagnosis {
+            diagnosis.add(&[byte], true);
+        }
+        if let Some(dump) = &mut self.dump {
+            if dump.bytes.len() < DUMP_MAX_BYTES {
+                dump.bytes.push(byte);
+            } else {
+                dump.omitted += 1;
+            }
+        }
+        self.report();
+    }
+
+    /// Log a diagnosis once per run of failures
+    fn report(&mut self) {
+        let Some(diagnosis) = &mut self.diagnosis else {
+            return;
+        };
+        if diagnosis.reported || diagnosis.garbage < DIAGNOSIS_THRESHOLD_BYTES {
+            return;
+        }
+        diagnosis.reported = true;
+        warn!(
+            "{} has produced {} bytes that don't parse as MAVLink: {}",
+            self.conn_id,
+            diagnosis.garbage,
+            diagnose(&diagnosis.sample)
+        );
+    }
+}
+
+/// Unparseable bytes waiting to be logged
+#[derive(Debug, Default)]
+struct ByteDump {
+    bytes: Vec<u8>,
+    /// Skipped past `DUMP_MAX_BYTES`, so only counted
+    omitted: u64,
+    logged: Option<Instant>,
+}
+
+/// `hexdump -C` style rows: offset, 16 bytes in hex, then as ASCII
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("  {:04x}  {:<47}  |{}|", row * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Token bucket for frames read from one link, allowing bursts of up to a
+/// second's worth
+#[derive(Debug)]
+struct IngressLimit {
+    rate: u32,
+    tokens: f64,
+    refilled: Option<Instant>,
+    /// Frames dropped since the last warning, and when that was
+    dropped: u64,
+    logged: Option<Instant>,
+}
+
+impl IngressLimit {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            refilled: None,
+            dropped: 0,
+            logged: None,
+        }
+    }
+
+    fn admit(&mut self, now: Instant) -> bool {
+        let rate = self.rate as f64;
+        if let Some(last) = self.refilled {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(rate);
+        }
+        self.refilled = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Bytes seen since the last frame with a verified checksum
+#[derive(Debug, Default)]
+struct StreamDiagnosis {
+    /// The most recent of them, in stream order
+    sample: Vec<u8>,
+    /// How many were skipped or part of a frame with a bad checksum
+    garbage: usize,
+    /// Whether this run of failures has been diagnosed already
+    reported: bool,
+}
+
+impl StreamDiagnosis {
+    fn add(&mut self, bytes: &[u8], garbage: bool) {
+        self.sample.extend_from_slice(bytes);
+        if self.sample.len() > DIAGNOSIS_SAMPLE_BYTES {
+            let excess = self.sample.len() - DIAGNOSIS_SAMPLE_BYTES;
+            self.sample.drain(..excess);
+        }
+        if garbage {
+            self.garbage += bytes.len();
+        }
+    }
+
+    fn reset(&mut self) {
+        if self.reported {
+            debug!("Stream parsing again after {} bad bytes", self.garbage);
+        }
+        *self = Self::default();
+    }
+}
+
+/// Likely cause of a stream that doesn't parse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cause {
+    /// Valid frames appear once each pair of bytes is swapped
+    ByteSwapped,
+    /// Mostly printable text
+    Text,
+    /// Hardly any frame start bytes
+    NoFrameStarts,
+    /// Start bytes are there, but what follows doesn't check out
+    Corrupt,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cause::ByteSwapped => {
+                "it parses with each pair of bytes swapped, so something in between is \
+                 reordering 16-bit words (check any converter or bridge on the link)"
+            }
+            Cause::Text => {
+                "it is mostly text, so the device is probably not speaking MAVLink \
+                 (e.g. an NMEA GPS, a modem or a console)"
+            }
+            Cause::NoFrameStarts => {
+                "it has almost no MAVLink start bytes, so the baud rate is probably \
+                 wrong, or the device doesn't speak MAVLink"
+            }
+            Cause::Corrupt => {
+                "start bytes are there but checksums fail, suggesting a marginal baud \
+                 rate, line noise, or messages from a dialect with different definitions"
+            }
+        })
+    }
+}
+
+fn diagnose(sample: &[u8]) -> Cause {
+    if verified_frames(&swap_pairs(sample)) >= 2 || verified_frames(&swap_pairs(&sample[1..])) >= 2
+    {
+        return Cause::ByteSwapped;
+    }
+
+    let text = sample
+        .iter()
+        .filter(|&&b| b.is_ascii_graphic() || b" \r\n\t".contains(&b))
+        .count();
+    if text * 10 >= sample.len() * 9 {
+        return Cause::Text;
+    }
+
+    let starts = sample.iter().filter(|&&b| b == 0xFD || b == 0xFE).count();
+    if starts * MAX_FRAME_LEN < sample.len() {
+        return Cause::NoFrameStarts;
+    }
+
+    Cause::Corrupt
+}
+
+fn swap_pairs(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .chunks(2)
+        .flat_map(|pair| pair.iter().rev().copied())
+        .collect()
+}
+
+/// Frames with a verified checksum found anywhere in `bytes`
+fn verified_frames(bytes: &[u8]) -> usize {
+    let mut found = 0;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        match MavFrame::parse(&bytes[offset..]) {
+            Ok((frame, consumed)) if frame.crc_valid() == Some(true) => {
+                found += 1;
+                offset += consumed;
+            }
+            _ => offset += 1,
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    fn heartbeats(count: u8) -> Vec<u8> {
+        (0..count)
+            .flat_map(|seq| {
+                MavFrame::new_v2(seq, 1, 1, messages::HEARTBEAT, &[seq + 1; 9])
+                    .unwrap()
+                    .as_bytes()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diagnose() {
+        assert_eq!(diagnose(&swap_pairs(&heartbeats(12))), Cause::ByteSwapped);
+        let nmea = b"$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+        assert_eq!(diagnose(&nmea.repeat(4)), Cause::Text);
+        assert_eq!(diagnose(&[0x80; 400]), Cause::NoFrameStarts);
+
+        let mut corrupt = heartbeats(12);
+        for b in corrupt.iter_mut().skip(3).step_by(5) {
+            *b ^= 0x5A;
+        }
+        assert_eq!(diagnose(&corrupt), Cause::Corrupt);
+    }
+
+    #[test]
+    fn test_ingress_limit() {
+        let start = Instant::now();
+        let mut limit = IngressLimit::new(10);
+        // A second's worth goes through at once, then nothing
+        assert_eq!((0..15).filter(|_| limit.admit(start)).count(), 10);
+        // Then one frame per 100ms
+        assert!(!limit.admit(start + Duration::from_millis(50)));
+        assert!(limit.admit(start + Duration::from_millis(110)));
+        assert!(!limit.admit(start + Duration::from_millis(120)));
+        // An idle link earns back a full burst, but no more
+        let later = start + Duration::from_secs(10);
+        assert_eq!((0..15).filter(|_| limit.admit(later)).count(), 10);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let bytes = b"$GPGGA,123519\r\n\xfe\x09";
+        assert_eq!(
+            hex_dump(bytes),
+            "  0000  24 47 50 47 47 41 2c 31 32 33 35 31 39 0d 0a fe  |$GPGGA,123519...|\n  \
+             0010  09                                               |.|"
+        );
+    }
+
+    #[test]
+    fn test_good_frames_reset_diagnosis() {
+        let link = LinkConfig::default();
+        let mut decoder = FrameDecoder::new(ConnectionId::new_tcp(0), &link);
+        let mut buf = BytesMut::from(&[0x55; 300][..]);
+        buf.extend_from_slice(&heartbeats(1));
+
+        let frame = decoder.next_frame(&mut buf).unwrap().unwrap();
+        assert_eq!(frame.msg_id(), messages::HEARTBEAT);
+        let diagnosis = decoder.diagnosis.as_ref().unwrap();
+        assert_eq!(diagnosis.garbage, 0);
+        assert!(!diagnosis.reported);
+    }
+
+    #[test]
+    fn test_strict_framing_disconnect() {
+        let mut garbled = BytesMut::from(&b"\x55\x55"[..]);
+        garbled.extend_from_slice(&heartbeats(1));
+        let decode = |link: LinkConfig| {
+            let mut decoder = FrameDecoder::new(ConnectionId::new_uart(0), &link);
+            decoder.next_frame(&mut garbled.clone())
+        };
+
+        // Skipped, loudly or not, unless the link is to drop
+        assert!(decode(LinkConfig::default()).unwrap().is_some());
+        let strict = LinkConfig {
+            strict_framing: true,
+            ..LinkConfig::default()
+        };
+        assert!(decode(strict.clone()).unwrap().is_some());
+        let disconnect = LinkConfig {
+            strict_framing_disconnect: true,
+            ..strict
+        };
+        assert!(decode(disconnect.clone()).is_err());
+
+        // Which takes strict_framing as well
+        let lenient = LinkConfig {
+            strict_framing: false,
+            ..disconnect
+        };
+        assert!(decode(lenient).unwrap().is_some());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::config::{HttpSourceConfig, HttpSourceMode};
+use crate::connection::framing::FrameDecoder;
+use crate::connection::tcp::RouterMessage;
+use crate::connection::{
+    message_channel, next_io, ConnectionId, IoEvent, MessageReceiver, RouterSender,
+};
+use base64::Engine;
+use bytes::BytesMut;
+use reqwest::header::ACCEPT;
+use std::time::Instant;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, info, warn};
+
+/// Delay before reconnecting after the stream ends or fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Limit on establishing the HTTP connection (the stream itself is unbounded)
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause before polling again after an empty long-poll response
+const EMPTY_POLL_DELAY: Duration = Duration::from_secs(1);
+
+/// Receive-only MAVLink stream read from Server-Sent Events or long polling
+pub struct HttpSource {
+    conn_id: ConnectionId,
+    config: HttpSourceConfig,
+    client: reqwest::Client,
+}
+
+impl HttpSource {
+    pub fn new(id: usize, config: HttpSourceConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            conn_id: ConnectionId::new_http(id),
+            config,
+            client,
+        })
+    }
+
+    pub async fn start(self, router_tx: RouterSender) {
+        let (tx, rx) = message_channel();
+
+        // Notify router of new connection
+        let _ = router_tx.send(RouterMessage::NewConnection {
+            conn_id: self.conn_id,
+            tx,
+            link: Box::new(self.config.link.clone()),
+        });
+
+        tokio::spawn(async move {
+            self.run_with_reconnect(rx, router_tx).await;
+        });
+    }
+
+    async fn run_with_reconnect(&self, mut rx: MessageReceiver, router_tx: RouterSender) {
+        let display_name = self.config.name.as_deref().unwrap_or(&self.config.url);
+
+        loop {
+            info!(
+                "HTTP source {} ({}) connecting to {}",
+                self.conn_id, display_name, self.config.url
+            );
+
+            let result = self.handle_connection(&mut rx, router_tx.clone()).await;
+            if router_tx.is_closed() {
+                debug!(
+                    "HTTP source {} ({}) stopping, the router has shut down",
+                    self.conn_id, display_name
+                );
+                return;
+            }
+            match result {
+                Ok(()) => info!(
+                    "HTTP source {} ({}) stream ended, will reconnect",
+                    self.conn_id, display_name
+                ),
+                Err(e) => warn!(
+                    "HTTP source {} ({}) error: {}, will reconnect",
+                    self.conn_id, display_name, e
+                ),
+            }
+
+            // Keep the outbound queue drained while we wait
+            let delay = sleep(RECONNECT_DELAY);
+            tokio::pin!(delay);
+            loop {
+                tokio::select! {
+                    _ = &mut delay => break,
+                    Some(data) = rx.recv() => self.discard(data.len()),
+                }
+            }
+        }
+    }
+
+    async fn handle_connection(
+        &self,
+        rx: &mut MessageReceiver,
+        router_tx: RouterSender,
+    ) -> anyhow::Result<()> {
+        let accept = match self.config.mode {
+            HttpSourceMode::Sse => "text/event-stream",
+            HttpSourceMode::LongPoll => "application/octet-stream",
+        };
+        let mut read_buf = BytesMut::with_capacity(4096);
+        let mut decoder = FrameDecoder::new(self.conn_id, &self.config.link)
+            .with_dead_letters(router_tx.dead_letters());
+        let mut events = SseDecoder::default();
+        let mut up = false;
+
+        loop {
+            let mut response = self
+                .client
+                .get(&self.config.url)
+                .header(ACCEPT, accept)
+                .send()
+                .await?
+                .error_for_status()?;
+            debug!(
+                "HTTP source {} response {}",
+                self.conn_id,
+                response.status()
+            );
+            // Long polls make many requests in one session
+            if !up {
+                up = true;
+                let _ = router_tx.send(RouterMessage::LinkUp {
+                    conn_id: self.conn_id,
+                });
+            }
+
+            let mut received = 0;
+            loop {
+                match next_io(self.config.link.io_priority, response.chunk(), rx).await {
+                    // Read from the response body
+                    IoEvent::Read(chunk) => {
+                        let Some(chunk) = chunk? else {
+                            break;
+                        };
+                        debug!("HTTP source {} read {} bytes", self.conn_id, chunk.len());
+                        received += chunk.len();
+
+                        match self.config.mode {
+                            HttpSourceMode::Sse => events.feed(&chunk, &mut read_buf)?,
+                            HttpSourceMode::LongPoll => read_buf.extend_from_slice(&chunk),
+                        }
+
+                        // Parse MAVLink frames
+                        while let Some(frame) = decoder.next_frame(&mut read_buf)? {
+                            debug!(
+                                "HTTP {} received MAVLink msg: sysid={} compid={} msgid={}",
+                                self.conn_id,
+                                frame.sys_id(),
+                                frame.comp_id(),
+                                frame.msg_id()
+                            );
+
+                            // Send to router
+                            router_tx.send(RouterMessage::Frame {
+                                source: self.conn_id,
+                                frame,
+                                received: Instant::now(),
+                            })?;
+                        }
+                    }
+
+                    IoEvent::Write(data) => self.discard(data.len()),
+                    IoEvent::Closed => return Ok(()),
+                }
+            }
+
+            // An event stream is meant to stay open; a poll just starts the next one
+            if self.config.mode == HttpSourceMode::Sse {
+                return Ok(());
+            }
+            // Don't spin on a server that answers polls straight away with nothing
+            if received == 0 {
+                sleep(EMPTY_POLL_DELAY).await;
+            }
+        }
+    }
+
+    fn discard(&self, len: usize) {
+        debug!(
+            "HTTP source {} is receive-only, discarding {} bytes",
+            self.conn_id, len
+        );
+    }
+}
+
+/// Incremental Server-Sent Events parser that base64-decodes each event's
+/// data into the byte stream
+#[derive(Default)]
+struct SseDecoder {
+    /// Partial line carried over from the previous chunk
+    line: Vec<u8>,
+    /// Data lines of the event being assembled
+    data: String,
+}
+
+impl SseDecoder {
+    fn feed(&mut self, chunk: &[u8], out: &mut BytesMut) -> Result<(), base64::DecodeError> {
+        for &byte in chunk {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let line = String::from_utf8_lossy(&line);
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+
+            if line.is_empty() {
+                // Blank line: dispatch the event
+                if !self.data.is_empty() {
+                    let bytes = base64::engine::general_purpose::STANDARD.decode(&self.data)?;
+                    out.extend_from_slice(&bytes);
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data.push_str(value.strip_prefix(' ').unwrap_or(value));
+            }
+            // Comments (":") and other fields (event, id, retry) are ignored
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_events_split_across_chunks() {
+        let mut events = SseDecoder::default();
+        let mut out = BytesMut::new();
+
+        // "AQID" = [1, 2, 3], "BAU=" = [4, 5]
+        let stream = b": keepalive\r\nevent: mavlink\r\ndata: AQ\r\ndata: ID\r\n\r\ndata: BAU=\n\n";
+        for chunk in stream.chunks(3) {
+            events.feed(chunk, &mut out).unwrap();
+        }
+
+        assert_eq!(&out[..], &[1, 2, 3, 4, 5]);
+    }
+}
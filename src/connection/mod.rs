@@ -1,14 +1,75 @@
+pub mod backoff;
+#[cfg(target_os = "linux")]
+pub mod can;
+pub mod compression;
+pub mod framing;
+pub mod http_source;
+pub mod psk;
 pub mod tcp;
+pub mod tcp_client;
 pub mod uart;
 pub mod uart_discovery;
+pub mod udp;
 
+use crate::config::IoPriority;
+use crate::connection::tcp::RouterMessage;
+use crate::dead_letter::DeadLetters;
+use crate::metrics::Metrics;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
-use tokio::sync::mpsc;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot, watch};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ConnectionType {
     Tcp,
     Uart,
+    Can,
+    Udp,
+    Http,
+    #[serde(rename = "tcp_client")]
+    TcpClient,
+}
+
+/// Which end of the network a connection faces, for routing rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionRole {
+    Vehicle,
+    GroundStation,
+}
+
+impl ConnectionType {
+    pub fn role(self) -> ConnectionRole {
+        match self {
+            ConnectionType::Tcp => ConnectionRole::GroundStation,
+            ConnectionType::Uart
+            | ConnectionType::Can
+            | ConnectionType::Udp
+            | ConnectionType::Http
+            | ConnectionType::TcpClient => ConnectionRole::Vehicle,
+        }
+    }
+
+    /// Links to another router or a simulator, carrying whatever is on their
+    /// far side. They are routed as the other end of each link they exchange
+    /// frames with.
+    pub fn is_relay(self) -> bool {
+        matches!(self, ConnectionType::Udp | ConnectionType::TcpClient)
+    }
+}
+
+impl ConnectionRole {
+    pub fn opposite(self) -> Self {
+        match self {
+            ConnectionRole::Vehicle => ConnectionRole::GroundStation,
+            ConnectionRole::GroundStation => ConnectionRole::Vehicle,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -31,6 +92,35 @@ impl ConnectionId {
             id,
         }
     }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn new_can(id: usize) -> Self {
+        Self {
+            conn_type: ConnectionType::Can,
+            id,
+        }
+    }
+
+    pub fn new_udp(id: usize) -> Self {
+        Self {
+            conn_type: ConnectionType::Udp,
+            id,
+        }
+    }
+
+    pub fn new_http(id: usize) -> Self {
+        Self {
+            conn_type: ConnectionType::Http,
+            id,
+        }
+    }
+
+    pub fn new_tcp_client(id: usize) -> Self {
+        Self {
+            conn_type: ConnectionType::TcpClient,
+            id,
+        }
+    }
 }
 
 impl fmt::Display for ConnectionId {
@@ -38,9 +128,488 @@ impl fmt::Display for ConnectionId {
         match self.conn_type {
             ConnectionType::Tcp => write!(f, "TCP-{}", self.id),
             ConnectionType::Uart => write!(f, "UART-{}", self.id),
+            ConnectionType::Can => write!(f, "CAN-{}", self.id),
+            ConnectionType::Udp => write!(f, "UDP-{}", self.id),
+            ConnectionType::Http => write!(f, "HTTP-{}", self.id),
+            ConnectionType::TcpClient => write!(f, "TCPOUT-{}", self.id),
+        }
+    }
+}
+
+/// Router-side handle to a connection's outbound queue
+#[derive(Debug, Clone)]
+pub struct MessageSender {
+    tx: mpsc::UnboundedSender<Bytes>,
+    queued_bytes: Arc<AtomicUsize>,
+    /// Most bytes ever queued at once
+    peak_bytes: Arc<AtomicUsize>,
+    /// Closed when the connection drops its end
+    receiver_open: Arc<watch::Sender<()>>,
+}
+
+/// Connection-side end of the outbound queue
+#[derive(Debug)]
+pub struct MessageReceiver {
+    rx: mpsc::UnboundedReceiver<Bytes>,
+    queued_bytes: Arc<AtomicUsize>,
+    _open: watch::Receiver<()>,
+}
+
+/// Waits for a connection to be done with its outbound queue
+pub struct QueueDrained(Arc<watch::Sender<()>>);
+
+impl QueueDrained {
+    /// Resolves once the connection has dropped its end of the queue, having
+    /// sent what was in it or given up
+    pub async fn wait(self) {
+        self.0.closed().await;
+    }
+}
+
+/// Create a connection's outbound queue, which tracks how many bytes it holds
+pub fn message_channel() -> (MessageSender, MessageReceiver) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let queued_bytes = Arc::new(AtomicUsize::new(0));
+    let (open_tx, open_rx) = watch::channel(());
+    (
+        MessageSender {
+            tx,
+            queued_bytes: queued_bytes.clone(),
+            peak_bytes: Arc::new(AtomicUsize::new(0)),
+            receiver_open: Arc::new(open_tx),
+        },
+        MessageReceiver {
+            rx,
+            queued_bytes,
+            _open: open_rx,
+        },
+    )
+}
+
+impl MessageSender {
+    pub fn send(&self, data: Bytes) -> Result<(), mpsc::error::SendError<Bytes>> {
+        // Count first, so the receiver never subtracts bytes not yet added
+        let len = data.len();
+        let queued = self.queued_bytes.fetch_add(len, Ordering::Relaxed) + len;
+        self.peak_bytes.fetch_max(queued, Ordering::Relaxed);
+        self.tx.send(data).inspect_err(|_| {
+            self.queued_bytes.fetch_sub(len, Ordering::Relaxed);
+        })
+    }
+
+    /// Bytes sent but not yet taken by the connection task
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load(Ordering::Relaxed)
+    }
+
+    /// High-water mark of `queued_bytes` since the connection was added
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_bytes.load(Ordering::Relaxed)
+    }
+
+    /// For waiting until the connection is done with the queue, once every
+    /// sender has been dropped
+    pub fn drained(&self) -> QueueDrained {
+        QueueDrained(self.receiver_open.clone())
+    }
+}
+
+impl MessageReceiver {
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        let data = self.rx.recv().await?;
+        self.queued_bytes.fetch_sub(data.len(), Ordering::Relaxed);
+        Some(data)
+    }
+
+    /// Take the next queued message without waiting
+    pub fn try_recv(&mut self) -> Option<Bytes> {
+        let data = self.rx.try_recv().ok()?;
+        self.queued_bytes.fetch_sub(data.len(), Ordering::Relaxed);
+        Some(data)
+    }
+}
+
+/// Connection-side handle to the router's input. Connections' messages
+/// reach the router in the order each sent them; admin queries and shutdown
+/// go ahead of them. Everything gets through, except that if the router was
+/// given a frame capacity, frames arriving while that many wait are dropped
+/// and counted rather than queued.
+#[derive(Debug, Clone)]
+pub struct RouterSender {
+    control: mpsc::UnboundedSender<RouterMessage>,
+    links: mpsc::UnboundedSender<RouterMessage>,
+    /// Frames waiting in `links`, if they are limited
+    queued_frames: Option<Arc<AtomicUsize>>,
+    frame_capacity: usize,
+    metrics: Metrics,
+    dead_letters: Option<DeadLetters>,
+}
+
+/// The router has stopped taking messages
+#[derive(Debug)]
+pub struct RouterClosed;
+
+impl fmt::Display for RouterClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "router has shut down")
+    }
+}
+
+impl std::error::Error for RouterClosed {}
+
+/// Router-side end of its input
+#[derive(Debug)]
+pub struct RouterReceiver {
+    control: mpsc::UnboundedReceiver<RouterMessage>,
+    links: mpsc::UnboundedReceiver<RouterMessage>,
+    queued_frames: Option<Arc<AtomicUsize>>,
+}
+
+/// Create the router's input, holding at most `frame_capacity` frames if
+/// that isn't 0
+pub fn router_channel(frame_capacity: usize, metrics: Metrics) -> (RouterSender, RouterReceiver) {
+    let (control_tx, control_rx) = mpsc::unbounded_channel();
+    let (links_tx, links_rx) = mpsc::unbounded_channel();
+    let queued_frames = (frame_capacity > 0).then(|| Arc::new(AtomicUsize::new(0)));
+    (
+        RouterSender {
+            control: control_tx,
+            links: links_tx,
+            queued_frames: queued_frames.clone(),
+            frame_capacity,
+            metrics,
+            dead_letters: None,
+        },
+        RouterReceiver {
+            control: control_rx,
+            links: links_rx,
+            queued_frames,
+        },
+    )
+}
+
+impl RouterSender {
+    /// Log frames dropped because the router is full
+    pub fn with_dead_letters(mut self, dead_letters: Option<DeadLetters>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    /// Where frames dropped on their way to the router are logged, if they are
+    pub fn dead_letters(&self) -> Option<DeadLetters> {
+        self.dead_letters.clone()
+    }
+
+    /// Fails only once the router has stopped
+    pub fn send(&self, msg: RouterMessage) -> Result<(), RouterClosed> {
+        match msg {
+            RouterMessage::Query(_) | RouterMessage::Shutdown => {
+                return self.control.send(msg).map_err(|_| RouterClosed);
+            }
+            RouterMessage::Frame { source, frame, .. } if self.is_full() => {
+                if self.links.is_closed() {
+                    return Err(RouterClosed);
+                }
+                self.metrics.record_router_full();
+                if let Some(log) = &self.dead_letters {
+                    log.record(source, None, &frame, "router_full");
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        let frame = matches!(msg, RouterMessage::Frame { .. });
+        self.links.send(msg).map_err(|_| RouterClosed)?;
+        if let (true, Some(queued)) = (frame, &self.queued_frames) {
+            queued.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn is_full(&self) -> bool {
+        self.queued_frames
+            .as_ref()
+            .is_some_and(|queued| queued.load(Ordering::Relaxed) >= self.frame_capacity)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.control.is_closed()
+    }
+}
+
+impl RouterReceiver {
+    /// The next message: queries and shutdown first, then connections'
+    /// messages in order
+    pub async fn recv(&mut self) -> Option<RouterMessage> {
+        let msg = tokio::select! {
+            biased;
+            Some(msg) = self.control.recv() => msg,
+            Some(msg) = self.links.recv() => msg,
+            else => return None,
+        };
+        if let (RouterMessage::Frame { .. }, Some(queued)) = (&msg, &self.queued_frames) {
+            queued.fetch_sub(1, Ordering::Relaxed);
         }
+        Some(msg)
+    }
+
+    /// Stop accepting messages; those already sent can still be received
+    pub fn close(&mut self) {
+        self.control.close();
+        self.links.close();
+    }
+
+    /// Messages waiting to be received
+    pub fn len(&self) -> usize {
+        self.control.len() + self.links.len()
+    }
+}
+
+/// Connections configured with `start = "manual"` that are still waiting
+/// to be opened, by name
+#[derive(Debug, Clone, Default)]
+pub struct ManualStarts {
+    waiting: Arc<Mutex<BTreeMap<String, oneshot::Sender<()>>>>,
+}
+
+impl ManualStarts {
+    /// Register a connection, returning what its task waits on before opening
+    pub fn register(&self, name: String) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(name, tx);
+        rx
+    }
+
+    /// Let the named connection open. Returns false if none is waiting
+    /// under that name (unknown, or already opened).
+    pub fn open(&self, name: &str) -> bool {
+        let tx = self.waiting.lock().unwrap().remove(name);
+        tx.is_some_and(|tx| tx.send(()).is_ok())
+    }
+
+    /// Names of the connections still waiting
+    pub fn waiting(&self) -> Vec<String> {
+        self.waiting.lock().unwrap().keys().cloned().collect()
     }
 }
 
-pub type MessageSender = mpsc::UnboundedSender<bytes::Bytes>;
-pub type MessageReceiver = mpsc::UnboundedReceiver<bytes::Bytes>;
+/// Out-of-band signalling on a serial line, e.g. to reset an autopilot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialSignal {
+    /// Hold the line in the break condition for a moment
+    Break,
+    Dtr(LineAction),
+    Rts(LineAction),
+}
+
+/// What to do with a modem control line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineAction {
+    /// Assert it
+    On,
+    /// Deassert it
+    Off,
+    /// Deassert it briefly, then assert it again
+    Pulse,
+}
+
+impl fmt::Display for SerialSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, action) = match self {
+            SerialSignal::Break => return write!(f, "break"),
+            SerialSignal::Dtr(action) => ("DTR", action),
+            SerialSignal::Rts(action) => ("RTS", action),
+        };
+        let action = match action {
+            LineAction::On => "on",
+            LineAction::Off => "off",
+            LineAction::Pulse => "pulse",
+        };
+        write!(f, "{} {}", line, action)
+    }
+}
+
+/// A signal for a UART task to send, and where to report the outcome
+pub struct SerialControl {
+    pub signal: SerialSignal,
+    pub reply: oneshot::Sender<std::io::Result<()>>,
+}
+
+/// UART ports that are currently open, by device path, for signalling
+/// from the admin channel
+#[derive(Debug, Clone, Default)]
+pub struct SerialControls {
+    open: Arc<Mutex<BTreeMap<String, SerialControlHandle>>>,
+}
+
+#[derive(Debug)]
+struct SerialControlHandle {
+    name: Option<String>,
+    tx: mpsc::UnboundedSender<SerialControl>,
+}
+
+impl SerialControls {
+    /// Register an open port, returning where its task receives signals
+    pub fn register(
+        &self,
+        path: &str,
+        name: Option<&str>,
+    ) -> mpsc::UnboundedReceiver<SerialControl> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let handle = SerialControlHandle {
+            name: name.map(str::to_string),
+            tx,
+        };
+        self.open.lock().unwrap().insert(path.to_string(), handle);
+        rx
+    }
+
+    /// Forget a port once it has closed
+    pub fn unregister(&self, path: &str) {
+        self.open.lock().unwrap().remove(path);
+    }
+
+    /// Send a signal on the open port with the given path or name, waiting
+    /// for it to be done
+    pub async fn signal(&self, uart: &str, signal: SerialSignal) -> anyhow::Result<()> {
+        let (reply, done) = oneshot::channel();
+        {
+            let open = self.open.lock().unwrap();
+            let handle = open
+                .get(uart)
+                .or_else(|| open.values().find(|h| h.name.as_deref() == Some(uart)))
+                .ok_or_else(|| anyhow::anyhow!("no open UART '{}'", uart))?;
+            handle
+                .tx
+                .send(SerialControl { signal, reply })
+                .map_err(|_| anyhow::anyhow!("UART '{}' closed", uart))?;
+        }
+        done.await
+            .map_err(|_| anyhow::anyhow!("UART '{}' closed", uart))??;
+        Ok(())
+    }
+
+    /// Paths of the open ports
+    pub fn open_ports(&self) -> Vec<String> {
+        self.open.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/// Next thing a connection task has to do
+pub enum IoEvent<R> {
+    /// The pending read completed
+    Read(R),
+    /// A frame is queued for sending
+    Write(Bytes),
+    /// The router is gone and everything it queued has been taken
+    Closed,
+}
+
+/// Wait for either `read` or an outbound frame, resolving ties per `priority`
+pub async fn next_io<R>(
+    priority: IoPriority,
+    read: impl Future<Output = R>,
+    rx: &mut MessageReceiver,
+) -> IoEvent<R> {
+    tokio::pin!(read);
+    match priority {
+        IoPriority::Fair => tokio::select! {
+            result = &mut read => IoEvent::Read(result),
+            data = rx.recv() => data.map_or(IoEvent::Closed, IoEvent::Write),
+        },
+        IoPriority::ReadFirst => tokio::select! {
+            biased;
+            result = &mut read => IoEvent::Read(result),
+            data = rx.recv() => data.map_or(IoEvent::Closed, IoEvent::Write),
+        },
+        IoPriority::WriteFirst => tokio::select! {
+            biased;
+            data = rx.recv() => data.map_or(IoEvent::Closed, IoEvent::Write),
+            result = &mut read => IoEvent::Read(result),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::{messages, MavFrame};
+
+    #[tokio::test]
+    async fn test_router_channel_full() {
+        let metrics = Metrics::new();
+        let (tx, mut rx) = router_channel(2, metrics.clone());
+        let conn_id = ConnectionId::new_uart(0);
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let frame = || RouterMessage::Frame {
+            source: conn_id,
+            frame: heartbeat.clone(),
+            received: std::time::Instant::now(),
+        };
+
+        // Frames past the capacity are dropped; other messages never are
+        for _ in 0..3 {
+            tx.send(frame()).unwrap();
+        }
+        tx.send(RouterMessage::Disconnect { conn_id }).unwrap();
+        assert_eq!(metrics.router_full.load(Ordering::Relaxed), 1);
+        assert_eq!(rx.len(), 3);
+
+        // Shutdown goes first; a connection's own messages stay in order
+        tx.send(RouterMessage::Shutdown).unwrap();
+        assert!(matches!(rx.recv().await, Some(RouterMessage::Shutdown)));
+        assert!(matches!(rx.recv().await, Some(RouterMessage::Frame { .. })));
+        // Received frames make room for more
+        tx.send(frame()).unwrap();
+        assert_eq!(metrics.router_full.load(Ordering::Relaxed), 1);
+
+        rx.close();
+        assert!(tx.send(frame()).is_err());
+        assert!(tx.is_closed());
+        assert!(matches!(rx.recv().await, Some(RouterMessage::Frame { .. })));
+        assert!(matches!(
+            rx.recv().await,
+            Some(RouterMessage::Disconnect { .. })
+        ));
+        assert!(matches!(rx.recv().await, Some(RouterMessage::Frame { .. })));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_next_io_priority() {
+        let (tx, mut rx) = message_channel();
+        let ready = || std::future::ready(());
+
+        // Both ready: the preferred side wins every time
+        for _ in 0..8 {
+            tx.send(Bytes::from_static(b"frame")).unwrap();
+            assert!(matches!(
+                next_io(IoPriority::ReadFirst, ready(), &mut rx).await,
+                IoEvent::Read(())
+            ));
+            assert!(matches!(
+                next_io(IoPriority::WriteFirst, ready(), &mut rx).await,
+                IoEvent::Write(_)
+            ));
+        }
+
+        // Only one side ready: that one, whatever the priority
+        let pending = std::future::pending::<()>;
+        tx.send(Bytes::from_static(b"frame")).unwrap();
+        assert!(matches!(
+            next_io(IoPriority::ReadFirst, pending(), &mut rx).await,
+            IoEvent::Write(_)
+        ));
+        assert!(matches!(
+            next_io(IoPriority::WriteFirst, ready(), &mut rx).await,
+            IoEvent::Read(())
+        ));
+
+        // The router gone and the queue empty
+        drop(tx);
+        assert!(matches!(
+            next_io(IoPriority::Fair, pending(), &mut rx).await,
+            IoEvent::Closed
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// HKDF salt and context, so the key is specific to this use
+const KEY_SALT: &[u8] = b"mav-lite udp psk";
+const KEY_INFO: &[u8] = b"chacha20-poly1305 datagram key";
+
+/// Bytes a sealed datagram adds to its contents
+pub const OVERHEAD: usize = NONCE_LEN + 16;
+
+/// How far behind the clock a datagram's counter may be
+const MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Counters behind the newest that may still arrive, out of order
+const WINDOW: u64 = 64;
+
+/// ChaCha20-Poly1305 under a key derived from the `psk` with HKDF-SHA256.
+/// A datagram is sent as a 12-byte nonce (a microsecond counter, then 4
+/// random bytes), the ciphertext and its 16-byte tag.
+pub struct Psk {
+    key: LessSafeKey,
+    /// Last counter sent
+    counter: AtomicU64,
+    /// The rest of each nonce sent
+    sender: [u8; 4],
+}
+
+impl Psk {
+    pub fn new(psk: &str) -> Self {
+        let prk = Salt::new(HKDF_SHA256, KEY_SALT).extract(psk.as_bytes());
+        let okm = prk
+            .expand(&[KEY_INFO], &CHACHA20_POLY1305)
+            .expect("output is one key long");
+        let mut sender = [0u8; 4];
+        SystemRandom::new()
+            .fill(&mut sender)
+            .expect("the system has a random source");
+        Self {
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            counter: AtomicU64::new(0),
+            sender,
+        }
+    }
+
+    /// Encrypt and authenticate one datagram's worth of frames
+    pub fn seal(&self, plaintext: &[u8]) -> Option<Vec<u8>> {
+        let now = unix_micros(SystemTime::now());
+        let last = self
+            .counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .expect("the update always succeeds");
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..8].copy_from_slice(&now.max(last + 1).to_be_bytes());
+        nonce[8..].copy_from_slice(&self.sender);
+
+        let mut datagram = Vec::with_capacity(plaintext.len() + OVERHEAD);
+        datagram.extend_from_slice(&nonce);
+        let mut body = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut body)
+            .ok()?;
+        datagram.extend_from_slice(&body);
+        Some(datagram)
+    }
+
+    /// The counter and contents of a sealed datagram, decrypted in place,
+    /// or `None` if it doesn't authenticate
+    pub fn open<'a>(&self, datagram: &'a mut [u8]) -> Option<(u64, &'a [u8])> {
+        if datagram.len() < OVERHEAD {
+            return None;
+        }
+        let (nonce, body) = datagram.split_at_mut(NONCE_LEN);
+        let counter = u64::from_be_bytes(nonce[..8].try_into().expect("8 bytes"));
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let plaintext = self.key.open_in_place(nonce, Aad::empty(), body).ok()?;
+        Some((counter, plaintext))
+    }
+}
+
+/// Counters of the datagrams received lately
+#[derive(Debug, Default)]
+pub struct ReplayWindow {
+    newest: u64,
+    /// Bit n set: `newest - n` has been received
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Whether a datagram's counter hasn't been seen and isn't too old, and
+    /// if so whether it is the newest yet
+    pub fn accept(&mut self, counter: u64, now: SystemTime) -> Option<bool> {
+        if counter.saturating_add(MAX_AGE.as_micros() as u64) < unix_micros(now) {
+            return None;
+        }
+        if counter > self.newest {
+            let ahead = counter - self.newest;
+            self.seen = if ahead >= WINDOW {
+                0
+            } else {
+                self.seen << ahead
+            };
+            self.seen |= 1;
+            self.newest = counter;
+            return Some(true);
+        }
+        let behind = self.newest - counter;
+        if behind >= WINDOW || self.seen & (1 << behind) != 0 {
+            return None;
+        }
+        self.seen |= 1 << behind;
+        Some(false)
+    }
+}
+
+fn unix_micros(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let psk = Psk::new("correct horse battery staple");
+        let frames = b"\xfd\x09\x00\x00\x00\x01\x01\x00\x00\x00";
+
+        let mut sealed = psk.seal(frames).unwrap();
+        assert_eq!(sealed.len(), frames.len() + OVERHEAD);
+        assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + frames.len()], frames);
+        assert_eq!(psk.open(&mut sealed.clone()).unwrap().1, frames);
+
+        // Another key, or any tampering, and it doesn't open
+        let other = Psk::new("wrong");
+        assert!(other.open(&mut sealed.clone()).is_none());
+        sealed[NONCE_LEN] ^= 1;
+        assert!(psk.open(&mut sealed).is_none());
+        assert!(psk.open(&mut [0u8; 8]).is_none());
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let psk = Psk::new("correct horse battery staple");
+        let now = SystemTime::now();
+        let counter = |sealed: &mut Vec<u8>| psk.open(sealed).unwrap().0;
+        let first = counter(&mut psk.seal(b"a").unwrap());
+        let second = counter(&mut psk.seal(b"b").unwrap());
+        let third = counter(&mut psk.seal(b"c").unwrap());
+        assert!(first < second && second < third);
+
+        // Each counter once; late ones are fine, but aren't the newest
+        let mut window = ReplayWindow::default();
+        assert_eq!(window.accept(first, now), Some(true));
+        assert_eq!(window.accept(third, now), Some(true));
+        assert_eq!(window.accept(second, now), Some(false));
+        assert_eq!(window.accept(second, now), None);
+        assert_eq!(window.accept(third, now), None);
+
+        // Too far behind the newest, or the clock
+        assert_eq!(window.accept(third + WINDOW, now), Some(true));
+        assert_eq!(window.accept(third - 1, now), None);
+        let later = now + MAX_AGE + Duration::from_secs(1);
+        assert_eq!(ReplayWindow::default().accept(third, later), None);
+    }
+}
@@ -1,44 +1,97 @@
-use crate::connection::{ConnectionId, MessageReceiver, MessageSender};
+use crate::config::{Compression, IoPriority, LinkConfig};
+use crate::connection::compression::{compress_block, decompress_blocks};
+use crate::connection::framing::FrameDecoder;
+use crate::connection::{
+    message_channel, next_io, ConnectionId, IoEvent, MessageReceiver, MessageSender, RouterSender,
+};
 use crate::mavlink::MavFrame;
-use bytes::{Buf, BytesMut};
+use crate::router::RouterQuery;
+use bytes::BytesMut;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+/// Upper bound on queued bytes gathered into one compressed block
+pub const COMPRESS_BATCH_BYTES: usize = 16 * 1024;
 
 pub struct TcpServer {
     listener: TcpListener,
     next_id: usize,
+    compression: Compression,
+    max_write_bytes: usize,
+    max_lifetime: Option<Duration>,
+    link: LinkConfig,
 }
 
 impl TcpServer {
-    pub async fn bind(addr: &str) -> anyhow::Result<Self> {
+    pub async fn bind(
+        addr: &str,
+        compression: Compression,
+        max_write_bytes: usize,
+        max_lifetime: Option<Duration>,
+        link: LinkConfig,
+    ) -> anyhow::Result<Self> {
         let listener = TcpListener::bind(addr).await?;
         info!("TCP server listening on {}", addr);
+        if compression != Compression::None {
+            info!("TCP clients must use {:?} compression", compression);
+        }
+        if let Some(max_lifetime) = max_lifetime {
+            info!(
+                "TCP clients are disconnected after {}s, to reconnect",
+                max_lifetime.as_secs()
+            );
+        }
         Ok(Self {
             listener,
             next_id: 0,
+            compression,
+            max_write_bytes,
+            max_lifetime,
+            link,
         })
     }
 
-    pub async fn accept(
-        &mut self,
-        router_tx: mpsc::UnboundedSender<RouterMessage>,
-    ) -> anyhow::Result<()> {
+    pub async fn accept(&mut self, router_tx: RouterSender) -> anyhow::Result<()> {
         let (stream, addr) = self.listener.accept().await?;
         let conn_id = ConnectionId::new_tcp(self.next_id);
         self.next_id += 1;
 
         info!("New TCP connection {} from {}", conn_id, addr);
 
-        let (tx, rx) = mpsc::unbounded_channel();
+        let (tx, rx) = message_channel();
 
         // Notify router of new connection
-        router_tx.send(RouterMessage::NewConnection { conn_id, tx })?;
+        router_tx.send(RouterMessage::NewConnection {
+            conn_id,
+            tx,
+            link: Box::new(self.link.clone()),
+        })?;
+        router_tx.send(RouterMessage::LinkUp { conn_id })?;
 
         // Spawn handler task
+        let decoder =
+            FrameDecoder::new(conn_id, &self.link).with_dead_letters(router_tx.dead_letters());
+        let options = ClientOptions {
+            compression: self.compression,
+            max_write_bytes: self.max_write_bytes,
+            io_priority: self.link.io_priority,
+            max_lifetime: self.max_lifetime,
+        };
         tokio::spawn(async move {
-            if let Err(e) = handle_tcp_connection(conn_id, stream, rx, router_tx.clone()).await {
+            let result =
+                handle_tcp_connection(conn_id, stream, rx, router_tx.clone(), decoder, options)
+                    .await;
+            if router_tx.is_closed() {
+                debug!(
+                    "TCP connection {} stopping, the router has shut down",
+                    conn_id
+                );
+                return;
+            }
+            if let Err(e) = result {
                 error!("TCP connection {} error: {}", conn_id, e);
             }
             // Notify router of disconnect
@@ -50,77 +103,176 @@ impl TcpServer {
     }
 }
 
+/// How a client's stream is read and written
+#[derive(Debug, Clone, Copy)]
+struct ClientOptions {
+    compression: Compression,
+    max_write_bytes: usize,
+    io_priority: IoPriority,
+    /// How long before the client is disconnected, to reconnect
+    max_lifetime: Option<Duration>,
+}
+
 async fn handle_tcp_connection(
     conn_id: ConnectionId,
     mut stream: TcpStream,
     mut rx: MessageReceiver,
-    router_tx: mpsc::UnboundedSender<RouterMessage>,
+    router_tx: RouterSender,
+    mut decoder: FrameDecoder,
+    options: ClientOptions,
 ) -> anyhow::Result<()> {
+    let ClientOptions {
+        compression,
+        max_write_bytes,
+        io_priority,
+        max_lifetime,
+    } = options;
     let (mut read_half, mut write_half) = stream.split();
     let mut read_buf = BytesMut::with_capacity(4096);
+    // Compressed bytes not yet forming a whole block
+    let mut wire_buf = BytesMut::new();
+    let expiry = async {
+        match max_lifetime {
+            Some(lifetime) => sleep(lifetime).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(expiry);
 
     loop {
-        tokio::select! {
-            // Read from TCP socket
-            result = read_half.read_buf(&mut read_buf) => {
-                match result {
-                    Ok(0) => {
-                        debug!("TCP connection {} EOF", conn_id);
-                        break;
-                    }
-                    Ok(n) => {
-                        debug!("TCP connection {} read {} bytes", conn_id, n);
-
-                        // Parse MAVLink frames
-                        while !read_buf.is_empty() {
-                            match MavFrame::parse(&read_buf) {
-                                Ok((frame, consumed)) => {
-                                    debug!(
-                                        "TCP {} received MAVLink msg: sysid={} compid={} msgid={}",
-                                        conn_id, frame.sys_id(), frame.comp_id(), frame.msg_id()
-                                    );
-
-                                    // Send to router
-                                    router_tx.send(RouterMessage::Frame {
-                                        source: conn_id,
-                                        frame,
-                                    })?;
-
-                                    read_buf.advance(consumed);
-                                }
-                                Err(crate::mavlink::ParseError::Incomplete(_, _)) => {
-                                    // Need more data
-                                    break;
-                                }
-                                Err(e) => {
-                                    warn!("TCP {} parse error: {}, skipping byte", conn_id, e);
-                                    read_buf.advance(1);
-                                }
-                            }
+        let read_target = match compression {
+            Compression::None => &mut read_buf,
+            Compression::Lz4 => &mut wire_buf,
+        };
+
+        let event = tokio::select! {
+            event = next_io(io_priority, read_half.read_buf(read_target), &mut rx) => event,
+            _ = &mut expiry => {
+                info!(
+                    "TCP connection {} open for {}s, closing it so the client reconnects",
+                    conn_id,
+                    max_lifetime.unwrap_or_default().as_secs()
+                );
+                // Send what is already queued, then close our side cleanly
+                let mut batch = BytesMut::new();
+                while let Some(more) = rx.try_recv() {
+                    batch.extend_from_slice(&more);
+                }
+                match compression {
+                    Compression::None => write_half.write_all(&batch).await?,
+                    Compression::Lz4 => {
+                        for chunk in batch.chunks(COMPRESS_BATCH_BYTES) {
+                            write_half.write_all(&compress_block(chunk)).await?;
                         }
                     }
-                    Err(e) => {
-                        error!("TCP connection {} read error: {}", conn_id, e);
-                        break;
-                    }
                 }
+                write_half.shutdown().await?;
+                return Ok(());
             }
+        };
+
+        match event {
+            // Read from TCP socket
+            IoEvent::Read(result) => match result {
+                Ok(0) => {
+                    debug!("TCP connection {} EOF", conn_id);
+                    break;
+                }
+                Ok(n) => {
+                    debug!("TCP connection {} read {} bytes", conn_id, n);
+
+                    if compression == Compression::Lz4 {
+                        decompress_blocks(&mut wire_buf, &mut read_buf)?;
+                    }
+
+                    // Parse MAVLink frames
+                    while let Some(frame) = decoder.next_frame(&mut read_buf)? {
+                        debug!(
+                            "TCP {} received MAVLink msg: sysid={} compid={} msgid={}",
+                            conn_id,
+                            frame.sys_id(),
+                            frame.comp_id(),
+                            frame.msg_id()
+                        );
+
+                        // Send to router
+                        router_tx.send(RouterMessage::Frame {
+                            source: conn_id,
+                            frame,
+                            received: Instant::now(),
+                        })?;
+                    }
+                }
+                Err(e) => {
+                    error!("TCP connection {} read error: {}", conn_id, e);
+                    break;
+                }
+            },
 
             // Write to TCP socket
-            Some(data) = rx.recv() => {
-                write_half.write_all(&data).await?;
-                debug!("TCP connection {} wrote {} bytes", conn_id, data.len());
-            }
+            IoEvent::Write(data) => match compression {
+                Compression::None if max_write_bytes == 0 => {
+                    write_half.write_all(&data).await?;
+                    debug!("TCP connection {} wrote {} bytes", conn_id, data.len());
+                }
+                Compression::None => {
+                    for batch in write_batches(&data, &mut rx, max_write_bytes) {
+                        write_half.write_all(&batch).await?;
+                        debug!("TCP connection {} wrote {} bytes", conn_id, batch.len());
+                    }
+                }
+                Compression::Lz4 => {
+                    // Compress whatever else is already queued along with it,
+                    // within max_write_bytes if set
+                    let limit = match max_write_bytes {
+                        0 => COMPRESS_BATCH_BYTES,
+                        max => max.min(COMPRESS_BATCH_BYTES),
+                    };
+                    let mut batch = BytesMut::from(&data[..]);
+                    while batch.len() < limit {
+                        match rx.try_recv() {
+                            Some(more) => batch.extend_from_slice(&more),
+                            None => break,
+                        }
+                    }
+                    let block = compress_block(&batch);
+                    write_half.write_all(&block).await?;
+                    debug!(
+                        "TCP connection {} wrote {} bytes ({} uncompressed)",
+                        conn_id,
+                        block.len(),
+                        batch.len()
+                    );
+                }
+            },
+
+            // The router is gone and everything it queued is written
+            IoEvent::Closed => break,
         }
     }
 
     Ok(())
 }
 
+/// `data` and whatever else is already queued along with it, in writes of at
+/// most `max_write_bytes` unless a single message is larger
+fn write_batches(data: &[u8], rx: &mut MessageReceiver, max_write_bytes: usize) -> Vec<BytesMut> {
+    let mut batch = BytesMut::from(data);
+    while let Some(more) = rx.try_recv() {
+        if batch.len() + more.len() > max_write_bytes {
+            // Leave the rest for the next turn, so reads aren't starved
+            return vec![batch, BytesMut::from(&more[..])];
+        }
+        batch.extend_from_slice(&more);
+    }
+    vec![batch]
+}
+
 pub enum RouterMessage {
     NewConnection {
         conn_id: ConnectionId,
         tx: MessageSender,
+        link: Box<LinkConfig>,
     },
     Disconnect {
         conn_id: ConnectionId,
@@ -128,5 +280,144 @@ pub enum RouterMessage {
     Frame {
         source: ConnectionId,
         frame: MavFrame,
+        /// When the frame was read off its connection
+        received: Instant,
+    },
+    Query(RouterQuery),
+    /// The connection's link (re)opened, starting a new session
+    LinkUp {
+        conn_id: ConnectionId,
     },
+    /// Stop taking frames, route those already received, and stop
+    Shutdown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TcpClientConfig;
+    use crate::connection::tcp_client::TcpClientConnection;
+    use crate::connection::{message_channel, router_channel, RouterReceiver};
+    use crate::mavlink::messages;
+    use crate::metrics::Metrics;
+    use bytes::Bytes;
+    use tokio::time::timeout;
+
+    async fn next_frame(router_rx: &mut RouterReceiver) -> MavFrame {
+        loop {
+            match timeout(Duration::from_secs(5), router_rx.recv()).await {
+                Ok(Some(RouterMessage::Frame { frame, .. })) => return frame,
+                Ok(Some(_)) => continue,
+                _ => panic!("no frame received"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_client() {
+        let mut server = TcpServer::bind(
+            "127.0.0.1:0",
+            Compression::Lz4,
+            0,
+            None,
+            LinkConfig::default(),
+        )
+        .await
+        .unwrap();
+        let config = TcpClientConfig {
+            addr: server.listener.local_addr().unwrap().to_string(),
+            name: None,
+            min_reconnect_delay_ms: 20,
+            max_reconnect_delay_ms: 100,
+            compression: Compression::Lz4,
+            link: LinkConfig::default(),
+        };
+        let (client_tx, mut client_rx) = router_channel(0, Metrics::new());
+        TcpClientConnection::new(0, config).start(client_tx).await;
+        let Some(RouterMessage::NewConnection {
+            tx: client_queue, ..
+        }) = client_rx.recv().await
+        else {
+            panic!("no client connection registered");
+        };
+        let (server_tx, mut server_rx) = router_channel(0, Metrics::new());
+        server.accept(server_tx).await.unwrap();
+        let Some(RouterMessage::NewConnection {
+            tx: server_queue, ..
+        }) = server_rx.recv().await
+        else {
+            panic!("no server connection registered");
+        };
+
+        // Frames get through LZ4 both ways, several to a block
+        let heartbeat =
+            |sysid| MavFrame::new_v2(0, sysid, 1, messages::HEARTBEAT, &[1; 9]).unwrap();
+        client_queue.send(heartbeat(1).to_bytes()).unwrap();
+        client_queue.send(heartbeat(2).to_bytes()).unwrap();
+        assert_eq!(next_frame(&mut server_rx).await.sys_id(), 1);
+        assert_eq!(next_frame(&mut server_rx).await.sys_id(), 2);
+        server_queue.send(heartbeat(3).to_bytes()).unwrap();
+        assert_eq!(next_frame(&mut client_rx).await.sys_id(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_recycles_after_max_lifetime() {
+        let mut server = TcpServer::bind(
+            "127.0.0.1:0",
+            Compression::None,
+            0,
+            Some(Duration::from_millis(200)),
+            LinkConfig::default(),
+        )
+        .await
+        .unwrap();
+        let addr = server.listener.local_addr().unwrap();
+        let (router_tx, mut router_rx) = router_channel(0, Metrics::new());
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        server.accept(router_tx).await.unwrap();
+
+        let Some(RouterMessage::NewConnection { conn_id, tx, .. }) = router_rx.recv().await else {
+            panic!("no connection registered");
+        };
+        tx.send(Bytes::from_static(b"queued")).unwrap();
+
+        // What was queued arrives before the clean close
+        let mut received = Vec::new();
+        timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .expect("connection not closed")
+            .unwrap();
+        assert_eq!(received, b"queued");
+
+        loop {
+            match timeout(Duration::from_secs(5), router_rx.recv()).await {
+                Ok(Some(RouterMessage::Disconnect { conn_id: closed })) => {
+                    assert_eq!(closed, conn_id);
+                    break;
+                }
+                Ok(Some(_)) => continue,
+                _ => panic!("router not told of the disconnect"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_batches() {
+        let (tx, mut rx) = message_channel();
+        for _ in 0..3 {
+            tx.send(Bytes::from_static(&[1; 30])).unwrap();
+        }
+        tx.send(Bytes::from_static(&[2; 30])).unwrap();
+
+        // Filled up to the cap, then the message that didn't fit on its own
+        let batches = write_batches(&[0; 30], &mut rx, 100);
+        let lens: Vec<usize> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(lens, [90, 30]);
+        assert_eq!(rx.try_recv().unwrap()[..], [2; 30]);
+
+        // What's left goes in one write; an oversized message goes whole
+        let batches = write_batches(&[0; 150], &mut rx, 100);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 150);
+    }
 }
@@ -0,0 +1,257 @@
+use crate::config::{Compression, TcpClientConfig};
+use crate::connection::backoff::Backoff;
+use crate::connection::compression::{compress_block, decompress_blocks};
+use crate::connection::framing::FrameDecoder;
+use crate::connection::tcp::{RouterMessage, COMPRESS_BATCH_BYTES};
+use crate::connection::{
+    message_channel, next_io, ConnectionId, IoEvent, MessageReceiver, RouterSender,
+};
+use bytes::BytesMut;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+use tracing::{debug, info, warn};
+
+/// Limit on establishing the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outbound connection to another MAVLink endpoint, reconnecting with backoff
+pub struct TcpClientConnection {
+    conn_id: ConnectionId,
+    config: TcpClientConfig,
+}
+
+impl TcpClientConnection {
+    pub fn new(id: usize, config: TcpClientConfig) -> Self {
+        Self {
+            conn_id: ConnectionId::new_tcp_client(id),
+            config,
+        }
+    }
+
+    pub async fn start(self, router_tx: RouterSender) {
+        let (tx, rx) = message_channel();
+
+        // Notify router of new connection
+        let _ = router_tx.send(RouterMessage::NewConnection {
+            conn_id: self.conn_id,
+            tx,
+            link: Box::new(self.config.link.clone()),
+        });
+
+        tokio::spawn(async move {
+            self.run_with_reconnect(rx, router_tx).await;
+        });
+    }
+
+    async fn run_with_reconnect(&self, mut rx: MessageReceiver, router_tx: RouterSender) {
+        let display_name = self.config.name.as_deref().unwrap_or(&self.config.addr);
+        let mut backoff = Backoff::new(
+            Duration::from_millis(self.config.min_reconnect_delay_ms),
+            Duration::from_millis(self.config.max_reconnect_delay_ms),
+        );
+
+        loop {
+            match timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.config.addr)).await {
+                Ok(Ok(stream)) => {
+                    info!(
+                        "TCP client {} ({}) connected to {}",
+                        self.conn_id, display_name, self.config.addr
+                    );
+                    backoff.reset();
+                    let _ = router_tx.send(RouterMessage::LinkUp {
+                        conn_id: self.conn_id,
+                    });
+                    let _ = stream.set_nodelay(true);
+
+                    let result = self
+                        .handle_connection(stream, &mut rx, router_tx.clone())
+                        .await;
+                    if router_tx.is_closed() {
+                        debug!(
+                            "TCP client {} ({}) stopping, the router has shut down",
+                            self.conn_id, display_name
+                        );
+                        return;
+                    }
+                    match result {
+                        Ok(()) => info!(
+                            "TCP client {} ({}) closed by the remote",
+                            self.conn_id, display_name
+                        ),
+                        Err(e) => warn!(
+                            "TCP client {} ({}) error: {}",
+                            self.conn_id, display_name, e
+                        ),
+                    }
+                }
+                Ok(Err(e)) => warn!(
+                    "TCP client {} ({}) failed to connect to {}: {}",
+                    self.conn_id, display_name, self.config.addr, e
+                ),
+                Err(_) => warn!(
+                    "TCP client {} ({}) timed out connecting to {}",
+                    self.conn_id, display_name, self.config.addr
+                ),
+            }
+
+            let delay = backoff.next_delay();
+            info!(
+                "TCP client {} ({}) reconnecting in {:.1}s",
+                self.conn_id,
+                display_name,
+                delay.as_secs_f64()
+            );
+
+            // Keep the outbound queue drained while we wait
+            let delay = sleep(delay);
+            tokio::pin!(delay);
+            loop {
+                tokio::select! {
+                    _ = &mut delay => break,
+                    Some(data) = rx.recv() => debug!(
+                        "TCP client {} not connected, dropping {} bytes",
+                        self.conn_id,
+                        data.len()
+                    ),
+                }
+            }
+        }
+    }
+
+    async fn handle_connection(
+        &self,
+        stream: TcpStream,
+        rx: &mut MessageReceiver,
+        router_tx: RouterSender,
+    ) -> anyhow::Result<()> {
+        let (mut read_half, mut write_half) = stream.into_split();
+        let mut read_buf = BytesMut::with_capacity(4096);
+        // Compressed bytes not yet forming a whole block
+        let mut wire_buf = BytesMut::new();
+        let compression = self.config.compression;
+        let mut decoder = FrameDecoder::new(self.conn_id, &self.config.link)
+            .with_dead_letters(router_tx.dead_letters());
+
+        loop {
+            let read_target = match compression {
+                Compression::None => &mut read_buf,
+                Compression::Lz4 => &mut wire_buf,
+            };
+
+            match next_io(
+                self.config.link.io_priority,
+                read_half.read_buf(read_target),
+                rx,
+            )
+            .await
+            {
+                IoEvent::Read(Ok(0)) => return Ok(()),
+                IoEvent::Read(Ok(n)) => {
+                    debug!("TCP client {} read {} bytes", self.conn_id, n);
+
+                    if compression == Compression::Lz4 {
+                        decompress_blocks(&mut wire_buf, &mut read_buf)?;
+                    }
+
+                    while let Some(frame) = decoder.next_frame(&mut read_buf)? {
+                        router_tx.send(RouterMessage::Frame {
+                            source: self.conn_id,
+                            frame,
+                            received: Instant::now(),
+                        })?;
+                    }
+                }
+                IoEvent::Read(Err(e)) => return Err(e.into()),
+                IoEvent::Write(data) => match compression {
+                    Compression::None => {
+                        write_half.write_all(&data).await?;
+                        debug!("TCP client {} wrote {} bytes", self.conn_id, data.len());
+                    }
+                    Compression::Lz4 => {
+                        // Compress whatever else is already queued along with it
+                        let mut batch = BytesMut::from(&data[..]);
+                        while batch.len() < COMPRESS_BATCH_BYTES {
+                            match rx.try_recv() {
+                                Some(more) => batch.extend_from_slice(&more),
+                                None => break,
+                            }
+                        }
+                        let block = compress_block(&batch);
+                        write_half.write_all(&block).await?;
+                        debug!(
+                            "TCP client {} wrote {} bytes ({} uncompressed)",
+                            self.conn_id,
+                            block.len(),
+                            batch.len()
+                        );
+                    }
+                },
+                // The router is gone and everything it queued is written
+                IoEvent::Closed => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LinkConfig;
+    use crate::connection::{router_channel, RouterReceiver};
+    use crate::mavlink::{messages, MavFrame};
+    use crate::metrics::Metrics;
+    use tokio::net::TcpListener;
+
+    async fn next_frame(router_rx: &mut RouterReceiver) -> MavFrame {
+        loop {
+            match timeout(Duration::from_secs(5), router_rx.recv()).await {
+                Ok(Some(RouterMessage::Frame { frame, .. })) => return frame,
+                Ok(Some(_)) => continue,
+                _ => panic!("no frame from the TCP client"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_when_server_returns() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = TcpClientConfig {
+            addr: addr.to_string(),
+            name: None,
+            min_reconnect_delay_ms: 20,
+            max_reconnect_delay_ms: 100,
+            compression: Compression::None,
+            link: LinkConfig::default(),
+        };
+        let (router_tx, mut router_rx) = router_channel(0, Metrics::new());
+        TcpClientConnection::new(0, config).start(router_tx).await;
+        // Held as the router would, or the client stops for want of one
+        let Some(RouterMessage::NewConnection { tx: _queue, .. }) = router_rx.recv().await else {
+            panic!("no connection registered");
+        };
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        server.write_all(heartbeat.as_bytes()).await.unwrap();
+        assert_eq!(next_frame(&mut router_rx).await.sys_id(), 1);
+
+        // Server goes down: the client keeps trying until it is back
+        drop(server);
+        drop(listener);
+        sleep(Duration::from_millis(200)).await;
+        let listener = TcpListener::bind(addr).await.unwrap();
+
+        let (mut server, _) = timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .expect("client did not reconnect")
+            .unwrap();
+        server.write_all(heartbeat.as_bytes()).await.unwrap();
+        assert_eq!(
+            next_frame(&mut router_rx).await.msg_id(),
+            messages::HEARTBEAT
+        );
+    }
+}
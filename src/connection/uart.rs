@@ -1,73 +1,370 @@
-use crate::connection::{ConnectionId, MessageReceiver};
-use crate::mavlink::MavFrame;
-use bytes::{Buf, BytesMut};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
-use tokio_serial::SerialPortBuilderExt;
+use crate::config::{LinkConfig, UartConfig, UartReconnectConfig};
+use crate::connection::framing::FrameDecoder;
+use crate::connection::{
+    message_channel, next_io, ConnectionId, IoEvent, LineAction, MessageReceiver, SerialControl,
+    SerialControls, SerialSignal,
+};
+use bytes::{Bytes, BytesMut};
+use std::collections::{HashSet, VecDeque};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
 use tracing::{debug, error, info, warn};
 
+/// Delay between reopen attempts once the router has been told the link is down
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Delay between reopen attempts while still inside the disconnect debounce
+const DEBOUNCE_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// How long a serial break is held
+const BREAK_DURATION: Duration = Duration::from_millis(250);
+
+/// How long a control line is deasserted for a pulse
+const LINE_PULSE_DURATION: Duration = Duration::from_millis(100);
+
+/// How long after opening before the stream is checked for a baud rate
+/// mismatch
+const BAUD_CHECK_WINDOW: Duration = Duration::from_secs(5);
+
+/// Unparseable bytes, with no frame among them, that point to a mismatch
+/// rather than a quiet line
+const BAUD_CHECK_MIN_BYTES: u64 = 256;
+
+/// Rates tried in turn with `auto_baud`, after the configured one
+const AUTO_BAUD_RATES: [u32; 9] = [
+    57600, 115200, 921600, 460800, 230400, 38400, 19200, 9600, 1500000,
+];
+
+/// Ids derived from device paths fall in this range, clear of the ones
+/// handed out in order
+const STABLE_ID_BASE: usize = 1000;
+const STABLE_ID_RANGE: usize = 9000;
+
+/// Connection ids given to UARTs, so fixed, derived and sequential ids
+/// never collide
+#[derive(Debug, Default)]
+pub struct UartIds {
+    taken: HashSet<usize>,
+    next: usize,
+}
+
+impl UartIds {
+    /// Reserve the fixed ids of the static UARTs
+    pub fn with_fixed(uarts: &[UartConfig]) -> anyhow::Result<Self> {
+        let mut ids = Self::default();
+        for uart in uarts {
+            if let Some(id) = uart.id {
+                if !ids.taken.insert(id) {
+                    anyhow::bail!("uart id {} is used more than once", id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Id for a static UART: its fixed one, reserved already, or the next
+    /// free one in order
+    pub fn assign_static(&mut self, uart: &UartConfig) -> usize {
+        uart.id.unwrap_or_else(|| self.next_free())
+    }
+
+    /// Id for a discovered UART: `fixed` if set and free, otherwise one
+    /// derived from `stable_path` if given, otherwise the next free one
+    pub fn assign(&mut self, fixed: Option<usize>, stable_path: Option<&str>) -> usize {
+        if let Some(id) = fixed {
+            if self.taken.insert(id) {
+                return id;
+            }
+            warn!("UART id {} is already in use, assigning another", id);
+        }
+
+        let Some(path) = stable_path else {
+            return self.next_free();
+        };
+        let offset = (fnv1a(path.as_bytes()) % STABLE_ID_RANGE as u64) as usize;
+        let id = (0..STABLE_ID_RANGE)
+            .map(|probe| STABLE_ID_BASE + (offset + probe) % STABLE_ID_RANGE)
+            .find(|id| !self.taken.contains(id))
+            .unwrap_or_else(|| self.next_free());
+        self.taken.insert(id);
+        id
+    }
+
+    fn next_free(&mut self) -> usize {
+        while self.taken.contains(&self.next) {
+            self.next += 1;
+        }
+        self.taken.insert(self.next);
+        self.next
+    }
+}
+
+/// The rate to try after `current` when cycling: the configured rate first,
+/// then the common ones, then round again
+fn next_baud_rate(current: u32, configured: u32) -> u32 {
+    let rates: Vec<u32> = std::iter::once(configured)
+        .chain(
+            AUTO_BAUD_RATES
+                .into_iter()
+                .filter(|&rate| rate != configured),
+        )
+        .collect();
+    let next = rates
+        .iter()
+        .position(|&rate| rate == current)
+        .map_or(0, |i| (i + 1) % rates.len());
+    rates[next]
+}
+
+/// The port was closed because nothing parsed at its baud rate
+#[derive(Debug)]
+struct BaudMismatch(u32);
+
+impl std::fmt::Display for BaudMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no MAVLink frames at {} baud", self.0)
+    }
+}
+
+impl std::error::Error for BaudMismatch {}
+
+/// How a UART outage stands against the disconnect debounce
+#[derive(Debug, PartialEq, Eq)]
+enum Outage {
+    /// Still inside it: reopen again after this long
+    RidingOut(Duration),
+    /// Past it, down this long: time to tell the router
+    Over(Duration),
+}
+
+/// When a UART went down, so brief stalls aren't reported to the router
+#[derive(Debug)]
+struct Debounce {
+    window: Duration,
+    down_since: Option<Instant>,
+}
+
+impl Debounce {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            down_since: None,
+        }
+    }
+
+    /// The port was open and closed; a new outage starts
+    fn closed(&mut self, now: Instant) {
+        self.down_since = Some(now);
+    }
+
+    /// The port didn't open; the outage under way, if any, goes on
+    fn failed_to_open(&mut self, now: Instant) {
+        self.down_since.get_or_insert(now);
+    }
+
+    /// Where the current outage stands, if the port has gone down
+    fn check(&self, now: Instant) -> Option<Outage> {
+        let down_for = now.duration_since(self.down_since?);
+        Some(if down_for >= self.window {
+            Outage::Over(down_for)
+        } else {
+            Outage::RidingOut(DEBOUNCE_RETRY_DELAY.min(self.window - down_for))
+        })
+    }
+}
+
+/// 64-bit FNV-1a, which unlike std's hasher is the same on every build
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub struct UartConnection {
     conn_id: ConnectionId,
     path: String,
     baud_rate: u32,
     name: Option<String>,
+    link: LinkConfig,
+    controls: Option<SerialControls>,
+    auto_baud: bool,
+    /// Separate device frames are written to, if `path` is only read
+    tx_path: Option<String>,
+    reconnect: UartReconnectConfig,
 }
 
 impl UartConnection {
-    pub fn new(id: usize, path: String, baud_rate: u32, name: Option<String>) -> Self {
+    pub fn new(
+        id: usize,
+        path: String,
+        baud_rate: u32,
+        name: Option<String>,
+        link: LinkConfig,
+    ) -> Self {
         Self {
             conn_id: ConnectionId::new_uart(id),
             path,
             baud_rate,
             name,
+            link,
+            controls: None,
+            auto_baud: false,
+            tx_path: None,
+            reconnect: UartReconnectConfig::default(),
         }
     }
 
-    pub async fn start(
+    /// Ride out outages as `reconnect` says
+    pub fn with_reconnect(mut self, reconnect: UartReconnectConfig) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Cycle through common baud rates while the configured one looks wrong
+    pub fn with_auto_baud(mut self, auto_baud: bool) -> Self {
+        self.auto_baud = auto_baud;
+        self
+    }
+
+    /// Write frames to `tx_path` and only read `path`, as one connection
+    pub fn with_tx_path(mut self, tx_path: Option<String>) -> Self {
+        self.tx_path = tx_path;
+        self
+    }
+
+    /// Accept signals (break, DTR, RTS) through `controls` while the port
+    /// is open
+    pub fn with_controls(mut self, controls: SerialControls) -> Self {
+        self.controls = Some(controls);
+        self
+    }
+
+    pub async fn start(self, router_tx: crate::connection::RouterSender) {
+        let rx = self.register(&router_tx);
+
+        tokio::spawn(async move {
+            self.run_with_reconnect(rx, router_tx).await;
+        });
+    }
+
+    /// Like `start`, but the port isn't opened, nor the router told about
+    /// it, until `activate` fires
+    pub async fn start_on_demand(
         self,
-        router_tx: mpsc::UnboundedSender<crate::connection::tcp::RouterMessage>,
+        router_tx: crate::connection::RouterSender,
+        activate: oneshot::Receiver<()>,
     ) {
-        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let display_name = self.name.as_deref().unwrap_or(&self.path);
+            info!(
+                "UART connection {} ({}) waiting to be opened on demand",
+                self.conn_id, display_name
+            );
+            if activate.await.is_err() {
+                return;
+            }
+            info!(
+                "UART connection {} ({}) activated",
+                self.conn_id, display_name
+            );
+
+            let rx = self.register(&router_tx);
+            self.run_with_reconnect(rx, router_tx).await;
+        });
+    }
 
-        // Notify router of new connection
+    /// Notify router of new connection, returning its outbound queue
+    fn register(&self, router_tx: &crate::connection::RouterSender) -> MessageReceiver {
+        let (tx, rx) = message_channel();
         let _ = router_tx.send(crate::connection::tcp::RouterMessage::NewConnection {
             conn_id: self.conn_id,
             tx,
+            link: Box::new(self.link.clone()),
         });
-
-        tokio::spawn(async move {
-            self.run_with_reconnect(rx, router_tx).await;
-        });
+        rx
     }
 
     async fn run_with_reconnect(
         &self,
-        mut rx: MessageReceiver,
-        router_tx: mpsc::UnboundedSender<crate::connection::tcp::RouterMessage>,
+        rx: MessageReceiver,
+        router_tx: crate::connection::RouterSender,
     ) {
-        let display_name = self
-            .name
-            .as_deref()
-            .unwrap_or(&self.path);
+        let display_name = self.name.as_deref().unwrap_or(&self.path);
+        let mut debounce =
+            Debounce::new(Duration::from_millis(self.reconnect.disconnect_debounce_ms));
+
+        // Outbound queue, present while the router has us registered
+        let mut rx = Some(rx);
+        // Outbound frames received while the port was down
+        let mut pending: VecDeque<(Instant, Bytes)> = VecDeque::new();
+        // Differs from the configured rate once auto_baud has moved on
+        let mut baud_rate = self.baud_rate;
 
         loop {
-            info!(
-                "UART connection {} ({}) attempting to open {}",
-                self.conn_id, display_name, self.path
-            );
+            match &self.tx_path {
+                Some(tx_path) => info!(
+                    "UART connection {} ({}) attempting to open {} (rx) and {} (tx) at {} baud",
+                    self.conn_id, display_name, self.path, tx_path, baud_rate
+                ),
+                None => info!(
+                    "UART connection {} ({}) attempting to open {} at {} baud",
+                    self.conn_id, display_name, self.path, baud_rate
+                ),
+            }
 
-            match tokio_serial::new(&self.path, self.baud_rate).open_native_async() {
-                Ok(mut port) => {
+            match self.open_ports(baud_rate) {
+                Ok((mut port, mut tx_port)) => {
                     info!(
                         "UART connection {} ({}) opened successfully",
                         self.conn_id, display_name
                     );
 
-                    if let Err(e) = self
-                        .handle_connection(&mut port, &mut rx, router_tx.clone())
-                        .await
+                    let rx = rx.get_or_insert_with(|| self.register(&router_tx));
+                    let _ = router_tx.send(crate::connection::tcp::RouterMessage::LinkUp {
+                        conn_id: self.conn_id,
+                    });
+                    let mut signals = self
+                        .controls
+                        .as_ref()
+                        .map(|controls| controls.register(&self.path, self.name.as_deref()));
+                    let writer = tx_port.as_mut().unwrap_or(&mut port);
+                    let result = match self.flush_pending(writer, &mut pending).await {
+                        Ok(()) => {
+                            self.handle_connection(
+                                &mut port,
+                                tx_port.as_mut(),
+                                rx,
+                                &mut signals,
+                                router_tx.clone(),
+                                baud_rate,
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Some(controls) = &self.controls {
+                        controls.unregister(&self.path);
+                    }
+                    if router_tx.is_closed() {
+                        debug!(
+                            "UART connection {} ({}) stopping, the router has shut down",
+                            self.conn_id, display_name
+                        );
+                        return;
+                    }
+                    if let Some(BaudMismatch(tried)) =
+                        result.as_ref().err().and_then(|e| e.downcast_ref())
                     {
+                        baud_rate = next_baud_rate(*tried, self.baud_rate);
+                        info!(
+                            "UART connection {} ({}) reopening at {} baud",
+                            self.conn_id, display_name, baud_rate
+                        );
+                        continue;
+                    }
+                    if let Err(e) = result {
                         error!(
                             "UART connection {} ({}) error: {}",
                             self.conn_id, display_name, e
@@ -75,34 +372,171 @@ impl UartConnection {
                     }
 
                     info!(
-                        "UART connection {} ({}) disconnected, will retry in 5s",
+                        "UART connection {} ({}) disconnected, will retry",
                         self.conn_id, display_name
                     );
+                    debounce.closed(Instant::now());
                 }
                 Err(e) => {
                     warn!(
-                        "UART connection {} ({}) failed to open: {}, retrying in 5s",
+                        "UART connection {} ({}) failed to open: {}, retrying",
                         self.conn_id, display_name, e
                     );
+                    debounce.failed_to_open(Instant::now());
+                }
+            }
+
+            // Only tell the router once the link has stayed down past the
+            // debounce, so brief stalls don't disrupt routing
+            let mut retry_delay = RECONNECT_DELAY;
+            let outage = match rx {
+                Some(_) => debounce.check(Instant::now()),
+                None => None,
+            };
+            match outage {
+                Some(Outage::Over(down_for)) => {
+                    info!(
+                        "UART connection {} ({}) down for {}ms, notifying router",
+                        self.conn_id,
+                        display_name,
+                        down_for.as_millis()
+                    );
+                    let _ = router_tx.send(crate::connection::tcp::RouterMessage::Disconnect {
+                        conn_id: self.conn_id,
+                    });
+                    rx = None;
+                    pending.clear();
+                }
+                Some(Outage::RidingOut(delay)) => retry_delay = delay,
+                None => {}
+            }
+
+            match rx.as_mut() {
+                Some(rx) => self.hold_outbound(rx, &mut pending, retry_delay).await,
+                None => sleep(retry_delay).await,
+            }
+        }
+    }
+
+    /// Open the port, and the one frames are written to if that is separate
+    fn open_ports(&self, baud_rate: u32) -> anyhow::Result<(SerialStream, Option<SerialStream>)> {
+        let open = |path: &str| {
+            tokio_serial::new(path, baud_rate)
+                .open_native_async()
+                .map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+        };
+        let port = open(&self.path)?;
+        let tx_port = self.tx_path.as_deref().map(open).transpose()?;
+        Ok((port, tx_port))
+    }
+
+    /// Wait out `delay` while the port is down, keeping what the router sends
+    /// in the meantime (up to `reconnect_buffer_frames`, oldest dropped first)
+    async fn hold_outbound(
+        &self,
+        rx: &mut MessageReceiver,
+        pending: &mut VecDeque<(Instant, Bytes)>,
+        delay: Duration,
+    ) {
+        let limit = self.reconnect.reconnect_buffer_frames;
+        let deadline = sleep(delay);
+        tokio::pin!(deadline);
+
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                Some(data) = rx.recv() => {
+                    if limit == 0 {
+                        debug!(
+                            "UART connection {} down, dropping {} bytes",
+                            self.conn_id,
+                            data.len()
+                        );
+                        continue;
+                    }
+                    if pending.len() >= limit {
+                        pending.pop_front();
+                        debug!(
+                            "UART connection {} reconnect buffer full, dropped oldest frame",
+                            self.conn_id
+                        );
+                    }
+                    pending.push_back((Instant::now(), data));
                 }
             }
+        }
+    }
+
+    /// Send frames buffered during the outage, skipping any that went stale
+    async fn flush_pending(
+        &self,
+        port: &mut (impl AsyncWrite + Unpin),
+        pending: &mut VecDeque<(Instant, Bytes)>,
+    ) -> std::io::Result<()> {
+        let max_age = Duration::from_millis(self.reconnect.reconnect_buffer_max_age_ms);
+        let mut sent = 0;
+        let mut stale = 0;
+
+        while let Some((queued_at, data)) = pending.pop_front() {
+            if queued_at.elapsed() > max_age {
+                stale += 1;
+                continue;
+            }
+            port.write_all(&data).await?;
+            sent += 1;
+        }
 
-            sleep(Duration::from_secs(5)).await;
+        if sent > 0 || stale > 0 {
+            info!(
+                "UART connection {} flushed {} buffered frame(s), discarded {} stale",
+                self.conn_id, sent, stale
+            );
         }
+        Ok(())
     }
 
+    /// Read `port` and write `tx_port`, or `port` too if there is no
+    /// separate one
     async fn handle_connection(
         &self,
         port: &mut tokio_serial::SerialStream,
+        mut tx_port: Option<&mut tokio_serial::SerialStream>,
         rx: &mut MessageReceiver,
-        router_tx: mpsc::UnboundedSender<crate::connection::tcp::RouterMessage>,
+        signals: &mut Option<mpsc::UnboundedReceiver<SerialControl>>,
+        router_tx: crate::connection::RouterSender,
+        baud_rate: u32,
     ) -> anyhow::Result<()> {
         let mut read_buf = BytesMut::with_capacity(4096);
+        let mut decoder =
+            FrameDecoder::new(self.conn_id, &self.link).with_dead_letters(router_tx.dead_letters());
+        let opened = Instant::now();
+        let mut baud_checked = false;
 
         loop {
-            tokio::select! {
+            let event = tokio::select! {
+                event = next_io(self.link.io_priority, port.read_buf(&mut read_buf), rx) => event,
+                control = next_signal(signals) => {
+                    info!("UART connection {} sending {}", self.conn_id, control.signal);
+                    // Break and the control lines are outputs, so they go
+                    // out with the frames
+                    let result = match tx_port.as_deref_mut() {
+                        Some(tx_port) => send_signal(tx_port, control.signal).await,
+                        None => send_signal(port, control.signal).await,
+                    };
+                    if let Err(e) = &result {
+                        warn!(
+                            "UART connection {} failed to send {}: {}",
+                            self.conn_id, control.signal, e
+                        );
+                    }
+                    let _ = control.reply.send(result);
+                    continue;
+                }
+            };
+
+            match event {
                 // Read from UART
-                result = port.read_buf(&mut read_buf) => {
+                IoEvent::Read(result) => {
                     match result {
                         Ok(0) => {
                             debug!("UART connection {} EOF", self.conn_id);
@@ -112,31 +546,26 @@ impl UartConnection {
                             debug!("UART connection {} read {} bytes", self.conn_id, n);
 
                             // Parse MAVLink frames
-                            while !read_buf.is_empty() {
-                                match MavFrame::parse(&read_buf) {
-                                    Ok((frame, consumed)) => {
-                                        debug!(
-                                            "UART {} received MAVLink msg: sysid={} compid={} msgid={}",
-                                            self.conn_id, frame.sys_id(), frame.comp_id(), frame.msg_id()
-                                        );
-
-                                        // Send to router
-                                        router_tx.send(crate::connection::tcp::RouterMessage::Frame {
-                                            source: self.conn_id,
-                                            frame,
-                                        })?;
-
-                                        read_buf.advance(consumed);
-                                    }
-                                    Err(crate::mavlink::ParseError::Incomplete(_, _)) => {
-                                        // Need more data
-                                        break;
-                                    }
-                                    Err(e) => {
-                                        warn!("UART {} parse error: {}, skipping byte", self.conn_id, e);
-                                        read_buf.advance(1);
-                                    }
-                                }
+                            while let Some(frame) = decoder.next_frame(&mut read_buf)? {
+                                debug!(
+                                    "UART {} received MAVLink msg: sysid={} compid={} msgid={}",
+                                    self.conn_id,
+                                    frame.sys_id(),
+                                    frame.comp_id(),
+                                    frame.msg_id()
+                                );
+
+                                // Send to router
+                                router_tx.send(crate::connection::tcp::RouterMessage::Frame {
+                                    source: self.conn_id,
+                                    frame,
+                                    received: std::time::Instant::now(),
+                                })?;
+                            }
+
+                            if !baud_checked && opened.elapsed() >= BAUD_CHECK_WINDOW {
+                                baud_checked = true;
+                                self.check_baud(&decoder, baud_rate)?;
                             }
                         }
                         Err(e) => {
@@ -147,13 +576,333 @@ impl UartConnection {
                 }
 
                 // Write to UART
-                Some(data) = rx.recv() => {
-                    port.write_all(&data).await?;
-                    debug!("UART connection {} wrote {} bytes", self.conn_id, data.len());
+                IoEvent::Write(data) => {
+                    match tx_port.as_deref_mut() {
+                        Some(tx_port) => tx_port.write_all(&data).await?,
+                        None => port.write_all(&data).await?,
+                    }
+                    debug!(
+                        "UART connection {} wrote {} bytes",
+                        self.conn_id,
+                        data.len()
+                    );
                 }
+
+                // The router is gone and everything it queued is written
+                IoEvent::Closed => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Judge the baud rate by what has been read since the port opened.
+    /// Plenty of bytes and not one frame is the classic sign of the wrong
+    /// rate; with `auto_baud` that closes the port to try the next one.
+    fn check_baud(&self, decoder: &FrameDecoder, baud_rate: u32) -> anyhow::Result<()> {
+        if decoder.frames_parsed() > 0 {
+            if baud_rate != self.baud_rate {
+                info!(
+                    "UART connection {} found MAVLink on {} at {} baud (configured {})",
+                    self.conn_id, self.path, baud_rate, self.baud_rate
+                );
             }
+            return Ok(());
+        }
+        if decoder.bytes_skipped() < BAUD_CHECK_MIN_BYTES {
+            return Ok(());
         }
 
+        let rate = if baud_rate == self.baud_rate {
+            format!("configured {}", baud_rate)
+        } else {
+            format!("trying {}, configured {}", baud_rate, self.baud_rate)
+        };
+        warn!(
+            "UART connection {}: possible baud rate mismatch on {} ({}): {} unparseable bytes and no frames in {}s",
+            self.conn_id,
+            self.path,
+            rate,
+            decoder.bytes_skipped(),
+            BAUD_CHECK_WINDOW.as_secs()
+        );
+        if self.auto_baud {
+            return Err(BaudMismatch(baud_rate).into());
+        }
         Ok(())
     }
 }
+
+/// The next signal requested for an open port; never resolves if the port
+/// takes no signals
+async fn next_signal(
+    signals: &mut Option<mpsc::UnboundedReceiver<SerialControl>>,
+) -> SerialControl {
+    match signals {
+        Some(rx) => match rx.recv().await {
+            Some(control) => control,
+            None => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+async fn send_signal(port: &mut SerialStream, signal: SerialSignal) -> std::io::Result<()> {
+    match signal {
+        SerialSignal::Break => {
+            port.set_break()?;
+            sleep(BREAK_DURATION).await;
+            port.clear_break()?;
+        }
+        SerialSignal::Dtr(action) => {
+            set_line(port, action, SerialStream::write_data_terminal_ready).await?
+        }
+        SerialSignal::Rts(action) => {
+            set_line(port, action, SerialStream::write_request_to_send).await?
+        }
+    }
+    Ok(())
+}
+
+async fn set_line(
+    port: &mut SerialStream,
+    action: LineAction,
+    write: fn(&mut SerialStream, bool) -> tokio_serial::Result<()>,
+) -> tokio_serial::Result<()> {
+    match action {
+        LineAction::On => write(port, true),
+        LineAction::Off => write(port, false),
+        LineAction::Pulse => {
+            write(port, false)?;
+            sleep(LINE_PULSE_DURATION).await;
+            write(port, true)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A pseudo-terminal standing in for a serial device at `path`: a link
+    /// to its slave end. Returns the master end, which plays the device.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn pty(path: &std::path::Path) -> std::fs::File {
+        use std::ffi::CStr;
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        // SAFETY: plain libc calls, each checked; the master fd is owned as
+        // soon as it exists
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0, "no pseudo-terminals");
+            let master = OwnedFd::from_raw_fd(master);
+            assert_eq!(libc::grantpt(master.as_raw_fd()), 0);
+            assert_eq!(libc::unlockpt(master.as_raw_fd()), 0);
+            let mut name = [0 as libc::c_char; 64];
+            let named = libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len());
+            assert_eq!(named, 0);
+            let slave = CStr::from_ptr(name.as_ptr()).to_str().unwrap();
+            std::os::unix::fs::symlink(slave, path).unwrap();
+            std::fs::File::from(master)
+        }
+    }
+
+    fn uart(path: &str, id: Option<usize>) -> UartConfig {
+        let uart: UartConfig = toml::from_str(&format!("path = \"{}\"", path)).unwrap();
+        UartConfig { id, ..uart }
+    }
+
+    #[test]
+    fn test_debounce() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut debounce = Debounce::new(ms(2000));
+        assert_eq!(debounce.check(start), None);
+
+        // A short stall is ridden out with quick reopens, the last one timed
+        // to the end of the window
+        debounce.failed_to_open(start);
+        assert_eq!(
+            debounce.check(start + ms(100)),
+            Some(Outage::RidingOut(ms(250)))
+        );
+        debounce.failed_to_open(start + ms(1000));
+        assert_eq!(
+            debounce.check(start + ms(1900)),
+            Some(Outage::RidingOut(ms(100)))
+        );
+
+        // Back, then down again: counted from the new outage
+        debounce.closed(start + ms(1950));
+        assert_eq!(
+            debounce.check(start + ms(2500)),
+            Some(Outage::RidingOut(ms(250)))
+        );
+
+        // A longer one is reported
+        debounce.failed_to_open(start + ms(3000));
+        assert_eq!(
+            debounce.check(start + ms(4000)),
+            Some(Outage::Over(ms(2050)))
+        );
+
+        // Without a debounce, at once
+        let mut debounce = Debounce::new(Duration::ZERO);
+        debounce.closed(start);
+        assert_eq!(debounce.check(start), Some(Outage::Over(Duration::ZERO)));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_buffer() {
+        let reconnect = UartReconnectConfig {
+            reconnect_buffer_frames: 2,
+            reconnect_buffer_max_age_ms: 1000,
+            ..UartReconnectConfig::default()
+        };
+        let path = "/dev/null".to_string();
+        let uart = UartConnection::new(0, path, 57600, None, LinkConfig::default())
+            .with_reconnect(reconnect);
+        let (tx, mut rx) = message_channel();
+        let mut pending = VecDeque::new();
+
+        // Full: the oldest goes
+        for frame in [&b"one"[..], b"two", b"three"] {
+            tx.send(Bytes::from_static(frame)).unwrap();
+        }
+        uart.hold_outbound(&mut rx, &mut pending, Duration::from_millis(50))
+            .await;
+        let held: Vec<&[u8]> = pending.iter().map(|(_, data)| &data[..]).collect();
+        assert_eq!(held, [&b"two"[..], b"three"]);
+
+        // Stale by the time the port is back: discarded, not sent late
+        pending[0].0 -= Duration::from_secs(2);
+        let mut port = Vec::new();
+        uart.flush_pending(&mut port, &mut pending).await.unwrap();
+        assert_eq!(port, b"three");
+        assert!(pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_manual_start() {
+        use crate::connection::tcp::RouterMessage;
+        use crate::connection::{router_channel, ManualStarts};
+        use crate::metrics::Metrics;
+        use tokio::time::timeout;
+
+        let starts = ManualStarts::default();
+        let (router_tx, mut router_rx) = router_channel(0, Metrics::new());
+        let path = "/dev/mav-lite-test-missing".to_string();
+        let name = Some("backup".to_string());
+        UartConnection::new(0, path, 57600, name, LinkConfig::default())
+            .start_on_demand(router_tx, starts.register("backup".to_string()))
+            .await;
+
+        // Unknown to the router until the admin channel opens it, by name
+        assert_eq!(starts.waiting(), ["backup"]);
+        assert!(timeout(Duration::from_millis(100), router_rx.recv())
+            .await
+            .is_err());
+        assert!(!starts.open("primary"));
+        assert!(starts.open("backup"));
+        assert!(matches!(
+            timeout(Duration::from_secs(5), router_rx.recv()).await,
+            Ok(Some(RouterMessage::NewConnection { .. }))
+        ));
+
+        // Once only
+        assert!(starts.waiting().is_empty());
+        assert!(!starts.open("backup"));
+    }
+
+    #[test]
+    fn test_next_baud_rate() {
+        // The configured rate leads, and isn't tried twice per round
+        assert_eq!(next_baud_rate(115200, 115200), 57600);
+        assert_eq!(next_baud_rate(57600, 115200), 921600);
+        assert_eq!(next_baud_rate(1500000, 115200), 115200);
+        assert_eq!(next_baud_rate(250000, 250000), 57600);
+        assert_eq!(next_baud_rate(1500000, 250000), 250000);
+    }
+
+    #[test]
+    fn test_ids() {
+        let static_uarts = [uart("/dev/ttyUSB0", None), uart("/dev/ttyUSB1", Some(0))];
+        let mut ids = UartIds::with_fixed(&static_uarts).unwrap();
+        assert_eq!(ids.assign_static(&static_uarts[0]), 1);
+        assert_eq!(ids.assign_static(&static_uarts[1]), 0);
+        assert_eq!(ids.assign(Some(1), None), 2);
+
+        // Derived ids depend only on the path
+        let path = "/dev/serial/by-id/usb-Hex_ProfiCNC_CubeOrange-if00";
+        let id = ids.assign(None, Some(path));
+        assert!((STABLE_ID_BASE..STABLE_ID_BASE + STABLE_ID_RANGE).contains(&id));
+        assert_eq!(UartIds::default().assign(None, Some(path)), id);
+        assert_ne!(ids.assign(None, Some(path)), id);
+
+        let duplicate = [uart("/dev/ttyUSB0", Some(3)), uart("/dev/ttyUSB1", Some(3))];
+        assert!(UartIds::with_fixed(&duplicate).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_split_tx_path() {
+        use crate::connection::tcp::RouterMessage;
+        use crate::connection::{router_channel, ConnectionId};
+        use crate::mavlink::{messages, MavFrame};
+        use crate::metrics::Metrics;
+        use std::io::{Read, Write};
+        use std::os::fd::AsRawFd;
+        use tokio::time::timeout;
+
+        let dir = std::env::temp_dir().join("mav-lite-split-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (rx_path, tx_path) = (dir.join("ttyRX"), dir.join("ttyTX"));
+        let (mut downlink, mut uplink) = (pty(&rx_path), pty(&tx_path));
+
+        let (router_tx, mut router_rx) = router_channel(0, Metrics::new());
+        let path = rx_path.to_string_lossy().to_string();
+        UartConnection::new(0, path, 57600, None, LinkConfig::default())
+            .with_tx_path(Some(tx_path.to_string_lossy().to_string()))
+            .start(router_tx)
+            .await;
+        let Some(RouterMessage::NewConnection { tx, .. }) = router_rx.recv().await else {
+            panic!("no connection registered");
+        };
+        assert!(matches!(
+            timeout(Duration::from_secs(5), router_rx.recv()).await,
+            Ok(Some(RouterMessage::LinkUp { .. }))
+        ));
+
+        // One connection reads the downlink device...
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[1; 9]).unwrap();
+        downlink.write_all(heartbeat.as_bytes()).unwrap();
+        let Ok(Some(RouterMessage::Frame { source, frame, .. })) =
+            timeout(Duration::from_secs(5), router_rx.recv()).await
+        else {
+            panic!("no frame from the downlink device");
+        };
+        assert_eq!(source, ConnectionId::new_uart(0));
+        assert_eq!(frame.as_bytes(), heartbeat.as_bytes());
+
+        // ...and writes the uplink device only
+        tx.send(Bytes::from_static(b"uplink")).unwrap();
+        let written = tokio::task::spawn_blocking(move || {
+            let mut written = [0u8; 6];
+            uplink.read_exact(&mut written).unwrap();
+            written
+        });
+        let written = timeout(Duration::from_secs(5), written)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&written, b"uplink");
+        // SAFETY: setting a flag on an fd we own
+        unsafe { libc::fcntl(downlink.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+        let read = downlink.read(&mut [0u8; 16]);
+        assert_eq!(read.unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
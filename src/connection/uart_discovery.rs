@@ -1,70 +1,239 @@
-use crate::config::UartDiscoveryConfig;
-use crate::connection::uart::UartConnection;
+use crate::config::{StartMode, UartConfig, UartDiscoveryConfig};
+use crate::connection::uart::{UartConnection, UartIds};
+use crate::connection::SerialControls;
 use crate::mavlink::MavFrame;
 use bytes::{Buf, BytesMut};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc;
-use tokio::time::{sleep, timeout, Duration};
+use tokio::time::{sleep, timeout, Duration, Instant};
 use tokio_serial::SerialPortBuilderExt;
 use tracing::{debug, error, info, warn};
 
+/// Udev's stable per-device symlinks
+const SERIAL_BY_ID_DIR: &str = "/dev/serial/by-id";
+
 pub struct UartDiscovery {
     config: UartDiscoveryConfig,
     active_devices: HashSet<PathBuf>,
-    next_uart_id: usize,
+    uart_ids: UartIds,
+    /// Stable paths of devices loaded from the persist file
+    persisted: Vec<PathBuf>,
+    /// Discovered devices waiting to prove stable before being persisted
+    pending_persist: Vec<(PathBuf, Instant)>,
+    /// Devices the last scan ran out of time for, probed first next time
+    deferred: Vec<PathBuf>,
+    /// When a scan that found nothing to connect to was last warned about
+    idle_warned: Option<Instant>,
+    serial_controls: SerialControls,
+}
+
+/// Paths matching the device pattern
+#[derive(Debug, Default)]
+struct Matches {
+    devices: Vec<PathBuf>,
+    /// Matches dropped by `exclude_patterns`
+    excluded: usize,
+    /// Matches that couldn't be read, e.g. for lack of permission
+    unreadable: usize,
+}
+
+/// Contents of the persist sidecar file
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct PersistedDevices {
+    #[serde(default)]
+    uart: Vec<UartConfig>,
 }
 
 impl UartDiscovery {
-    pub fn new(config: UartDiscoveryConfig, starting_id: usize) -> Self {
+    pub fn new(
+        config: UartDiscoveryConfig,
+        uart_ids: UartIds,
+        serial_controls: SerialControls,
+    ) -> Self {
         Self {
             config,
             active_devices: HashSet::new(),
-            next_uart_id: starting_id,
+            uart_ids,
+            persisted: Vec::new(),
+            pending_persist: Vec::new(),
+            deferred: Vec::new(),
+            idle_warned: None,
+            serial_controls,
         }
     }
 
-    pub async fn run(
-        mut self,
-        router_tx: mpsc::UnboundedSender<crate::connection::tcp::RouterMessage>,
-    ) {
+    pub async fn run(mut self, router_tx: crate::connection::RouterSender) {
         info!("UART discovery started");
-        info!(
-            "  Device pattern: {}",
-            self.config.device_pattern
-        );
+        info!("  Device pattern: {}", self.config.device_pattern);
+        if !self.config.exclude_patterns.is_empty() {
+            info!("  Excluding: {}", self.config.exclude_patterns.join(", "));
+        }
         info!("  Baud rate: {}", self.config.baud_rate);
         info!(
             "  Detection timeout: {}s",
             self.config.detection_timeout_secs
         );
-        info!(
-            "  Rescan interval: {}s",
-            self.config.rescan_interval_secs
-        );
+        if self.config.max_scan_duration_secs > 0 {
+            info!("  Scan budget: {}s", self.config.max_scan_duration_secs);
+        }
+        info!("  Rescan interval: {}s", self.config.rescan_interval_secs);
+
+        if self.config.persist_discovered {
+            info!(
+                "  Persisting stable devices to {}",
+                self.config.persist_file
+            );
+            self.start_persisted(&router_tx).await;
+        }
 
         loop {
+            if router_tx.is_closed() {
+                debug!("UART discovery stopping, the router has shut down");
+                return;
+            }
             self.scan_and_connect(&router_tx).await;
+            if self.config.persist_discovered {
+                self.persist_stable_devices();
+            }
             sleep(Duration::from_secs(self.config.rescan_interval_secs)).await;
         }
     }
 
-    async fn scan_and_connect(
-        &mut self,
-        router_tx: &mpsc::UnboundedSender<crate::connection::tcp::RouterMessage>,
-    ) {
-        info!("Scanning for UART devices matching {}", self.config.device_pattern);
-
-        let devices = match self.enumerate_devices().await {
+    /// Connect directly to devices remembered from previous runs
+    async fn start_persisted(&mut self, router_tx: &crate::connection::RouterSender) {
+        let devices = match load_persisted(&self.config.persist_file) {
             Ok(devices) => devices,
+            Err(e) => {
+                warn!(
+                    "Failed to load persisted devices from {}: {}",
+                    self.config.persist_file, e
+                );
+                return;
+            }
+        };
+
+        for device in devices.uart {
+            info!("Connecting to persisted device {}", device.path);
+            let stable_path = self.config.stable_ids.then_some(device.path.as_str());
+            let uart_conn = UartConnection::new(
+                self.uart_ids.assign(device.id, stable_path),
+                device.path.clone(),
+                device.baud_rate,
+                device.name.clone(),
+                device.link.clone(),
+            )
+            .with_reconnect(device.reconnect.clone())
+            .with_controls(self.serial_controls.clone());
+            uart_conn.start(router_tx.clone()).await;
+            self.persisted.push(PathBuf::from(device.path));
+        }
+    }
+
+    /// Whether a scanned device is one we already connected to from the persist file
+    fn is_persisted(&self, device_path: &Path) -> bool {
+        let Ok(target) = std::fs::canonicalize(device_path) else {
+            return false;
+        };
+        self.persisted
+            .iter()
+            .any(|p| std::fs::canonicalize(p).is_ok_and(|p| p == target))
+    }
+
+    /// Write devices that have stayed present long enough to the persist file
+    fn persist_stable_devices(&mut self) {
+        let min_age = Duration::from_secs(self.config.persist_after_secs);
+        let (stable, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_persist)
+            .into_iter()
+            .filter(|(path, _)| path.exists())
+            .partition(|(_, since)| since.elapsed() >= min_age);
+        self.pending_persist = pending;
+
+        if stable.is_empty() {
+            return;
+        }
+
+        let mut devices = load_persisted(&self.config.persist_file).unwrap_or_default();
+        for (device_path, _) in stable {
+            let stable_path = stable_device_path(&device_path);
+            let path_str = stable_path.to_string_lossy().to_string();
+            if devices.uart.iter().any(|d| d.path == path_str) {
+                continue;
+            }
+
+            info!("Persisting discovered device {}", path_str);
+            devices.uart.push(UartConfig {
+                name: Some(format!("Persisted: {}", path_str)),
+                path: path_str,
+                baud_rate: self.config.baud_rate,
+                id: None,
+                start: StartMode::Auto,
+                auto_baud: false,
+                tx_path: None,
+                reconnect: self.config.reconnect.clone(),
+                link: self.config.link.clone(),
+            });
+            self.persisted.push(stable_path);
+        }
+
+        if let Err(e) = save_persisted(&self.config.persist_file, &devices) {
+            error!(
+                "Failed to write persisted devices to {}: {}",
+                self.config.persist_file, e
+            );
+        }
+    }
+
+    async fn scan_and_connect(&mut self, router_tx: &crate::connection::RouterSender) {
+        info!(
+            "Scanning for UART devices matching {}",
+            self.config.device_pattern
+        );
+
+        let matches = match self.enumerate_devices().await {
+            Ok(matches) => matches,
             Err(e) => {
                 error!("Failed to enumerate devices: {}", e);
                 return;
             }
         };
+        let mut devices = matches.devices;
 
         info!("Found {} potential device(s)", devices.len());
+        if devices.is_empty() {
+            let mut reason = format!(
+                "no devices match {} (not plugged in, or the wrong pattern?)",
+                self.config.device_pattern
+            );
+            if matches.excluded > 0 {
+                reason = format!(
+                    "{} device(s) match {}, all excluded by exclude_patterns",
+                    matches.excluded, self.config.device_pattern
+                );
+            }
+            if matches.unreadable > 0 {
+                reason += &format!(
+                    "; {} path(s) couldn't be read, check permissions",
+                    matches.unreadable
+                );
+            }
+            self.report_idle(&reason);
+            return;
+        }
+        let mut probed = 0;
+        let mut detected = false;
+
+        // Devices the last scan didn't get to go first
+        let deferred = std::mem::take(&mut self.deferred);
+        devices.sort_by_key(|d| !deferred.contains(d));
+
+        let budget = match self.config.max_scan_duration_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        let started = Instant::now();
+        let detection_timeout = Duration::from_secs(self.config.detection_timeout_secs);
 
         for device_path in devices {
             // Skip if already active
@@ -73,19 +242,48 @@ impl UartDiscovery {
                 continue;
             }
 
+            if self.is_persisted(&device_path) {
+                debug!(
+                    "Device {:?} connected from persist file, skipping",
+                    device_path
+                );
+                self.active_devices.insert(device_path);
+                continue;
+            }
+
+            // Never let one probe run past the scan budget
+            let probe_timeout = match budget {
+                Some(budget) => {
+                    let left = budget.saturating_sub(started.elapsed());
+                    if left.is_zero() {
+                        debug!("Scan budget used up, deferring {:?}", device_path);
+                        self.deferred.push(device_path);
+                        continue;
+                    }
+                    detection_timeout.min(left)
+                }
+                None => detection_timeout,
+            };
+
             // Test if device has MAVLink traffic
             info!("Testing device {:?} for MAVLink traffic...", device_path);
-            match self.test_for_mavlink(&device_path).await {
+            probed += 1;
+            match self.test_for_mavlink(&device_path, probe_timeout).await {
                 Ok(true) => {
+                    detected = true;
                     info!(
                         "MAVLink traffic detected on {:?}, connecting...",
                         device_path
                     );
 
-                    let uart_id = self.next_uart_id;
-                    self.next_uart_id += 1;
-
                     let path_str = device_path.to_string_lossy().to_string();
+                    let uart_id = if self.config.stable_ids {
+                        let stable_path = stable_device_path(&device_path);
+                        self.uart_ids
+                            .assign(None, Some(&stable_path.to_string_lossy()))
+                    } else {
+                        self.uart_ids.assign(None, None)
+                    };
                     let name = format!("Auto-discovered: {}", path_str);
 
                     let uart_conn = UartConnection::new(
@@ -93,10 +291,17 @@ impl UartDiscovery {
                         path_str.clone(),
                         self.config.baud_rate,
                         Some(name),
-                    );
+                        self.config.link.clone(),
+                    )
+                    .with_reconnect(self.config.reconnect.clone())
+                    .with_controls(self.serial_controls.clone());
 
                     uart_conn.start(router_tx.clone()).await;
                     self.active_devices.insert(device_path.clone());
+                    if self.config.persist_discovered {
+                        self.pending_persist
+                            .push((device_path.clone(), Instant::now()));
+                    }
 
                     info!(
                         "Started UART connection {} for device {:?}",
@@ -111,25 +316,74 @@ impl UartDiscovery {
                 }
             }
         }
+
+        if !self.deferred.is_empty() {
+            info!(
+                "Scan budget of {}s used up, {} device(s) left for the next scan",
+                self.config.max_scan_duration_secs,
+                self.deferred.len()
+            );
+        }
+
+        if probed > 0 && !detected && self.active_devices.is_empty() {
+            self.report_idle(&format!(
+                "{} device(s) match {} but none sent MAVLink at {} baud",
+                probed, self.config.device_pattern, self.config.baud_rate
+            ));
+        } else if detected || !self.active_devices.is_empty() {
+            self.idle_warned = None;
+        }
     }
 
-    async fn enumerate_devices(&self) -> anyhow::Result<Vec<PathBuf>> {
+    /// Log why a scan found nothing to connect to: as a warning at most every
+    /// `idle_warn_interval_secs`, otherwise at debug level
+    fn report_idle(&mut self, reason: &str) {
+        let interval = Duration::from_secs(self.config.idle_warn_interval_secs);
+        let due = !interval.is_zero() && self.idle_warned.is_none_or(|at| at.elapsed() >= interval);
+        if due {
+            warn!("UART discovery: {}", reason);
+            self.idle_warned = Some(Instant::now());
+        } else {
+            debug!("UART discovery: {}", reason);
+        }
+    }
+
+    async fn enumerate_devices(&self) -> anyhow::Result<Matches> {
         let pattern = &self.config.device_pattern;
+        let excludes = self
+            .config
+            .exclude_patterns
+            .iter()
+            .map(|p| glob::Pattern::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        // Use glob to find matching devices
-        let paths: Vec<PathBuf> = glob::glob(pattern)?
-            .filter_map(Result::ok)
-            .collect();
+        // Use glob to find matching devices, minus the excluded ones
+        let mut matches = Matches::default();
+        for entry in glob::glob(pattern)? {
+            let Ok(path) = entry else {
+                matches.unreadable += 1;
+                continue;
+            };
+            if excludes.iter().any(|p| p.matches_path(&path)) {
+                debug!("Skipping excluded device {:?}", path);
+                matches.excluded += 1;
+                continue;
+            }
+            matches.devices.push(path);
+        }
 
-        Ok(paths)
+        Ok(matches)
     }
 
-    async fn test_for_mavlink(&self, device_path: &PathBuf) -> anyhow::Result<bool> {
+    async fn test_for_mavlink(
+        &self,
+        device_path: &PathBuf,
+        detection_duration: Duration,
+    ) -> anyhow::Result<bool> {
         let path_str = device_path.to_string_lossy().to_string();
 
         // Try to open the device
-        let mut port = match tokio_serial::new(&path_str, self.config.baud_rate)
-            .open_native_async()
+        let mut port = match tokio_serial::new(&path_str, self.config.baud_rate).open_native_async()
         {
             Ok(port) => port,
             Err(e) => {
@@ -140,7 +394,8 @@ impl UartDiscovery {
 
         // Read data with timeout
         let mut read_buf = BytesMut::with_capacity(4096);
-        let detection_duration = Duration::from_secs(self.config.detection_timeout_secs);
+        let mut frames = 0;
+        let needed = self.config.min_frames_to_confirm.max(1);
 
         let result = timeout(detection_duration, async {
             loop {
@@ -153,14 +408,33 @@ impl UartDiscovery {
                         // Try to parse MAVLink frames
                         while !read_buf.is_empty() {
                             match MavFrame::parse(&read_buf) {
-                                Ok((frame, _consumed)) => {
+                                Ok((frame, _))
+                                    if self.config.require_valid_crc
+                                        && frame.crc_valid() != Some(true) =>
+                                {
+                                    // Structurally a frame, but unverified;
+                                    // resync from the next byte
+                                    debug!(
+                                        "Unverified frame on {:?} (msgid={}), not counted",
+                                        device_path,
+                                        frame.msg_id()
+                                    );
+                                    read_buf.advance(1);
+                                }
+                                Ok((frame, consumed)) => {
+                                    frames += 1;
                                     debug!(
-                                        "Detected MAVLink frame on {:?}: sysid={} msgid={}",
+                                        "Detected MAVLink frame {}/{} on {:?}: sysid={} msgid={}",
+                                        frames,
+                                        needed,
                                         device_path,
                                         frame.sys_id(),
                                         frame.msg_id()
                                     );
-                                    return true;
+                                    if frames >= needed {
+                                        return true;
+                                    }
+                                    read_buf.advance(consumed);
                                 }
                                 Err(crate::mavlink::ParseError::Incomplete(_, _)) => {
                                     // Need more data
@@ -185,10 +459,279 @@ impl UartDiscovery {
         match result {
             Ok(has_mavlink) => Ok(has_mavlink),
             Err(_) => {
-                // Timeout - no MAVLink detected
-                debug!("Timeout waiting for MAVLink on {:?}", device_path);
+                // Timeout - no MAVLink detected, or not enough of it
+                debug!(
+                    "Timeout waiting for MAVLink on {:?} ({} of {} frames)",
+                    device_path, frames, needed
+                );
                 Ok(false)
             }
         }
     }
 }
+
+fn load_persisted(path: &str) -> anyhow::Result<PersistedDevices> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(toml::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PersistedDevices::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_persisted(path: &str, devices: &PersistedDevices) -> anyhow::Result<()> {
+    let content = format!(
+        "# Written by mav-lite UART discovery; safe to edit or delete\n{}",
+        toml::to_string_pretty(devices)?
+    );
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Map a device node to its udev by-id symlink, which survives re-enumeration.
+/// Falls back to the node itself when no symlink exists.
+fn stable_device_path(device_path: &Path) -> PathBuf {
+    let Ok(target) = std::fs::canonicalize(device_path) else {
+        return device_path.to_path_buf();
+    };
+
+    std::fs::read_dir(SERIAL_BY_ID_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|link| std::fs::canonicalize(link).is_ok_and(|p| p == target))
+        .unwrap_or_else(|| device_path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_os = "linux")]
+    use crate::connection::uart::tests::pty;
+
+    #[tokio::test]
+    async fn test_enumerate_counts_excluded() {
+        let dir = std::env::temp_dir().join("mav-lite-discovery-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["ttyACM0", "ttyACM1", "ttyUSB0"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let config = UartDiscoveryConfig {
+            device_pattern: format!("{}/ttyACM*", dir.display()),
+            exclude_patterns: vec![format!("{}/ttyACM1", dir.display())],
+            ..UartDiscoveryConfig::default()
+        };
+        let discovery = UartDiscovery::new(config, UartIds::default(), SerialControls::default());
+        let matches = discovery.enumerate_devices().await.unwrap();
+        assert_eq!(matches.devices, vec![dir.join("ttyACM0")]);
+        assert_eq!(matches.excluded, 1);
+
+        // A pattern matching nothing is not an error
+        let config = UartDiscoveryConfig {
+            device_pattern: format!("{}/ttyS*", dir.display()),
+            ..UartDiscoveryConfig::default()
+        };
+        let discovery = UartDiscovery::new(config, UartIds::default(), SerialControls::default());
+        let matches = discovery.enumerate_devices().await.unwrap();
+        assert!(matches.devices.is_empty());
+        assert_eq!(matches.excluded, 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_persist_round_trip() {
+        let dir = std::env::temp_dir().join("mav-lite-persist-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (stable, recent, gone) = (
+            dir.join("ttyACM0"),
+            dir.join("ttyACM1"),
+            dir.join("ttyACM2"),
+        );
+        for device in [&stable, &recent] {
+            std::fs::write(device, b"").unwrap();
+        }
+
+        let config = UartDiscoveryConfig {
+            baud_rate: 115200,
+            persist_discovered: true,
+            persist_file: dir.join("devices.toml").to_string_lossy().to_string(),
+            persist_after_secs: 60,
+            ..UartDiscoveryConfig::default()
+        };
+        let mut discovery =
+            UartDiscovery::new(config, UartIds::default(), SerialControls::default());
+        let long_ago = Instant::now() - Duration::from_secs(120);
+        discovery.pending_persist = vec![
+            (stable.clone(), long_ago),
+            (recent.clone(), Instant::now()),
+            (gone.clone(), long_ago),
+        ];
+
+        // Only the device that stayed long enough, and is still there
+        discovery.persist_stable_devices();
+        assert_eq!(discovery.pending_persist.len(), 1);
+        assert!(discovery.is_persisted(&stable));
+        let devices = load_persisted(&discovery.config.persist_file).unwrap();
+        assert_eq!(devices.uart.len(), 1);
+        let device = &devices.uart[0];
+        assert_eq!(device.path, stable.to_string_lossy());
+        assert_eq!(device.baud_rate, 115200);
+        assert_eq!(device.start, StartMode::Auto);
+
+        // Written once, however often it comes up
+        discovery.pending_persist.push((stable.clone(), long_ago));
+        discovery.persist_stable_devices();
+        let devices = load_persisted(&discovery.config.persist_file).unwrap();
+        assert_eq!(devices.uart.len(), 1);
+
+        // No file yet is no devices
+        let missing = dir.join("missing.toml");
+        assert!(load_persisted(&missing.to_string_lossy())
+            .unwrap()
+            .uart
+            .is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_exclude_patterns() {
+        let dir = std::env::temp_dir().join("mav-lite-exclude-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["ttyACM0", "ttyACM1", "ttyACM2", "ttyACM10"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let discovery = |exclude_patterns: &[&str]| {
+            let config = UartDiscoveryConfig {
+                device_pattern: format!("{}/ttyACM*", dir.display()),
+                exclude_patterns: exclude_patterns
+                    .iter()
+                    .map(|pattern| format!("{}/{}", dir.display(), pattern))
+                    .collect(),
+                ..UartDiscoveryConfig::default()
+            };
+            UartDiscovery::new(config, UartIds::default(), SerialControls::default())
+        };
+
+        // Any of several patterns, each a glob of its own
+        let matches = discovery(&["ttyACM1*", "ttyACM[2-9]"])
+            .enumerate_devices()
+            .await
+            .unwrap();
+        assert_eq!(matches.devices, vec![dir.join("ttyACM0")]);
+        assert_eq!(matches.excluded, 3);
+
+        // A bad pattern is an error, not a silently empty exclusion
+        assert!(discovery(&["ttyACM[1"]).enumerate_devices().await.is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_scan_budget() {
+        use crate::connection::router_channel;
+        use crate::metrics::Metrics;
+
+        let dir = std::env::temp_dir().join("mav-lite-budget-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("ttyACM0"), dir.join("ttyACM1"));
+        let _devices = [pty(&first), pty(&second)];
+
+        let config = UartDiscoveryConfig {
+            device_pattern: format!("{}/ttyACM*", dir.display()),
+            detection_timeout_secs: 5,
+            max_scan_duration_secs: 1,
+            ..UartDiscoveryConfig::default()
+        };
+        let mut discovery =
+            UartDiscovery::new(config, UartIds::default(), SerialControls::default());
+        let (router_tx, _router_rx) = router_channel(0, Metrics::new());
+
+        // The first silent device uses up the budget, not its whole timeout
+        let started = Instant::now();
+        discovery.scan_and_connect(&router_tx).await;
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(discovery.deferred, vec![second.clone()]);
+
+        // The one left over goes first next time
+        discovery.scan_and_connect(&router_tx).await;
+        assert_eq!(discovery.deferred, vec![first]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_detection_confirms() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join("mav-lite-detect-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let heartbeat = |seq| {
+            let frame = MavFrame::new_v2(seq, 1, 1, crate::mavlink::messages::HEARTBEAT, &[1; 9]);
+            frame.unwrap().as_bytes().to_vec()
+        };
+        let corrupted = |seq| {
+            let mut bytes = heartbeat(seq);
+            *bytes.last_mut().unwrap() ^= 0xFF;
+            bytes
+        };
+
+        // Whether a device sending `stream` once the port is open is taken
+        // for a flight controller
+        let detects = |name: &str, config: UartDiscoveryConfig, stream: Vec<u8>| {
+            let path = dir.join(name);
+            let mut device = pty(&path);
+            let writer = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                device.write_all(&stream).unwrap();
+                device
+            });
+            async move {
+                let discovery =
+                    UartDiscovery::new(config, UartIds::default(), SerialControls::default());
+                let detected = discovery
+                    .test_for_mavlink(&path, Duration::from_millis(500))
+                    .await
+                    .unwrap();
+                drop(writer.join());
+                detected
+            }
+        };
+
+        let confirm = UartDiscoveryConfig {
+            min_frames_to_confirm: 3,
+            require_valid_crc: true,
+            ..UartDiscoveryConfig::default()
+        };
+        let valid: Vec<u8> = (0..3).flat_map(heartbeat).collect();
+        let invalid: Vec<u8> = (0..3).flat_map(corrupted).collect();
+
+        // Enough frames, and only ones whose CRC checks out
+        assert!(detects("ttyACM0", confirm.clone(), valid.clone()).await);
+        assert!(
+            !detects(
+                "ttyACM1",
+                confirm.clone(),
+                valid[..valid.len() / 3 * 2].to_vec()
+            )
+            .await
+        );
+        assert!(!detects("ttyACM2", confirm.clone(), invalid.clone()).await);
+        let unchecked = UartDiscoveryConfig {
+            require_valid_crc: false,
+            ..confirm
+        };
+        assert!(detects("ttyACM3", unchecked, invalid).await);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
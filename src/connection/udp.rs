@@ -0,0 +1,464 @@
+use crate::config::UdpConfig;
+use crate::connection::framing::FrameDecoder;
+use crate::connection::psk::{self, Psk};
+use crate::connection::tcp::RouterMessage;
+use crate::connection::{
+    message_channel, next_io, ConnectionId, IoEvent, MessageReceiver, RouterSender,
+};
+use crate::mavlink::decode::{Heartbeat, MAV_AUTOPILOT_INVALID, MAV_STATE_ACTIVE, MAV_TYPE_GCS};
+use bytes::BytesMut;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Instant, SystemTime};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{sleep, timeout, Duration};
+use tracing::{debug, error, info, warn};
+
+/// Delay between attempts to bind the socket
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How often the handshake HEARTBEAT is sent until the remote replies
+const HANDSHAKE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sysid/compid the handshake HEARTBEAT is sent as, unless `gcs_sysid` is set
+const HANDSHAKE_SYSID: u8 = 255;
+const HANDSHAKE_COMPID: u8 = 190;
+
+/// Largest possible UDP payload
+const MAX_DATAGRAM: usize = 65536;
+
+/// Whole frames per datagram, to `remote_addr` or else whoever was last heard
+/// from
+pub struct UdpConnection {
+    conn_id: ConnectionId,
+    config: UdpConfig,
+    psk: Option<Psk>,
+}
+
+impl UdpConnection {
+    pub fn new(id: usize, config: UdpConfig) -> Self {
+        Self {
+            conn_id: ConnectionId::new_udp(id),
+            psk: config.psk.as_deref().map(Psk::new),
+            config,
+        }
+    }
+
+    pub async fn start(self, router_tx: RouterSender) {
+        let (tx, rx) = message_channel();
+
+        // Notify router of new connection
+        let _ = router_tx.send(RouterMessage::NewConnection {
+            conn_id: self.conn_id,
+            tx,
+            link: Box::new(self.config.link.clone()),
+        });
+
+        tokio::spawn(async move {
+            self.run_with_reconnect(rx, router_tx).await;
+        });
+    }
+
+    async fn run_with_reconnect(&self, mut rx: MessageReceiver, router_tx: RouterSender) {
+        let bind_addr = self.config.bind_addr();
+        let display_name = self.config.name.as_deref().unwrap_or(&bind_addr);
+
+        loop {
+            match self.open(&bind_addr).await {
+                Ok((socket, remote)) => {
+                    let _ = router_tx.send(RouterMessage::LinkUp {
+                        conn_id: self.conn_id,
+                    });
+                    match remote {
+                        Some(remote) => info!(
+                            "UDP connection {} ({}) bound to {}, sending to {}",
+                            self.conn_id, display_name, bind_addr, remote
+                        ),
+                        None => info!(
+                            "UDP connection {} ({}) bound to {}, replying to last sender",
+                            self.conn_id, display_name, bind_addr
+                        ),
+                    }
+
+                    let result = self
+                        .handle_connection(&socket, remote, &mut rx, router_tx.clone())
+                        .await;
+                    if router_tx.is_closed() {
+                        debug!(
+                            "UDP connection {} ({}) stopping, the router has shut down",
+                            self.conn_id, display_name
+                        );
+                        return;
+                    }
+                    if let Err(e) = result {
+                        error!(
+                            "UDP connection {} ({}) error: {}",
+                            self.conn_id, display_name, e
+                        );
+                    }
+
+                    info!(
+                        "UDP connection {} ({}) closed, will retry",
+                        self.conn_id, display_name
+                    );
+                }
+                Err(e) => {
+                    warn!(
+                        "UDP connection {} ({}) failed to bind {}: {}, retrying",
+                        self.conn_id, display_name, bind_addr, e
+                    );
+                }
+            }
+
+            sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Bind the local socket and resolve the configured remote, if any
+    async fn open(&self, bind_addr: &str) -> io::Result<(UdpSocket, Option<SocketAddr>)> {
+        let socket = UdpSocket::bind(bind_addr).await?;
+        let remote = match self.config.remote_addr() {
+            Some(addr) => Some(lookup_host(&addr).await?.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve", addr))
+            })?),
+            None => None,
+        };
+        Ok((socket, remote))
+    }
+
+    async fn handle_connection(
+        &self,
+        socket: &UdpSocket,
+        remote: Option<SocketAddr>,
+        rx: &mut MessageReceiver,
+        router_tx: RouterSender,
+    ) -> anyhow::Result<()> {
+        let mut datagram = vec![0u8; MAX_DATAGRAM];
+        let mut read_buf = BytesMut::with_capacity(4096);
+        let mut decoder = FrameDecoder::new(self.conn_id, &self.config.link)
+            .with_dead_letters(router_tx.dead_letters());
+        let mut peer = remote;
+        let mut heard_from_peer = false;
+        let mut handshake_seq: u8 = 0;
+        let mut unauthenticated: u64 = 0;
+        let mut replays = psk::ReplayWindow::default();
+        // Room for the encryption overhead within the MTU
+        let max_packed = match self.psk {
+            Some(_) => self.config.mtu.saturating_sub(psk::OVERHEAD),
+            None => self.config.mtu,
+        };
+
+        loop {
+            // Until the remote answers, wake up periodically to greet it
+            let handshaking = remote.is_some() && !heard_from_peer;
+            let read = async {
+                if handshaking {
+                    timeout(HANDSHAKE_INTERVAL, socket.recv_from(&mut datagram))
+                        .await
+                        .ok()
+                } else {
+                    Some(socket.recv_from(&mut datagram).await)
+                }
+            };
+
+            match next_io(self.config.link.io_priority, read, rx).await {
+                // Handshake interval elapsed without hearing from the remote
+                IoEvent::Read(None) => {
+                    if let Some(remote) = remote {
+                        self.send_handshake(socket, remote, handshake_seq).await;
+                        handshake_seq = handshake_seq.wrapping_add(1);
+                    }
+                }
+
+                // Read from UDP
+                IoEvent::Read(Some(result)) => {
+                    let (n, from) = match result {
+                        Ok(received) => received,
+                        // ICMP port unreachable from an earlier send; the
+                        // remote isn't up yet
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            debug!("UDP connection {} remote not reachable", self.conn_id);
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    debug!(
+                        "UDP connection {} read {} bytes from {}",
+                        self.conn_id, n, from
+                    );
+
+                    // Anything that doesn't authenticate, or is a replay, is
+                    // ignored entirely, so it can't redirect replies either;
+                    // nor can a late one
+                    let (contents, newest) = match &self.psk {
+                        Some(psk) => match psk.open(&mut datagram[..n]) {
+                            Some((counter, contents)) => {
+                                match replays.accept(counter, SystemTime::now()) {
+                                    Some(newest) => (contents, newest),
+                                    None => {
+                                        debug!(
+                                            "UDP connection {} dropped a replayed or old datagram from {}",
+                                            self.conn_id, from
+                                        );
+                                        continue;
+                                    }
+                                }
+                            }
+                            None => {
+                                if unauthenticated == 0 {
+                                    warn!(
+                                        "UDP connection {} dropped a datagram from {} that isn't encrypted with its psk",
+                                        self.conn_id, from
+                                    );
+                                }
+                                unauthenticated += 1;
+                                debug!(
+                                    "UDP connection {} dropped unauthenticated datagram from {} ({} so far)",
+                                    self.conn_id, from, unauthenticated
+                                );
+                                continue;
+                            }
+                        },
+                        None => (&datagram[..n], true),
+                    };
+
+                    if remote.is_none() && newest && peer != Some(from) {
+                        info!("UDP connection {} now replying to {}", self.conn_id, from);
+                        peer = Some(from);
+                    }
+                    if handshaking {
+                        info!("UDP connection {} heard from {}", self.conn_id, from);
+                        heard_from_peer = true;
+                    }
+
+                    // Parse MAVLink frames. A datagram holds whole frames, so
+                    // what's left of one is never the start of the next
+                    read_buf.clear();
+                    read_buf.extend_from_slice(contents);
+                    while let Some(frame) = decoder.next_frame(&mut read_buf)? {
+                        debug!(
+                            "UDP {} received MAVLink msg: sysid={} compid={} msgid={}",
+                            self.conn_id,
+                            frame.sys_id(),
+                            frame.comp_id(),
+                            frame.msg_id()
+                        );
+
+                        // Send to router
+                        router_tx.send(RouterMessage::Frame {
+                            source: self.conn_id,
+                            frame,
+                            received: Instant::now(),
+                        })?;
+                    }
+                }
+
+                // Write to UDP
+                IoEvent::Write(data) => {
+                    let Some(peer) = peer else {
+                        debug!(
+                            "UDP connection {} has no peer yet, dropping {} bytes",
+                            self.conn_id,
+                            data.len()
+                        );
+                        continue;
+                    };
+                    if !self.config.pack_frames {
+                        self.send(socket, peer, &data).await?;
+                        continue;
+                    }
+
+                    // Pack whatever else is already queued along with it,
+                    // without going over the MTU
+                    let mut packed = BytesMut::from(&data[..]);
+                    while let Some(more) = rx.try_recv() {
+                        if packed.len() + more.len() > max_packed {
+                            self.send(socket, peer, &packed).await?;
+                            // Leave the rest for the next turn, so reads aren't starved
+                            packed.clear();
+                            packed.extend_from_slice(&more);
+                            break;
+                        }
+                        packed.extend_from_slice(&more);
+                    }
+                    self.send(socket, peer, &packed).await?;
+                }
+
+                // The router is gone and everything it queued is written
+                IoEvent::Closed => return Ok(()),
+            }
+        }
+    }
+
+    async fn send(&self, socket: &UdpSocket, peer: SocketAddr, data: &[u8]) -> io::Result<()> {
+        let sealed;
+        let data = match &self.psk {
+            Some(psk) => {
+                sealed = psk
+                    .seal(data)
+                    .ok_or_else(|| io::Error::other("encryption failed"))?;
+                &sealed[..]
+            }
+            None => data,
+        };
+        match socket.send_to(data, peer).await {
+            Ok(_) => {
+                debug!("UDP connection {} wrote {} bytes", self.conn_id, data.len());
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                debug!("UDP connection {} remote not reachable", self.conn_id);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Announce ourselves as a GCS so the remote starts streaming
+    async fn send_handshake(&self, socket: &UdpSocket, remote: SocketAddr, seq: u8) {
+        let heartbeat = Heartbeat {
+            custom_mode: 0,
+            mav_type: MAV_TYPE_GCS,
+            autopilot: MAV_AUTOPILOT_INVALID,
+            base_mode: 0,
+            system_status: MAV_STATE_ACTIVE,
+        };
+        let sysid = self.config.link.gcs_sysid.unwrap_or(HANDSHAKE_SYSID);
+        let frame = heartbeat.to_frame(seq, sysid, HANDSHAKE_COMPID);
+
+        debug!(
+            "UDP connection {} sending handshake to {}",
+            self.conn_id, remote
+        );
+        if let Err(e) = self.send(socket, remote, frame.as_bytes()).await {
+            debug!("UDP connection {} handshake failed: {}", self.conn_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UdpPreset;
+    use crate::connection::{router_channel, RouterReceiver};
+    use crate::mavlink::{messages, MavFrame};
+    use crate::metrics::Metrics;
+    use bytes::Bytes;
+
+    fn udp(config: &str) -> UdpConfig {
+        toml::from_str(config).unwrap()
+    }
+
+    async fn next_frame(router_rx: &mut RouterReceiver) -> MavFrame {
+        loop {
+            match timeout(Duration::from_secs(5), router_rx.recv()).await {
+                Ok(Some(RouterMessage::Frame { frame, .. })) => return frame,
+                Ok(Some(_)) => continue,
+                _ => panic!("no frame from the UDP connection"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        let px4 = udp("preset = \"px4_sitl\"");
+        assert_eq!(px4.preset, Some(UdpPreset::Px4Sitl));
+        assert_eq!(px4.bind_addr(), "0.0.0.0:14540");
+        assert_eq!(px4.remote_addr().as_deref(), Some("127.0.0.1:14580"));
+
+        let ardupilot = udp("preset = \"ardupilot_sitl\"");
+        assert_eq!(ardupilot.bind_addr(), "0.0.0.0:14550");
+        assert_eq!(ardupilot.remote_addr(), None);
+
+        // Addresses that are set win over the preset's
+        let custom = udp(
+            "preset = \"px4_sitl\"\nbind_addr = \"127.0.0.1:24540\"\nremote_addr = \"10.0.0.2:24580\"",
+        );
+        assert_eq!(custom.bind_addr(), "127.0.0.1:24540");
+        assert_eq!(custom.remote_addr().as_deref(), Some("10.0.0.2:24580"));
+
+        let neither = udp("");
+        assert_eq!(neither.bind_addr(), "0.0.0.0:0");
+        assert_eq!(neither.remote_addr(), None);
+    }
+
+    #[tokio::test]
+    async fn test_frames_dont_span_datagrams() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = udp(&format!("bind_addr = \"127.0.0.1:{}\"", port));
+        let (router_tx, mut router_rx) = router_channel(0, Metrics::new());
+        UdpConnection::new(0, config).start(router_tx).await;
+        let Some(RouterMessage::NewConnection { tx: _queue, .. }) = router_rx.recv().await else {
+            panic!("no connection registered");
+        };
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bound = format!("127.0.0.1:{}", port);
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let (head, tail) = heartbeat.as_bytes().split_at(10);
+        let truncated = MavFrame::new_v2(0, 2, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+
+        // Half a frame in each of two datagrams is no frame at all
+        sleep(Duration::from_millis(100)).await;
+        socket.send_to(head, &bound).await.unwrap();
+        socket.send_to(tail, &bound).await.unwrap();
+        socket
+            .send_to(&truncated.as_bytes()[..10], &bound)
+            .await
+            .unwrap();
+        socket.send_to(heartbeat.as_bytes(), &bound).await.unwrap();
+        let frame = next_frame(&mut router_rx).await;
+        assert_eq!((frame.sys_id(), frame.sequence()), (1, 0));
+        assert!(
+            timeout(Duration::from_millis(100), next_frame(&mut router_rx))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pack_frames_within_mtu() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = udp(&format!(
+            "bind_addr = \"127.0.0.1:0\"\nremote_addr = \"{}\"\npack_frames = true\nmtu = 50",
+            socket.local_addr().unwrap()
+        ));
+        let (router_tx, mut router_rx) = router_channel(0, Metrics::new());
+        UdpConnection::new(0, config).start(router_tx).await;
+        let Some(RouterMessage::NewConnection { tx, .. }) = router_rx.recv().await else {
+            panic!("no connection registered");
+        };
+
+        // 21 bytes each, so two fit in a datagram
+        let heartbeats: Vec<MavFrame> = (1..=4)
+            .map(|sysid| MavFrame::new_v2(0, sysid, 1, messages::HEARTBEAT, &[1; 9]).unwrap())
+            .collect();
+        for heartbeat in &heartbeats {
+            tx.send(Bytes::copy_from_slice(heartbeat.as_bytes()))
+                .unwrap();
+        }
+
+        let mut datagram = [0u8; MAX_DATAGRAM];
+        let mut received = Vec::new();
+        let mut datagrams = 0;
+        while received.len() < heartbeats.len() * 21 {
+            let (n, _) = timeout(Duration::from_secs(5), socket.recv_from(&mut datagram))
+                .await
+                .expect("no datagram")
+                .unwrap();
+            assert!(n <= 50 && n % 21 == 0, "{}-byte datagram", n);
+            received.extend_from_slice(&datagram[..n]);
+            datagrams += 1;
+        }
+        assert!(datagrams < heartbeats.len());
+        let sent: Vec<u8> = heartbeats
+            .iter()
+            .flat_map(|h| h.as_bytes().to_vec())
+            .collect();
+        assert_eq!(received, sent);
+    }
+}
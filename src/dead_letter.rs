@@ -0,0 +1,255 @@
+use crate::config::DeadLetterConfig;
+use crate::connection::ConnectionId;
+use crate::mavlink::{messages, MavFrame};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, info, warn};
+
+/// Entries waiting for the writer; beyond this they are counted, not kept,
+/// so a storm of drops can't take the router's memory with it
+const QUEUE_ENTRIES: usize = 4096;
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    time: f64,
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<String>,
+    reason: &'static str,
+    sysid: u8,
+    compid: u8,
+    msgid: u32,
+    message: &'static str,
+    frame: String,
+}
+
+/// Handle to the dead-letter file's writer
+#[derive(Debug, Clone)]
+pub struct DeadLetters {
+    tx: mpsc::Sender<Entry>,
+    /// Entries lost because the writer was behind
+    overflowed: Arc<AtomicU64>,
+}
+
+impl DeadLetters {
+    /// Open the file for `[dead_letter_log]`. Returns `None` when disabled,
+    /// and an error if it can't be opened.
+    pub fn start(config: &DeadLetterConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let mut writer = Writer::open(config)
+            .map_err(|e| anyhow::anyhow!("dead_letter_log: can't open {}: {}", config.path, e))?;
+        info!(
+            "Dead-letter log: dropped frames to {} ({} MB x {})",
+            config.path,
+            config.max_size_mb,
+            config.max_files + 1
+        );
+
+        let (tx, mut rx) = mpsc::channel::<Entry>(QUEUE_ENTRIES);
+        let overflowed = Arc::new(AtomicU64::new(0));
+        let lost = overflowed.clone();
+        tokio::task::spawn_blocking(move || {
+            while let Some(entry) = rx.blocking_recv() {
+                let line = serde_json::to_string(&entry).expect("entries serialize");
+                writer.write(&line);
+                if rx.is_empty() {
+                    writer.flush();
+                    let lost = lost.swap(0, Ordering::Relaxed);
+                    if lost > 0 {
+                        warn!(
+                            "Dead-letter log: fell behind, {} dropped frames not logged",
+                            lost
+                        );
+                    }
+                }
+            }
+        });
+
+        Ok(Some(Self { tx, overflowed }))
+    }
+
+    /// Log a frame the router dropped, and for which connection if it was
+    /// dropped on its way to one
+    pub fn record(
+        &self,
+        source: ConnectionId,
+        destination: Option<ConnectionId>,
+        frame: &MavFrame,
+        reason: &'static str,
+    ) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let entry = Entry {
+            time,
+            source: source.to_string(),
+            destination: destination.map(|dest| dest.to_string()),
+            reason,
+            sysid: frame.sys_id(),
+            compid: frame.comp_id(),
+            msgid: frame.msg_id(),
+            message: messages::lookup(frame.msg_id()).map_or("?", |m| m.name),
+            frame: hex(frame.as_bytes()),
+        };
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(entry) {
+            self.overflowed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+/// The current file, rotated to `<path>.1`, `<path>.2`, ... as it fills
+struct Writer {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl Writer {
+    fn open(config: &DeadLetterConfig) -> std::io::Result<Self> {
+        let path = PathBuf::from(&config.path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes: config.max_size_mb.max(1) * 1024 * 1024,
+            max_files: config.max_files,
+            file: BufWriter::new(file),
+            written,
+        })
+    }
+
+    fn write(&mut self, line: &str) {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                error!(
+                    "Dead-letter log: rotating {} failed: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => self.written += len,
+            Err(e) => error!(
+                "Dead-letter log: write to {} failed: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.file.flush() {
+            error!(
+                "Dead-letter log: write to {} failed: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Shift the older files along, dropping the oldest, and start afresh
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = rotated(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// `<path>.<n>`
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_by_size() {
+        let name = format!("mav-lite-dead-letter-{}", std::process::id());
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dead.jsonl");
+        let config = DeadLetterConfig {
+            enabled: true,
+            path: path.to_string_lossy().into_owned(),
+            max_size_mb: 1,
+            max_files: 2,
+        };
+
+        // Four files' worth: the first is dropped, three are left
+        let line = "x".repeat(1023);
+        let mut writer = Writer::open(&config).unwrap();
+        for _ in 0..4 * 1024 {
+            writer.write(&line);
+        }
+        writer.flush();
+
+        let len = |path: &Path| fs::metadata(path).map(|m| m.len()).ok();
+        assert_eq!(len(&path), Some(1024 * 1024));
+        assert_eq!(len(&rotated(&path, 1)), Some(1024 * 1024));
+        assert_eq!(len(&rotated(&path, 2)), Some(1024 * 1024));
+        assert_eq!(len(&rotated(&path, 3)), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_entry() {
+        let frame = MavFrame::new_v2(7, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let entry = Entry {
+            time: 1.5,
+            source: "UART-0".to_string(),
+            destination: None,
+            reason: "dialect",
+            sysid: 1,
+            compid: 1,
+            msgid: 0,
+            message: "HEARTBEAT",
+            frame: hex(frame.as_bytes()),
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.starts_with(
+            "{\"time\":1.5,\"source\":\"UART-0\",\"reason\":\"dialect\",\"sysid\":1,"
+        ));
+        assert!(line.contains("\"frame\":\"fd01000007010100000000"));
+    }
+}
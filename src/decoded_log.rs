@@ -0,0 +1,303 @@
+use crate::config::{DecodedLogConfig, DecodedLogFormat};
+use crate::mavlink::decode::{
+    Attitude, BatteryStatus, GlobalPositionInt, GpsRawInt, Heartbeat, RadioStatus, SysStatus,
+};
+use crate::mavlink::{messages, MavFrame};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Router-side handle: passes on frames of the logged messages
+#[derive(Debug, Clone)]
+pub struct DecodedLog {
+    messages: Arc<HashSet<u32>>,
+    tx: mpsc::UnboundedSender<(f64, MavFrame)>,
+}
+
+impl DecodedLog {
+    /// Start the writer for `[decoded_log]`. Returns `None` when disabled,
+    /// and an error for messages there is no decoder for.
+    pub fn start(config: &DecodedLogConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        for &msg_id in &config.messages {
+            if !DECODED.contains(&msg_id) {
+                anyhow::bail!("decoded_log: no decoder for message id {}", msg_id);
+            }
+        }
+        info!(
+            "Decoded log: messages {:?} as {:?} to {}",
+            config.messages, config.format, config.path
+        );
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<(f64, MavFrame)>();
+        let mut writer = Writer::new(config);
+        tokio::task::spawn_blocking(move || {
+            while let Some((time, frame)) = rx.blocking_recv() {
+                writer.write(time, &frame);
+                if rx.is_empty() {
+                    writer.flush();
+                }
+            }
+        });
+
+        Ok(Some(Self {
+            messages: Arc::new(config.messages.iter().copied().collect()),
+            tx,
+        }))
+    }
+
+    /// Log a received frame, if it is of one of the chosen messages
+    pub fn record(&self, frame: &MavFrame) {
+        if !self.messages.contains(&frame.msg_id()) {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let _ = self.tx.send((time, frame.clone()));
+    }
+}
+
+/// Messages there is a decoder for
+pub(crate) const DECODED: &[u32] = &[
+    messages::HEARTBEAT,
+    messages::SYS_STATUS,
+    messages::GPS_RAW_INT,
+    messages::ATTITUDE,
+    messages::GLOBAL_POSITION_INT,
+    messages::RADIO_STATUS,
+    messages::BATTERY_STATUS,
+];
+
+/// Decoded fields of a frame, in definition order
+pub(crate) fn fields(frame: &MavFrame) -> Option<Vec<(&'static str, Value)>> {
+    if let Some(m) = Heartbeat::decode(frame) {
+        return Some(vec![
+            ("custom_mode", m.custom_mode.into()),
+            ("type", m.mav_type.into()),
+            ("autopilot", m.autopilot.into()),
+            ("base_mode", m.base_mode.into()),
+            ("system_status", m.system_status.into()),
+        ]);
+    }
+    if let Some(m) = SysStatus::decode(frame) {
+        return Some(vec![
+            ("voltage_battery", m.voltage_battery.into()),
+            ("current_battery", m.current_battery.into()),
+            ("battery_remaining", m.battery_remaining.into()),
+        ]);
+    }
+    if let Some(m) = GpsRawInt::decode(frame) {
+        return Some(vec![
+            ("lat", m.lat.into()),
+            ("lon", m.lon.into()),
+            ("alt", m.alt.into()),
+            ("fix_type", m.fix_type.into()),
+            ("satellites_visible", m.satellites_visible.into()),
+        ]);
+    }
+    if let Some(m) = Attitude::decode(frame) {
+        return Some(vec![
+            ("time_boot_ms", m.time_boot_ms.into()),
+            ("roll", m.roll.into()),
+            ("pitch", m.pitch.into()),
+            ("yaw", m.yaw.into()),
+            ("rollspeed", m.rollspeed.into()),
+            ("pitchspeed", m.pitchspeed.into()),
+            ("yawspeed", m.yawspeed.into()),
+        ]);
+    }
+    if let Some(m) = GlobalPositionInt::decode(frame) {
+        return Some(vec![
+            ("time_boot_ms", m.time_boot_ms.into()),
+            ("lat", m.lat.into()),
+            ("lon", m.lon.into()),
+            ("alt", m.alt.into()),
+            ("relative_alt", m.relative_alt.into()),
+            ("vx", m.vx.into()),
+            ("vy", m.vy.into()),
+            ("vz", m.vz.into()),
+            ("hdg", m.hdg.into()),
+        ]);
+    }
+    if let Some(m) = RadioStatus::decode(frame) {
+        return Some(vec![
+            ("rssi", m.rssi.into()),
+            ("remrssi", m.remrssi.into()),
+            ("txbuf", m.txbuf.into()),
+            ("rxerrors", m.rxerrors.into()),
+        ]);
+    }
+    if let Some(m) = BatteryStatus::decode(frame) {
+        return Some(vec![
+            ("id", m.id.into()),
+            ("voltage", m.voltage.into()),
+            ("current_battery", m.current_battery.into()),
+            ("current_consumed", m.current_consumed.into()),
+            ("energy_consumed", m.energy_consumed.into()),
+            ("temperature", m.temperature.into()),
+            ("battery_remaining", m.battery_remaining.into()),
+        ]);
+    }
+    None
+}
+
+/// One JSON object per line, `time`, `sysid`, `compid` and `message` first
+fn json_line(time: f64, frame: &MavFrame, name: &str, fields: &[(&str, Value)]) -> String {
+    let mut line = format!(
+        "{{\"time\":{:.3},\"sysid\":{},\"compid\":{},\"message\":{}",
+        time,
+        frame.sys_id(),
+        frame.comp_id(),
+        Value::from(name)
+    );
+    for (field, value) in fields {
+        line.push_str(&format!(",{}:{}", Value::from(*field), value));
+    }
+    line.push('}');
+    line
+}
+
+fn csv_header(fields: &[(&str, Value)]) -> String {
+    let mut header = "time,sysid,compid".to_string();
+    for (field, _) in fields {
+        header.push(',');
+        header.push_str(field);
+    }
+    header
+}
+
+fn csv_row(time: f64, frame: &MavFrame, fields: &[(&str, Value)]) -> String {
+    let mut row = format!("{:.3},{},{}", time, frame.sys_id(), frame.comp_id());
+    for (_, value) in fields {
+        row.push(',');
+        row.push_str(&value.to_string());
+    }
+    row
+}
+
+/// Open output files, by message id for CSV
+struct Writer {
+    format: DecodedLogFormat,
+    path: PathBuf,
+    files: HashMap<u32, BufWriter<File>>,
+    /// Files that failed to open, so the error is logged once
+    failed: HashSet<u32>,
+}
+
+impl Writer {
+    fn new(config: &DecodedLogConfig) -> Self {
+        Self {
+            format: config.format,
+            path: PathBuf::from(&config.path),
+            files: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    fn write(&mut self, time: f64, frame: &MavFrame) {
+        let Some(fields) = fields(frame) else {
+            return;
+        };
+        let name = messages::lookup(frame.msg_id()).map_or("?", |m| m.name);
+
+        let (key, path, line) = match self.format {
+            DecodedLogFormat::Jsonl => {
+                (0, self.path.clone(), json_line(time, frame, name, &fields))
+            }
+            DecodedLogFormat::Csv => (
+                frame.msg_id(),
+                csv_path(&self.path, name),
+                csv_row(time, frame, &fields),
+            ),
+        };
+        if self.failed.contains(&key) {
+            return;
+        }
+
+        if !self.files.contains_key(&key) {
+            let header = (self.format == DecodedLogFormat::Csv).then(|| csv_header(&fields));
+            match open(&path, header.as_deref()) {
+                Ok(file) => {
+                    self.files.insert(key, file);
+                }
+                Err(e) => {
+                    error!("Decoded log: can't open {}: {}", path.display(), e);
+                    self.failed.insert(key);
+                    return;
+                }
+            }
+        }
+
+        let file = self.files.get_mut(&key).expect("opened above");
+        if let Err(e) = writeln!(file, "{}", line) {
+            error!("Decoded log: write to {} failed: {}", path.display(), e);
+        }
+    }
+
+    fn flush(&mut self) {
+        for file in self.files.values_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// `<dir>/<stem>_<MESSAGE>.csv` for a configured `<dir>/<stem>.csv`
+fn csv_path(path: &Path, message: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map_or("decoded".into(), |s| s.to_string_lossy());
+    path.with_file_name(format!("{}_{}.csv", stem, message))
+}
+
+/// Open a file for appending, starting a new one with `header`
+fn open(path: &Path, header: Option<&str>) -> std::io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_new = file.metadata()?.len() == 0;
+    let mut file = BufWriter::new(file);
+    if let (true, Some(header)) = (is_new, header) {
+        writeln!(file, "{}", header)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let mut payload = vec![0u8; 28];
+        payload[4..8].copy_from_slice(&473_977_418i32.to_le_bytes());
+        payload[26..28].copy_from_slice(&9000u16.to_le_bytes());
+        let frame = MavFrame::new_v2(0, 1, 1, messages::GLOBAL_POSITION_INT, &payload).unwrap();
+        let fields = fields(&frame).unwrap();
+
+        assert_eq!(
+            json_line(12.5, &frame, "GLOBAL_POSITION_INT", &fields),
+            "{\"time\":12.500,\"sysid\":1,\"compid\":1,\"message\":\"GLOBAL_POSITION_INT\",\
+             \"time_boot_ms\":0,\"lat\":473977418,\"lon\":0,\"alt\":0,\"relative_alt\":0,\
+             \"vx\":0,\"vy\":0,\"vz\":0,\"hdg\":9000}"
+        );
+        assert_eq!(
+            csv_header(&fields),
+            "time,sysid,compid,time_boot_ms,lat,lon,alt,relative_alt,vx,vy,vz,hdg"
+        );
+        assert_eq!(
+            csv_row(12.5, &frame, &fields),
+            "12.500,1,1,0,473977418,0,0,0,0,0,0,9000"
+        );
+        assert_eq!(
+            csv_path(Path::new("/tmp/flight.csv"), "ATTITUDE"),
+            Path::new("/tmp/flight_ATTITUDE.csv")
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::config::DedupMatch;
+use crate::connection::ConnectionId;
+use crate::mavlink::MavFrame;
+use crate::reliable::{key, FrameKey};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Most frames remembered; past it the oldest are forgotten early
+const MAX_SEEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum DedupKey {
+    Wire(FrameKey),
+    /// sysid, compid, sequence, message id and a hash of the payload
+    Content(u8, u8, u8, u32, u64),
+}
+
+/// Frames recently sent to one destination, so copies arriving over other
+/// paths within the window can be dropped
+pub struct Dedup {
+    window: Duration,
+    matching: DedupMatch,
+    allow_retransmit: bool,
+    /// When each was sent, and where it came from
+    seen: HashMap<DedupKey, (Instant, ConnectionId)>,
+    /// The same, oldest first. Entries since sent again are left in place
+    /// and skipped when they come up.
+    order: VecDeque<(DedupKey, Instant)>,
+}
+
+impl Dedup {
+    pub fn new(window: Duration, matching: DedupMatch, allow_retransmit: bool) -> Self {
+        Self {
+            window,
+            matching,
+            allow_retransmit,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn key(&self, frame: &MavFrame) -> DedupKey {
+        match self.matching {
+            DedupMatch::Wire => DedupKey::Wire(key(frame)),
+            DedupMatch::Content => {
+                let payload = frame.payload();
+                let len = payload.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                let mut hasher = DefaultHasher::new();
+                payload[..len].hash(&mut hasher);
+                DedupKey::Content(
+                    frame.sys_id(),
+                    frame.comp_id(),
+                    frame.sequence(),
+                    frame.msg_id(),
+                    hasher.finish(),
+                )
+            }
+        }
+    }
+
+    /// Whether a copy of `frame` was already let through within the window.
+    /// If not, it is remembered as sent.
+    pub fn is_duplicate(&mut self, frame: &MavFrame, source: ConnectionId, now: Instant) -> bool {
+        self.forget(now);
+
+        let key = self.key(frame);
+        let duplicate = self.seen.get(&key).is_some_and(|&(sent, from)| {
+            now.duration_since(sent) < self.window && !(self.allow_retransmit && from == source)
+        });
+        if !duplicate {
+            self.seen.insert(key, (now, source));
+            self.order.push_back((key, now));
+        }
+        duplicate
+    }
+
+    /// Drop frames sent before the window, and the oldest beyond `MAX_SEEN`
+    fn forget(&mut self, now: Instant) {
+        while let Some(&(key, sent)) = self.order.front() {
+            if now.duration_since(sent) < self.window && self.order.len() < MAX_SEEN {
+                break;
+            }
+            self.order.pop_front();
+            if self
+                .seen
+                .get(&key)
+                .is_some_and(|&(latest, _)| latest == sent)
+            {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    #[test]
+    fn test_drops_copies_within_window() {
+        let mut dedup = Dedup::new(Duration::from_millis(500), DedupMatch::Wire, false);
+        let (a, b) = (ConnectionId::new_uart(0), ConnectionId::new_uart(1));
+        let frame = MavFrame::new_v2(7, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let next = MavFrame::new_v2(8, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate(&frame, a, now));
+        assert!(dedup.is_duplicate(&frame, b, now + Duration::from_millis(100)));
+        assert!(!dedup.is_duplicate(&next, b, now + Duration::from_millis(100)));
+
+        // Past the window a repeat of the sequence number is a new frame
+        assert!(!dedup.is_duplicate(&frame, a, now + Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn test_content_and_retransmits() {
+        let mut dedup = Dedup::new(Duration::from_millis(500), DedupMatch::Content, true);
+        let (a, b) = (ConnectionId::new_uart(0), ConnectionId::new_uart(1));
+        let full = MavFrame::new_v2(7, 1, 1, messages::ATTITUDE, &[1, 2, 3]).unwrap();
+        // The same message as MAVLink 1, full length
+        let mut v1 = vec![0xFE, 28, 7, 1, 1, messages::ATTITUDE as u8, 1, 2, 3];
+        v1.resize(6 + 28 + 2, 0);
+        let (v1, _) = MavFrame::parse(&v1).unwrap();
+        let other = MavFrame::new_v2(7, 1, 1, messages::ATTITUDE, &[1, 2, 4]).unwrap();
+        let now = Instant::now();
+
+        // The same frame, as it arrived over another path
+        assert!(!dedup.is_duplicate(&full, a, now));
+        assert!(dedup.is_duplicate(&v1, b, now));
+        assert!(!dedup.is_duplicate(&other, b, now));
+
+        // Sent again over the same path on purpose
+        assert!(!dedup.is_duplicate(&full, a, now + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_forgets_oldest_past_cap() {
+        let mut dedup = Dedup::new(Duration::from_secs(60), DedupMatch::Wire, false);
+        let (a, b) = (ConnectionId::new_uart(0), ConnectionId::new_uart(1));
+        let frame = |n: usize| {
+            MavFrame::new_v2(
+                (n % 256) as u8,
+                (n / 256) as u8 + 1,
+                1,
+                messages::HEARTBEAT,
+                &[0; 9],
+            )
+            .unwrap()
+        };
+        let now = Instant::now();
+
+        for n in 0..MAX_SEEN + 10 {
+            assert!(!dedup.is_duplicate(&frame(n), a, now));
+        }
+        assert!(dedup.seen.len() <= MAX_SEEN);
+        assert!(dedup.order.len() <= MAX_SEEN);
+
+        // The newest are still caught, the oldest have made room for them
+        assert!(dedup.is_duplicate(&frame(MAX_SEEN + 9), b, now));
+        assert!(!dedup.is_duplicate(&frame(0), b, now));
+
+        // Everything goes once the window has passed
+        dedup.is_duplicate(&frame(1), a, now + Duration::from_secs(61));
+        assert_eq!(dedup.seen.len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::config::LinkQualityConfig;
+use crate::mavlink::decode::RadioStatus;
+use crate::mavlink::MavFrame;
+use crate::metrics::SequenceStats;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How much traffic a link's quality is judged on
+pub const QUALITY_WINDOW: Duration = Duration::from_secs(5);
+
+/// Quality of one connection, measured from the frames received on it, and
+/// the throttling applied to frames sent over it while it is degraded
+#[derive(Debug, Default)]
+pub struct LinkQuality {
+    /// Sequence tracking per (sysid, compid) over the current window
+    sequences: HashMap<(u8, u8), SequenceStats>,
+    /// Weakest RSSI reported by RADIO_STATUS in the current window
+    rssi: Option<u8>,
+    window_start: Option<Instant>,
+    degraded: bool,
+    /// When each (sysid, msgid) was last let through while degraded
+    last_sent: HashMap<(u8, u32), Instant>,
+}
+
+/// What the last window measured
+#[derive(Debug, Clone, Copy)]
+pub struct QualitySample {
+    pub loss_percent: f64,
+    pub rssi: Option<u8>,
+}
+
+impl LinkQuality {
+    /// Account for a frame received on this link
+    pub fn observe(&mut self, frame: &MavFrame) {
+        self.window_start.get_or_insert_with(Instant::now);
+        self.sequences
+            .entry((frame.sys_id(), frame.comp_id()))
+            .or_default()
+            .record(frame.sequence());
+
+        if let Some(rssi) = RadioStatus::decode(frame).and_then(|r| r.worst_rssi()) {
+            self.rssi = Some(self.rssi.map_or(rssi, |worst| worst.min(rssi)));
+        }
+    }
+
+    /// Once a full window has passed, judge it against the thresholds and
+    /// start a new one. Returns the sample if the link changed state.
+    pub fn evaluate(&mut self, config: &LinkQualityConfig) -> Option<QualitySample> {
+        if self.window_start?.elapsed() < QUALITY_WINDOW {
+            return None;
+        }
+
+        let (received, lost) = self
+            .sequences
+            .values()
+            .fold((0, 0), |(r, l), s| (r + s.received, l + s.lost));
+        let sample = QualitySample {
+            loss_percent: if received + lost == 0 {
+                0.0
+            } else {
+                lost as f64 * 100.0 / (received + lost) as f64
+            },
+            rssi: self.rssi,
+        };
+
+        // Keep the last sequence numbers so the next window counts gaps
+        // across the boundary
+        for stream in self.sequences.values_mut() {
+            stream.received = 0;
+            stream.lost = 0;
+        }
+        self.rssi = None;
+        self.window_start = Some(Instant::now());
+
+        let degraded = config
+            .min_rssi
+            .is_some_and(|min| sample.rssi.is_some_and(|rssi| rssi < min))
+            || config
+                .max_loss_percent
+                .is_some_and(|max| sample.loss_percent > max);
+        if degraded == self.degraded {
+            return None;
+        }
+        self.degraded = degraded;
+        if !degraded {
+            self.last_sent.clear();
+        }
+        Some(sample)
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Whether a frame may be sent over this link now
+    pub fn allow(&mut self, config: &LinkQualityConfig, frame: &MavFrame) -> bool {
+        if !self.degraded || !config.throttle_msgids.contains(&frame.msg_id()) {
+            return true;
+        }
+
+        let min_gap = Duration::from_secs_f64(1.0 / config.throttled_rate_hz.max(f64::EPSILON));
+        let now = Instant::now();
+        let key = (frame.sys_id(), frame.msg_id());
+        match self.last_sent.get(&key) {
+            Some(last) if now.duration_since(*last) < min_gap => false,
+            _ => {
+                self.last_sent.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    fn config() -> LinkQualityConfig {
+        LinkQualityConfig {
+            min_rssi: Some(50),
+            max_loss_percent: Some(20.0),
+            throttle_msgids: vec![messages::GPS_RAW_INT],
+            throttled_rate_hz: 1.0,
+        }
+    }
+
+    fn frame(seq: u8, msg_id: u32) -> MavFrame {
+        MavFrame::new_v2(seq, 1, 1, msg_id, &[0; 30]).unwrap()
+    }
+
+    #[test]
+    fn test_loss_degrades_and_throttles() {
+        let config = config();
+        let mut quality = LinkQuality::default();
+
+        // Every other frame missing: 50% loss
+        for seq in (0..20).step_by(2) {
+            quality.observe(&frame(seq, messages::HEARTBEAT));
+        }
+        quality.window_start = Some(Instant::now() - QUALITY_WINDOW);
+        let sample = quality.evaluate(&config).unwrap();
+        assert!(quality.is_degraded());
+        assert!((sample.loss_percent - 47.4).abs() < 0.1);
+
+        // Throttled msgids get one per period, everything else passes
+        assert!(quality.allow(&config, &frame(0, messages::GPS_RAW_INT)));
+        assert!(!quality.allow(&config, &frame(1, messages::GPS_RAW_INT)));
+        assert!(quality.allow(&config, &frame(2, messages::HEARTBEAT)));
+
+        // A clean window restores the link
+        for seq in 20..30 {
+            quality.observe(&frame(seq, messages::HEARTBEAT));
+        }
+        quality.window_start = Some(Instant::now() - QUALITY_WINDOW);
+        assert!(quality.evaluate(&config).is_some());
+        assert!(!quality.is_degraded());
+        assert!(quality.allow(&config, &frame(3, messages::GPS_RAW_INT)));
+    }
+}
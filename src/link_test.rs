@@ -0,0 +1,119 @@
+use crate::connection::ConnectionId;
+use crate::mavlink::decode::{command_long, CommandAck, MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES};
+use crate::mavlink::MavFrame;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// How a link test ended, short of timing out
+#[derive(Debug)]
+pub enum LinkTestResult {
+    Answered {
+        ack: CommandAck,
+        via: ConnectionId,
+        round_trip: Duration,
+    },
+    /// The router knows no link to the vehicle
+    NotSeen,
+}
+
+/// A test waiting on the vehicle's COMMAND_ACK, which shows commands reach
+/// it whatever the result
+#[derive(Debug)]
+pub struct LinkTest {
+    sysid: u8,
+    /// The router's own sysid/compid the request was sent from
+    from: (u8, u8),
+    sent: Instant,
+    reply: oneshot::Sender<LinkTestResult>,
+}
+
+impl LinkTest {
+    pub fn new(sysid: u8, from: (u8, u8), reply: oneshot::Sender<LinkTestResult>) -> Self {
+        Self {
+            sysid,
+            from,
+            sent: Instant::now(),
+            reply,
+        }
+    }
+
+    pub fn sysid(&self) -> u8 {
+        self.sysid
+    }
+
+    /// The request, for `target_component` of the vehicle
+    pub fn request(&self, target_component: u8) -> MavFrame {
+        let mut params = [0.0; 7];
+        params[0] = 1.0;
+        command_long(
+            0,
+            self.from,
+            (self.sysid, target_component),
+            MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES,
+            params,
+        )
+    }
+
+    /// The acknowledgement in `frame`, if it answers this test
+    pub fn answer(&self, frame: &MavFrame) -> Option<CommandAck> {
+        let ack = CommandAck::decode(frame)?;
+        (frame.sys_id() == self.sysid
+            && ack.command == MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES
+            && ack.target_system == self.from.0)
+            .then_some(ack)
+    }
+
+    /// Whether the admin client has stopped waiting
+    pub fn abandoned(&self) -> bool {
+        self.reply.is_closed()
+    }
+
+    pub fn not_seen(self) {
+        let _ = self.reply.send(LinkTestResult::NotSeen);
+    }
+
+    pub fn finish(self, ack: CommandAck, via: ConnectionId) {
+        let _ = self.reply.send(LinkTestResult::Answered {
+            ack,
+            via,
+            round_trip: self.sent.elapsed(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    fn ack(sysid: u8, command: u16, target_system: u8) -> MavFrame {
+        let mut payload = [0u8; 10];
+        payload[0..2].copy_from_slice(&command.to_le_bytes());
+        payload[2] = 3;
+        payload[8] = target_system;
+        MavFrame::new_v2(0, sysid, 1, messages::COMMAND_ACK, &payload).unwrap()
+    }
+
+    #[test]
+    fn test_request_and_answer() {
+        let (reply, _rx) = oneshot::channel();
+        let test = LinkTest::new(1, (250, 191), reply);
+
+        let request = test.request(1);
+        assert_eq!(request.msg_id(), messages::COMMAND_LONG);
+        assert_eq!((request.sys_id(), request.comp_id()), (250, 191));
+        assert_eq!(request.target_system(), Some(1));
+        assert_eq!(request.target_component(), Some(1));
+
+        // Any result counts, as long as it is this vehicle answering us
+        let answer = test.answer(&ack(1, MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES, 250));
+        assert_eq!(answer.map(|ack| ack.result_name()), Some("unsupported"));
+        assert!(test
+            .answer(&ack(2, MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES, 250))
+            .is_none());
+        assert!(test.answer(&ack(1, 400, 250)).is_none());
+        assert!(test
+            .answer(&ack(1, MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES, 255))
+            .is_none());
+    }
+}
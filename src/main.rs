@@ -1,19 +1,50 @@
+mod adaptive_rate;
+mod admin;
+mod announce;
+mod audit_log;
+mod boot_suppression;
+mod byte_budget;
+mod compid_map;
 mod config;
+mod conflate;
 mod connection;
+mod dead_letter;
+mod decoded_log;
+mod dedup;
+mod link_quality;
+mod link_test;
 mod mavlink;
 mod metrics;
+mod param_cache;
+mod peer_version;
+mod reliable;
+mod retention;
 mod router;
+mod signing;
+mod transform;
+mod tsdb;
 
-use config::Config;
-use connection::tcp::TcpServer;
-use connection::uart::UartConnection;
+use admin::AdminServer;
+use announce::Announcer;
+use audit_log::AuditLog;
+use config::{Config, StartMode};
+use connection::tcp::{RouterMessage, TcpServer};
+use connection::uart::{UartConnection, UartIds};
 use connection::uart_discovery::UartDiscovery;
+use connection::{ManualStarts, SerialControls};
+use decoded_log::DecodedLog;
+use mavlink::dialect::Dialect;
 use metrics::Metrics;
 use router::Router;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+/// How long shutdown waits for connections to send what is queued for them
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Load config
@@ -49,70 +80,309 @@ async fn main() -> anyhow::Result<()> {
     info!("  Log level: {}", config.log_level);
     info!("  TCP: {}:{}", config.tcp.bind_addr, config.tcp.listen_port);
     info!("  UART devices: {}", config.uart.len());
-    info!("  UART discovery: {}", if config.uart_discovery.enabled { "enabled" } else { "disabled" });
+    info!("  CAN interfaces: {}", config.can.len());
+    info!("  UDP endpoints: {}", config.udp.len());
+    info!("  HTTP sources: {}", config.http_source.len());
+    info!("  TCP clients: {}", config.tcp_client.len());
+    info!(
+        "  UART discovery: {}",
+        if config.uart_discovery.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
     info!("  Stats interval: {}s", config.stats_interval_secs);
+    if let Some(warn_ms) = config.clock_skew_warn_ms {
+        info!("  Clock skew: warning beyond {} ms", warn_ms);
+    }
+    if config.admin.enabled {
+        info!(
+            "  Admin: {}:{}",
+            config.admin.bind_addr, config.admin.listen_port
+        );
+    }
     info!("  Routing:");
     info!("    UART->UART: {}", config.routing.allow_uart_to_uart);
     info!("    UART->TCP: {}", config.routing.allow_uart_to_tcp);
     info!("    TCP->UART: {}", config.routing.allow_tcp_to_uart);
     info!("    TCP->TCP: {}", config.routing.allow_tcp_to_tcp);
+    if config.routing.strict_role_separation {
+        info!("    Strict role separation: vehicle<->GCS only");
+    }
+    if config.routing.param_cache {
+        info!("    Parameter cache: enabled");
+    }
+    if let Some(compids) = &config.routing.gcs_compids {
+        info!("    GCS component ids: {}-{}", compids.first, compids.last);
+    }
+    if let Some(standby) = &config.routing.standby {
+        match standby.primary_sysid {
+            Some(sysid) => info!(
+                "    Standby: taking over when sysid {} is silent for {}s",
+                sysid, standby.primary_timeout_secs
+            ),
+            None => info!("    Standby: until promoted from the admin channel"),
+        }
+    }
+    for ns in &config.routing.sysid_namespace {
+        info!(
+            "    Sysid namespace: {:?}->{:?} offset {}",
+            ns.from, ns.to, ns.offset
+        );
+    }
+
+    for signing in config.links().filter_map(|link| link.signing.as_ref()) {
+        if signing::LinkSigning::new(signing)
+            .active(std::time::SystemTime::now())
+            .is_none()
+        {
+            warn!(
+                "A link's signing keys ({}) are none of them valid now",
+                signing
+                    .keys
+                    .iter()
+                    .map(|key| key.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
 
     // Create metrics and start stats logger
-    let metrics = Metrics::new();
+    let metrics = Metrics::new()
+        .with_connection_stats(config.connection_stats)
+        .with_clock_skew(config.clock_skew_warn_ms);
     if config.stats_interval_secs > 0 {
         info!(
             "Starting performance monitoring (stats every {}s)",
             config.stats_interval_secs
         );
-        metrics.clone().start_stats_logger(config.stats_interval_secs);
+        metrics
+            .clone()
+            .start_stats_logger(config.stats_interval_secs, config.loss_warn_percent);
     } else {
         info!("Performance monitoring disabled (stats_interval_secs = 0)");
     }
 
+    retention::start(&config.logging);
+
     // Create router channel
-    let (router_tx, router_rx) = mpsc::unbounded_channel();
+    let dead_letters = dead_letter::DeadLetters::start(&config.dead_letter_log)?;
+    let (router_tx, router_rx) =
+        connection::router_channel(config.routing.input_queue_frames, metrics.clone());
+    let router_tx = router_tx.with_dead_letters(dead_letters.clone());
+
+    // Load the dialects links are restricted to
+    let mut dialects = HashMap::new();
+    for name in config.links().filter_map(|link| link.dialect.as_ref()) {
+        if dialects.contains_key(name) {
+            continue;
+        }
+        let dialect = Dialect::load(name, &config.dialect_dir)?;
+        info!("Loaded dialect {} ({} messages)", name, dialect.len());
+        dialects.insert(name.clone(), Arc::new(dialect));
+    }
+
+    let decoded_log = DecodedLog::start(&config.decoded_log)?;
+    let audit_log = AuditLog::start(&config.audit_log)?;
+    let tsdb = tsdb::TsdbExporter::start(&config.tsdb)?;
+
+    if config.discovery_announce.health_msgid == reliable::MAV_LITE_ACK {
+        anyhow::bail!(
+            "discovery_announce: health_msgid {} is MAV_LITE_ACK's",
+            reliable::MAV_LITE_ACK
+        );
+    }
+    let announcer = Announcer::new(&config.discovery_announce);
+    if announcer.is_some() {
+        info!(
+            "Announcing as sysid {} compid {}",
+            config.discovery_announce.sysid, config.discovery_announce.compid
+        );
+        announce::start_broadcast(&config.discovery_announce);
+    }
 
     // Start router task
-    let router = Router::new(config.routing.clone(), metrics);
-    tokio::spawn(async move {
-        router.run(router_rx).await;
-    });
+    let events = router::event_channel();
+    let router = Router::new(
+        config.routing.clone(),
+        metrics.clone(),
+        events.clone(),
+        dialects,
+        decoded_log,
+        audit_log,
+        announcer,
+    )
+    .with_tsdb(tsdb)
+    .with_dead_letters(dead_letters);
+    let router_task = tokio::spawn(async move { router.run(router_rx).await });
 
-    // Start static UART connections
-    let mut next_uart_id = 0;
+    // Start static UART connections; manual ones wait for the admin channel
+    let manual_starts = ManualStarts::default();
+    let serial_controls = SerialControls::default();
+    let mut uart_ids = UartIds::with_fixed(&config.uart)?;
     for uart_cfg in &config.uart {
         let uart_conn = UartConnection::new(
-            next_uart_id,
+            uart_ids.assign_static(uart_cfg),
             uart_cfg.path.clone(),
             uart_cfg.baud_rate,
             uart_cfg.name.clone(),
+            uart_cfg.link.clone(),
+        )
+        .with_controls(serial_controls.clone())
+        .with_auto_baud(uart_cfg.auto_baud)
+        .with_reconnect(uart_cfg.reconnect.clone())
+        .with_tx_path(uart_cfg.tx_path.clone());
+        match uart_cfg.start {
+            StartMode::Auto => uart_conn.start(router_tx.clone()).await,
+            StartMode::Manual => {
+                let name = uart_cfg
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| uart_cfg.path.clone());
+                let activate = manual_starts.register(name);
+                uart_conn.start_on_demand(router_tx.clone(), activate).await;
+            }
+        }
+    }
+    if !config.admin.enabled && !manual_starts.waiting().is_empty() {
+        warn!(
+            "UART connections with start = \"manual\" can only be opened from the admin channel, which is disabled"
         );
-        uart_conn.start(router_tx.clone()).await;
-        next_uart_id += 1;
+    }
+
+    // Start CAN connections
+    #[cfg(target_os = "linux")]
+    for (can_id, can_cfg) in config.can.iter().enumerate() {
+        let can_conn = connection::can::CanConnection::new(can_id, can_cfg.clone());
+        can_conn.start(router_tx.clone()).await;
+    }
+    #[cfg(not(target_os = "linux"))]
+    if !config.can.is_empty() {
+        warn!("CAN connections are only supported on Linux, ignoring [[can]]");
+    }
+
+    // Start UDP connections
+    for (udp_id, udp_cfg) in config.udp.iter().enumerate() {
+        let udp_conn = connection::udp::UdpConnection::new(udp_id, udp_cfg.clone());
+        udp_conn.start(router_tx.clone()).await;
+    }
+
+    // Start HTTP sources
+    for (http_id, http_cfg) in config.http_source.iter().enumerate() {
+        let source = connection::http_source::HttpSource::new(http_id, http_cfg.clone())?;
+        source.start(router_tx.clone()).await;
+    }
+
+    // Start outbound TCP connections
+    for (client_id, client_cfg) in config.tcp_client.iter().enumerate() {
+        let client =
+            connection::tcp_client::TcpClientConnection::new(client_id, client_cfg.clone());
+        client.start(router_tx.clone()).await;
     }
 
     // Start dynamic UART discovery if enabled
     if config.uart_discovery.enabled {
-        let discovery = UartDiscovery::new(config.uart_discovery.clone(), next_uart_id);
+        let discovery = UartDiscovery::new(
+            config.uart_discovery.clone(),
+            uart_ids,
+            serial_controls.clone(),
+        );
         let discovery_tx = router_tx.clone();
         tokio::spawn(async move {
             discovery.run(discovery_tx).await;
         });
     }
 
+    // Start admin channel if enabled
+    if config.admin.enabled {
+        let admin_addr = format!("{}:{}", config.admin.bind_addr, config.admin.listen_port);
+        let admin = AdminServer::bind(
+            &admin_addr,
+            Arc::new(config.clone()),
+            router_tx.clone(),
+            metrics.clone(),
+            manual_starts.clone(),
+            serial_controls.clone(),
+            events.clone(),
+        )
+        .await?;
+        tokio::spawn(admin.run());
+    }
+
     // Start TCP server
     let bind_addr = format!("{}:{}", config.tcp.bind_addr, config.tcp.listen_port);
-    let mut tcp_server = TcpServer::bind(&bind_addr).await?;
+    let mut tcp_server = TcpServer::bind(
+        &bind_addr,
+        config.tcp.compression,
+        config.tcp.max_write_bytes,
+        (config.tcp.max_connection_lifetime_secs > 0)
+            .then(|| Duration::from_secs(config.tcp.max_connection_lifetime_secs)),
+        config.tcp.link.clone(),
+    )
+    .await?;
 
     info!("mav-lite ready");
 
-    // Accept TCP connections in a loop
+    // Accept TCP connections until asked to stop
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        match tcp_server.accept(router_tx.clone()).await {
-            Ok(_) => {}
+        tokio::select! {
+            result = tcp_server.accept(router_tx.clone()) => {
+                if let Err(e) = result {
+                    error!("Failed to accept TCP connection: {}", e);
+                }
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    // Close the router to new frames first, so connections stop on their
+    // own, then let it finish with what it has
+    info!("mav-lite shutting down");
+    drop(tcp_server);
+    let _ = router_tx.send(RouterMessage::Shutdown);
+    let queues = router_task.await.unwrap_or_default();
+
+    // Give connections a moment to send what the router left them
+    let drained = async {
+        for queue in queues {
+            queue.wait().await;
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, drained)
+        .await
+        .is_err()
+    {
+        warn!(
+            "Connections still sending after {}s, stopping anyway",
+            SHUTDOWN_DRAIN_TIMEOUT.as_secs()
+        );
+    }
+    info!("mav-lite stopped");
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
             Err(e) => {
-                error!("Failed to accept TCP connection: {}", e);
+                warn!("Can't listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
             }
         }
     }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
@@ -0,0 +1,580 @@
+use super::messages;
+use super::MavFrame;
+
+/// Little-endian field reader over a zero-extended payload
+struct Fields<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(payload: &'a [u8]) -> Self {
+        Self { payload }
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut out = [0u8; N];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = self.payload.get(offset + i).copied().unwrap_or(0);
+        }
+        out
+    }
+
+    fn u8(&self, offset: usize) -> u8 {
+        self.bytes::<1>(offset)[0]
+    }
+
+    fn i8(&self, offset: usize) -> i8 {
+        self.u8(offset) as i8
+    }
+
+    fn u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.bytes(offset))
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.bytes(offset))
+    }
+
+    fn u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.bytes(offset))
+    }
+
+    fn u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.bytes(offset))
+    }
+
+    fn f32(&self, offset: usize) -> f32 {
+        f32::from_le_bytes(self.bytes(offset))
+    }
+
+    /// A 16-character parameter name, NUL-terminated unless it fills the field
+    fn param_id(&self, offset: usize) -> String {
+        let bytes = self.bytes::<PARAM_ID_LEN>(offset);
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(PARAM_ID_LEN);
+        String::from_utf8_lossy(&bytes[..len]).into_owned()
+    }
+}
+
+/// Length of the `param_id` field of the parameter messages
+const PARAM_ID_LEN: usize = 16;
+
+/// MAV_MODE_FLAG_SAFETY_ARMED
+const MODE_FLAG_SAFETY_ARMED: u8 = 0x80;
+
+/// MAV_TYPE_GCS
+pub const MAV_TYPE_GCS: u8 = 6;
+
+/// MAV_TYPE_ONBOARD_CONTROLLER: a companion computer or similar
+pub const MAV_TYPE_ONBOARD_CONTROLLER: u8 = 18;
+
+/// MAV_AUTOPILOT_INVALID: not a flight controller
+pub const MAV_AUTOPILOT_INVALID: u8 = 8;
+
+/// MAV_STATE_ACTIVE
+pub const MAV_STATE_ACTIVE: u8 = 4;
+
+/// MAV_STATE_CRITICAL: the system is in a failsafe state
+pub const MAV_STATE_CRITICAL: u8 = 5;
+
+/// `mavlink_version` carried in HEARTBEAT
+const MAVLINK_VERSION: u8 = 3;
+
+/// Offset of `system_status` in the HEARTBEAT payload
+const HEARTBEAT_SYSTEM_STATUS: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub custom_mode: u32,
+    pub mav_type: u8,
+    pub autopilot: u8,
+    pub base_mode: u8,
+    pub system_status: u8,
+}
+
+impl Heartbeat {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::HEARTBEAT {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            custom_mode: f.u32(0),
+            mav_type: f.u8(4),
+            autopilot: f.u8(5),
+            base_mode: f.u8(6),
+            system_status: f.u8(HEARTBEAT_SYSTEM_STATUS),
+        })
+    }
+
+    pub fn armed(&self) -> bool {
+        self.base_mode & MODE_FLAG_SAFETY_ARMED != 0
+    }
+
+    /// Encode as a HEARTBEAT frame sent by `sys_id`/`comp_id`
+    pub fn to_frame(self, seq: u8, sys_id: u8, comp_id: u8) -> MavFrame {
+        let mut payload = self.custom_mode.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[
+            self.mav_type,
+            self.autopilot,
+            self.base_mode,
+            self.system_status,
+            MAVLINK_VERSION,
+        ]);
+        MavFrame::new_v2(seq, sys_id, comp_id, messages::HEARTBEAT, &payload)
+            .expect("HEARTBEAT is in the message table")
+    }
+
+    /// Copy of a HEARTBEAT frame reporting a different `system_status`
+    pub fn with_system_status(frame: &MavFrame, status: u8) -> Option<MavFrame> {
+        if frame.msg_id() != messages::HEARTBEAT {
+            return None;
+        }
+        frame.with_payload_byte(HEARTBEAT_SYSTEM_STATUS, status)
+    }
+}
+
+/// MAV_SEVERITY_INFO
+pub const MAV_SEVERITY_INFO: u8 = 6;
+
+/// Length of the STATUSTEXT `text` field
+const STATUSTEXT_TEXT_LEN: usize = 50;
+
+/// Encode a STATUSTEXT frame sent by `sys_id`/`comp_id`. Text past the
+/// 50-byte field is cut off.
+pub fn status_text(seq: u8, sys_id: u8, comp_id: u8, severity: u8, text: &str) -> MavFrame {
+    let mut payload = vec![severity];
+    payload.extend(text.bytes().take(STATUSTEXT_TEXT_LEN));
+    payload.resize(1 + STATUSTEXT_TEXT_LEN, 0);
+    MavFrame::new_v2(seq, sys_id, comp_id, messages::STATUSTEXT, &payload)
+        .expect("STATUSTEXT is in the message table")
+}
+
+/// Length of the NAMED_VALUE_FLOAT `name` field
+const NAMED_VALUE_NAME_LEN: usize = 10;
+
+/// Encode a NAMED_VALUE_FLOAT frame sent by `sys_id`/`comp_id`. Names past
+/// 10 bytes are cut off.
+pub fn named_value_float(
+    seq: u8,
+    sys_id: u8,
+    comp_id: u8,
+    time_boot_ms: u32,
+    name: &str,
+    value: f32,
+) -> MavFrame {
+    let mut payload = time_boot_ms.to_le_bytes().to_vec();
+    payload.extend_from_slice(&value.to_le_bytes());
+    payload.extend(name.bytes().take(NAMED_VALUE_NAME_LEN));
+    payload.resize(8 + NAMED_VALUE_NAME_LEN, 0);
+    MavFrame::new_v2(seq, sys_id, comp_id, messages::NAMED_VALUE_FLOAT, &payload)
+        .expect("NAMED_VALUE_FLOAT is in the message table")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysStatus {
+    /// Battery voltage in millivolts (u16::MAX = unknown)
+    pub voltage_battery: u16,
+    /// Battery current in centiamps (-1 = unknown)
+    pub current_battery: i16,
+    /// Remaining battery in percent (-1 = unknown)
+    pub battery_remaining: i8,
+}
+
+impl SysStatus {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::SYS_STATUS {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            voltage_battery: f.u16(14),
+            current_battery: f.i16(16),
+            battery_remaining: f.i8(30),
+        })
+    }
+}
+
+/// SYSTEM_TIME, the sender's clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemTime {
+    /// Microseconds since the Unix epoch, 0 until the sender has the time
+    /// (usually from GPS)
+    pub time_unix_usec: u64,
+    /// Milliseconds since the sender booted
+    pub time_boot_ms: u32,
+}
+
+impl SystemTime {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::SYSTEM_TIME {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            time_unix_usec: f.u64(0),
+            time_boot_ms: f.u32(8),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadioStatus {
+    /// Local signal strength (device-dependent units, 255 = unknown)
+    pub rssi: u8,
+    /// Remote signal strength (device-dependent units, 255 = unknown)
+    pub remrssi: u8,
+    /// Remaining free transmit buffer in percent
+    pub txbuf: u8,
+    /// Receive errors
+    pub rxerrors: u16,
+}
+
+impl RadioStatus {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::RADIO_STATUS {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            rxerrors: f.u16(0),
+            rssi: f.u8(4),
+            remrssi: f.u8(5),
+            txbuf: f.u8(6),
+        })
+    }
+
+    /// Weaker end of the link, if either end reported one
+    pub fn worst_rssi(&self) -> Option<u8> {
+        [self.rssi, self.remrssi]
+            .into_iter()
+            .filter(|&rssi| rssi != u8::MAX)
+            .min()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpsRawInt {
+    /// Latitude in degE7
+    pub lat: i32,
+    /// Longitude in degE7
+    pub lon: i32,
+    /// Altitude (MSL) in millimetres
+    pub alt: i32,
+    pub fix_type: u8,
+    pub satellites_visible: u8,
+}
+
+impl GpsRawInt {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::GPS_RAW_INT {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            lat: f.i32(8),
+            lon: f.i32(12),
+            alt: f.i32(16),
+            fix_type: f.u8(28),
+            satellites_visible: f.u8(29),
+        })
+    }
+
+    /// Human-readable GPS_FIX_TYPE
+    pub fn fix_name(&self) -> &'static str {
+        match self.fix_type {
+            0 => "no GPS",
+            1 => "no fix",
+            2 => "2D",
+            3 => "3D",
+            4 => "DGPS",
+            5 => "RTK float",
+            6 => "RTK fixed",
+            7 => "static",
+            8 => "PPP",
+            _ => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attitude {
+    pub time_boot_ms: u32,
+    /// Roll, pitch and yaw in radians
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    /// Angular speeds in rad/s
+    pub rollspeed: f32,
+    pub pitchspeed: f32,
+    pub yawspeed: f32,
+}
+
+impl Attitude {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::ATTITUDE {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            time_boot_ms: f.u32(0),
+            roll: f.f32(4),
+            pitch: f.f32(8),
+            yaw: f.f32(12),
+            rollspeed: f.f32(16),
+            pitchspeed: f.f32(20),
+            yawspeed: f.f32(24),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlobalPositionInt {
+    pub time_boot_ms: u32,
+    /// Latitude in degE7
+    pub lat: i32,
+    /// Longitude in degE7
+    pub lon: i32,
+    /// Altitude (MSL) in millimetres
+    pub alt: i32,
+    /// Altitude above home in millimetres
+    pub relative_alt: i32,
+    /// Ground speed (NED) in cm/s
+    pub vx: i16,
+    pub vy: i16,
+    pub vz: i16,
+    /// Heading in cdeg (u16::MAX = unknown)
+    pub hdg: u16,
+}
+
+impl GlobalPositionInt {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::GLOBAL_POSITION_INT {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            time_boot_ms: f.u32(0),
+            lat: f.i32(4),
+            lon: f.i32(8),
+            alt: f.i32(12),
+            relative_alt: f.i32(16),
+            vx: f.i16(20),
+            vy: f.i16(22),
+            vz: f.i16(24),
+            hdg: f.u16(26),
+        })
+    }
+}
+
+/// BATTERY_STATUS, for one battery of several
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryStatus {
+    pub id: u8,
+    /// Pack voltage in millivolts, the sum of the cells reported
+    pub voltage: u32,
+    /// Current in centiamps (-1 = unknown)
+    pub current_battery: i16,
+    /// Charge consumed in mAh (-1 = unknown)
+    pub current_consumed: i32,
+    /// Energy consumed in hJ (-1 = unknown)
+    pub energy_consumed: i32,
+    /// Temperature in cdegC (i16::MAX = unknown)
+    pub temperature: i16,
+    /// Remaining battery in percent (-1 = unknown)
+    pub battery_remaining: i8,
+}
+
+impl BatteryStatus {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::BATTERY_STATUS {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        // Unused cells are u16::MAX
+        let voltage = (0..10)
+            .map(|cell| f.u16(10 + cell * 2))
+            .take_while(|&mv| mv != u16::MAX)
+            .map(u32::from)
+            .sum();
+        Some(Self {
+            current_consumed: f.i32(0),
+            energy_consumed: f.i32(4),
+            temperature: f.i16(8),
+            voltage,
+            current_battery: f.i16(30),
+            id: f.u8(32),
+            battery_remaining: f.i8(35),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamValue {
+    pub param_id: String,
+    /// Total parameters the vehicle has
+    pub param_count: u16,
+    pub param_index: u16,
+}
+
+impl ParamValue {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::PARAM_VALUE {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            param_count: f.u16(4),
+            param_index: f.u16(6),
+            param_id: f.param_id(8),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParamRequestList {
+    pub target_system: u8,
+    pub target_component: u8,
+}
+
+impl ParamRequestList {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::PARAM_REQUEST_LIST {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            target_system: f.u8(0),
+            target_component: f.u8(1),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamRequestRead {
+    /// Index to read, or -1 to read by `param_id`
+    pub param_index: i16,
+    pub target_system: u8,
+    pub target_component: u8,
+    pub param_id: String,
+}
+
+impl ParamRequestRead {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::PARAM_REQUEST_READ {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            param_index: f.i16(0),
+            target_system: f.u8(2),
+            target_component: f.u8(3),
+            param_id: f.param_id(4),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamSet {
+    pub target_system: u8,
+    pub target_component: u8,
+    pub param_id: String,
+}
+
+impl ParamSet {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::PARAM_SET {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            target_system: f.u8(4),
+            target_component: f.u8(5),
+            param_id: f.param_id(6),
+        })
+    }
+}
+
+/// MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES
+pub const MAV_CMD_REQUEST_AUTOPILOT_CAPABILITIES: u16 = 520;
+
+/// Encode a COMMAND_LONG frame sent by `sys_id`/`comp_id`
+pub fn command_long(
+    seq: u8,
+    (sys_id, comp_id): (u8, u8),
+    (target_system, target_component): (u8, u8),
+    command: u16,
+    params: [f32; 7],
+) -> MavFrame {
+    let mut payload: Vec<u8> = params.iter().flat_map(|p| p.to_le_bytes()).collect();
+    payload.extend_from_slice(&command.to_le_bytes());
+    payload.extend_from_slice(&[target_system, target_component, 0]);
+    MavFrame::new_v2(seq, sys_id, comp_id, messages::COMMAND_LONG, &payload)
+        .expect("COMMAND_LONG is in the message table")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandAck {
+    pub command: u16,
+    /// MAV_RESULT
+    pub result: u8,
+    /// Zero from senders that leave out the extension fields
+    pub target_system: u8,
+    pub target_component: u8,
+}
+
+impl CommandAck {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::COMMAND_ACK {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        Some(Self {
+            command: f.u16(0),
+            result: f.u8(2),
+            target_system: f.u8(8),
+            target_component: f.u8(9),
+        })
+    }
+
+    pub fn result_name(&self) -> &'static str {
+        match self.result {
+            0 => "accepted",
+            1 => "temporarily rejected",
+            2 => "denied",
+            3 => "unsupported",
+            4 => "failed",
+            5 => "in progress",
+            6 => "cancelled",
+            _ => "unknown result",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusText {
+    /// MAV_SEVERITY, 0 (emergency) to 7 (debug)
+    pub severity: u8,
+    pub text: String,
+}
+
+impl StatusText {
+    pub fn decode(frame: &MavFrame) -> Option<Self> {
+        if frame.msg_id() != messages::STATUSTEXT {
+            return None;
+        }
+        let f = Fields::new(frame.payload());
+        let text = f.bytes::<STATUSTEXT_TEXT_LEN>(1);
+        let len = text
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(STATUSTEXT_TEXT_LEN);
+        Some(Self {
+            severity: f.u8(0),
+            text: String::from_utf8_lossy(&text[..len]).into_owned(),
+        })
+    }
+}
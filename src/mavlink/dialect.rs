@@ -0,0 +1,144 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Every message id a dialect defines, its includes' included
+#[derive(Debug)]
+pub struct Dialect {
+    pub name: String,
+    ids: HashSet<u32>,
+}
+
+impl Dialect {
+    /// Load a dialect given as a path to its XML file, or as a name looked up
+    /// as `<name>.xml` in `dir`
+    pub fn load(dialect: &str, dir: &str) -> anyhow::Result<Self> {
+        let path = if dialect.ends_with(".xml") {
+            PathBuf::from(dialect)
+        } else {
+            Path::new(dir).join(format!("{}.xml", dialect))
+        };
+
+        let mut ids = HashSet::new();
+        let mut visited = HashSet::new();
+        read_definitions(&path, &mut ids, &mut visited)?;
+
+        Ok(Self {
+            name: dialect.to_string(),
+            ids,
+        })
+    }
+
+    pub fn contains(&self, msg_id: u32) -> bool {
+        self.ids.contains(&msg_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+/// Collect the message ids of one definition file and, recursively, the
+/// files it includes (relative to its own directory)
+fn read_definitions(
+    path: &Path,
+    ids: &mut HashSet<u32>,
+    visited: &mut HashSet<PathBuf>,
+) -> anyhow::Result<()> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("dialect definition {}", path.display()))?;
+    if !visited.insert(canonical) {
+        return Ok(());
+    }
+
+    let xml = std::fs::read_to_string(path)
+        .with_context(|| format!("dialect definition {}", path.display()))?;
+    let (includes, message_ids) = parse_definitions(&xml);
+    ids.extend(message_ids);
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for include in includes {
+        read_definitions(&dir.join(include), ids, visited)?;
+    }
+    Ok(())
+}
+
+/// Included file names and message ids defined in one XML file
+fn parse_definitions(xml: &str) -> (Vec<String>, Vec<u32>) {
+    let includes = tag_contents(xml, "<include>", "</include>")
+        .map(|name| name.trim().to_string())
+        .collect();
+
+    let ids = xml
+        .split("<message ")
+        .skip(1)
+        .filter_map(|rest| {
+            let attrs = &rest[..rest.find('>')?];
+            let value = attrs.split("id=\"").nth(1)?;
+            value[..value.find('"')?].trim().parse().ok()
+        })
+        .collect();
+
+    (includes, ids)
+}
+
+fn tag_contents<'a>(
+    xml: &'a str,
+    open: &'a str,
+    close: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    xml.split(open)
+        .skip(1)
+        .filter_map(move |rest| rest.find(close).map(|end| &rest[..end]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_definitions() {
+        let xml = r#"<?xml version="1.0"?>
+<mavlink>
+  <include>common.xml</include>
+  <include> uAvionix.xml </include>
+  <messages>
+    <message id="150" name="SENSOR_OFFSETS">
+      <description>Offsets and calibrations</description>
+    </message>
+    <message name="MEMINFO" id="152">
+    </message>
+  </messages>
+</mavlink>"#;
+        let (includes, ids) = parse_definitions(xml);
+        assert_eq!(includes, ["common.xml", "uAvionix.xml"]);
+        assert_eq!(ids, [150, 152]);
+    }
+
+    #[test]
+    fn test_load_follows_includes() {
+        let name = format!("mav-lite-dialect-{}", std::process::id());
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("common.xml"),
+            r#"<mavlink><messages><message id="0" name="HEARTBEAT"></message></messages></mavlink>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("custom.xml"),
+            r#"<mavlink><include>common.xml</include><include>custom.xml</include>
+<messages><message id="42000" name="CUSTOM"></message></messages></mavlink>"#,
+        )
+        .unwrap();
+
+        let dialect = Dialect::load("custom", dir.to_str().unwrap()).unwrap();
+        assert!(dialect.contains(0));
+        assert!(dialect.contains(42000));
+        assert!(!dialect.contains(1));
+        assert!(Dialect::load("missing", dir.to_str().unwrap()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
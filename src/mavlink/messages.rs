@@ -0,0 +1,257 @@
+/// Per-message metadata, for the messages the router looks inside; others
+/// are forwarded untouched
+#[derive(Debug, Clone, Copy)]
+pub struct MessageInfo {
+    pub id: u32,
+    pub name: &'static str,
+    /// CRC_EXTRA seed byte from the message definition
+    pub crc_extra: u8,
+    /// Payload length without MAVLink 2 extension fields
+    pub base_len: u8,
+    /// Payload length with every extension field
+    pub max_len: u8,
+    /// Payload offset of the `target_system` field, if the message has one
+    pub target_system_offset: Option<usize>,
+    /// Whether it is a command, a response or telemetry
+    pub class: MessageClass,
+}
+
+impl MessageInfo {
+    /// Payload offset of the `target_component` field. Every message in the
+    /// table with both fields has it right after `target_system`; SET_MODE
+    /// and MANUAL_CONTROL address a system only.
+    pub fn target_component_offset(&self) -> Option<usize> {
+        match self.id {
+            11 | 69 => None,
+            _ => self.target_system_offset.map(|offset| offset + 1),
+        }
+    }
+}
+
+/// Rough role of a message, for routing rules that treat them differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageClass {
+    /// Asks a system to do or change something, or to send something back
+    Command,
+    /// Answers a command (acks, requested values, mission handshakes)
+    Response,
+    /// Everything streamed unprompted; also any message not in the table
+    Telemetry,
+}
+
+const fn msg(
+    id: u32,
+    name: &'static str,
+    crc_extra: u8,
+    base_len: u8,
+    max_len: u8,
+    target_system_offset: Option<usize>,
+    class: MessageClass,
+) -> MessageInfo {
+    MessageInfo {
+        id,
+        name,
+        crc_extra,
+        base_len,
+        max_len,
+        target_system_offset,
+        class,
+    }
+}
+
+pub const HEARTBEAT: u32 = 0;
+pub const SYS_STATUS: u32 = 1;
+pub const SYSTEM_TIME: u32 = 2;
+pub const PING: u32 = 4;
+pub const PARAM_REQUEST_READ: u32 = 20;
+pub const PARAM_REQUEST_LIST: u32 = 21;
+pub const PARAM_VALUE: u32 = 22;
+pub const PARAM_SET: u32 = 23;
+pub const GPS_RAW_INT: u32 = 24;
+pub const ATTITUDE: u32 = 30;
+pub const GLOBAL_POSITION_INT: u32 = 33;
+pub const COMMAND_INT: u32 = 75;
+pub const COMMAND_LONG: u32 = 76;
+pub const COMMAND_ACK: u32 = 77;
+pub const RADIO_STATUS: u32 = 109;
+pub const TIMESYNC: u32 = 111;
+pub const BATTERY_STATUS: u32 = 147;
+pub const AUTOPILOT_VERSION: u32 = 148;
+pub const EXTENDED_SYS_STATE: u32 = 245;
+pub const NAMED_VALUE_FLOAT: u32 = 251;
+pub const STATUSTEXT: u32 = 253;
+
+use MessageClass::{Command, Response, Telemetry};
+
+/// Known messages, sorted by id
+static MESSAGES: &[MessageInfo] = &[
+    msg(HEARTBEAT, "HEARTBEAT", 50, 9, 9, None, Telemetry),
+    msg(SYS_STATUS, "SYS_STATUS", 124, 31, 43, None, Telemetry),
+    msg(SYSTEM_TIME, "SYSTEM_TIME", 137, 12, 12, None, Telemetry),
+    msg(PING, "PING", 237, 14, 14, Some(12), Telemetry),
+    msg(11, "SET_MODE", 89, 6, 6, Some(4), Command),
+    msg(
+        PARAM_REQUEST_READ,
+        "PARAM_REQUEST_READ",
+        214,
+        20,
+        20,
+        Some(2),
+        Command,
+    ),
+    msg(
+        PARAM_REQUEST_LIST,
+        "PARAM_REQUEST_LIST",
+        159,
+        2,
+        2,
+        Some(0),
+        Command,
+    ),
+    msg(PARAM_VALUE, "PARAM_VALUE", 220, 25, 25, None, Response),
+    msg(PARAM_SET, "PARAM_SET", 168, 23, 23, Some(4), Command),
+    msg(GPS_RAW_INT, "GPS_RAW_INT", 24, 30, 52, None, Telemetry),
+    msg(29, "SCALED_PRESSURE", 115, 14, 16, None, Telemetry),
+    msg(ATTITUDE, "ATTITUDE", 39, 28, 28, None, Telemetry),
+    msg(31, "ATTITUDE_QUATERNION", 246, 32, 48, None, Telemetry),
+    msg(32, "LOCAL_POSITION_NED", 185, 28, 28, None, Telemetry),
+    msg(
+        GLOBAL_POSITION_INT,
+        "GLOBAL_POSITION_INT",
+        104,
+        28,
+        28,
+        None,
+        Telemetry,
+    ),
+    msg(36, "SERVO_OUTPUT_RAW", 222, 21, 37, None, Telemetry),
+    msg(39, "MISSION_ITEM", 254, 37, 38, Some(32), Command),
+    msg(40, "MISSION_REQUEST", 230, 4, 5, Some(2), Response),
+    msg(41, "MISSION_SET_CURRENT", 28, 4, 4, Some(2), Command),
+    msg(43, "MISSION_REQUEST_LIST", 132, 2, 3, Some(0), Command),
+    msg(44, "MISSION_COUNT", 221, 4, 9, Some(2), Command),
+    msg(45, "MISSION_CLEAR_ALL", 232, 2, 3, Some(0), Command),
+    msg(47, "MISSION_ACK", 153, 3, 8, Some(0), Response),
+    msg(51, "MISSION_REQUEST_INT", 196, 4, 5, Some(2), Response),
+    msg(62, "NAV_CONTROLLER_OUTPUT", 183, 26, 26, None, Telemetry),
+    msg(65, "RC_CHANNELS", 118, 42, 42, None, Telemetry),
+    msg(66, "REQUEST_DATA_STREAM", 148, 6, 6, Some(2), Command),
+    msg(69, "MANUAL_CONTROL", 243, 11, 30, Some(10), Command),
+    msg(70, "RC_CHANNELS_OVERRIDE", 124, 18, 38, Some(16), Command),
+    msg(73, "MISSION_ITEM_INT", 38, 37, 38, Some(32), Command),
+    msg(74, "VFR_HUD", 20, 20, 20, None, Telemetry),
+    msg(COMMAND_INT, "COMMAND_INT", 158, 35, 35, Some(30), Command),
+    msg(COMMAND_LONG, "COMMAND_LONG", 152, 33, 33, Some(30), Command),
+    // target_system is a MAVLink 2 extension field at offset 8
+    msg(COMMAND_ACK, "COMMAND_ACK", 143, 3, 10, Some(8), Response),
+    msg(82, "SET_ATTITUDE_TARGET", 49, 39, 51, Some(36), Command),
+    msg(
+        84,
+        "SET_POSITION_TARGET_LOCAL_NED",
+        143,
+        53,
+        53,
+        Some(50),
+        Command,
+    ),
+    msg(
+        86,
+        "SET_POSITION_TARGET_GLOBAL_INT",
+        5,
+        53,
+        53,
+        Some(50),
+        Command,
+    ),
+    msg(RADIO_STATUS, "RADIO_STATUS", 185, 9, 9, None, Telemetry),
+    msg(
+        110,
+        "FILE_TRANSFER_PROTOCOL",
+        84,
+        254,
+        254,
+        Some(1),
+        Command,
+    ),
+    msg(TIMESYNC, "TIMESYNC", 34, 16, 18, None, Telemetry),
+    msg(117, "LOG_REQUEST_LIST", 128, 6, 6, Some(4), Command),
+    msg(119, "LOG_REQUEST_DATA", 116, 12, 12, Some(10), Command),
+    msg(
+        BATTERY_STATUS,
+        "BATTERY_STATUS",
+        154,
+        36,
+        54,
+        None,
+        Telemetry,
+    ),
+    msg(
+        AUTOPILOT_VERSION,
+        "AUTOPILOT_VERSION",
+        178,
+        60,
+        78,
+        None,
+        Response,
+    ),
+    msg(241, "VIBRATION", 90, 32, 32, None, Telemetry),
+    msg(242, "HOME_POSITION", 104, 52, 60, None, Telemetry),
+    msg(
+        EXTENDED_SYS_STATE,
+        "EXTENDED_SYS_STATE",
+        130,
+        2,
+        2,
+        None,
+        Telemetry,
+    ),
+    msg(
+        NAMED_VALUE_FLOAT,
+        "NAMED_VALUE_FLOAT",
+        170,
+        18,
+        18,
+        None,
+        Telemetry,
+    ),
+    msg(STATUSTEXT, "STATUSTEXT", 83, 51, 54, None, Telemetry),
+];
+
+/// Look up metadata for a message id
+pub fn lookup(msg_id: u32) -> Option<&'static MessageInfo> {
+    MESSAGES
+        .binary_search_by_key(&msg_id, |m| m.id)
+        .ok()
+        .map(|i| &MESSAGES[i])
+}
+
+/// Class of a message id; unknown messages count as telemetry
+pub fn class(msg_id: u32) -> MessageClass {
+    lookup(msg_id).map_or(MessageClass::Telemetry, |m| m.class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_sorted() {
+        assert!(MESSAGES.windows(2).all(|w| w[0].id < w[1].id));
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup(COMMAND_LONG).unwrap().crc_extra, 152);
+        assert!(lookup(12345).is_none());
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(class(COMMAND_LONG), MessageClass::Command);
+        assert_eq!(class(PARAM_SET), MessageClass::Command);
+        assert_eq!(class(COMMAND_ACK), MessageClass::Response);
+        assert_eq!(class(PARAM_VALUE), MessageClass::Response);
+        assert_eq!(class(HEARTBEAT), MessageClass::Telemetry);
+        assert_eq!(class(12345), MessageClass::Telemetry);
+    }
+}
@@ -1,3 +1,6 @@
+pub mod decode;
+pub mod dialect;
+pub mod messages;
 pub mod packet;
 
 pub use packet::{MavFrame, ParseError};
@@ -1,4 +1,6 @@
+use super::messages::{self, MessageClass};
 use bytes::Bytes;
+use ring::digest::{Context, SHA256};
 use std::io;
 use thiserror::Error;
 
@@ -19,6 +21,7 @@ pub enum ParseError {
     Incomplete(usize, usize),
 
     #[error("Invalid CRC: expected {expected:#x}, got {got:#x}")]
+    #[allow(dead_code)]
     InvalidCrc { expected: u16, got: u16 },
 
     #[error("IO error: {0}")]
@@ -43,9 +46,62 @@ pub struct MavFrame {
     payload_offset: usize,
     /// Payload length
     payload_len: usize,
+    /// CRC_EXTRA it was sealed with, if it was built here rather than parsed
+    sealed_with: Option<u8>,
 }
 
 impl MavFrame {
+    /// Build an unsigned MAVLink 2 frame for a message in the message table.
+    /// Returns `None` for messages we don't know the CRC_EXTRA of.
+    pub fn new_v2(seq: u8, sys_id: u8, comp_id: u8, msg_id: u32, payload: &[u8]) -> Option<Self> {
+        let crc_extra = messages::lookup(msg_id)?.crc_extra;
+        Some(Self::new_v2_with_crc_extra(
+            seq, sys_id, comp_id, msg_id, crc_extra, payload,
+        ))
+    }
+
+    /// Build an unsigned MAVLink 2 frame for any message, such as a custom
+    /// one outside the table, given its CRC_EXTRA
+    pub fn new_v2_with_crc_extra(
+        seq: u8,
+        sys_id: u8,
+        comp_id: u8,
+        msg_id: u32,
+        crc_extra: u8,
+        payload: &[u8],
+    ) -> Self {
+        // MAVLink 2 drops trailing zero bytes from the payload
+        let len = payload.iter().rposition(|&b| b != 0).map_or(1, |i| i + 1);
+        let payload = &payload[..len.min(payload.len())];
+
+        let id = msg_id.to_le_bytes();
+        let mut buf =
+            Vec::with_capacity(MAVLINK_V2_HEADER_LEN + payload.len() + MAVLINK_CHECKSUM_LEN);
+        buf.extend_from_slice(&[
+            MAVLINK_STX_V2,
+            payload.len() as u8,
+            0,
+            0,
+            seq,
+            sys_id,
+            comp_id,
+            id[0],
+            id[1],
+            id[2],
+        ]);
+        buf.extend_from_slice(payload);
+        let crc = crc_accumulate(calculate_crc(&buf[1..]), crc_extra);
+        buf.extend_from_slice(&crc.to_le_bytes());
+
+        MavFrame {
+            data: Bytes::from(buf),
+            version: MavVersion::V2,
+            payload_offset: MAVLINK_V2_HEADER_LEN,
+            payload_len: payload.len(),
+            sealed_with: Some(crc_extra),
+        }
+    }
+
     /// Parse a MAVLink frame (v1 or v2) from a buffer
     /// Returns the frame and number of bytes consumed
     pub fn parse(buf: &[u8]) -> Result<(Self, usize), ParseError> {
@@ -83,6 +139,7 @@ impl MavFrame {
             version: MavVersion::V1,
             payload_offset: MAVLINK_V1_HEADER_LEN,
             payload_len,
+            sealed_with: None,
         };
 
         Ok((frame, total_len))
@@ -114,6 +171,7 @@ impl MavFrame {
             version: MavVersion::V2,
             payload_offset: MAVLINK_V2_HEADER_LEN,
             payload_len,
+            sealed_with: None,
         };
 
         Ok((frame, total_len))
@@ -149,7 +207,6 @@ impl MavFrame {
     }
 
     #[inline]
-    #[allow(dead_code)]
     pub fn sequence(&self) -> u8 {
         match self.version {
             MavVersion::V1 => self.data[2],
@@ -158,16 +215,269 @@ impl MavFrame {
     }
 
     #[inline]
-    #[allow(dead_code)]
     pub fn payload(&self) -> &[u8] {
         &self.data[self.payload_offset..self.payload_offset + self.payload_len]
     }
 
     #[inline]
+    #[allow(dead_code)]
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
 
+    /// Cheap handle to the raw frame bytes
+    #[inline]
+    pub fn to_bytes(&self) -> Bytes {
+        self.data.clone()
+    }
+
+    /// Checksum as carried on the wire
+    pub fn checksum(&self) -> u16 {
+        let crc_offset = self.payload_offset + self.payload_len;
+        u16::from_le_bytes([self.data[crc_offset], self.data[crc_offset + 1]])
+    }
+
+    /// Recover the CRC_EXTRA byte this frame was sealed with.
+    ///
+    /// The checksum is the CRC of the header and payload followed by one
+    /// CRC_EXTRA byte, so the seed can be solved from the frame itself, for
+    /// checking a frame against one that is expected. About one corrupt
+    /// frame in 256 solves to some value, so it is never used to reseal.
+    pub fn crc_extra(&self) -> Option<u8> {
+        let partial = calculate_crc(&self.data[1..self.payload_offset + self.payload_len]);
+        let expected = self.checksum();
+        (0..=u8::MAX).find(|&extra| crc_accumulate(partial, extra) == expected)
+    }
+
+    /// The CRC_EXTRA to reseal the frame with once changed: the one it was
+    /// built with, or for a received frame the message table's, if its
+    /// checksum matches. Frames of messages outside the table, or with a bad
+    /// checksum, can't be resealed, so garbage never goes out looking valid.
+    fn sealing_crc_extra(&self) -> Option<u8> {
+        if let Some(crc_extra) = self.sealed_with {
+            return Some(crc_extra);
+        }
+        let info = messages::lookup(self.msg_id())?;
+        (self.crc_valid() == Some(true)).then_some(info.crc_extra)
+    }
+
+    /// Whether the checksum is right for the message's CRC_EXTRA, for
+    /// messages in the table
+    pub fn crc_valid(&self) -> Option<bool> {
+        let info = messages::lookup(self.msg_id())?;
+        let partial = calculate_crc(&self.data[1..self.payload_offset + self.payload_len]);
+        Some(crc_accumulate(partial, info.crc_extra) == self.checksum())
+    }
+
+    /// Whether the payload length fits the message's definition, for frames
+    /// whose checksum shows they were sealed as that message. A MAVLink 1
+    /// payload must be exactly the base length; a MAVLink 2 one may be
+    /// truncated or carry extensions, so anything from one byte up to the
+    /// full length fits. Returns `None` for messages not in the table and for
+    /// checksums that don't match, which say nothing about the length.
+    pub fn length_matches(&self) -> Option<bool> {
+        if self.crc_valid() != Some(true) {
+            return None;
+        }
+        let info = messages::lookup(self.msg_id())?;
+
+        Some(match self.version {
+            MavVersion::V1 => self.payload_len == info.base_len as usize,
+            MavVersion::V2 => (1..=info.max_len as usize).contains(&self.payload_len),
+        })
+    }
+
+    /// Value of the `target_system` field for messages known to carry one.
+    /// Fields cut off by MAVLink 2 payload truncation read as zero.
+    pub fn target_system(&self) -> Option<u8> {
+        let offset = messages::lookup(self.msg_id())?.target_system_offset?;
+        Some(self.payload().get(offset).copied().unwrap_or(0))
+    }
+
+    /// Value of the `target_component` field, for messages known to carry one
+    pub fn target_component(&self) -> Option<u8> {
+        let offset = messages::lookup(self.msg_id())?.target_component_offset()?;
+        Some(self.payload().get(offset).copied().unwrap_or(0))
+    }
+
+    /// Whether this is a command, a response or telemetry
+    pub fn message_class(&self) -> MessageClass {
+        messages::class(self.msg_id())
+    }
+
+    /// Copy of this frame with a different source system id
+    pub fn with_sys_id(&self, sys_id: u8) -> Option<MavFrame> {
+        let sysid_offset = match self.version {
+            MavVersion::V1 => 3,
+            MavVersion::V2 => 5,
+        };
+        self.rebuild(|header, _| header[sysid_offset] = sys_id)
+    }
+
+    /// Copy of this frame with a different source component id
+    pub fn with_comp_id(&self, comp_id: u8) -> Option<MavFrame> {
+        let compid_offset = match self.version {
+            MavVersion::V1 => 4,
+            MavVersion::V2 => 6,
+        };
+        self.rebuild(|header, _| header[compid_offset] = comp_id)
+    }
+
+    /// Copy of this frame with a different `target_system`. Returns `None` for
+    /// messages without the field, or where it would not fit (v1 frames can't
+    /// carry extension fields).
+    pub fn with_target_system(&self, target: u8) -> Option<MavFrame> {
+        let offset = messages::lookup(self.msg_id())?.target_system_offset?;
+        self.with_payload_byte(offset, target)
+    }
+
+    /// Copy of this frame with a different `target_component`
+    pub fn with_target_component(&self, target: u8) -> Option<MavFrame> {
+        let offset = messages::lookup(self.msg_id())?.target_component_offset()?;
+        self.with_payload_byte(offset, target)
+    }
+
+    /// Copy of this frame with one payload byte replaced. Returns `None` if
+    /// the offset is past the end of a v1 payload.
+    pub fn with_payload_byte(&self, offset: usize, value: u8) -> Option<MavFrame> {
+        if self.version == MavVersion::V1 && offset >= self.payload_len {
+            return None;
+        }
+
+        self.rebuild(|_, payload| {
+            if offset >= payload.len() {
+                // Restore bytes dropped by MAVLink 2 zero truncation
+                payload.resize(offset + 1, 0);
+            }
+            payload[offset] = value;
+        })
+    }
+
+    /// Copy of this frame with a different sequence number
+    pub fn with_sequence(&self, seq: u8) -> Option<MavFrame> {
+        let seq_offset = match self.version {
+            MavVersion::V1 => 2,
+            MavVersion::V2 => 4,
+        };
+        self.rebuild(|header, _| header[seq_offset] = seq)
+    }
+
+    /// Re-seal a copy of the frame after editing its header and payload.
+    /// Returns `None` for frames that can't be resealed (see
+    /// `sealing_crc_extra`).
+    ///
+    /// Signed frames lose their signature, since we can't re-sign them.
+    fn rebuild(&self, edit: impl FnOnce(&mut [u8], &mut Vec<u8>)) -> Option<MavFrame> {
+        let crc_extra = self.sealing_crc_extra()?;
+
+        let mut header = self.data[..self.payload_offset].to_vec();
+        let mut payload = self.payload().to_vec();
+        edit(&mut header, &mut payload);
+
+        header[1] = payload.len() as u8;
+        if self.version == MavVersion::V2 {
+            header[2] &= !MAVLINK_IFLAG_SIGNED;
+        }
+
+        let mut buf = Vec::with_capacity(header.len() + payload.len() + MAVLINK_CHECKSUM_LEN);
+        buf.extend_from_slice(&header);
+        buf.extend_from_slice(&payload);
+        let crc = crc_accumulate(calculate_crc(&buf[1..]), crc_extra);
+        buf.extend_from_slice(&crc.to_le_bytes());
+
+        Some(MavFrame {
+            data: Bytes::from(buf),
+            version: self.version,
+            payload_offset: header.len(),
+            payload_len: payload.len(),
+            sealed_with: Some(crc_extra),
+        })
+    }
+
+    /// The same message as a MAVLink 1 frame, for a peer that only speaks
+    /// MAVLink 1. The payload is zero-extended to the message's base length
+    /// and extension fields and any signature are dropped. Returns `None` for
+    /// ids that don't fit in a byte, messages not in the table (whose base
+    /// length is unknown) and corrupt frames.
+    pub fn to_v1(&self) -> Option<MavFrame> {
+        if self.version == MavVersion::V1 {
+            return Some(self.clone());
+        }
+        let msg_id = u8::try_from(self.msg_id()).ok()?;
+        let base_len = messages::lookup(self.msg_id())?.base_len as usize;
+        let crc_extra = self.sealing_crc_extra()?;
+
+        let mut payload = self.payload().to_vec();
+        payload.resize(base_len, 0);
+        let mut buf = Vec::with_capacity(MAVLINK_V1_HEADER_LEN + base_len + MAVLINK_CHECKSUM_LEN);
+        buf.extend_from_slice(&[
+            MAVLINK_STX_V1,
+            base_len as u8,
+            self.sequence(),
+            self.sys_id(),
+            self.comp_id(),
+            msg_id,
+        ]);
+        buf.extend_from_slice(&payload);
+        let crc = crc_accumulate(calculate_crc(&buf[1..]), crc_extra);
+        buf.extend_from_slice(&crc.to_le_bytes());
+
+        Some(MavFrame {
+            data: Bytes::from(buf),
+            version: MavVersion::V1,
+            payload_offset: MAVLINK_V1_HEADER_LEN,
+            payload_len: base_len,
+            sealed_with: Some(crc_extra),
+        })
+    }
+
+    /// The signature block (link id, timestamp, signature) of a signed
+    /// MAVLink 2 frame
+    pub fn signature(&self) -> Option<&[u8]> {
+        let crc_end = self.payload_offset + self.payload_len + MAVLINK_CHECKSUM_LEN;
+        (self.version == MavVersion::V2 && self.data[2] & MAVLINK_IFLAG_SIGNED != 0)
+            .then(|| &self.data[crc_end..])
+    }
+
+    /// Whether the frame is signed with `secret_key`; `None` if it isn't
+    /// signed at all
+    pub fn signature_valid(&self, secret_key: &[u8; 32]) -> Option<bool> {
+        let block = self.signature()?;
+        let signed_len = self.data.len() - SIGNATURE_HASH_LEN;
+        Some(signature_hash(secret_key, &self.data[..signed_len]) == block[7..])
+    }
+
+    /// Copy of this MAVLink 2 frame signed with `secret_key`, as from
+    /// `link_id` at `timestamp` (10 µs units since 2015-01-01). Returns `None`
+    /// for MAVLink 1 frames, which can't be signed, and corrupt ones.
+    pub fn signed(&self, secret_key: &[u8; 32], link_id: u8, timestamp: u64) -> Option<MavFrame> {
+        if self.version != MavVersion::V2 {
+            return None;
+        }
+        let unsigned = self.rebuild(|_, _| {})?;
+
+        let mut buf = unsigned.data.to_vec();
+        buf[2] |= MAVLINK_IFLAG_SIGNED;
+        // The flag is covered by the checksum
+        let crc_extra = unsigned.sealing_crc_extra()?;
+        let crc_offset = unsigned.payload_offset + unsigned.payload_len;
+        let crc = crc_accumulate(calculate_crc(&buf[1..crc_offset]), crc_extra);
+        buf[crc_offset..].copy_from_slice(&crc.to_le_bytes());
+
+        buf.push(link_id);
+        buf.extend_from_slice(&timestamp.to_le_bytes()[..6]);
+        let hash = signature_hash(secret_key, &buf);
+        buf.extend_from_slice(&hash);
+
+        Some(MavFrame {
+            data: Bytes::from(buf),
+            version: MavVersion::V2,
+            payload_offset: unsigned.payload_offset,
+            payload_len: unsigned.payload_len,
+            sealed_with: Some(crc_extra),
+        })
+    }
+
     #[inline]
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
@@ -175,16 +485,31 @@ impl MavFrame {
     }
 }
 
+/// Bytes of SHA-256 kept as a frame's signature
+const SIGNATURE_HASH_LEN: usize = 6;
+
+/// First 48 bits of SHA-256 over the secret key and the signed bytes
+fn signature_hash(secret_key: &[u8; 32], signed: &[u8]) -> [u8; SIGNATURE_HASH_LEN] {
+    let mut context = Context::new(&SHA256);
+    context.update(secret_key);
+    context.update(signed);
+    let mut hash = [0u8; SIGNATURE_HASH_LEN];
+    hash.copy_from_slice(&context.finish().as_ref()[..SIGNATURE_HASH_LEN]);
+    hash
+}
+
+const X25_CRC_TABLE: [u16; 256] = generate_crc_table();
+
 /// Fast CRC-16/MCRF4XX calculation for MAVLink
 fn calculate_crc(buf: &[u8]) -> u16 {
-    const X25_CRC_TABLE: [u16; 256] = generate_crc_table();
+    buf.iter()
+        .fold(0xFFFF, |crc, &byte| crc_accumulate(crc, byte))
+}
 
-    let mut crc: u16 = 0xFFFF;
-    for &byte in buf {
-        let tmp = byte ^ (crc as u8);
-        crc = (crc >> 8) ^ X25_CRC_TABLE[tmp as usize];
-    }
-    crc
+#[inline]
+fn crc_accumulate(crc: u16, byte: u8) -> u16 {
+    let tmp = byte ^ (crc as u8);
+    (crc >> 8) ^ X25_CRC_TABLE[tmp as usize]
 }
 
 const fn generate_crc_table() -> [u16; 256] {
@@ -233,6 +558,161 @@ mod tests {
         assert!(matches!(result, Err(ParseError::Incomplete(_, _))));
     }
 
+    /// Build a sealed v2 frame for tests
+    fn build_v2(sys_id: u8, msg_id: u32, payload: &[u8], crc_extra: u8) -> MavFrame {
+        let id = msg_id.to_le_bytes();
+        let mut buf = vec![
+            MAVLINK_STX_V2,
+            payload.len() as u8,
+            0,
+            0,
+            0,
+            sys_id,
+            1,
+            id[0],
+            id[1],
+            id[2],
+        ];
+        buf.extend_from_slice(payload);
+        let crc = crc_accumulate(calculate_crc(&buf[1..]), crc_extra);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        MavFrame::parse(&buf).unwrap().0
+    }
+
+    #[test]
+    fn test_crc_extra_recovery() {
+        let frame = build_v2(1, messages::HEARTBEAT, &[0; 9], 50);
+        assert_eq!(frame.crc_extra(), Some(50));
+    }
+
+    #[test]
+    fn test_with_sys_id() {
+        let frame = build_v2(1, messages::HEARTBEAT, &[0; 9], 50);
+        let rewritten = frame.with_sys_id(101).unwrap();
+        assert_eq!(rewritten.sys_id(), 101);
+        assert_eq!(rewritten.crc_extra(), Some(50));
+        assert_eq!(rewritten.payload(), frame.payload());
+    }
+
+    #[test]
+    fn test_rebuild_needs_valid_crc() {
+        // Sealed with a wrong CRC_EXTRA, as a corrupt frame may appear to be
+        let corrupt = build_v2(1, messages::HEARTBEAT, &[0; 9], 51);
+        assert_eq!(corrupt.crc_valid(), Some(false));
+        assert_eq!(corrupt.crc_extra(), Some(51));
+        assert!(corrupt.with_sys_id(101).is_none());
+        assert!(corrupt.to_v1().is_none());
+        assert!(corrupt.signed(&[0; 32], 0, 1).is_none());
+
+        // Nothing vouches for a received message outside the table...
+        let custom = build_v2(1, 60000, &[1, 2, 3], 7);
+        assert!(custom.with_sys_id(101).is_none());
+
+        // ...but one built here knows how it was sealed
+        let own = MavFrame::new_v2_with_crc_extra(0, 1, 1, 60000, 7, &[1, 2, 3]);
+        let rewritten = own.with_sys_id(101).unwrap();
+        assert_eq!(rewritten.crc_extra(), Some(7));
+        let signed = own.signed(&[0; 32], 0, 1).unwrap();
+        assert_eq!(signed.signature_valid(&[0; 32]), Some(true));
+    }
+
+    #[test]
+    fn test_component_ids() {
+        let payload = [0, 0, 0, 0, 0, 0, 0, 0, 255, 25];
+        let ack = MavFrame::new_v2(0, 1, 1, messages::COMMAND_ACK, &payload).unwrap();
+        assert_eq!(ack.target_component(), Some(25));
+        let ack = ack.with_target_component(190).unwrap();
+        assert_eq!(ack.target_component(), Some(190));
+        assert_eq!(ack.target_system(), Some(255));
+
+        let heartbeat = build_v2(1, messages::HEARTBEAT, &[0; 9], 50);
+        assert_eq!(heartbeat.target_component(), None);
+        assert_eq!(heartbeat.with_comp_id(25).unwrap().comp_id(), 25);
+    }
+
+    #[test]
+    fn test_new_v2_truncates_payload() {
+        let payload = [0, 0, 0, 0, 6, 8, 0, 0, 3];
+        let frame = MavFrame::new_v2(7, 255, 190, messages::HEARTBEAT, &payload).unwrap();
+        let (parsed, consumed) = MavFrame::parse(frame.as_bytes()).unwrap();
+        assert_eq!(consumed, frame.len());
+        assert_eq!(parsed.payload().len(), 9);
+        assert_eq!(parsed.sequence(), 7);
+        assert_eq!(parsed.crc_extra(), Some(50));
+
+        let truncated = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[1, 0, 0]).unwrap();
+        assert_eq!(truncated.payload(), &[1]);
+    }
+
+    #[test]
+    fn test_length_matches() {
+        // Truncated and full-length MAVLink 2 payloads both fit
+        assert_eq!(
+            build_v2(1, messages::HEARTBEAT, &[1], 50).length_matches(),
+            Some(true)
+        );
+        assert_eq!(
+            build_v2(1, messages::HEARTBEAT, &[1; 9], 50).length_matches(),
+            Some(true)
+        );
+        // So do extension fields, but nothing past them
+        let ack = build_v2(1, messages::COMMAND_ACK, &[1; 10], 143);
+        assert_eq!(ack.length_matches(), Some(true));
+        let long = build_v2(1, messages::HEARTBEAT, &[1; 12], 50);
+        assert_eq!(long.length_matches(), Some(false));
+        // A checksum sealed with the wrong CRC_EXTRA proves nothing
+        assert_eq!(
+            build_v2(1, messages::HEARTBEAT, &[1; 12], 7).length_matches(),
+            None
+        );
+        assert_eq!(build_v2(1, 12345, &[1; 12], 7).length_matches(), None);
+    }
+
+    #[test]
+    fn test_with_sequence_and_payload_byte() {
+        let frame = build_v2(1, messages::HEARTBEAT, &[3; 9], 50);
+        let rewritten = frame
+            .with_payload_byte(7, 5)
+            .and_then(|f| f.with_sequence(42))
+            .unwrap();
+        assert_eq!(rewritten.sequence(), 42);
+        assert_eq!(rewritten.payload()[7], 5);
+        assert_eq!(rewritten.crc_extra(), Some(50));
+    }
+
+    #[test]
+    fn test_with_target_system_truncated() {
+        // COMMAND_LONG truncated to 29 bytes: target_system (offset 30) is implicit zero
+        let frame = build_v2(255, messages::COMMAND_LONG, &[1; 29], 152);
+        assert_eq!(frame.target_system(), Some(0));
+
+        let rewritten = frame.with_target_system(7).unwrap();
+        assert_eq!(rewritten.target_system(), Some(7));
+        assert_eq!(rewritten.payload().len(), 31);
+        assert_eq!(rewritten.crc_extra(), Some(152));
+    }
+
+    #[test]
+    fn test_to_v1() {
+        // Truncated to one byte on the wire, back to 9 as MAVLink 1
+        let mut payload = [0u8; 9];
+        payload[0] = 4;
+        let heartbeat = MavFrame::new_v2(3, 1, 1, messages::HEARTBEAT, &payload).unwrap();
+        let v1 = heartbeat.to_v1().unwrap();
+        assert_eq!(v1.version(), MavVersion::V1);
+        assert_eq!(v1.payload(), &payload[..]);
+        assert_eq!(
+            (v1.sequence(), v1.sys_id(), v1.msg_id()),
+            (3, 1, messages::HEARTBEAT)
+        );
+        assert_eq!(v1.crc_valid(), Some(true));
+        assert_eq!(v1.length_matches(), Some(true));
+
+        // No room for a 24-bit message id
+        let custom = MavFrame::new_v2_with_crc_extra(0, 1, 1, 59100, 7, &[1]);
+        assert!(custom.to_v1().is_none());
+    }
+
     #[test]
     fn test_invalid_magic() {
         let bad_buf = [0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
@@ -1,9 +1,16 @@
+use crate::connection::ConnectionId;
+use crate::mavlink::decode;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::time::interval;
 use tracing::{info, warn};
 
+/// Forward jumps in the sequence number larger than this are taken as a
+/// sender restart or reordering rather than loss
+const MAX_SEQ_GAP: u8 = 128;
+
 /// Global metrics for the router
 #[derive(Debug, Clone)]
 pub struct Metrics {
@@ -13,8 +20,52 @@ pub struct Metrics {
     pub messages_received: Arc<AtomicU64>,
     /// Total messages dropped (backpressure)
     pub messages_dropped: Arc<AtomicU64>,
+    /// Total messages shed by the global queue cap
+    pub messages_shed: Arc<AtomicU64>,
+    /// Total messages that had no eligible destination
+    pub frames_unrouted: Arc<AtomicU64>,
+    /// Total messages held back from degraded or congested links
+    pub frames_throttled: Arc<AtomicU64>,
+    /// Total copies of a frame not sent to a link that had already had it
+    pub frames_deduplicated: Arc<AtomicU64>,
+    /// Total messages rejected for being outside their link's dialect
+    pub frames_rejected: Arc<AtomicU64>,
+    /// Total messages dropped for waiting too long for the router
+    pub frames_stale: Arc<AtomicU64>,
+    /// Total frames dropped because the router's input queue was full
+    pub router_full: Arc<AtomicU64>,
+    /// Total commands refused for vehicles with no recent HEARTBEAT
+    pub commands_interlocked: Arc<AtomicU64>,
+    /// Total messages dropped for a length that doesn't fit their definition
+    pub frames_bad_length: Arc<AtomicU64>,
+    /// Total PARAM_VALUEs answered from the parameter cache
+    pub params_served: Arc<AtomicU64>,
+    /// Total STATUSTEXTs not sent to links wanting only more severe ones
+    pub statustext_filtered: Arc<AtomicU64>,
+    /// Total boot-burst messages held back from links
+    pub boot_suppressed: Arc<AtomicU64>,
+    /// Total frames dropped for going over their link's byte budget
+    pub over_budget: Arc<AtomicU64>,
+    /// Total frames dropped for not being signed with a valid key
+    pub frames_bad_signature: Arc<AtomicU64>,
+    /// Total frames dropped for a sysid that doesn't fit its namespace
+    pub frames_out_of_namespace: Arc<AtomicU64>,
+    /// Total state messages superseded by a newer one before being sent
+    pub frames_conflated: Arc<AtomicU64>,
+    /// Total MAVLink 2 frames not sent to peers yet to show they speak it
+    pub frames_v2_withheld: Arc<AtomicU64>,
     /// Total bytes routed
     pub bytes_routed: Arc<AtomicU64>,
+    /// Bytes currently queued across all connections
+    pub bytes_queued: Arc<AtomicU64>,
+    /// Sequence tracking per vehicle (sysid, compid), for loss estimates
+    pub sequences: Arc<Mutex<HashMap<(u8, u8), SequenceStats>>>,
+    /// Traffic per connection, when tracked
+    pub connections: Option<Arc<Mutex<HashMap<ConnectionId, ConnectionStats>>>>,
+    /// Clock of each vehicle against the host's, when tracked
+    pub clocks: Option<Arc<Mutex<HashMap<u8, ClockSkew>>>>,
+    /// Skew beyond which a vehicle's clock is warned about
+    clock_skew_warn_ms: u64,
     /// Start time for calculating uptime
     pub start_time: Instant,
 }
@@ -31,11 +82,47 @@ impl Metrics {
             messages_routed: Arc::new(AtomicU64::new(0)),
             messages_received: Arc::new(AtomicU64::new(0)),
             messages_dropped: Arc::new(AtomicU64::new(0)),
+            messages_shed: Arc::new(AtomicU64::new(0)),
+            frames_unrouted: Arc::new(AtomicU64::new(0)),
+            frames_throttled: Arc::new(AtomicU64::new(0)),
+            frames_deduplicated: Arc::new(AtomicU64::new(0)),
+            frames_rejected: Arc::new(AtomicU64::new(0)),
+            frames_stale: Arc::new(AtomicU64::new(0)),
+            router_full: Arc::new(AtomicU64::new(0)),
+            commands_interlocked: Arc::new(AtomicU64::new(0)),
+            frames_bad_length: Arc::new(AtomicU64::new(0)),
+            params_served: Arc::new(AtomicU64::new(0)),
+            statustext_filtered: Arc::new(AtomicU64::new(0)),
+            boot_suppressed: Arc::new(AtomicU64::new(0)),
+            over_budget: Arc::new(AtomicU64::new(0)),
+            frames_conflated: Arc::new(AtomicU64::new(0)),
+            frames_v2_withheld: Arc::new(AtomicU64::new(0)),
+            frames_bad_signature: Arc::new(AtomicU64::new(0)),
+            frames_out_of_namespace: Arc::new(AtomicU64::new(0)),
             bytes_routed: Arc::new(AtomicU64::new(0)),
+            bytes_queued: Arc::new(AtomicU64::new(0)),
+            sequences: Arc::new(Mutex::new(HashMap::new())),
+            connections: None,
+            clocks: None,
+            clock_skew_warn_ms: 0,
             start_time: Instant::now(),
         }
     }
 
+    /// Also keep traffic counts for each connection, per session and in total
+    pub fn with_connection_stats(mut self, enabled: bool) -> Self {
+        self.connections = enabled.then(|| Arc::new(Mutex::new(HashMap::new())));
+        self
+    }
+
+    /// Also compare vehicles' SYSTEM_TIME with the host clock, warning
+    /// beyond `warn_ms` of skew
+    pub fn with_clock_skew(mut self, warn_ms: Option<u64>) -> Self {
+        self.clocks = warn_ms.map(|_| Arc::new(Mutex::new(HashMap::new())));
+        self.clock_skew_warn_ms = warn_ms.unwrap_or(0);
+        self
+    }
+
     pub fn record_received(&self) {
         self.messages_received.fetch_add(1, Ordering::Relaxed);
     }
@@ -50,18 +137,226 @@ impl Metrics {
         warn!("Message dropped due to backpressure!");
     }
 
+    pub fn record_shed(&self) {
+        self.messages_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_unrouted(&self) {
+        self.frames_unrouted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_throttled(&self) {
+        self.frames_throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_deduplicated(&self) {
+        self.frames_deduplicated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected(&self) {
+        self.frames_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_stale(&self) {
+        self.frames_stale.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_router_full(&self) {
+        self.router_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_interlocked(&self) {
+        self.commands_interlocked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bad_length(&self) {
+        self.frames_bad_length.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_params_served(&self, count: usize) {
+        self.params_served
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_statustext_filtered(&self) {
+        self.statustext_filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_boot_suppressed(&self) {
+        self.boot_suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_over_budget(&self) {
+        self.over_budget.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bad_signature(&self) {
+        self.frames_bad_signature.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_out_of_namespace(&self) {
+        self.frames_out_of_namespace.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_conflated(&self) {
+        self.frames_conflated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_v2_withheld(&self) {
+        self.frames_v2_withheld.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_bytes_queued(&self, bytes: usize) {
+        self.bytes_queued.store(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// A connection's link (re)opened: its session counts start over
+    pub fn start_session(&self, conn_id: ConnectionId) {
+        if let Some(connections) = &self.connections {
+            let mut connections = connections.lock().unwrap();
+            connections.entry(conn_id).or_default().start_session();
+        }
+    }
+
+    /// Forget a connection that won't be back
+    pub fn forget_connection(&self, conn_id: ConnectionId) {
+        if let Some(connections) = &self.connections {
+            connections.lock().unwrap().remove(&conn_id);
+        }
+    }
+
+    /// A frame read from a connection
+    pub fn record_connection_rx(&self, conn_id: ConnectionId, bytes: usize) {
+        if let Some(connections) = &self.connections {
+            let mut connections = connections.lock().unwrap();
+            let stats = connections.entry(conn_id).or_default();
+            stats.total.add_rx(bytes);
+            stats.session.add_rx(bytes);
+        }
+    }
+
+    /// A frame queued for a connection
+    pub fn record_connection_tx(&self, conn_id: ConnectionId, bytes: usize) {
+        if let Some(connections) = &self.connections {
+            let mut connections = connections.lock().unwrap();
+            let stats = connections.entry(conn_id).or_default();
+            stats.total.add_tx(bytes);
+            stats.session.add_tx(bytes);
+        }
+    }
+
+    /// Per-connection traffic, sorted by connection name; empty when not
+    /// tracked
+    pub fn connection_stats(&self) -> Vec<(String, ConnectionStats)> {
+        let Some(connections) = &self.connections else {
+            return Vec::new();
+        };
+        let mut stats: Vec<_> = connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, stats)| (id.to_string(), stats.clone()))
+            .collect();
+        stats.sort_by(|(a, _), (b, _)| a.cmp(b));
+        stats
+    }
+
+    pub fn record_sequence(&self, sysid: u8, compid: u8, seq: u8) {
+        let mut sequences = self.sequences.lock().unwrap();
+        sequences.entry((sysid, compid)).or_default().record(seq);
+    }
+
+    /// A SYSTEM_TIME from vehicle `sysid`, when clocks are tracked
+    pub fn record_system_time(&self, sysid: u8, time: &decode::SystemTime) {
+        let Some(clocks) = &self.clocks else {
+            return;
+        };
+        let mut clocks = clocks.lock().unwrap();
+        let clock = clocks.entry(sysid).or_default();
+        let was_skewed = clock.skewed(self.clock_skew_warn_ms);
+        clock.record(time, SystemTime::now());
+        match (
+            clock.skew_ms,
+            was_skewed,
+            clock.skewed(self.clock_skew_warn_ms),
+        ) {
+            (Some(skew), false, true) => warn!(
+                "Vehicle {} clock is {} ms {} the host's",
+                sysid,
+                skew.abs(),
+                if skew > 0 { "ahead of" } else { "behind" }
+            ),
+            (Some(skew), true, false) => info!(
+                "Vehicle {} clock is back within {} ms of the host's ({:+} ms)",
+                sysid, self.clock_skew_warn_ms, skew
+            ),
+            _ => {}
+        }
+    }
+
+    /// Per-vehicle clock skew, sorted by sysid; empty when not tracked
+    pub fn clock_stats(&self) -> Vec<(u8, ClockSkew)> {
+        let Some(clocks) = &self.clocks else {
+            return Vec::new();
+        };
+        let mut stats: Vec<_> = clocks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(sysid, clock)| (*sysid, clock.clone()))
+            .collect();
+        stats.sort_by_key(|(sysid, _)| *sysid);
+        stats
+    }
+
+    /// Per-vehicle sequence stats, sorted by (sysid, compid)
+    pub fn sequence_stats(&self) -> Vec<((u8, u8), SequenceStats)> {
+        let sequences = self.sequences.lock().unwrap();
+        let mut stats: Vec<_> = sequences.iter().map(|(k, v)| (*k, v.clone())).collect();
+        stats.sort_by_key(|(k, _)| *k);
+        stats
+    }
+
+    /// Like `sequence_stats`, but starts a new interval for each vehicle
+    fn take_sequence_stats(&self) -> Vec<((u8, u8), SequenceStats)> {
+        let stats = self.sequence_stats();
+        for stream in self.sequences.lock().unwrap().values_mut() {
+            stream.received = 0;
+            stream.lost = 0;
+        }
+        stats
+    }
+
     pub fn get_stats(&self) -> MetricsSnapshot {
         MetricsSnapshot {
             messages_received: self.messages_received.load(Ordering::Relaxed),
             messages_routed: self.messages_routed.load(Ordering::Relaxed),
             messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            messages_shed: self.messages_shed.load(Ordering::Relaxed),
+            frames_unrouted: self.frames_unrouted.load(Ordering::Relaxed),
+            frames_throttled: self.frames_throttled.load(Ordering::Relaxed),
+            frames_deduplicated: self.frames_deduplicated.load(Ordering::Relaxed),
+            frames_rejected: self.frames_rejected.load(Ordering::Relaxed),
+            frames_stale: self.frames_stale.load(Ordering::Relaxed),
+            router_full: self.router_full.load(Ordering::Relaxed),
+            commands_interlocked: self.commands_interlocked.load(Ordering::Relaxed),
+            frames_bad_length: self.frames_bad_length.load(Ordering::Relaxed),
+            params_served: self.params_served.load(Ordering::Relaxed),
+            statustext_filtered: self.statustext_filtered.load(Ordering::Relaxed),
+            boot_suppressed: self.boot_suppressed.load(Ordering::Relaxed),
+            over_budget: self.over_budget.load(Ordering::Relaxed),
+            frames_conflated: self.frames_conflated.load(Ordering::Relaxed),
+            frames_v2_withheld: self.frames_v2_withheld.load(Ordering::Relaxed),
+            frames_bad_signature: self.frames_bad_signature.load(Ordering::Relaxed),
+            frames_out_of_namespace: self.frames_out_of_namespace.load(Ordering::Relaxed),
             bytes_routed: self.bytes_routed.load(Ordering::Relaxed),
+            bytes_queued: self.bytes_queued.load(Ordering::Relaxed),
             uptime: self.start_time.elapsed(),
         }
     }
 
     /// Start a background task that logs stats periodically
-    pub fn start_stats_logger(self, interval_secs: u64) {
+    pub fn start_stats_logger(self, interval_secs: u64, loss_warn_percent: f64) {
         tokio::spawn(async move {
             let mut interval = interval(Duration::from_secs(interval_secs));
             let mut last_stats = self.get_stats();
@@ -89,12 +384,149 @@ impl Metrics {
                     delta.messages_per_sec, delta.kbytes_per_sec
                 );
                 info!("  Total data: {:.2} MB", delta.total_mb);
+                info!(
+                    "  Queued: {:.1} KB across connections",
+                    current_stats.bytes_queued as f64 / 1024.0
+                );
 
-                if current_stats.messages_dropped > last_stats.messages_dropped {
+                for (name, conn) in self.connection_stats() {
+                    info!(
+                        "  {}: {:.2} MB total, {:.2} MB this session ({}, up {}s)",
+                        name,
+                        conn.total.bytes() as f64 / 1024.0 / 1024.0,
+                        conn.session.bytes() as f64 / 1024.0 / 1024.0,
+                        match conn.sessions {
+                            1 => "1 session".to_string(),
+                            n => format!("{} sessions", n),
+                        },
+                        conn.session_age().as_secs()
+                    );
+                }
+
+                for ((sysid, compid), stream) in self.take_sequence_stats() {
+                    if stream.received == 0 {
+                        continue;
+                    }
+                    let loss = stream.loss_percent();
+                    if loss >= loss_warn_percent {
+                        warn!(
+                            "  ⚠ Vehicle {}/{}: {:.1}% packet loss ({} lost, {} received)",
+                            sysid, compid, loss, stream.lost, stream.received
+                        );
+                    } else {
+                        info!(
+                            "  Vehicle {}/{}: {:.1}% packet loss ({} lost, {} received)",
+                            sysid, compid, loss, stream.lost, stream.received
+                        );
+                    }
+                }
+
+                for (sysid, clock) in self.clock_stats() {
+                    match clock.skew_ms {
+                        Some(skew) if clock.skewed(self.clock_skew_warn_ms) => {
+                            warn!("  ⚠ Vehicle {}: clock {:+} ms from the host", sysid, skew)
+                        }
+                        Some(skew) => {
+                            info!("  Vehicle {}: clock {:+} ms from the host", sysid, skew)
+                        }
+                        None => info!(
+                            "  Vehicle {}: no wall-clock time yet (up {}s)",
+                            sysid,
+                            clock.time_boot_ms / 1000
+                        ),
+                    }
+                }
+
+                // How much a counter went up since the last report
+                let rise = |count: fn(&MetricsSnapshot) -> u64| {
+                    count(&current_stats).saturating_sub(count(&last_stats))
+                };
+                let noted = [
+                    ("messages had no destination", rise(|s| s.frames_unrouted)),
+                    (
+                        "messages throttled on degraded or congested links",
+                        rise(|s| s.frames_throttled),
+                    ),
+                    (
+                        "duplicate messages suppressed",
+                        rise(|s| s.frames_deduplicated),
+                    ),
+                    ("parameters served from cache", rise(|s| s.params_served)),
+                    (
+                        "low-severity STATUSTEXTs filtered",
+                        rise(|s| s.statustext_filtered),
+                    ),
+                    ("boot-burst messages held back", rise(|s| s.boot_suppressed)),
+                    (
+                        "frames over their share of a link's bytes dropped",
+                        rise(|s| s.over_budget),
+                    ),
+                    (
+                        "state messages superseded before sending",
+                        rise(|s| s.frames_conflated),
+                    ),
+                    (
+                        "MAVLink 2 frames dropped for peers not yet sending it",
+                        rise(|s| s.frames_v2_withheld),
+                    ),
+                ];
+                for (label, count) in noted.into_iter().filter(|&(_, count)| count > 0) {
+                    info!("  {} {} in last {} seconds", count, label, interval_secs);
+                }
+
+                let warned = [
+                    (
+                        "frames without a valid signature rejected",
+                        rise(|s| s.frames_bad_signature),
+                    ),
+                    (
+                        "messages with a sysid outside their namespace dropped",
+                        rise(|s| s.frames_out_of_namespace),
+                    ),
+                    (
+                        "messages outside their link's dialect rejected",
+                        rise(|s| s.frames_rejected),
+                    ),
+                    (
+                        "messages with a bad payload length dropped",
+                        rise(|s| s.frames_bad_length),
+                    ),
+                    (
+                        "commands for vehicles with no recent HEARTBEAT dropped",
+                        rise(|s| s.commands_interlocked),
+                    ),
+                ];
+                for (label, count) in warned.into_iter().filter(|&(_, count)| count > 0) {
+                    warn!("  {} {} in last {} seconds", count, label, interval_secs);
+                }
+
+                // Frames lost to an overloaded router or overfull queues
+                let alarms = [
+                    (
+                        "messages dropped",
+                        "ROUTER BEHIND",
+                        rise(|s| s.frames_stale),
+                    ),
+                    (
+                        "frames dropped",
+                        "ROUTER INPUT FULL",
+                        rise(|s| s.router_full),
+                    ),
+                    (
+                        "messages shed",
+                        "QUEUE CAP REACHED",
+                        rise(|s| s.messages_shed),
+                    ),
+                    (
+                        "messages dropped",
+                        "BACKPRESSURE DETECTED",
+                        rise(|s| s.messages_dropped),
+                    ),
+                ];
+                for (label, cause, count) in alarms.into_iter().filter(|&(.., count)| count > 0) {
                     warn!(
-                        "  ⚠ {} messages dropped in last {} seconds (BACKPRESSURE DETECTED)",
-                        current_stats.messages_dropped - last_stats.messages_dropped,
-                        interval_secs
+                        "  ⚠ {} {} in last {} seconds ({})",
+                        count, label, interval_secs, cause
                     );
                 }
 
@@ -104,18 +536,168 @@ impl Metrics {
     }
 }
 
+/// Frames and bytes through a connection, each way
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Traffic {
+    pub frames_rx: u64,
+    pub bytes_rx: u64,
+    pub frames_tx: u64,
+    pub bytes_tx: u64,
+}
+
+impl Traffic {
+    fn add_rx(&mut self, bytes: usize) {
+        self.frames_rx += 1;
+        self.bytes_rx += bytes as u64;
+    }
+
+    fn add_tx(&mut self, bytes: usize) {
+        self.frames_tx += 1;
+        self.bytes_tx += bytes as u64;
+    }
+
+    /// Bytes moved either way
+    pub fn bytes(&self) -> u64 {
+        self.bytes_rx + self.bytes_tx
+    }
+}
+
+/// Traffic through one connection since startup, and since its link last
+/// (re)opened
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    /// Times the link has opened
+    pub sessions: u32,
+    pub total: Traffic,
+    pub session: Traffic,
+    session_start: Option<Instant>,
+}
+
+impl ConnectionStats {
+    fn start_session(&mut self) {
+        self.sessions += 1;
+        self.session = Traffic::default();
+        self.session_start = Some(Instant::now());
+    }
+
+    /// How long the current session has lasted
+    pub fn session_age(&self) -> Duration {
+        self.session_start
+            .map_or(Duration::ZERO, |start| start.elapsed())
+    }
+}
+
+/// A vehicle's clock against the host's, from its latest SYSTEM_TIME
+#[derive(Debug, Clone, Default)]
+pub struct ClockSkew {
+    /// Vehicle time minus host time on arrival, including the link's
+    /// latency; `None` while the vehicle has no wall-clock time
+    pub skew_ms: Option<i64>,
+    /// The vehicle's time since boot
+    pub time_boot_ms: u32,
+    /// When the SYSTEM_TIME arrived
+    pub updated: Option<Instant>,
+}
+
+impl ClockSkew {
+    fn record(&mut self, time: &decode::SystemTime, host: SystemTime) {
+        let host_ms = host
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        self.skew_ms =
+            (time.time_unix_usec != 0).then(|| (time.time_unix_usec / 1000) as i64 - host_ms);
+        self.time_boot_ms = time.time_boot_ms;
+        self.updated = Some(Instant::now());
+    }
+
+    /// Whether the vehicle's clock is off by more than `warn_ms`
+    pub fn skewed(&self, warn_ms: u64) -> bool {
+        self.skew_ms
+            .is_some_and(|skew| skew.unsigned_abs() > warn_ms)
+    }
+}
+
+/// Loss estimate for one (sysid, compid) stream, from gaps in its sequence
+/// numbers
+#[derive(Debug, Clone, Default)]
+pub struct SequenceStats {
+    last_seq: Option<u8>,
+    /// Frames received in the current stats interval
+    pub received: u64,
+    /// Frames missed in the current stats interval
+    pub lost: u64,
+    pub total_received: u64,
+    pub total_lost: u64,
+}
+
+impl SequenceStats {
+    pub fn record(&mut self, seq: u8) {
+        if let Some(last) = self.last_seq {
+            // Wrapping arithmetic handles the 255 -> 0 rollover; a repeat or
+            // step backwards wraps to a large gap and is ignored
+            let gap = seq.wrapping_sub(last).wrapping_sub(1);
+            if gap < MAX_SEQ_GAP {
+                self.lost += gap as u64;
+                self.total_lost += gap as u64;
+            }
+        }
+        self.last_seq = Some(seq);
+        self.received += 1;
+        self.total_received += 1;
+    }
+
+    /// Loss over the current interval, in percent
+    pub fn loss_percent(&self) -> f64 {
+        loss_percent(self.received, self.lost)
+    }
+
+    /// Loss since the vehicle was first seen, in percent
+    pub fn total_loss_percent(&self) -> f64 {
+        loss_percent(self.total_received, self.total_lost)
+    }
+}
+
+fn loss_percent(received: u64, lost: u64) -> f64 {
+    let expected = received + lost;
+    if expected == 0 {
+        0.0
+    } else {
+        lost as f64 * 100.0 / expected as f64
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MetricsSnapshot {
     pub messages_received: u64,
     pub messages_routed: u64,
     pub messages_dropped: u64,
+    pub messages_shed: u64,
+    pub frames_unrouted: u64,
+    pub frames_throttled: u64,
+    pub frames_deduplicated: u64,
+    pub frames_rejected: u64,
+    pub frames_stale: u64,
+    pub router_full: u64,
+    pub commands_interlocked: u64,
+    pub frames_bad_length: u64,
+    pub params_served: u64,
+    pub statustext_filtered: u64,
+    pub boot_suppressed: u64,
+    pub over_budget: u64,
+    pub frames_conflated: u64,
+    pub frames_v2_withheld: u64,
+    pub frames_bad_signature: u64,
+    pub frames_out_of_namespace: u64,
     pub bytes_routed: u64,
+    pub bytes_queued: u64,
     pub uptime: Duration,
 }
 
 impl MetricsSnapshot {
     pub fn delta(&self, previous: &MetricsSnapshot, interval_secs: u64) -> MetricsDelta {
-        let messages_diff = self.messages_routed.saturating_sub(previous.messages_routed);
+        let messages_diff = self
+            .messages_routed
+            .saturating_sub(previous.messages_routed);
         let bytes_diff = self.bytes_routed.saturating_sub(previous.bytes_routed);
 
         MetricsDelta {
@@ -132,3 +714,75 @@ pub struct MetricsDelta {
     pub kbytes_per_sec: f64,
     pub total_mb: f64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_skew() {
+        let host = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut clock = ClockSkew::default();
+
+        // No GPS time yet
+        clock.record(
+            &decode::SystemTime {
+                time_unix_usec: 0,
+                time_boot_ms: 5000,
+            },
+            host,
+        );
+        assert_eq!(clock.skew_ms, None);
+        assert!(!clock.skewed(1000));
+
+        clock.record(
+            &decode::SystemTime {
+                time_unix_usec: 1_699_999_998_500_000,
+                time_boot_ms: 6000,
+            },
+            host,
+        );
+        assert_eq!(clock.skew_ms, Some(-1500));
+        assert!(clock.skewed(1000));
+        assert!(!clock.skewed(2000));
+    }
+
+    #[test]
+    fn test_sequence_loss_across_wraparound() {
+        let mut stream = SequenceStats::default();
+        for seq in [253, 254, 255, 0, 3, 4] {
+            stream.record(seq);
+        }
+        // 1 and 2 missing after the rollover
+        assert_eq!(stream.received, 6);
+        assert_eq!(stream.lost, 2);
+        assert!((stream.loss_percent() - 25.0).abs() < 1e-9);
+
+        // Repeats and reordering aren't counted as loss
+        stream.record(4);
+        stream.record(3);
+        assert_eq!(stream.total_lost, 2);
+    }
+
+    #[test]
+    fn test_connection_sessions() {
+        let metrics = Metrics::new().with_connection_stats(true);
+        let uart = ConnectionId::new_uart(0);
+        metrics.start_session(uart);
+        metrics.record_connection_rx(uart, 100);
+        metrics.record_connection_tx(uart, 50);
+
+        // Reconnecting starts the session counts over, not the totals
+        metrics.start_session(uart);
+        metrics.record_connection_rx(uart, 10);
+        let stats = metrics.connection_stats();
+        let (name, uart) = &stats[0];
+        assert_eq!(name, "UART-0");
+        assert_eq!(uart.sessions, 2);
+        assert_eq!(uart.total.bytes(), 160);
+        assert_eq!(uart.session.bytes(), 10);
+        assert_eq!(uart.session.frames_rx, 1);
+
+        assert!(Metrics::new().connection_stats().is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::mavlink::decode::{ParamRequestList, ParamRequestRead, ParamSet, ParamValue};
+use crate::mavlink::{messages, MavFrame};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Parameters seen from each vehicle component, by (sysid, compid)
+#[derive(Debug)]
+pub struct ParamCache {
+    components: HashMap<(u8, u8), Params>,
+    /// How long a value stays fresh, if not forever
+    ttl: Option<Duration>,
+    /// Whether PARAM_REQUEST_READ is answered
+    reads: bool,
+}
+
+#[derive(Debug, Default)]
+struct Params {
+    /// How many parameters the component says it has
+    count: u16,
+    /// Its PARAM_VALUEs, by index, and when each arrived
+    values: BTreeMap<u16, (MavFrame, Instant)>,
+    indexes: HashMap<String, u16>,
+}
+
+impl Params {
+    fn is_complete(&self) -> bool {
+        self.count > 0 && self.values.len() == self.count as usize
+    }
+}
+
+impl ParamCache {
+    pub fn new(ttl: Option<Duration>, reads: bool) -> Self {
+        Self {
+            components: HashMap::new(),
+            ttl,
+            reads,
+        }
+    }
+
+    fn is_fresh(&self, received: Instant, now: Instant) -> bool {
+        self.ttl
+            .is_none_or(|ttl| now.duration_since(received) < ttl)
+    }
+
+    /// Keep a PARAM_VALUE from a vehicle. A change in the parameter count
+    /// means the set itself changed, so everything else is dropped.
+    pub fn store(&mut self, frame: &MavFrame, now: Instant) {
+        let Some(value) = ParamValue::decode(frame) else {
+            return;
+        };
+        let params = self
+            .components
+            .entry((frame.sys_id(), frame.comp_id()))
+            .or_default();
+        if params.count != value.param_count {
+            *params = Params {
+                count: value.param_count,
+                ..Default::default()
+            };
+        }
+
+        // Some autopilots answer a PARAM_SET without the index
+        if value.param_index >= value.param_count {
+            if let Some(index) = params.indexes.get(&value.param_id) {
+                params.values.remove(index);
+            }
+            return;
+        }
+        params.indexes.insert(value.param_id, value.param_index);
+        params
+            .values
+            .insert(value.param_index, (frame.clone(), now));
+    }
+
+    /// Forget the value a PARAM_SET is about to change
+    pub fn invalidate(&mut self, frame: &MavFrame) {
+        let Some(set) = ParamSet::decode(frame) else {
+            return;
+        };
+        let Some(params) = self.resolve(set.target_system, set.target_component) else {
+            return;
+        };
+        if let Some(index) = params.indexes.get(&set.param_id).copied() {
+            params.values.remove(&index);
+        }
+    }
+
+    /// The PARAM_VALUEs answering a GCS request, if the cache can answer it
+    /// with fresh values
+    pub fn answer(&mut self, frame: &MavFrame, now: Instant) -> Option<Vec<MavFrame>> {
+        let values: Vec<(MavFrame, Instant)> = match frame.msg_id() {
+            messages::PARAM_REQUEST_LIST => {
+                let request = ParamRequestList::decode(frame)?;
+                let params = self.resolve(request.target_system, request.target_component)?;
+                if !params.is_complete() {
+                    return None;
+                }
+                params.values.values().cloned().collect()
+            }
+            messages::PARAM_REQUEST_READ if self.reads => {
+                let request = ParamRequestRead::decode(frame)?;
+                let params = self.resolve(request.target_system, request.target_component)?;
+                let index = match u16::try_from(request.param_index) {
+                    Ok(index) => index,
+                    // -1 reads by name
+                    Err(_) => *params.indexes.get(&request.param_id)?,
+                };
+                vec![params.values.get(&index)?.clone()]
+            }
+            _ => return None,
+        };
+
+        // One stale value and the vehicle is asked instead
+        if !values
+            .iter()
+            .all(|&(_, received)| self.is_fresh(received, now))
+        {
+            return None;
+        }
+        Some(values.into_iter().map(|(frame, _)| frame).collect())
+    }
+
+    /// Drop everything cached for a vehicle
+    pub fn forget(&mut self, sysid: u8) {
+        self.components.retain(|&(id, _), _| id != sysid);
+    }
+
+    /// The component a request is for. Compid 0 means any, which is only
+    /// answerable when a single component of the system has parameters.
+    fn resolve(&mut self, sysid: u8, compid: u8) -> Option<&mut Params> {
+        let key = if compid != 0 {
+            (sysid, compid)
+        } else {
+            let mut keys = self.components.keys().filter(|(id, _)| *id == sysid);
+            let key = *keys.next()?;
+            if keys.next().is_some() {
+                return None;
+            }
+            key
+        };
+        self.components.get_mut(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param_value(name: &str, count: u16, index: u16) -> MavFrame {
+        let mut payload = [0u8; 25];
+        payload[4..6].copy_from_slice(&count.to_le_bytes());
+        payload[6..8].copy_from_slice(&index.to_le_bytes());
+        payload[8..8 + name.len()].copy_from_slice(name.as_bytes());
+        MavFrame::new_v2(index as u8, 1, 1, messages::PARAM_VALUE, &payload).unwrap()
+    }
+
+    fn read_by_name(name: &str) -> MavFrame {
+        let mut payload = [0u8; 20];
+        payload[0..2].copy_from_slice(&(-1i16).to_le_bytes());
+        payload[2] = 1;
+        payload[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        MavFrame::new_v2(0, 255, 190, messages::PARAM_REQUEST_READ, &payload).unwrap()
+    }
+
+    #[test]
+    fn test_answers_once_complete() {
+        let mut cache = ParamCache::new(Some(Duration::from_secs(60)), true);
+        let now = Instant::now();
+        let list = MavFrame::new_v2(0, 255, 190, messages::PARAM_REQUEST_LIST, &[1, 0]).unwrap();
+
+        cache.store(&param_value("SYSID_THISMAV", 2, 0), now);
+        assert!(cache.answer(&list, now).is_none());
+        assert_eq!(
+            cache
+                .answer(&read_by_name("SYSID_THISMAV"), now)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        cache.store(&param_value("BATT_CAPACITY", 2, 1), now);
+        let answer = cache.answer(&list, now).unwrap();
+        assert_eq!(answer.len(), 2);
+        assert_eq!(
+            answer[1].as_bytes(),
+            param_value("BATT_CAPACITY", 2, 1).as_bytes()
+        );
+
+        // Setting a parameter leaves it unknown until the vehicle reports it
+        let mut set = [0u8; 23];
+        set[4] = 1;
+        set[6..6 + 13].copy_from_slice(b"BATT_CAPACITY");
+        cache.invalidate(&MavFrame::new_v2(0, 255, 190, messages::PARAM_SET, &set).unwrap());
+        assert!(cache.answer(&list, now).is_none());
+        assert!(cache.answer(&read_by_name("BATT_CAPACITY"), now).is_none());
+        cache.store(&param_value("BATT_CAPACITY", 2, 1), now);
+        assert!(cache.answer(&list, now).is_some());
+
+        // A different count is a different parameter set
+        cache.store(&param_value("NEW_PARAM", 3, 2), now);
+        assert!(cache.answer(&read_by_name("SYSID_THISMAV"), now).is_none());
+
+        cache.forget(1);
+        assert!(cache.answer(&read_by_name("NEW_PARAM"), now).is_none());
+    }
+
+    #[test]
+    fn test_stale_and_uncached_reads() {
+        let mut cache = ParamCache::new(Some(Duration::from_secs(60)), true);
+        let now = Instant::now();
+        cache.store(&param_value("SYSID_THISMAV", 1, 0), now);
+        let read = read_by_name("SYSID_THISMAV");
+        assert!(cache.answer(&read, now + Duration::from_secs(59)).is_some());
+        assert!(cache.answer(&read, now + Duration::from_secs(61)).is_none());
+
+        let mut cache = ParamCache::new(None, false);
+        cache.store(&param_value("SYSID_THISMAV", 1, 0), now);
+        assert!(cache.answer(&read, now).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::{AwaitPeerV2Config, PeerV2Fallback};
+use crate::connection::ConnectionId;
+use crate::mavlink::packet::MavVersion;
+use crate::mavlink::MavFrame;
+use std::collections::VecDeque;
+
+/// What to do with a frame for the peer
+#[derive(Debug)]
+pub enum Outgoing {
+    /// Send it as it is
+    Send,
+    /// Send this MAVLink 1 copy instead
+    Downgraded(MavFrame),
+    /// Held until the peer is confirmed, in place of the oldest held frame
+    /// if there was no room, which is now never sent
+    Held(Option<(ConnectionId, MavFrame)>),
+    /// It can't be sent to the peer
+    Dropped,
+}
+
+/// What is known of one link's peer
+#[derive(Debug, Default)]
+pub struct PeerVersion {
+    /// The peer has sent a MAVLink 2 frame since the link opened
+    confirmed: bool,
+    /// Frames waiting for that, and where they came from
+    held: VecDeque<(ConnectionId, MavFrame)>,
+}
+
+impl PeerVersion {
+    pub fn offer(
+        &mut self,
+        config: &AwaitPeerV2Config,
+        source: ConnectionId,
+        frame: &MavFrame,
+    ) -> Outgoing {
+        if self.confirmed || frame.version() == MavVersion::V1 {
+            return Outgoing::Send;
+        }
+        match config.fallback {
+            PeerV2Fallback::Downgrade => match frame.to_v1() {
+                Some(v1) => Outgoing::Downgraded(v1),
+                None => Outgoing::Dropped,
+            },
+            PeerV2Fallback::Hold if config.hold_frames > 0 => {
+                let evicted = (self.held.len() >= config.hold_frames)
+                    .then(|| self.held.pop_front())
+                    .flatten();
+                self.held.push_back((source, frame.clone()));
+                Outgoing::Held(evicted)
+            }
+            PeerV2Fallback::Hold | PeerV2Fallback::Drop => Outgoing::Dropped,
+        }
+    }
+
+    /// As `offer`, for a frame the router made itself
+    pub fn offer_own(&self, config: &AwaitPeerV2Config, frame: &MavFrame) -> Outgoing {
+        if self.confirmed || frame.version() == MavVersion::V1 {
+            return Outgoing::Send;
+        }
+        match (config.fallback, frame.to_v1()) {
+            (PeerV2Fallback::Drop, _) | (_, None) => Outgoing::Dropped,
+            (_, Some(v1)) => Outgoing::Downgraded(v1),
+        }
+    }
+
+    /// Note a frame from the peer. Returns the frames held for it if this is
+    /// the first MAVLink 2 frame it has sent.
+    pub fn received(&mut self, frame: &MavFrame) -> Option<Vec<(ConnectionId, MavFrame)>> {
+        if self.confirmed || frame.version() != MavVersion::V2 {
+            return None;
+        }
+        self.confirmed = true;
+        Some(self.held.drain(..).collect())
+    }
+
+    /// The link reopened, perhaps to another device. Returns the frames
+    /// that were held for the old peer, which are dropped.
+    pub fn reset(&mut self) -> Vec<(ConnectionId, MavFrame)> {
+        self.confirmed = false;
+        self.held.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    #[test]
+    fn test_hold_until_confirmed() {
+        let config = AwaitPeerV2Config {
+            fallback: PeerV2Fallback::Hold,
+            hold_frames: 2,
+        };
+        let source = ConnectionId::new_uart(0);
+        let mut peer = PeerVersion::default();
+        let v2 = |seq| MavFrame::new_v2(seq, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        let v1 = v2(0).to_v1().unwrap();
+
+        assert!(matches!(peer.offer(&config, source, &v1), Outgoing::Send));
+        assert!(matches!(
+            peer.offer(&config, source, &v2(1)),
+            Outgoing::Held(None)
+        ));
+        assert!(matches!(
+            peer.offer(&config, source, &v2(2)),
+            Outgoing::Held(None)
+        ));
+        match peer.offer(&config, source, &v2(3)) {
+            Outgoing::Held(Some((_, evicted))) => assert_eq!(evicted.sequence(), 1),
+            outgoing => panic!("expected the oldest to be evicted, got {:?}", outgoing),
+        }
+
+        // MAVLink 1 from the peer proves nothing; MAVLink 2 releases the rest
+        assert!(peer.received(&v1).is_none());
+        let held = peer.received(&v2(0)).unwrap();
+        let seqs: Vec<u8> = held.iter().map(|(_, frame)| frame.sequence()).collect();
+        assert_eq!(seqs, [2, 3]);
+        assert!(matches!(
+            peer.offer(&config, source, &v2(4)),
+            Outgoing::Send
+        ));
+        assert!(peer.received(&v2(0)).is_none());
+
+        peer.reset();
+        assert!(matches!(
+            peer.offer(&config, source, &v2(5)),
+            Outgoing::Held(None)
+        ));
+        // Held for a peer that is gone, never sent
+        assert_eq!(peer.reset().len(), 1);
+        assert!(peer.received(&v2(0)).unwrap().is_empty());
+
+        // The router's own frames aren't held
+        peer.reset();
+        assert!(matches!(
+            peer.offer_own(&config, &v2(6)),
+            Outgoing::Downgraded(_)
+        ));
+        assert!(peer.held.is_empty());
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let config = AwaitPeerV2Config {
+            fallback: PeerV2Fallback::Downgrade,
+            hold_frames: 64,
+        };
+        let source = ConnectionId::new_uart(0);
+        let mut peer = PeerVersion::default();
+
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+        match peer.offer(&config, source, &heartbeat) {
+            Outgoing::Downgraded(v1) => assert_eq!(v1.version(), MavVersion::V1),
+            outgoing => panic!("expected a MAVLink 1 copy, got {:?}", outgoing),
+        }
+        let custom = MavFrame::new_v2_with_crc_extra(0, 1, 1, 59100, 7, &[1]);
+        assert!(matches!(
+            peer.offer(&config, source, &custom),
+            Outgoing::Dropped
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::config::ReliableConfig;
+use crate::mavlink::MavFrame;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Message id of MAV_LITE_ACK
+pub const MAV_LITE_ACK: u32 = 59100;
+
+/// CRC_EXTRA of MAV_LITE_ACK
+const ACK_CRC_EXTRA: u8 = 41;
+
+/// Unacknowledged frames kept for resending before the oldest is given up
+const MAX_PENDING: usize = 256;
+
+/// Frames remembered for spotting repeats before the expired are cleared out
+const MAX_SEEN: usize = 1024;
+
+/// Identifies a frame on the wire: sysid, compid, sequence, message id and
+/// checksum
+pub type FrameKey = (u8, u8, u8, u32, u16);
+
+pub fn key(frame: &MavFrame) -> FrameKey {
+    (
+        frame.sys_id(),
+        frame.comp_id(),
+        frame.sequence(),
+        frame.msg_id(),
+        frame.checksum(),
+    )
+}
+
+/// A received frame, once the reliability layer has looked at it
+#[derive(Debug)]
+pub enum Received {
+    /// Not one of the acknowledged messages
+    Plain,
+    /// Acknowledged data: the ack to send back, and whether the frame was
+    /// already passed on
+    Data { ack: MavFrame, repeat: bool },
+    /// Acknowledgement of a frame we sent
+    Ack,
+}
+
+struct Pending {
+    frame: MavFrame,
+    sent_at: Instant,
+    retries: u32,
+}
+
+/// Frames awaiting acks, and frames already passed on, for one link
+pub struct ReliableLink {
+    config: ReliableConfig,
+    pending: VecDeque<Pending>,
+    seen: HashMap<FrameKey, Instant>,
+}
+
+impl ReliableLink {
+    pub fn new(config: ReliableConfig) -> Self {
+        Self {
+            config,
+            pending: VecDeque::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Whether a frame is of a message sent with acknowledgement
+    pub fn wants(&self, frame: &MavFrame) -> bool {
+        self.config.messages.contains(&frame.msg_id())
+    }
+
+    /// Remember a frame just sent for resending until it is acknowledged.
+    /// Returns one given up to make room, if any.
+    pub fn track(&mut self, frame: &MavFrame, now: Instant) -> Option<MavFrame> {
+        let evicted = if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front().map(|pending| pending.frame)
+        } else {
+            None
+        };
+        self.pending.push_back(Pending {
+            frame: frame.clone(),
+            sent_at: now,
+            retries: 0,
+        });
+        evicted
+    }
+
+    /// Sort out a frame received on the link
+    pub fn receive(&mut self, frame: &MavFrame, now: Instant) -> Received {
+        if frame.msg_id() == MAV_LITE_ACK {
+            if let Some(acked) = acked_key(frame) {
+                self.pending.retain(|pending| key(&pending.frame) != acked);
+            }
+            return Received::Ack;
+        }
+        if !self.wants(frame) {
+            return Received::Plain;
+        }
+
+        // The sender stops resending after max_retries, so a frame last seen
+        // longer ago than that is new
+        let hold = self.interval() * (self.config.max_retries + 2);
+        if self.seen.len() >= MAX_SEEN {
+            self.seen.retain(|_, seen| now.duration_since(*seen) < hold);
+        }
+        let key = key(frame);
+        let repeat = self
+            .seen
+            .get(&key)
+            .is_some_and(|seen| now.duration_since(*seen) < hold);
+        if !repeat {
+            self.seen.insert(key, now);
+        }
+        Received::Data {
+            ack: ack_for(frame),
+            repeat,
+        }
+    }
+
+    /// Frames to send again, and those given up on after `max_retries`
+    pub fn due(&mut self, now: Instant) -> (Vec<MavFrame>, Vec<MavFrame>) {
+        let interval = self.interval();
+        let max_retries = self.config.max_retries;
+        let mut resend = Vec::new();
+        let mut given_up = Vec::new();
+        self.pending.retain_mut(|pending| {
+            if now < pending.sent_at + interval {
+                return true;
+            }
+            if pending.retries >= max_retries {
+                given_up.push(pending.frame.clone());
+                return false;
+            }
+            pending.retries += 1;
+            pending.sent_at = now;
+            resend.push(pending.frame.clone());
+            true
+        });
+        (resend, given_up)
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.config.retransmit_ms.max(1))
+    }
+}
+
+/// MAV_LITE_ACK for a frame: its sysid, compid and sequence in the header,
+/// its message id and checksum in the payload
+fn ack_for(frame: &MavFrame) -> MavFrame {
+    let mut payload = frame.msg_id().to_le_bytes().to_vec();
+    payload.extend_from_slice(&frame.checksum().to_le_bytes());
+    MavFrame::new_v2_with_crc_extra(
+        frame.sequence(),
+        frame.sys_id(),
+        frame.comp_id(),
+        MAV_LITE_ACK,
+        ACK_CRC_EXTRA,
+        &payload,
+    )
+}
+
+/// The frame a MAV_LITE_ACK acknowledges
+fn acked_key(ack: &MavFrame) -> Option<FrameKey> {
+    if ack.crc_extra() != Some(ACK_CRC_EXTRA) {
+        return None;
+    }
+    // Trailing zeros are cut from MAVLink 2 payloads
+    let mut payload = [0u8; 6];
+    let len = ack.payload().len().min(payload.len());
+    payload[..len].copy_from_slice(&ack.payload()[..len]);
+    Some((
+        ack.sys_id(),
+        ack.comp_id(),
+        ack.sequence(),
+        u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+        u16::from_le_bytes([payload[4], payload[5]]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::messages;
+
+    fn command(seq: u8) -> MavFrame {
+        MavFrame::new_v2_with_crc_extra(seq, 255, 190, messages::COMMAND_LONG, 152, &[1; 33])
+    }
+
+    #[test]
+    fn test_ack_and_repeats() {
+        let mut sender = ReliableLink::new(ReliableConfig::default());
+        let mut receiver = ReliableLink::new(ReliableConfig::default());
+        let now = Instant::now();
+
+        let heartbeat = MavFrame::new_v2_with_crc_extra(0, 1, 1, messages::HEARTBEAT, 50, &[1; 9]);
+        assert!(!sender.wants(&heartbeat));
+        assert!(matches!(receiver.receive(&heartbeat, now), Received::Plain));
+
+        // Passed on once however many copies arrive, and acked every time
+        let frame = command(7);
+        assert!(sender.wants(&frame));
+        sender.track(&frame, now);
+        let Received::Data { ack, repeat: false } = receiver.receive(&frame, now) else {
+            panic!("not new data");
+        };
+        assert!(matches!(
+            receiver.receive(&frame, now),
+            Received::Data { repeat: true, .. }
+        ));
+
+        // Another frame with the same sequence is not a repeat
+        let other =
+            MavFrame::new_v2_with_crc_extra(7, 255, 190, messages::COMMAND_LONG, 152, &[2; 33]);
+        assert!(matches!(
+            receiver.receive(&other, now),
+            Received::Data { repeat: false, .. }
+        ));
+
+        // The ack settles only the frame it names
+        sender.track(&other, now);
+        assert!(matches!(sender.receive(&ack, now), Received::Ack));
+        let interval = Duration::from_millis(250);
+        assert_eq!(sender.due(now + interval).0.len(), 1);
+    }
+
+    #[test]
+    fn test_resend_until_given_up() {
+        let config = ReliableConfig {
+            max_retries: 2,
+            ..ReliableConfig::default()
+        };
+        let interval = Duration::from_millis(config.retransmit_ms);
+        let mut sender = ReliableLink::new(config);
+        let start = Instant::now();
+        let frame = command(1);
+        sender.track(&frame, start);
+
+        // Not due until the interval has passed, then resent each interval
+        assert!(sender.due(start).0.is_empty());
+        let (resend, given_up) = sender.due(start + interval);
+        assert_eq!(resend[0].as_bytes(), frame.as_bytes());
+        assert!(given_up.is_empty());
+        assert_eq!(sender.due(start + interval * 2).0.len(), 1);
+
+        // Given up after max_retries resends
+        let (resend, given_up) = sender.due(start + interval * 3);
+        assert!(resend.is_empty());
+        assert_eq!(given_up[0].as_bytes(), frame.as_bytes());
+        assert!(sender.due(start + interval * 10).0.is_empty());
+
+        // The oldest is given up when too many are waiting
+        for seq in 0..MAX_PENDING {
+            assert!(sender.track(&command(seq as u8), start).is_none());
+        }
+        let evicted = sender.track(&command(0), start).unwrap();
+        assert_eq!(evicted.sequence(), 0);
+    }
+
+    #[test]
+    fn test_repeats_forgotten_once_sender_stops() {
+        let config = ReliableConfig::default();
+        let hold = Duration::from_millis(config.retransmit_ms) * (config.max_retries + 2);
+        let mut receiver = ReliableLink::new(config);
+        let start = Instant::now();
+        let frame = command(3);
+
+        // A restarted sender reusing a sequence number long after isn't
+        // taken for a repeat
+        assert!(matches!(
+            receiver.receive(&frame, start),
+            Received::Data { repeat: false, .. }
+        ));
+        assert!(matches!(
+            receiver.receive(&frame, start + hold / 2),
+            Received::Data { repeat: true, .. }
+        ));
+        assert!(matches!(
+            receiver.receive(&frame, start + hold),
+            Received::Data { repeat: false, .. }
+        ));
+    }
+}
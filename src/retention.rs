@@ -0,0 +1,176 @@
+use crate::config::LoggingConfig;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::time::interval;
+use tracing::{info, warn};
+
+/// How often the log directory is scanned
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+    /// Still being written, so counted toward the total but never deleted
+    active: bool,
+}
+
+/// Start pruning the `[logging]` directory under its limits. Does nothing
+/// without a directory or a limit.
+pub fn start(config: &LoggingConfig) {
+    let Some(dir) = config.dir.clone().map(PathBuf::from) else {
+        return;
+    };
+    if config.max_total_size_mb == 0 && config.max_age_days == 0 {
+        warn!(
+            "Log retention: no max_total_size_mb or max_age_days set, leaving {} alone",
+            dir.display()
+        );
+        return;
+    }
+    info!(
+        "Log retention: {} up to {} MB, {} days (0 = no limit)",
+        dir.display(),
+        config.max_total_size_mb,
+        config.max_age_days
+    );
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut interval = interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let (config, dir) = (config.clone(), dir.clone());
+            let _ = tokio::task::spawn_blocking(move || prune(&config, &dir)).await;
+        }
+    });
+}
+
+/// Delete the files past the limits
+fn prune(config: &LoggingConfig, dir: &Path) {
+    for path in expired(scan(dir), config, SystemTime::now()) {
+        match fs::remove_file(&path) {
+            Ok(()) => info!("Log retention: deleted {}", path.display()),
+            // Deleted by someone else meanwhile
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Log retention: can't delete {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// The files directly in `dir`. The one written last is taken as the log
+/// being recorded.
+fn scan(dir: &Path) -> Vec<LogFile> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Log retention: can't read {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut files: Vec<LogFile> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            metadata.is_file().then(|| LogFile {
+                path: entry.path(),
+                len: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                active: false,
+            })
+        })
+        .collect();
+    if let Some(newest) = files.iter_mut().max_by_key(|file| file.modified) {
+        newest.active = true;
+    }
+    files
+}
+
+/// Files older than `max_age_days`, then the oldest of the rest until
+/// everything, the files being written included, fits in
+/// `max_total_size_mb`
+fn expired(mut files: Vec<LogFile>, config: &LoggingConfig, now: SystemTime) -> Vec<PathBuf> {
+    let max_age = Duration::from_secs(config.max_age_days.saturating_mul(24 * 60 * 60));
+    let max_total = config.max_total_size_mb.saturating_mul(1024 * 1024);
+
+    // Files being written first, since they stay whatever the limits, then
+    // newest first, so the running total reaches the limit at the oldest
+    files.sort_by_key(|file| (!file.active, std::cmp::Reverse(file.modified)));
+    let mut total = 0u64;
+    let mut expired = Vec::new();
+    for file in files {
+        let age = now.duration_since(file.modified).unwrap_or_default();
+        total = total.saturating_add(file.len);
+        if file.active {
+            continue;
+        }
+        if (config.max_age_days > 0 && age > max_age) || (max_total > 0 && total > max_total) {
+            expired.push(file.path);
+        }
+    }
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prunes_oldest_files() {
+        let name = format!("mav-lite-retention-{}", std::process::id());
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Half a megabyte each, just under n days old, and the file being
+        // recorded a megabyte
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let hour = Duration::from_secs(60 * 60);
+        let write = |name: &str, len: usize, age: Duration| {
+            let path = dir.join(name);
+            fs::write(&path, vec![b'x'; len]).unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - age).unwrap();
+        };
+        write("flight-0.tlog", 1 << 20, Duration::ZERO);
+        for n in 1..=4 {
+            write(&format!("flight-{}.tlog", n), 512 << 10, day * n - hour);
+        }
+
+        let limit = |max_total_size_mb, max_age_days| LoggingConfig {
+            dir: None,
+            max_total_size_mb,
+            max_age_days,
+        };
+        let names = |paths: Vec<PathBuf>| {
+            let mut names: Vec<_> = paths
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // Two megabytes, the file being recorded included, keeps the two
+        // newest besides it
+        assert_eq!(
+            names(expired(scan(&dir), &limit(2, 0), now)),
+            ["flight-3.tlog", "flight-4.tlog"]
+        );
+
+        // The file being recorded takes the whole megabyte, so everything
+        // else goes, but it stays even once over
+        assert_eq!(expired(scan(&dir), &limit(1, 0), now).len(), 4);
+        write("flight-0.tlog", 2 << 20, Duration::ZERO);
+        assert_eq!(expired(scan(&dir), &limit(1, 0), now).len(), 4);
+
+        // Two days drops everything older
+        prune(&limit(0, 2), &dir);
+        let left = names(scan(&dir).into_iter().map(|f| f.path).collect());
+        assert_eq!(left, ["flight-0.tlog", "flight-1.tlog", "flight-2.tlog"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
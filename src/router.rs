@@ -1,88 +1,892 @@
-use crate::config::RoutingConfig;
+use crate::adaptive_rate::AdaptiveRate;
+use crate::announce::{Announcer, Health};
+use crate::audit_log::{AuditLog, Decision};
+use crate::boot_suppression::BootSuppression;
+use crate::byte_budget::ByteBudget;
+use crate::compid_map::CompidMap;
+use crate::config::{LengthCheck, LinkConfig, LostSysidPolicy, RoutingConfig};
+use crate::conflate::{Conflation, Offer};
 use crate::connection::tcp::RouterMessage;
-use crate::connection::{ConnectionId, ConnectionType, MessageSender};
+use crate::connection::{
+    ConnectionId, ConnectionRole, ConnectionType, MessageSender, QueueDrained, RouterReceiver,
+};
+use crate::dead_letter::DeadLetters;
+use crate::decoded_log::DecodedLog;
+use crate::dedup::Dedup;
+use crate::link_quality::LinkQuality;
+use crate::link_test::{LinkTest, LinkTestResult};
+use crate::mavlink::decode::{Heartbeat, StatusText, MAV_STATE_CRITICAL};
+use crate::mavlink::dialect::Dialect;
+use crate::mavlink::messages::{self, MessageClass};
 use crate::mavlink::MavFrame;
 use crate::metrics::Metrics;
-use std::collections::HashMap;
-use tokio::sync::mpsc;
+use crate::param_cache::ParamCache;
+use crate::peer_version::{Outgoing, PeerVersion};
+use crate::reliable::{Received, ReliableLink};
+use crate::signing::{LinkSigning, Verified};
+use crate::transform::{EdgeTransforms, Rewrite};
+use crate::tsdb::TsdbExporter;
+use bytes::Bytes;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::{interval, MissedTickBehavior};
 use tracing::{debug, info, warn};
 
+/// How often housekeeping runs (matches the 1 Hz HEARTBEAT rate)
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Minimum time between debug logs about frames with no destination
+const UNROUTED_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Commands held per lost sysid under `lost_sysid_policy = "queue"`
+const LOST_SYSID_QUEUE_FRAMES: usize = 64;
+
+/// A vehicle is considered silent once its HEARTBEAT is this old
+const HEARTBEAT_SILENCE: Duration = Duration::from_millis(2500);
+
+/// How often reliable links are checked for frames due a resend, and held
+/// state messages for release on links that conflate them
+const RESEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Events buffered per subscriber before the oldest are skipped
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Minimum time between backpressure events for one connection
+const BACKPRESSURE_EVENT_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct Router {
     config: RoutingConfig,
     connections: HashMap<ConnectionId, Connection>,
     sysid_map: HashMap<u8, ConnectionId>,
+    /// Sysids whose link dropped recently, still reserved for their vehicle
+    lost_sysids: HashMap<u8, LostSysid>,
+    transforms: EdgeTransforms,
+    /// Most recent frame per (sysid, msgid), for inspection
+    last_frames: HashMap<(u8, u32), CachedFrame>,
+    /// Whether the global queue cap is currently being enforced
+    shedding: bool,
+    /// Where `primary_gcs_sysid` was last heard from
+    primary_gcs: Option<ConnectionId>,
+    /// Silent vehicles we are currently sending synthetic heartbeats for,
+    /// and the sequence number of the last one sent
+    synthesizing: HashMap<u8, u8>,
+    /// Vehicles commands are being refused for, until their HEARTBEAT is
+    /// heard again
+    interlocked: HashSet<u8>,
+    /// Active member of each failover group
+    failover_active: HashMap<String, ConnectionId>,
+    /// When a frame with no destination was last logged, and how many
+    /// have gone unlogged since
+    unrouted_log: Option<Instant>,
+    unrouted_suppressed: u64,
+    events: broadcast::Sender<StampedEvent>,
+    /// Loaded dialects, by the name links refer to them with
+    dialects: HashMap<String, Arc<Dialect>>,
+    decoded_log: Option<DecodedLog>,
+    tsdb: Option<TsdbExporter>,
+    audit_log: Option<AuditLog>,
+    dead_letters: Option<DeadLetters>,
+    /// The router's own HEARTBEAT, and when it was last sent
+    announcer: Option<Announcer>,
+    last_announce: Option<Instant>,
+    /// When the router last reported its health, if it does
+    last_health: Option<Instant>,
+    /// Vehicles' parameters, when GCS requests are answered from them
+    param_cache: Option<ParamCache>,
+    /// Component ids given to GCS clients, when they are told apart
+    compid_map: Option<CompidMap>,
+    /// Link tests waiting on their vehicle's answer
+    link_tests: Vec<LinkTest>,
+    /// Whether the router is a warm standby, sending nothing
+    standby: bool,
+    /// When the primary router's HEARTBEAT was last heard, or the router
+    /// started
+    primary_heard: Instant,
     metrics: Metrics,
 }
 
+/// Requests from the admin channel for router state
+pub enum RouterQuery {
+    /// Most recent frame of each message type seen from a system
+    LastFrames {
+        sysid: u8,
+        reply: oneshot::Sender<Vec<InspectedFrame>>,
+    },
+    /// How much is queued for each connection
+    Queues {
+        reply: oneshot::Sender<Vec<QueueDepth>>,
+    },
+    /// Make a standby router active; replies whether it was on standby
+    Promote { reply: oneshot::Sender<bool> },
+    /// Send a vehicle a request, from the router's own sysid/compid, and
+    /// report its answer
+    TestLink {
+        sysid: u8,
+        from: (u8, u8),
+        reply: oneshot::Sender<LinkTestResult>,
+    },
+}
+
+/// Something that changed in the router, streamed to admin subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RouterEvent {
+    ConnectionUp {
+        connection: String,
+    },
+    ConnectionDown {
+        connection: String,
+    },
+    VehicleDiscovered {
+        sysid: u8,
+        connection: String,
+    },
+    VehicleLost {
+        sysid: u8,
+        connection: String,
+    },
+    /// Frames to a connection are being dropped because its queue is full
+    Backpressure {
+        connection: String,
+    },
+    Failover {
+        group: String,
+        from: String,
+        to: String,
+    },
+    LinkDegraded {
+        connection: String,
+        loss_percent: f64,
+        rssi: Option<u8>,
+    },
+    LinkRecovered {
+        connection: String,
+        loss_percent: f64,
+        rssi: Option<u8>,
+    },
+    /// A standby router became active
+    Promoted {
+        reason: String,
+    },
+}
+
+/// A router event with when it happened (seconds since the Unix epoch)
+#[derive(Debug, Clone, Serialize)]
+pub struct StampedEvent {
+    pub time: f64,
+    #[serde(flatten)]
+    pub event: RouterEvent,
+}
+
+impl StampedEvent {
+    fn now(event: RouterEvent) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        Self { time, event }
+    }
+}
+
+/// Channel router events are published on; subscribe to receive them
+pub fn event_channel() -> broadcast::Sender<StampedEvent> {
+    broadcast::channel(EVENT_CHANNEL_CAPACITY).0
+}
+
+struct LostSysid {
+    since: Instant,
+    /// Frames for this vehicle held until it comes back
+    queued: VecDeque<(ConnectionId, MavFrame)>,
+}
+
+/// Which vehicle-side connections a frame may go to
+enum VehicleTarget {
+    /// Untargeted, or the target isn't known: every vehicle link
+    All,
+    /// The link the target vehicle was seen on
+    Only(ConnectionId),
+    /// The target's link recently dropped: none
+    Lost(u8),
+}
+
+struct CachedFrame {
+    frame: MavFrame,
+    source: ConnectionId,
+    received_at: Instant,
+}
+
+pub struct InspectedFrame {
+    pub frame: MavFrame,
+    pub source: ConnectionId,
+    pub age: Duration,
+}
+
+/// A connection's outbound queue. Queues are unbounded; the limits that
+/// apply are the adaptive telemetry threshold and the global cap.
+pub struct QueueDepth {
+    pub connection: ConnectionId,
+    pub queued_bytes: usize,
+    pub peak_bytes: usize,
+    /// `adaptive_telemetry.queued_bytes`, if set
+    pub congested_above: Option<usize>,
+}
+
 struct Connection {
     tx: MessageSender,
     conn_type: ConnectionType,
     sysid: Option<u8>,
+    link: LinkConfig,
+    /// Acknowledged delivery, if the link uses it
+    reliable: Option<ReliableLink>,
+    quality: LinkQuality,
+    /// Telemetry limit while the connection is congested
+    adaptive: AdaptiveRate,
+    /// When a frame was last received on it
+    last_rx: Option<Instant>,
+    /// When each sysid's first frame arrived on it since the link opened
+    first_frames: HashMap<u8, Instant>,
+    /// Boot-burst messages let through to it
+    boot: BootSuppression,
+    /// Bytes sent to it, if message types are capped at a share of them
+    budget: ByteBudget,
+    /// State messages held for it, if it gets only the latest
+    conflation: Conflation,
+    /// Whether its peer has shown it speaks MAVLink 2, if that is awaited
+    peer_version: PeerVersion,
+    /// When a backpressure event was last published for it
+    backpressure_reported: Option<Instant>,
+    /// Frames recently sent to it, if it drops duplicates
+    dedup: Option<Dedup>,
+    /// Dialect frames received on it must belong to
+    dialect: Option<Arc<Dialect>>,
+    /// Keys its traffic is signed with, if it is
+    signing: Option<LinkSigning>,
+    /// Foreign message ids already logged for this link
+    rejected_msgids: HashSet<u32>,
+    /// Message ids already logged for a bad length on this link
+    bad_length_msgids: HashSet<u32>,
 }
 
 impl Router {
-    pub fn new(config: RoutingConfig, metrics: Metrics) -> Self {
+    pub fn new(
+        config: RoutingConfig,
+        metrics: Metrics,
+        events: broadcast::Sender<StampedEvent>,
+        dialects: HashMap<String, Arc<Dialect>>,
+        decoded_log: Option<DecodedLog>,
+        audit_log: Option<AuditLog>,
+        announcer: Option<Announcer>,
+    ) -> Self {
         Self {
+            transforms: EdgeTransforms::new(
+                config.sysid_namespace.clone(),
+                config.broadcast_command_target,
+            ),
+            param_cache: config.param_cache.then(|| {
+                let ttl = (config.param_cache_ttl_secs > 0)
+                    .then(|| Duration::from_secs(config.param_cache_ttl_secs));
+                ParamCache::new(ttl, config.param_cache_reads)
+            }),
+            compid_map: config.gcs_compids.as_ref().map(CompidMap::new),
+            standby: config.standby.is_some(),
             config,
             connections: HashMap::new(),
             sysid_map: HashMap::new(),
+            lost_sysids: HashMap::new(),
+            last_frames: HashMap::new(),
+            shedding: false,
+            primary_gcs: None,
+            synthesizing: HashMap::new(),
+            interlocked: HashSet::new(),
+            failover_active: HashMap::new(),
+            unrouted_log: None,
+            unrouted_suppressed: 0,
+            events,
+            dialects,
+            decoded_log,
+            tsdb: None,
+            audit_log,
+            dead_letters: None,
+            announcer,
+            last_announce: None,
+            last_health: None,
+            link_tests: Vec::new(),
+            primary_heard: Instant::now(),
             metrics,
         }
     }
 
-    pub async fn run(mut self, mut rx: mpsc::UnboundedReceiver<RouterMessage>) {
+    /// Export decoded telemetry as it is received
+    pub fn with_tsdb(mut self, tsdb: Option<TsdbExporter>) -> Self {
+        self.tsdb = tsdb;
+        self
+    }
+
+    /// Log every frame dropped, and why
+    pub fn with_dead_letters(mut self, dead_letters: Option<DeadLetters>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    /// Route until shut down. Returns what to wait on for the connections to
+    /// send what was queued for them.
+    pub async fn run(mut self, mut rx: RouterReceiver) -> Vec<QueueDrained> {
         info!("Router started");
 
-        while let Some(msg) = rx.recv().await {
+        let mut tick = interval(TICK_INTERVAL);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut resend = interval(RESEND_INTERVAL);
+        resend.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = tick.tick() => {
+                    self.expire_lost_sysids();
+                    self.send_synthetic_heartbeats();
+                    self.evaluate_link_quality();
+                    self.adapt_telemetry_rates();
+                    self.update_failover();
+                    self.check_primary();
+                    self.announce();
+                    self.report_health();
+                    continue;
+                }
+                _ = resend.tick() => {
+                    self.resend_unacked();
+                    self.release_conflated();
+                    continue;
+                }
+            };
+
             match msg {
-                RouterMessage::NewConnection { conn_id, tx } => {
-                    self.handle_new_connection(conn_id, tx);
+                RouterMessage::NewConnection { conn_id, tx, link } => {
+                    self.handle_new_connection(conn_id, tx, *link);
                 }
                 RouterMessage::Disconnect { conn_id } => {
                     self.handle_disconnect(conn_id);
                 }
-                RouterMessage::Frame { source, frame } => {
+                RouterMessage::LinkUp { conn_id } => {
+                    self.metrics.start_session(conn_id);
+                    // Vehicles on it may be booting again, and the peer may
+                    // be another device
+                    if let Some(conn) = self.connections.get_mut(&conn_id) {
+                        conn.first_frames.clear();
+                        for (source, frame) in conn.peer_version.reset() {
+                            self.metrics.record_v2_withheld();
+                            if let Some(log) = &self.dead_letters {
+                                log.record(source, Some(conn_id), &frame, "peer_v2");
+                            }
+                        }
+                    }
+                }
+                RouterMessage::Frame {
+                    source,
+                    frame,
+                    received,
+                } => {
+                    if self.is_stale(received) {
+                        self.metrics.record_stale();
+                        debug!(
+                            "Dropped frame from {} (msgid={}): waited {:?} for the router",
+                            source,
+                            frame.msg_id(),
+                            received.elapsed()
+                        );
+                        self.audit(source, &frame, Decision::Denied, Some("stale"), &[]);
+                        self.dead_letter(source, None, &frame, "stale");
+                        continue;
+                    }
                     self.route_frame(source, frame);
                 }
+                RouterMessage::Query(query) => {
+                    self.handle_query(query);
+                }
+                RouterMessage::Shutdown => {
+                    // Connections see the channel closed and stop quietly;
+                    // anything they already sent is still routed
+                    info!(
+                        "Router shutting down, routing {} message(s) already received",
+                        rx.len()
+                    );
+                    rx.close();
+                }
             }
         }
 
         info!("Router stopped");
+        // Dropping the queues lets each connection finish once it has sent
+        // what they hold
+        self.connections
+            .drain()
+            .map(|(_, conn)| conn.tx.drained())
+            .collect()
     }
 
-    fn handle_new_connection(&mut self, conn_id: ConnectionId, tx: MessageSender) {
+    fn handle_new_connection(
+        &mut self,
+        conn_id: ConnectionId,
+        tx: MessageSender,
+        link: LinkConfig,
+    ) {
         info!("Router: new connection {}", conn_id);
+        let reliable = link.reliable.clone().map(ReliableLink::new);
+        let dedup = (link.dedup_window_ms > 0).then(|| {
+            Dedup::new(
+                Duration::from_millis(link.dedup_window_ms),
+                link.dedup_match,
+                link.dedup_allow_retransmit,
+            )
+        });
+        let dialect = link
+            .dialect
+            .as_ref()
+            .and_then(|name| self.dialects.get(name).cloned());
+        let signing = link.signing.as_ref().map(LinkSigning::new);
         self.connections.insert(
             conn_id,
             Connection {
                 tx,
                 conn_type: conn_id.conn_type,
                 sysid: None,
+                link,
+                reliable,
+                quality: LinkQuality::default(),
+                adaptive: AdaptiveRate::default(),
+                last_rx: None,
+                first_frames: HashMap::new(),
+                boot: BootSuppression::default(),
+                budget: ByteBudget::default(),
+                conflation: Conflation::default(),
+                peer_version: PeerVersion::default(),
+                backpressure_reported: None,
+                dedup,
+                dialect,
+                signing,
+                rejected_msgids: HashSet::new(),
+                bad_length_msgids: HashSet::new(),
             },
         );
+        self.publish(RouterEvent::ConnectionUp {
+            connection: conn_id.to_string(),
+        });
+        self.update_failover();
+        self.introduce(conn_id);
+    }
+
+    /// Send the router's HEARTBEAT to every connection, once per announce
+    /// interval
+    fn announce(&mut self) {
+        let Some(announcer) = &mut self.announcer else {
+            return;
+        };
+        if self.standby {
+            return;
+        }
+        if self
+            .last_announce
+            .is_some_and(|at| at.elapsed() < announcer.interval)
+        {
+            return;
+        }
+        self.last_announce = Some(Instant::now());
+
+        let frame = announcer.heartbeat();
+        let connections: Vec<ConnectionId> = self.connections.keys().copied().collect();
+        for conn_id in connections {
+            self.send_own(conn_id, &frame);
+        }
+    }
+
+    /// Take over from a primary router whose HEARTBEAT has stopped
+    fn check_primary(&mut self) {
+        let Some(standby) = &self.config.standby else {
+            return;
+        };
+        if !self.standby || standby.primary_sysid.is_none() {
+            return;
+        }
+        let silent = self.primary_heard.elapsed();
+        if silent >= Duration::from_secs(standby.primary_timeout_secs) {
+            let reason = format!("primary silent for {}s", silent.as_secs());
+            self.promote(&reason);
+        }
+    }
+
+    /// Leave standby and start routing
+    fn promote(&mut self, reason: &str) {
+        self.standby = false;
+        warn!("Router: promoted from standby ({}), now routing", reason);
+        self.publish(RouterEvent::Promoted {
+            reason: reason.to_string(),
+        });
+        let connections: Vec<ConnectionId> = self.connections.keys().copied().collect();
+        for conn_id in connections {
+            self.introduce(conn_id);
+        }
+    }
+
+    /// Send every connection the router's health, if it reports it
+    fn report_health(&mut self) {
+        let Some(announcer) = &mut self.announcer else {
+            return;
+        };
+        if self.standby {
+            return;
+        }
+        if !announcer.reports_health()
+            || self
+                .last_health
+                .is_some_and(|at| at.elapsed() < announcer.health_interval)
+        {
+            return;
+        }
+        self.last_health = Some(Instant::now());
+
+        let stats = self.metrics.get_stats();
+        let mut loss: Vec<(u8, f64)> = Vec::new();
+        // A vehicle's worst component stands for it
+        for ((sysid, _), stream) in self.metrics.sequence_stats() {
+            match loss.last_mut() {
+                Some((last, worst)) if *last == sysid => *worst = worst.max(stream.loss_percent()),
+                _ => loss.push((sysid, stream.loss_percent())),
+            }
+        }
+        let health = Health {
+            connections: self.connections.len(),
+            vehicles: self.sysid_map.len(),
+            received: stats.messages_received,
+            dropped: stats.messages_dropped
+                + stats.messages_shed
+                + stats.frames_stale
+                + stats.router_full,
+            loss,
+        };
+        let frames = announcer.health(&health);
+        let connections: Vec<ConnectionId> = self.connections.keys().copied().collect();
+        for frame in frames {
+            for &conn_id in &connections {
+                self.send_own(conn_id, &frame);
+            }
+        }
+    }
+
+    /// Identify the router to a connection that has just come up
+    fn introduce(&mut self, conn_id: ConnectionId) {
+        if self.standby {
+            return;
+        }
+        let Some(announcer) = &mut self.announcer else {
+            return;
+        };
+        for frame in [announcer.heartbeat(), announcer.identity()] {
+            self.send_own(conn_id, &frame);
+        }
+    }
+
+    /// Send a frame the router made itself, or is sending again, to one
+    /// connection, as the last step of a routed frame: as MAVLink 1 or not
+    /// at all if the link awaits MAVLink 2 from its peer, signed if the link
+    /// signs, and counted against its byte budget
+    fn send_own(&mut self, dest_id: ConnectionId, frame: &MavFrame) {
+        let Some(conn) = self.connections.get(&dest_id) else {
+            return;
+        };
+        let downgraded = match &conn.link.await_peer_v2 {
+            Some(config) => match conn.peer_version.offer_own(config, frame) {
+                Outgoing::Send => None,
+                Outgoing::Downgraded(v1) => Some(v1),
+                Outgoing::Held(_) | Outgoing::Dropped => {
+                    self.metrics.record_v2_withheld();
+                    debug!(
+                        "Dropped own MAVLink 2 frame for {}, not yet sending it (msgid={})",
+                        dest_id,
+                        frame.msg_id()
+                    );
+                    return;
+                }
+            },
+            None => None,
+        };
+        self.send(dest_id, downgraded.as_ref().unwrap_or(frame), dest_id);
+    }
+
+    /// Publish an event to any subscribers
+    fn publish(&self, event: RouterEvent) {
+        // No subscribers is fine
+        let _ = self.events.send(StampedEvent::now(event));
     }
 
     fn handle_disconnect(&mut self, conn_id: ConnectionId) {
         info!("Router: connection {} disconnected", conn_id);
+        if self.connections.contains_key(&conn_id) {
+            self.publish(RouterEvent::ConnectionDown {
+                connection: conn_id.to_string(),
+            });
+        }
+
+        if self.primary_gcs == Some(conn_id) {
+            info!("Router: primary GCS on {} gone", conn_id);
+            self.primary_gcs = None;
+        }
+        if let Some(map) = &mut self.compid_map {
+            map.release(conn_id);
+        }
+        // Accepted TCP clients get a new id each time
+        if conn_id.conn_type == ConnectionType::Tcp {
+            self.metrics.forget_connection(conn_id);
+        }
 
         // Remove from connections
         if let Some(conn) = self.connections.remove(&conn_id) {
+            self.update_failover();
+
             // Remove from sysid map if it had a sysid
             if let Some(sysid) = conn.sysid {
+                // The vehicle is still reachable over the rest of its failover group
+                if let Some(peer) = self.failover_peer_with_sysid(&conn.link, sysid) {
+                    self.sysid_map.insert(sysid, peer);
+                    info!("Router: sysid {} still reachable via {}", sysid, peer);
+                    return;
+                }
+
                 self.sysid_map.remove(&sysid);
-                info!("Router: removed sysid {} mapping", sysid);
+                if let Some(cache) = &mut self.param_cache {
+                    cache.forget(sysid);
+                }
+                self.publish(RouterEvent::VehicleLost {
+                    sysid,
+                    connection: conn_id.to_string(),
+                });
+
+                let grace = self.config.sysid_grace_secs;
+                if grace > 0 {
+                    self.lost_sysids.insert(
+                        sysid,
+                        LostSysid {
+                            since: Instant::now(),
+                            queued: VecDeque::new(),
+                        },
+                    );
+                    info!(
+                        "Router: sysid {} lost, holding its mapping for {}s",
+                        sysid, grace
+                    );
+                } else {
+                    info!("Router: removed sysid {} mapping", sysid);
+                }
+            }
+        }
+    }
+
+    /// A lost vehicle came back on `conn_id`: send anything held for it
+    fn restore_sysid(&mut self, sysid: u8, conn_id: ConnectionId) {
+        let Some(lost) = self.lost_sysids.remove(&sysid) else {
+            return;
+        };
+        info!(
+            "Router: sysid {} back on {} after {:.1}s, delivering {} held frame(s)",
+            sysid,
+            conn_id,
+            lost.since.elapsed().as_secs_f64(),
+            lost.queued.len()
+        );
+
+        let mut unacked = Vec::new();
+        for (source, frame) in lost.queued {
+            self.audit(source, &frame, Decision::Allowed, None, &[conn_id]);
+            if let Some(pending) = self.deliver(source, &frame, &frame.to_bytes(), conn_id) {
+                unacked.push((conn_id, pending));
+            }
+        }
+        self.track_unacked(unacked);
+    }
+
+    /// Forget lost sysids whose grace period has run out
+    fn expire_lost_sysids(&mut self) {
+        let grace = Duration::from_secs(self.config.sysid_grace_secs);
+        let audit_log = &self.audit_log;
+        let dead_letters = &self.dead_letters;
+        self.lost_sysids.retain(|sysid, lost| {
+            if lost.since.elapsed() < grace {
+                return true;
+            }
+            if let Some(log) = audit_log {
+                for (source, frame) in &lost.queued {
+                    log.record(*source, frame, Decision::Denied, Some("vehicle_lost"), &[]);
+                }
+            }
+            if let Some(log) = dead_letters {
+                for (source, frame) in &lost.queued {
+                    log.record(*source, None, frame, "vehicle_lost");
+                }
+            }
+            warn!(
+                "Router: sysid {} did not return within {}s, removed mapping ({} held frame(s) dropped)",
+                sysid,
+                grace.as_secs(),
+                lost.queued.len()
+            );
+            false
+        });
+    }
+
+    fn handle_query(&mut self, query: RouterQuery) {
+        match query {
+            RouterQuery::LastFrames { sysid, reply } => {
+                let mut frames: Vec<InspectedFrame> = self
+                    .last_frames
+                    .iter()
+                    .filter(|((id, _), _)| *id == sysid)
+                    .map(|(_, cached)| InspectedFrame {
+                        frame: cached.frame.clone(),
+                        source: cached.source,
+                        age: cached.received_at.elapsed(),
+                    })
+                    .collect();
+                frames.sort_by_key(|f| f.frame.msg_id());
+                let _ = reply.send(frames);
+            }
+            RouterQuery::Queues { reply } => {
+                let congested_above = self
+                    .config
+                    .adaptive_telemetry
+                    .as_ref()
+                    .map(|adaptive| adaptive.queued_bytes);
+                let mut queues: Vec<QueueDepth> = self
+                    .connections
+                    .iter()
+                    .map(|(&connection, conn)| QueueDepth {
+                        connection,
+                        queued_bytes: conn.tx.queued_bytes(),
+                        peak_bytes: conn.tx.peak_bytes(),
+                        congested_above,
+                    })
+                    .collect();
+                queues.sort_by_key(|queue| queue.connection.to_string());
+                let _ = reply.send(queues);
+            }
+            RouterQuery::Promote { reply } => {
+                let was_standby = self.standby;
+                if was_standby {
+                    self.promote("admin command");
+                }
+                let _ = reply.send(was_standby);
+            }
+            RouterQuery::TestLink { sysid, from, reply } => {
+                self.start_link_test(LinkTest::new(sysid, from, reply));
             }
         }
     }
 
+    /// Send a link test's request to its vehicle, addressed to the component
+    /// its HEARTBEAT comes from
+    fn start_link_test(&mut self, test: LinkTest) {
+        self.link_tests.retain(|test| !test.abandoned());
+
+        let sysid = test.sysid();
+        let Some(&conn_id) = self
+            .sysid_map
+            .get(&sysid)
+            .filter(|conn_id| self.connections.contains_key(conn_id))
+        else {
+            test.not_seen();
+            return;
+        };
+        let compid = self
+            .last_frames
+            .get(&(sysid, messages::HEARTBEAT))
+            .map_or(1, |cached| cached.frame.comp_id());
+        self.send_own(conn_id, &test.request(compid));
+        info!("Router: testing the command path to sysid {}", sysid);
+        self.link_tests.push(test);
+    }
+
+    /// Finish the link test `frame` answers, if any; the answer goes no
+    /// further
+    fn finish_link_test(&mut self, source: ConnectionId, frame: &MavFrame) -> bool {
+        let Some((i, ack)) = self
+            .link_tests
+            .iter()
+            .enumerate()
+            .find_map(|(i, test)| test.answer(frame).map(|ack| (i, ack)))
+        else {
+            return false;
+        };
+        self.link_tests.swap_remove(i).finish(ack, source);
+        true
+    }
+
+    /// Whether a frame read at `received` has waited for the router longer
+    /// than `max_router_age_ms`
+    fn is_stale(&self, received: Instant) -> bool {
+        self.config.max_router_age_ms > 0
+            && received.elapsed() > Duration::from_millis(self.config.max_router_age_ms)
+    }
+
     fn route_frame(&mut self, source: ConnectionId, frame: MavFrame) {
+        if !self.signature_ok(source, &frame) {
+            self.audit(source, &frame, Decision::Denied, Some("signature"), &[]);
+            self.dead_letter(source, None, &frame, "signature");
+            return;
+        }
+
+        self.confirm_peer_v2(source, &frame);
+
+        // Acks and repeats on reliable links go no further
+        if !self.receive_reliable(source, &frame) {
+            return;
+        }
+
+        if !self.in_dialect(source, &frame) {
+            self.audit(source, &frame, Decision::Denied, Some("dialect"), &[]);
+            self.dead_letter(source, None, &frame, "dialect");
+            return;
+        }
+
+        if !self.length_ok(source, &frame) {
+            self.audit(source, &frame, Decision::Denied, Some("length"), &[]);
+            self.dead_letter(source, None, &frame, "length");
+            return;
+        }
+
+        let frame = self.assign_gcs_compid(source, frame);
+
+        if let Some(log) = &self.decoded_log {
+            log.record(&frame);
+        }
+        if let Some(tsdb) = &self.tsdb {
+            tsdb.record(&frame);
+        }
+
         let sysid = frame.sys_id();
 
         // Record received message
         self.metrics.record_received();
+        self.metrics.record_connection_rx(source, frame.len());
+
+        if let Some(conn) = self.connections.get_mut(&source) {
+            conn.last_rx = Some(Instant::now());
+            if source.conn_type.role() == ConnectionRole::Vehicle {
+                conn.first_frames.entry(sysid).or_insert_with(Instant::now);
+            }
+            if self.config.link_quality.is_some() {
+                conn.quality.observe(&frame);
+            }
+        }
 
-        // Update sysid mapping for UART connections
-        if source.conn_type == ConnectionType::Uart {
+        // Update sysid mapping for vehicle connections
+        if source.conn_type.role() == ConnectionRole::Vehicle {
+            self.metrics
+                .record_sequence(sysid, frame.comp_id(), frame.sequence());
+            if let Some(time) = crate::mavlink::decode::SystemTime::decode(&frame) {
+                self.metrics.record_system_time(sysid, &time);
+            }
             if let Some(conn) = self.connections.get_mut(&source) {
                 if conn.sysid.is_none() {
                     conn.sysid = Some(sysid);
@@ -91,10 +895,32 @@ impl Router {
                         "Router: discovered sysid {} on connection {}",
                         sysid, source
                     );
+                    self.publish(RouterEvent::VehicleDiscovered {
+                        sysid,
+                        connection: source.to_string(),
+                    });
+                    self.restore_sysid(sysid, source);
                 }
             }
         }
 
+        if source.conn_type.role() == ConnectionRole::GroundStation
+            && self.config.primary_gcs_sysid == Some(sysid)
+            && self.primary_gcs != Some(source)
+        {
+            info!("Router: primary GCS (sysid {}) is on {}", sysid, source);
+            self.primary_gcs = Some(source);
+        }
+
+        self.last_frames.insert(
+            (sysid, frame.msg_id()),
+            CachedFrame {
+                frame: frame.clone(),
+                source,
+                received_at: Instant::now(),
+            },
+        );
+
         debug!(
             "Routing frame from {} (sysid={}, compid={}, msgid={})",
             source,
@@ -103,9 +929,458 @@ impl Router {
             frame.msg_id()
         );
 
-        // Route to all eligible connections
-        let frame_bytes = bytes::Bytes::copy_from_slice(frame.as_bytes());
-        let frame_len = frame_bytes.len();
+        if !self.link_tests.is_empty() && self.finish_link_test(source, &frame) {
+            return;
+        }
+
+        if frame.msg_id() == messages::HEARTBEAT
+            && self
+                .config
+                .standby
+                .as_ref()
+                .is_some_and(|standby| standby.primary_sysid == Some(sysid))
+        {
+            self.primary_heard = Instant::now();
+        }
+
+        if self.answer_from_param_cache(source, &frame) {
+            self.audit(source, &frame, Decision::Answered, None, &[]);
+            return;
+        }
+
+        // A standby router has what it needs from the frame; it sends nothing
+        if self.standby {
+            return;
+        }
+
+        // Enforce the global cap on queued bytes
+        if self.should_shed(&frame) {
+            self.metrics.record_shed();
+            debug!(
+                "Shed frame from {} (sysid={}, msgid={}): queue cap reached",
+                source,
+                sysid,
+                frame.msg_id()
+            );
+            // Observers lose it, the operator in control doesn't
+            self.dead_letter(source, None, &frame, "shed");
+            self.forward_to_primary(source, &frame);
+            return;
+        }
+
+        let target = self.vehicle_target(source, &frame);
+        if !self.command_allowed(source, &frame, &target) {
+            self.audit(source, &frame, Decision::Denied, Some("no_heartbeat"), &[]);
+            self.dead_letter(source, None, &frame, "no_heartbeat");
+            return;
+        }
+
+        // Commands for a vehicle whose link just dropped are held or dropped
+        // rather than broadcast to every other vehicle
+        let mut held = false;
+        if let VehicleTarget::Lost(target) = target {
+            if self.config.lost_sysid_policy == LostSysidPolicy::Queue {
+                if let Some(lost) = self.lost_sysids.get_mut(&target) {
+                    if lost.queued.len() >= LOST_SYSID_QUEUE_FRAMES {
+                        if let (Some((source, frame)), Some(log)) =
+                            (lost.queued.pop_front(), &self.dead_letters)
+                        {
+                            log.record(source, None, &frame, "vehicle_lost");
+                        }
+                    }
+                    lost.queued.push_back((source, frame.clone()));
+                    held = true;
+                }
+            }
+            debug!(
+                "Frame for lost sysid {} (msgid={}) {}",
+                target,
+                frame.msg_id(),
+                if held { "held" } else { "not sent to vehicles" }
+            );
+        }
+
+        let destinations = self.forward(source, &frame, None);
+        if held {
+            self.audit(source, &frame, Decision::Held, None, &destinations);
+        } else if !destinations.is_empty() {
+            self.audit(source, &frame, Decision::Allowed, None, &destinations);
+        } else {
+            let reason = match target {
+                VehicleTarget::Lost(_) => "vehicle_lost",
+                _ => "no_route",
+            };
+            self.audit(source, &frame, Decision::Denied, Some(reason), &[]);
+            self.dead_letter(source, None, &frame, reason);
+            self.record_unrouted(source, &frame);
+        }
+    }
+
+    /// Record what was done with a command in the audit log, if kept
+    fn audit(
+        &self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        decision: Decision,
+        reason: Option<&'static str>,
+        destinations: &[ConnectionId],
+    ) {
+        if let Some(log) = &self.audit_log {
+            log.record(source, frame, decision, reason, destinations);
+        }
+    }
+
+    /// Record a dropped frame in the dead-letter log, if kept
+    fn dead_letter(
+        &self,
+        source: ConnectionId,
+        destination: Option<ConnectionId>,
+        frame: &MavFrame,
+        reason: &'static str,
+    ) {
+        if let Some(log) = &self.dead_letters {
+            log.record(source, destination, frame, reason);
+        }
+    }
+
+    /// Give a GCS client's frames the component id assigned to it
+    fn assign_gcs_compid(&mut self, source: ConnectionId, frame: MavFrame) -> MavFrame {
+        let Some(map) = &mut self.compid_map else {
+            return frame;
+        };
+        if source.conn_type.role() != ConnectionRole::GroundStation {
+            return frame;
+        }
+
+        let compid = frame.comp_id();
+        let assigned = match map.get(source, compid) {
+            Some(assigned) => assigned,
+            None => match map.assign(source, compid) {
+                Some(assigned) => {
+                    info!(
+                        "Router: compid {} on {} is now compid {}",
+                        compid, source, assigned
+                    );
+                    assigned
+                }
+                None => {
+                    debug!(
+                        "No component id left for {} (compid {}), forwarding unchanged",
+                        source, compid
+                    );
+                    return frame;
+                }
+            },
+        };
+        if assigned == compid {
+            return frame;
+        }
+        frame.with_comp_id(assigned).unwrap_or(frame)
+    }
+
+    /// Keep parameter values from vehicles, and answer parameter requests
+    /// from a GCS out of them when possible. Returns whether the frame was
+    /// answered, and so should go no further.
+    fn answer_from_param_cache(&mut self, source: ConnectionId, frame: &MavFrame) -> bool {
+        if self.param_cache.is_none() {
+            return false;
+        }
+        // Requests are cached and answered for the sysid the vehicle is
+        // known by, not the namespaced one the GCS sees
+        let target = self.target_system(source, frame);
+        let unnamespaced = target
+            .filter(|&target| Some(target) != frame.target_system())
+            .and_then(|target| frame.with_target_system(target));
+        let frame = unnamespaced.as_ref().unwrap_or(frame);
+        let Some(cache) = &mut self.param_cache else {
+            return false;
+        };
+        if source.conn_type.role() == ConnectionRole::Vehicle {
+            cache.store(frame, Instant::now());
+            return false;
+        }
+        if frame.msg_id() == messages::PARAM_SET {
+            cache.invalidate(frame);
+            return false;
+        }
+        if self.standby {
+            return false;
+        }
+
+        // Only answer for a vehicle that is connected, so the values come
+        // over the same edge they would have
+        let Some(&vehicle) = target.and_then(|sysid| self.sysid_map.get(&sysid)) else {
+            return false;
+        };
+        let Some(values) = cache.answer(frame, Instant::now()) else {
+            return false;
+        };
+
+        debug!(
+            "Answered msgid {} from {} with {} cached parameter(s)",
+            frame.msg_id(),
+            source,
+            values.len()
+        );
+        self.metrics.record_params_served(values.len());
+        let mut unacked = Vec::new();
+        for value in values {
+            if let Some(pending) = self.deliver(vehicle, &value, &value.to_bytes(), source) {
+                unacked.push((source, pending));
+            }
+        }
+        self.track_unacked(unacked);
+        true
+    }
+
+    /// Whether a frame belongs to its link's dialect, if the link has one.
+    /// Rejected frames are counted, and logged once per message id.
+    fn in_dialect(&mut self, source: ConnectionId, frame: &MavFrame) -> bool {
+        let Some(conn) = self.connections.get_mut(&source) else {
+            return true;
+        };
+        let Some(dialect) = &conn.dialect else {
+            return true;
+        };
+        if dialect.contains(frame.msg_id()) {
+            return true;
+        }
+
+        self.metrics.record_rejected();
+        if conn.rejected_msgids.insert(frame.msg_id()) {
+            warn!(
+                "DIALECT: {} sent msgid {} (sysid={}), not in dialect {}; rejecting",
+                source,
+                frame.msg_id(),
+                frame.sys_id(),
+                dialect.name
+            );
+        } else {
+            debug!(
+                "Rejected msgid {} from {}: not in dialect {}",
+                frame.msg_id(),
+                source,
+                dialect.name
+            );
+        }
+        false
+    }
+
+    /// Whether a frame is signed with a valid key, if its link requires it.
+    /// Rejected frames are counted; the first from each link is logged.
+    fn signature_ok(&mut self, source: ConnectionId, frame: &MavFrame) -> bool {
+        let Some(signing) = self
+            .connections
+            .get_mut(&source)
+            .and_then(|conn| conn.signing.as_mut())
+        else {
+            return true;
+        };
+        let required = signing.required();
+        let reason = match signing.verify(frame, SystemTime::now()) {
+            Verified::Key(_) => return true,
+            _ if !required => return true,
+            Verified::Unsigned => "unsigned",
+            Verified::BadSignature => "not signed with a valid key",
+            Verified::Replayed => "replayed, or signed too long ago",
+        };
+
+        self.metrics.record_bad_signature();
+        if signing.record_rejected() {
+            warn!(
+                "SIGNING: {} sent a frame {} (sysid={}, msgid={}); rejecting",
+                source,
+                reason,
+                frame.sys_id(),
+                frame.msg_id()
+            );
+        } else {
+            debug!(
+                "Rejected frame from {} (sysid={}, msgid={}): {}",
+                source,
+                frame.sys_id(),
+                frame.msg_id(),
+                reason
+            );
+        }
+        false
+    }
+
+    /// The heartbeat interlock: a command for a vehicle only goes through if
+    /// that vehicle has sent a HEARTBEAT within
+    /// `command_heartbeat_timeout_secs`. Broadcasts, and commands for a
+    /// vehicle whose link dropped (see `lost_sysid_policy`), aren't checked.
+    fn command_allowed(
+        &mut self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        target: &VehicleTarget,
+    ) -> bool {
+        let timeout = Duration::from_secs(self.config.command_heartbeat_timeout_secs);
+        if timeout.is_zero()
+            || frame.message_class() != MessageClass::Command
+            || source.conn_type.role() == ConnectionRole::Vehicle
+            || matches!(target, VehicleTarget::Lost(_))
+        {
+            return true;
+        }
+        let Some(sysid) = self
+            .target_system(source, frame)
+            .filter(|&sysid| sysid != 0)
+        else {
+            return true;
+        };
+
+        let last_heartbeat = self
+            .last_frames
+            .get(&(sysid, messages::HEARTBEAT))
+            .filter(|cached| cached.source.conn_type.role() == ConnectionRole::Vehicle)
+            .map(|cached| cached.received_at.elapsed());
+        if last_heartbeat.is_some_and(|age| age <= timeout) {
+            if self.interlocked.remove(&sysid) {
+                info!(
+                    "Router: sysid {} heard from, forwarding commands again",
+                    sysid
+                );
+            }
+            return true;
+        }
+
+        self.metrics.record_interlocked();
+        if self.interlocked.insert(sysid) {
+            match last_heartbeat {
+                Some(age) => warn!(
+                    "Router: no HEARTBEAT from sysid {} for {}s, dropping commands for it",
+                    sysid,
+                    age.as_secs()
+                ),
+                None => warn!(
+                    "Router: no HEARTBEAT from sysid {} yet, dropping commands for it",
+                    sysid
+                ),
+            }
+        }
+        debug!(
+            "Dropped command for sysid {} from {} (msgid={}): no recent HEARTBEAT",
+            sysid,
+            source,
+            frame.msg_id()
+        );
+        false
+    }
+
+    /// Apply the link's `length_check` to a frame whose payload length
+    /// doesn't fit its message. Mismatches are logged once per message id at
+    /// warning level; dropped frames are counted.
+    fn length_ok(&mut self, source: ConnectionId, frame: &MavFrame) -> bool {
+        let Some(conn) = self.connections.get_mut(&source) else {
+            return true;
+        };
+        let check = conn.link.length_check;
+        if check == LengthCheck::Accept || frame.length_matches() != Some(false) {
+            return true;
+        }
+
+        let drop = check == LengthCheck::Drop;
+        if drop {
+            self.metrics.record_bad_length();
+        }
+        if conn.bad_length_msgids.insert(frame.msg_id()) {
+            warn!(
+                "LENGTH: {} sent msgid {} (sysid={}) with a {}-byte payload, which doesn't fit its definition{}",
+                source,
+                frame.msg_id(),
+                frame.sys_id(),
+                frame.payload().len(),
+                if drop { "; dropping" } else { "" }
+            );
+        } else {
+            debug!(
+                "Msgid {} from {} has a {}-byte payload{}",
+                frame.msg_id(),
+                source,
+                frame.payload().len(),
+                if drop { ", dropped" } else { "" }
+            );
+        }
+        !drop
+    }
+
+    /// A frame's `target_system`, as the sysid the vehicle is known by here
+    /// rather than the one its namespace shows `source`
+    fn target_system(&self, source: ConnectionId, frame: &MavFrame) -> Option<u8> {
+        let target = frame.target_system()?;
+        Some(self.transforms.local_sysid(source.conn_type, target))
+    }
+
+    fn vehicle_target(&self, source: ConnectionId, frame: &MavFrame) -> VehicleTarget {
+        // Broadcast commands may be narrowed to one vehicle on the way out
+        let target = self.target_system(source, frame).map(|target| {
+            match self.config.broadcast_command_target {
+                Some(scoped) if target == 0 && frame.message_class() == MessageClass::Command => {
+                    scoped
+                }
+                _ => target,
+            }
+        });
+        match target {
+            // 0 is a broadcast
+            Some(target) if target != 0 => {
+                if let Some(&conn_id) = self.sysid_map.get(&target) {
+                    VehicleTarget::Only(conn_id)
+                } else if self.lost_sysids.contains_key(&target) {
+                    VehicleTarget::Lost(target)
+                } else {
+                    VehicleTarget::All
+                }
+            }
+            _ => VehicleTarget::All,
+        }
+    }
+
+    /// Count a frame that went nowhere, logging at most once per interval
+    fn record_unrouted(&mut self, source: ConnectionId, frame: &MavFrame) {
+        self.metrics.record_unrouted();
+
+        if self
+            .unrouted_log
+            .is_some_and(|at| at.elapsed() < UNROUTED_LOG_INTERVAL)
+        {
+            self.unrouted_suppressed += 1;
+            return;
+        }
+
+        debug!(
+            "No destination for frame from {} (sysid={}, msgid={}), {} more since last report",
+            source,
+            frame.sys_id(),
+            frame.msg_id(),
+            self.unrouted_suppressed
+        );
+        self.unrouted_log = Some(Instant::now());
+        self.unrouted_suppressed = 0;
+    }
+
+    /// Send a frame to every connection the routing rules allow, optionally
+    /// only those with the given role. Targeted frames only go to the vehicle
+    /// they are addressed to, when it is known. Returns the destinations that
+    /// were eligible (including any that dropped it under backpressure).
+    fn forward(
+        &mut self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        role: Option<ConnectionRole>,
+    ) -> Vec<ConnectionId> {
+        let frame_bytes = frame.to_bytes();
+        let target = self.vehicle_target(source, frame);
+        let class = frame.message_class();
+        let mut destinations = Vec::new();
+
+        // Replies to an assigned component id are for that GCS alone
+        let reply_to = self
+            .compid_map
+            .as_ref()
+            .and_then(|map| map.owner(frame.target_component()?).map(|(owner, _)| owner));
 
         for (&dest_id, dest_conn) in &self.connections {
             // Don't send back to source
@@ -114,48 +1389,760 @@ impl Router {
             }
 
             // Check routing rules
-            if !self.should_route(source.conn_type, dest_conn.conn_type) {
+            if !self.should_route(source.conn_type, dest_conn.conn_type, class)
+                || role.is_some_and(|role| dest_conn.conn_type.role() != role)
+            {
                 continue;
             }
 
-            // Send the frame with backpressure detection
-            match dest_conn.tx.send(frame_bytes.clone()) {
-                Ok(_) => {
-                    self.metrics.record_routed(frame_len);
-                    debug!("Routed frame from {} to {}", source, dest_id);
+            if dest_conn.conn_type.role() == ConnectionRole::Vehicle {
+                // Only the active link of a failover group carries traffic
+                if let Some(group) = &dest_conn.link.failover_group {
+                    if self.failover_active.get(group) != Some(&dest_id) {
+                        continue;
+                    }
                 }
-                Err(e) => {
-                    self.metrics.record_dropped();
-                    warn!(
-                        "BACKPRESSURE: Failed to send to {} (channel full): {}",
-                        dest_id, e
-                    );
+
+                match target {
+                    VehicleTarget::All => {}
+                    VehicleTarget::Only(conn_id)
+                        if conn_id == dest_id || self.same_failover_group(conn_id, dest_conn) => {}
+                    VehicleTarget::Only(_) | VehicleTarget::Lost(_) => continue,
                 }
+            } else if reply_to.is_some_and(|owner| owner != dest_id) {
+                continue;
             }
+
+            destinations.push(dest_id);
         }
-    }
 
-    fn should_route(&self, src_type: ConnectionType, dst_type: ConnectionType) -> bool {
-        match (src_type, dst_type) {
-            (ConnectionType::Uart, ConnectionType::Uart) => self.config.allow_uart_to_uart,
-            (ConnectionType::Uart, ConnectionType::Tcp) => self.config.allow_uart_to_tcp,
-            (ConnectionType::Tcp, ConnectionType::Uart) => self.config.allow_tcp_to_uart,
-            (ConnectionType::Tcp, ConnectionType::Tcp) => self.config.allow_tcp_to_tcp,
+        // Frames sent on reliable links, kept until they are acknowledged
+        let mut unacked = Vec::new();
+        for &dest_id in &destinations {
+            if let Some(pending) = self.deliver(source, frame, &frame_bytes, dest_id) {
+                unacked.push((dest_id, pending));
+            }
         }
-    }
 
-    #[allow(dead_code)]
-    pub fn get_connection_by_sysid(&self, sysid: u8) -> Option<ConnectionId> {
-        self.sysid_map.get(&sysid).copied()
+        self.track_unacked(unacked);
+        destinations
     }
 
-    #[allow(dead_code)]
-    pub fn connection_count(&self) -> usize {
-        self.connections.len()
+    /// Send a frame that is otherwise being shed to the primary GCS alone,
+    /// if the routing rules allow it there
+    fn forward_to_primary(&mut self, source: ConnectionId, frame: &MavFrame) {
+        let Some(primary) = self.primary_gcs.filter(|&id| id != source) else {
+            return;
+        };
+        let Some(dest_conn) = self.connections.get(&primary) else {
+            return;
+        };
+        if !self.should_route(source.conn_type, dest_conn.conn_type, frame.message_class()) {
+            return;
+        }
+        if let Some(pending) = self.deliver(source, frame, &frame.to_bytes(), primary) {
+            self.track_unacked(vec![(primary, pending)]);
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn tcp_connection_count(&self) -> usize {
+    /// Apply the edge's rewrites and queue a frame on one connection, unless
+    /// the connection is degraded and the frame is being throttled. The
+    /// primary GCS isn't throttled.
+    /// Returns the frame sent if the link must have it acknowledged.
+    fn deliver(
+        &mut self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        frame_bytes: &Bytes,
+        dest_id: ConnectionId,
+    ) -> Option<MavFrame> {
+        if self.standby {
+            return None;
+        }
+        let first_frame = self
+            .connections
+            .get(&source)
+            .and_then(|conn| conn.first_frames.get(&frame.sys_id()).copied());
+        let primary = self.primary_gcs == Some(dest_id);
+        let dest_conn = self.connections.get_mut(&dest_id)?;
+
+        if let Some(quality) = self.config.link_quality.as_ref().filter(|_| !primary) {
+            if !dest_conn.quality.allow(quality, frame) {
+                self.metrics.record_throttled();
+                debug!(
+                    "Throttled frame to degraded {} (sysid={}, msgid={})",
+                    dest_id,
+                    frame.sys_id(),
+                    frame.msg_id()
+                );
+                self.dead_letter(source, Some(dest_id), frame, "link_quality");
+                return None;
+            }
+        }
+
+        if let Some(min) = dest_conn.link.min_statustext_severity {
+            if StatusText::decode(frame).is_some_and(|status| status.severity > min as u8) {
+                self.metrics.record_statustext_filtered();
+                debug!(
+                    "Filtered STATUSTEXT to {} (sysid={}): below {:?}",
+                    dest_id,
+                    frame.sys_id(),
+                    min
+                );
+                self.dead_letter(source, Some(dest_id), frame, "statustext");
+                return None;
+            }
+        }
+
+        if let Some(boot) = &dest_conn.link.boot_suppression {
+            if !dest_conn
+                .boot
+                .allow(boot, first_frame, frame, Instant::now())
+            {
+                self.metrics.record_boot_suppressed();
+                debug!(
+                    "Held back boot message to {} (sysid={}, msgid={})",
+                    dest_id,
+                    frame.sys_id(),
+                    frame.msg_id()
+                );
+                self.dead_letter(source, Some(dest_id), frame, "boot_suppression");
+                return None;
+            }
+        }
+
+        if let Some(budget) = dest_conn.link.byte_budget.as_ref().filter(|_| !primary) {
+            if !dest_conn
+                .budget
+                .allow(budget, frame.msg_id(), frame_bytes.len(), Instant::now())
+            {
+                self.metrics.record_over_budget();
+                debug!(
+                    "Dropped frame over its share of {} (sysid={}, msgid={})",
+                    dest_id,
+                    frame.sys_id(),
+                    frame.msg_id()
+                );
+                self.dead_letter(source, Some(dest_id), frame, "byte_budget");
+                return None;
+            }
+        }
+
+        if self.config.adaptive_telemetry.is_some()
+            && !primary
+            && frame.message_class() == MessageClass::Telemetry
+            && frame.msg_id() != messages::HEARTBEAT
+            && !dest_conn.adaptive.allow(Instant::now())
+        {
+            self.metrics.record_throttled();
+            debug!(
+                "Throttled frame to congested {} (sysid={}, msgid={})",
+                dest_id,
+                frame.sys_id(),
+                frame.msg_id()
+            );
+            self.dead_letter(source, Some(dest_id), frame, "adaptive_telemetry");
+            return None;
+        }
+
+        if let Some(conflate) = dest_conn.link.conflate.as_ref().filter(|_| !primary) {
+            match dest_conn
+                .conflation
+                .offer(conflate, source, frame, Instant::now())
+            {
+                Offer::Send => {}
+                Offer::Held => return None,
+                Offer::Replaced(replaced_source, replaced) => {
+                    self.metrics.record_conflated();
+                    self.dead_letter(replaced_source, Some(dest_id), &replaced, "conflated");
+                    return None;
+                }
+            }
+        }
+
+        self.transmit(source, frame, dest_id)
+    }
+
+    /// Send MAVLink 2 to a link awaiting it once its peer sends a MAVLink 2
+    /// frame, starting with anything held for it
+    fn confirm_peer_v2(&mut self, source: ConnectionId, frame: &MavFrame) {
+        let Some(conn) = self.connections.get_mut(&source) else {
+            return;
+        };
+        if conn.link.await_peer_v2.is_none() {
+            return;
+        }
+        let Some(held) = conn.peer_version.received(frame) else {
+            return;
+        };
+        info!(
+            "Router: {} speaks MAVLink 2, sending it MAVLink 2 ({} held frame(s))",
+            source,
+            held.len()
+        );
+
+        let mut unacked = Vec::new();
+        for (from, frame) in held {
+            if let Some(pending) = self.transmit(from, &frame, source) {
+                unacked.push((source, pending));
+            }
+        }
+        self.track_unacked(unacked);
+    }
+
+    /// Drop a copy the link already had, then rewrite and queue a frame
+    /// that has been let through to it
+    fn transmit(
+        &mut self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        dest_id: ConnectionId,
+    ) -> Option<MavFrame> {
+        let dest_conn = self.connections.get_mut(&dest_id)?;
+
+        // MAVLink 2 only once the peer has shown it speaks it
+        let downgraded = match &dest_conn.link.await_peer_v2 {
+            Some(config) => match dest_conn.peer_version.offer(config, source, frame) {
+                Outgoing::Send => None,
+                Outgoing::Downgraded(v1) => Some(v1),
+                Outgoing::Held(evicted) => {
+                    if let Some((evicted_source, evicted)) = evicted {
+                        self.metrics.record_v2_withheld();
+                        self.dead_letter(evicted_source, Some(dest_id), &evicted, "peer_v2");
+                    }
+                    return None;
+                }
+                Outgoing::Dropped => {
+                    self.metrics.record_v2_withheld();
+                    debug!(
+                        "Dropped MAVLink 2 frame for {}, not yet sending it (sysid={}, msgid={})",
+                        dest_id,
+                        frame.sys_id(),
+                        frame.msg_id()
+                    );
+                    self.dead_letter(source, Some(dest_id), frame, "peer_v2");
+                    return None;
+                }
+            },
+            None => None,
+        };
+        let frame = downgraded.as_ref().unwrap_or(frame);
+        if let Some(dedup) = &mut dest_conn.dedup {
+            if dedup.is_duplicate(frame, source, Instant::now()) {
+                self.metrics.record_deduplicated();
+                debug!(
+                    "Dropped duplicate to {} (sysid={}, msgid={}, seq={})",
+                    dest_id,
+                    frame.sys_id(),
+                    frame.msg_id(),
+                    frame.sequence()
+                );
+                self.dead_letter(source, Some(dest_id), frame, "duplicate");
+                return None;
+            }
+        }
+
+        // Apply any rewrites configured for this edge
+        let rewritten = match self.transforms.apply(
+            source.conn_type,
+            dest_conn.conn_type,
+            &dest_conn.link,
+            frame,
+        ) {
+            Rewrite::Unchanged => None,
+            Rewrite::Rewritten(rewritten) => Some(rewritten),
+            Rewrite::OutOfNamespace => {
+                self.metrics.record_out_of_namespace();
+                self.dead_letter(source, Some(dest_id), frame, "sysid_namespace");
+                return None;
+            }
+        };
+
+        // The GCS gets replies addressed to the component id it uses itself
+        let rewritten = match &self.compid_map {
+            Some(map) => {
+                let current = rewritten.as_ref().unwrap_or(frame);
+                current
+                    .target_component()
+                    .and_then(|compid| map.owner(compid))
+                    .filter(|&(owner, _)| owner == dest_id)
+                    .and_then(|(_, original)| current.with_target_component(original))
+                    .or(rewritten)
+            }
+            None => rewritten,
+        };
+
+        self.send(source, rewritten.as_ref().unwrap_or(frame), dest_id)
+    }
+
+    /// Sign a frame that is ready for a connection, if the link signs, and
+    /// queue it. Returns the frame sent if the link must have it
+    /// acknowledged.
+    fn send(
+        &mut self,
+        source: ConnectionId,
+        frame: &MavFrame,
+        dest_id: ConnectionId,
+    ) -> Option<MavFrame> {
+        let dest_conn = self.connections.get_mut(&dest_id)?;
+
+        // Signed with the link's active key, if it signs
+        let signed = dest_conn
+            .signing
+            .as_mut()
+            .and_then(|signing| signing.sign(frame, SystemTime::now()));
+        let data = signed.as_ref().unwrap_or(frame).to_bytes();
+        let data_len = data.len();
+
+        // Send the frame with backpressure detection
+        match dest_conn.tx.send(data) {
+            Ok(_) => {
+                self.metrics.record_routed(data_len);
+                self.metrics.record_connection_tx(dest_id, data_len);
+                if let Some(budget) = &dest_conn.link.byte_budget {
+                    dest_conn
+                        .budget
+                        .record(budget, frame.msg_id(), data_len, Instant::now());
+                }
+                debug!("Routed frame from {} to {}", source, dest_id);
+                if dest_conn
+                    .reliable
+                    .as_ref()
+                    .is_some_and(|link| link.wants(frame))
+                {
+                    return Some(signed.unwrap_or_else(|| frame.clone()));
+                }
+            }
+            Err(e) => {
+                self.metrics.record_dropped();
+                warn!(
+                    "BACKPRESSURE: Failed to send to {} (channel full): {}",
+                    dest_id, e
+                );
+                if let Some(log) = &self.dead_letters {
+                    log.record(source, Some(dest_id), frame, "backpressure");
+                }
+                if dest_conn
+                    .backpressure_reported
+                    .is_none_or(|at| at.elapsed() >= BACKPRESSURE_EVENT_INTERVAL)
+                {
+                    dest_conn.backpressure_reported = Some(Instant::now());
+                    let _ = self
+                        .events
+                        .send(StampedEvent::now(RouterEvent::Backpressure {
+                            connection: dest_id.to_string(),
+                        }));
+                }
+            }
+        }
+        None
+    }
+
+    /// Keep frames sent on reliable links until they are acknowledged
+    fn track_unacked(&mut self, unacked: Vec<(ConnectionId, MavFrame)>) {
+        let now = Instant::now();
+        for (dest_id, pending) in unacked {
+            let Some(link) = self
+                .connections
+                .get_mut(&dest_id)
+                .and_then(|conn| conn.reliable.as_mut())
+            else {
+                continue;
+            };
+            if let Some(evicted) = link.track(&pending, now) {
+                warn!(
+                    "Router: too many frames awaiting acks from {}, gave up on one (sysid={}, msgid={})",
+                    dest_id,
+                    evicted.sys_id(),
+                    evicted.msg_id()
+                );
+                self.dead_letter(dest_id, Some(dest_id), &evicted, "unacked");
+            }
+        }
+    }
+
+    /// Acknowledge frames that came reliably from the mav-lite at the other
+    /// end of a link, and settle the acks it sends for ours. Returns whether
+    /// the frame should be routed: acks and repeats aren't.
+    fn receive_reliable(&mut self, source: ConnectionId, frame: &MavFrame) -> bool {
+        let Some(conn) = self.connections.get_mut(&source) else {
+            return true;
+        };
+        let Some(link) = conn.reliable.as_mut() else {
+            return true;
+        };
+        match link.receive(frame, Instant::now()) {
+            Received::Plain => true,
+            Received::Ack => false,
+            Received::Data { ack, repeat } => {
+                // A standby router sends nothing, acks included
+                if !self.standby {
+                    self.send_own(source, &ack);
+                }
+                if repeat {
+                    debug!(
+                        "Dropped repeat from {} (sysid={}, msgid={}, seq={})",
+                        source,
+                        frame.sys_id(),
+                        frame.msg_id(),
+                        frame.sequence()
+                    );
+                }
+                !repeat
+            }
+        }
+    }
+
+    /// Send again whatever reliable links haven't acknowledged in time
+    fn resend_unacked(&mut self) {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (&conn_id, conn) in &mut self.connections {
+            let Some(link) = conn.reliable.as_mut() else {
+                continue;
+            };
+            let (resend, given_up) = link.due(now);
+            for frame in given_up {
+                warn!(
+                    "Router: {} never acknowledged frame, giving up (sysid={}, msgid={}, seq={})",
+                    conn_id,
+                    frame.sys_id(),
+                    frame.msg_id(),
+                    frame.sequence()
+                );
+                if let Some(log) = &self.dead_letters {
+                    log.record(conn_id, Some(conn_id), &frame, "unacked");
+                }
+            }
+            for frame in resend {
+                debug!(
+                    "Resending unacknowledged frame to {} (sysid={}, msgid={}, seq={})",
+                    conn_id,
+                    frame.sys_id(),
+                    frame.msg_id(),
+                    frame.sequence()
+                );
+                due.push((conn_id, frame));
+            }
+        }
+        // Re-signed, as a frame with the same signature would be a replay
+        for (conn_id, frame) in due {
+            self.send_own(conn_id, &frame);
+        }
+    }
+
+    /// Send the held state messages whose interval is up
+    fn release_conflated(&mut self) {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (&dest_id, conn) in &mut self.connections {
+            if let Some(conflate) = &conn.link.conflate {
+                due.extend(
+                    conn.conflation
+                        .due(conflate, now)
+                        .into_iter()
+                        .map(|(source, frame)| (dest_id, source, frame)),
+                );
+            }
+        }
+
+        let mut unacked = Vec::new();
+        for (dest_id, source, frame) in due {
+            if let Some(pending) = self.transmit(source, &frame, dest_id) {
+                unacked.push((dest_id, pending));
+            }
+        }
+        self.track_unacked(unacked);
+    }
+
+    /// Re-send the last HEARTBEAT of each silent vehicle, marked critical, to
+    /// ground stations until `synthetic_heartbeat_secs` has passed
+    fn send_synthetic_heartbeats(&mut self) {
+        let grace = Duration::from_secs(self.config.synthetic_heartbeat_secs);
+        if grace.is_zero() {
+            return;
+        }
+
+        let mut synthetic = Vec::new();
+        for (&(sysid, msg_id), cached) in &self.last_frames {
+            if msg_id != messages::HEARTBEAT
+                || cached.source.conn_type.role() != ConnectionRole::Vehicle
+            {
+                continue;
+            }
+
+            let age = cached.received_at.elapsed();
+            if age < HEARTBEAT_SILENCE {
+                if self.synthesizing.remove(&sysid).is_some() {
+                    info!("Router: HEARTBEAT from sysid {} resumed", sysid);
+                }
+                continue;
+            }
+            if age >= grace + HEARTBEAT_SILENCE {
+                if self.synthesizing.remove(&sysid).is_some() {
+                    warn!(
+                        "Router: sysid {} silent for {}s, stopping synthetic HEARTBEAT",
+                        sysid,
+                        age.as_secs()
+                    );
+                }
+                continue;
+            }
+
+            // Keep the sequence moving so the GCS doesn't see it as a duplicate
+            let seq = self.synthesizing.entry(sysid).or_insert_with(|| {
+                warn!(
+                    "Router: sysid {} stopped sending HEARTBEAT, standing in for up to {}s",
+                    sysid,
+                    grace.as_secs()
+                );
+                cached.frame.sequence()
+            });
+            *seq = seq.wrapping_add(1);
+            let seq = *seq;
+            if let Some(frame) = Heartbeat::with_system_status(&cached.frame, MAV_STATE_CRITICAL)
+                .and_then(|f| f.with_sequence(seq))
+            {
+                synthetic.push((cached.source, frame));
+            }
+        }
+
+        for (source, frame) in synthetic {
+            self.forward(source, &frame, Some(ConnectionRole::GroundStation));
+        }
+    }
+
+    /// Whether `conn_id` is in the same failover group as `other`
+    fn same_failover_group(&self, conn_id: ConnectionId, other: &Connection) -> bool {
+        let Some(group) = &other.link.failover_group else {
+            return false;
+        };
+        self.connections
+            .get(&conn_id)
+            .is_some_and(|conn| conn.link.failover_group.as_ref() == Some(group))
+    }
+
+    /// Another connection in `link`'s failover group that has seen `sysid`
+    fn failover_peer_with_sysid(&self, link: &LinkConfig, sysid: u8) -> Option<ConnectionId> {
+        let group = link.failover_group.as_ref()?;
+        self.connections
+            .iter()
+            .find(|(_, conn)| {
+                conn.sysid == Some(sysid) && conn.link.failover_group.as_ref() == Some(group)
+            })
+            .map(|(&conn_id, _)| conn_id)
+    }
+
+    /// Pick each failover group's active link: the preferred one among those
+    /// that have received something recently. With none alive, the current
+    /// one is kept.
+    fn update_failover(&mut self) {
+        let timeout = Duration::from_millis(self.config.failover_timeout_ms);
+
+        let mut members: HashMap<&String, Vec<(u8, ConnectionId, bool)>> = HashMap::new();
+        for (&conn_id, conn) in &self.connections {
+            if let Some(group) = &conn.link.failover_group {
+                let alive = conn.last_rx.is_some_and(|at| at.elapsed() < timeout);
+                members.entry(group).or_default().push((
+                    conn.link.failover_priority,
+                    conn_id,
+                    alive,
+                ));
+            }
+        }
+
+        let mut active = HashMap::new();
+        for (group, mut links) in members {
+            links.sort_by_key(|&(priority, conn_id, _)| (priority, conn_id.id));
+            let current = self
+                .failover_active
+                .get(group)
+                .filter(|id| links.iter().any(|(_, conn_id, _)| conn_id == *id));
+            let chosen = links
+                .iter()
+                .find(|(_, _, alive)| *alive)
+                .map(|&(_, conn_id, _)| conn_id)
+                .or(current.copied())
+                .unwrap_or(links[0].1);
+
+            match current {
+                Some(&previous) if previous == chosen => {}
+                Some(&previous) => {
+                    warn!(
+                        "FAILOVER: group '{}' switched from {} to {}",
+                        group, previous, chosen
+                    );
+                    let _ = self.events.send(StampedEvent::now(RouterEvent::Failover {
+                        group: group.clone(),
+                        from: previous.to_string(),
+                        to: chosen.to_string(),
+                    }));
+                }
+                None => info!("Router: failover group '{}' active on {}", group, chosen),
+            }
+            active.insert(group.clone(), chosen);
+        }
+        self.failover_active = active;
+    }
+
+    /// Tighten or relax each connection's telemetry limit by whether its
+    /// queue is over `queued_bytes`
+    fn adapt_telemetry_rates(&mut self) {
+        let Some(config) = &self.config.adaptive_telemetry else {
+            return;
+        };
+        for (conn_id, conn) in &mut self.connections {
+            let queued = conn.tx.queued_bytes();
+            let congested = queued > config.queued_bytes;
+            if !conn.adaptive.adjust(congested, config) {
+                if let Some(limit) = conn.adaptive.limit() {
+                    debug!(
+                        "{} telemetry limit now {:.0} frames/s ({} bytes queued)",
+                        conn_id, limit, queued
+                    );
+                }
+                continue;
+            }
+            match conn.adaptive.limit() {
+                Some(limit) => warn!(
+                    "{} is congested ({} bytes queued), limiting telemetry to {:.0} frames/s",
+                    conn_id, queued, limit
+                ),
+                None => info!("{} keeping up again, telemetry limit lifted", conn_id),
+            }
+        }
+    }
+
+    /// Judge each connection's last window of received traffic and switch
+    /// telemetry throttling on or off
+    fn evaluate_link_quality(&mut self) {
+        let Some(config) = &self.config.link_quality else {
+            return;
+        };
+
+        for (conn_id, conn) in &mut self.connections {
+            let Some(sample) = conn.quality.evaluate(config) else {
+                continue;
+            };
+            let rssi = sample
+                .rssi
+                .map_or_else(|| "n/a".to_string(), |rssi| rssi.to_string());
+            let connection = conn_id.to_string();
+            let event = if conn.quality.is_degraded() {
+                warn!(
+                    "LINK QUALITY: {} degraded ({:.1}% loss, rssi {}), throttling telemetry to {} Hz",
+                    conn_id, sample.loss_percent, rssi, config.throttled_rate_hz
+                );
+                RouterEvent::LinkDegraded {
+                    connection,
+                    loss_percent: sample.loss_percent,
+                    rssi: sample.rssi,
+                }
+            } else {
+                info!(
+                    "Link quality on {} recovered ({:.1}% loss, rssi {}), throttling stopped",
+                    conn_id, sample.loss_percent, rssi
+                );
+                RouterEvent::LinkRecovered {
+                    connection,
+                    loss_percent: sample.loss_percent,
+                    rssi: sample.rssi,
+                }
+            };
+            let _ = self.events.send(StampedEvent::now(event));
+        }
+    }
+
+    /// Check the total queued across all connections against the configured
+    /// cap. Over the cap, only untargeted telemetry is shed; anything carrying
+    /// a target_system (commands, param and mission traffic) and heartbeats
+    /// still go through. With a primary GCS connected, shedding starts at
+    /// `primary_gcs_shed_percent` of the cap, so observers give way before
+    /// the operator has to.
+    fn should_shed(&mut self, frame: &MavFrame) -> bool {
+        let queued: usize = self.connections.values().map(|c| c.tx.queued_bytes()).sum();
+        self.metrics.set_bytes_queued(queued);
+
+        let mut max_queued = self.config.max_queued_bytes;
+        if max_queued == 0 {
+            return false;
+        }
+        if self.primary_gcs.is_some() {
+            let percent = usize::from(self.config.primary_gcs_shed_percent.min(100));
+            max_queued = max_queued.saturating_mul(percent) / 100;
+        }
+
+        let over_cap = queued > max_queued;
+        if over_cap != self.shedding {
+            self.shedding = over_cap;
+            if over_cap {
+                warn!(
+                    "QUEUE CAP: {} bytes queued across connections (limit {}), shedding telemetry",
+                    queued, max_queued
+                );
+            } else {
+                info!(
+                    "Queued bytes back under limit ({} <= {}), shedding stopped",
+                    queued, max_queued
+                );
+            }
+        }
+
+        over_cap && frame.msg_id() != messages::HEARTBEAT && frame.target_system().is_none()
+    }
+
+    fn should_route(
+        &self,
+        src_type: ConnectionType,
+        dst_type: ConnectionType,
+        class: MessageClass,
+    ) -> bool {
+        let class_rules = match class {
+            MessageClass::Command => &self.config.commands,
+            MessageClass::Response => &self.config.responses,
+            MessageClass::Telemetry => &self.config.telemetry,
+        };
+
+        // A relay faces vehicles to a GCS and ground stations to a vehicle
+        let (src_role, dst_role) = match (src_type.is_relay(), dst_type.is_relay()) {
+            (true, false) => (dst_type.role().opposite(), dst_type.role()),
+            (false, true) => (src_type.role(), src_type.role().opposite()),
+            _ => (src_type.role(), dst_type.role()),
+        };
+
+        if self.config.strict_role_separation && src_role == dst_role {
+            return false;
+        }
+
+        // UART rules cover every vehicle-facing link, TCP rules every GCS-facing one
+        match (src_role, dst_role) {
+            (ConnectionRole::Vehicle, ConnectionRole::Vehicle) => {
+                self.config.allow_uart_to_uart && class_rules.allow_uart_to_uart
+            }
+            (ConnectionRole::Vehicle, ConnectionRole::GroundStation) => {
+                self.config.allow_uart_to_tcp && class_rules.allow_uart_to_tcp
+            }
+            (ConnectionRole::GroundStation, ConnectionRole::Vehicle) => {
+                self.config.allow_tcp_to_uart && class_rules.allow_tcp_to_uart
+            }
+            (ConnectionRole::GroundStation, ConnectionRole::GroundStation) => {
+                self.config.allow_tcp_to_tcp && class_rules.allow_tcp_to_tcp
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_connection_by_sysid(&self, sysid: u8) -> Option<ConnectionId> {
+        self.sysid_map.get(&sysid).copied()
+    }
+
+    #[allow(dead_code)]
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn tcp_connection_count(&self) -> usize {
         self.connections
             .values()
             .filter(|c| c.conn_type == ConnectionType::Tcp)
@@ -170,3 +2157,757 @@ impl Router {
             .count()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{StandbyConfig, SysidNamespaceConfig};
+    use crate::connection::{message_channel, router_channel, MessageReceiver};
+
+    fn router(config: RoutingConfig) -> Router {
+        Router::new(
+            config,
+            Metrics::new(),
+            event_channel(),
+            HashMap::new(),
+            None,
+            None,
+            None,
+        )
+    }
+
+    fn connect(router: &mut Router, conn_id: ConnectionId, link: LinkConfig) -> MessageReceiver {
+        let (tx, rx) = message_channel();
+        router.handle_new_connection(conn_id, tx, link);
+        rx
+    }
+
+    /// Message ids of everything queued for a connection
+    fn sent(rx: &mut MessageReceiver) -> Vec<u32> {
+        let mut msgids = Vec::new();
+        while let Some(data) = rx.try_recv() {
+            msgids.push(MavFrame::parse(&data).unwrap().0.msg_id());
+        }
+        msgids
+    }
+
+    /// Events published since last asked
+    fn published(events: &mut broadcast::Receiver<StampedEvent>) -> Vec<RouterEvent> {
+        std::iter::from_fn(|| events.try_recv().ok())
+            .map(|stamped| stamped.event)
+            .collect()
+    }
+
+    fn heartbeat(sysid: u8) -> MavFrame {
+        MavFrame::new_v2(0, sysid, 1, messages::HEARTBEAT, &[0; 9]).unwrap()
+    }
+
+    fn command(target: u8) -> MavFrame {
+        MavFrame::new_v2(0, 255, 190, messages::COMMAND_LONG, &[0; 33])
+            .unwrap()
+            .with_target_system(target)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lost_sysid_queued() {
+        let config = RoutingConfig {
+            sysid_grace_secs: 30,
+            lost_sysid_policy: LostSysidPolicy::Queue,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (gcs, lost, other, back) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_uart(0),
+            ConnectionId::new_uart(1),
+            ConnectionId::new_uart(2),
+        );
+        let _gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let _lost_rx = connect(&mut router, lost, LinkConfig::default());
+        let mut other_rx = connect(&mut router, other, LinkConfig::default());
+        router.route_frame(lost, heartbeat(1));
+        router.route_frame(other, heartbeat(2));
+
+        // Held for the lost vehicle, not broadcast to the others
+        router.handle_disconnect(lost);
+        router.route_frame(gcs, command(1));
+        assert!(sent(&mut other_rx).is_empty());
+
+        // And delivered once it is back, on whatever link
+        let mut back_rx = connect(&mut router, back, LinkConfig::default());
+        router.route_frame(back, heartbeat(1));
+        assert_eq!(sent(&mut back_rx), [messages::COMMAND_LONG]);
+        assert!(router.lost_sysids.is_empty());
+        assert_eq!(router.get_connection_by_sysid(1), Some(back));
+    }
+
+    #[test]
+    fn test_lost_sysid_dropped() {
+        let config = RoutingConfig {
+            sysid_grace_secs: 30,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (gcs, lost, other) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_uart(0),
+            ConnectionId::new_uart(1),
+        );
+        let _gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let _lost_rx = connect(&mut router, lost, LinkConfig::default());
+        let mut other_rx = connect(&mut router, other, LinkConfig::default());
+        router.route_frame(lost, heartbeat(1));
+
+        // Neither broadcast nor kept for the vehicle's return
+        router.handle_disconnect(lost);
+        router.route_frame(gcs, command(1));
+        assert!(sent(&mut other_rx).is_empty());
+        let mut back_rx = connect(&mut router, lost, LinkConfig::default());
+        router.route_frame(lost, heartbeat(1));
+        assert!(sent(&mut back_rx).is_empty());
+    }
+
+    #[test]
+    fn test_lost_sysid_expires() {
+        let config = RoutingConfig {
+            sysid_grace_secs: 30,
+            lost_sysid_policy: LostSysidPolicy::Queue,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (gcs, lost, other) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_uart(0),
+            ConnectionId::new_uart(1),
+        );
+        let _gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let _lost_rx = connect(&mut router, lost, LinkConfig::default());
+        let mut other_rx = connect(&mut router, other, LinkConfig::default());
+        router.route_frame(lost, heartbeat(1));
+        router.handle_disconnect(lost);
+        router.route_frame(gcs, command(1));
+
+        // Still inside the grace period
+        router.expire_lost_sysids();
+        assert_eq!(router.lost_sysids[&1].queued.len(), 1);
+
+        // Past it: what was held is dropped, and the sysid is unknown again,
+        // so commands for it go to every vehicle
+        router.lost_sysids.get_mut(&1).unwrap().since -= Duration::from_secs(31);
+        router.expire_lost_sysids();
+        assert!(router.lost_sysids.is_empty());
+        assert!(sent(&mut other_rx).is_empty());
+        router.route_frame(gcs, command(1));
+        assert_eq!(sent(&mut other_rx), [messages::COMMAND_LONG]);
+    }
+
+    #[test]
+    fn test_failover() {
+        let mut router = router(RoutingConfig::default());
+        let mut events = router.events.subscribe();
+        let link = |priority| LinkConfig {
+            failover_group: Some("drone1".to_string()),
+            failover_priority: priority,
+            ..LinkConfig::default()
+        };
+        let (gcs, primary, backup) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_uart(0),
+            ConnectionId::new_uart(1),
+        );
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let mut primary_rx = connect(&mut router, primary, link(0));
+        let mut backup_rx = connect(&mut router, backup, link(1));
+
+        // Both feed telemetry; only the preferred link carries commands
+        router.route_frame(primary, heartbeat(1));
+        router.route_frame(backup, heartbeat(1));
+        assert_eq!(
+            sent(&mut gcs_rx),
+            [messages::HEARTBEAT, messages::HEARTBEAT]
+        );
+        router.route_frame(gcs, command(1));
+        assert_eq!(sent(&mut primary_rx), [messages::COMMAND_LONG]);
+        assert!(sent(&mut backup_rx).is_empty());
+
+        // The primary goes quiet: the backup takes over, and says so
+        router.connections.get_mut(&primary).unwrap().last_rx =
+            Some(Instant::now() - Duration::from_secs(5));
+        router.update_failover();
+        router.route_frame(gcs, command(1));
+        assert!(sent(&mut primary_rx).is_empty());
+        assert_eq!(sent(&mut backup_rx), [messages::COMMAND_LONG]);
+        let switched = published(&mut events)
+            .into_iter()
+            .find_map(|event| match event {
+                RouterEvent::Failover { from, to, .. } => Some((from, to)),
+                _ => None,
+            });
+        assert_eq!(switched, Some((primary.to_string(), backup.to_string())));
+
+        // The vehicle stays reachable over the backup when the primary drops
+        router.handle_disconnect(primary);
+        assert_eq!(router.get_connection_by_sysid(1), Some(backup));
+        router.route_frame(gcs, command(1));
+        assert_eq!(sent(&mut backup_rx), [messages::COMMAND_LONG]);
+    }
+
+    #[test]
+    fn test_command_interlock() {
+        let config = RoutingConfig {
+            command_heartbeat_timeout_secs: 5,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (gcs, vehicle) = (ConnectionId::new_tcp(0), ConnectionId::new_uart(0));
+        let _gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let mut vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+
+        // Not heard from yet; broadcasts aren't checked
+        router.route_frame(gcs, command(1));
+        assert!(sent(&mut vehicle_rx).is_empty());
+        router.route_frame(gcs, command(0));
+        assert_eq!(sent(&mut vehicle_rx), [messages::COMMAND_LONG]);
+
+        router.route_frame(vehicle, heartbeat(1));
+        router.route_frame(gcs, command(1));
+        assert_eq!(sent(&mut vehicle_rx), [messages::COMMAND_LONG]);
+
+        // Its HEARTBEAT is too old
+        let cached = router
+            .last_frames
+            .get_mut(&(1, messages::HEARTBEAT))
+            .unwrap();
+        cached.received_at -= Duration::from_secs(6);
+        router.route_frame(gcs, command(1));
+        assert!(sent(&mut vehicle_rx).is_empty());
+        assert_eq!(router.metrics.get_stats().commands_interlocked, 2);
+    }
+
+    #[test]
+    fn test_strict_role_separation() {
+        let config = RoutingConfig {
+            allow_uart_to_uart: true,
+            strict_role_separation: true,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (gcs, observer, vehicle, other) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_tcp(1),
+            ConnectionId::new_uart(0),
+            ConnectionId::new_can(0),
+        );
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let mut observer_rx = connect(&mut router, observer, LinkConfig::default());
+        let mut vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let mut other_rx = connect(&mut router, other, LinkConfig::default());
+
+        // Vehicle to GCS and back, whatever allow_uart_to_uart and
+        // allow_tcp_to_tcp say
+        router.route_frame(vehicle, heartbeat(1));
+        assert!(sent(&mut other_rx).is_empty());
+        assert_eq!(sent(&mut gcs_rx), [messages::HEARTBEAT]);
+        assert_eq!(sent(&mut observer_rx), [messages::HEARTBEAT]);
+        router.route_frame(gcs, heartbeat(255));
+        assert!(sent(&mut observer_rx).is_empty());
+        assert_eq!(sent(&mut vehicle_rx), [messages::HEARTBEAT]);
+        assert_eq!(sent(&mut other_rx), [messages::HEARTBEAT]);
+
+        // The vehicle-GCS rules still apply
+        router.config.allow_tcp_to_uart = false;
+        router.route_frame(gcs, heartbeat(255));
+        assert!(sent(&mut vehicle_rx).is_empty());
+    }
+
+    #[test]
+    fn test_standby_promotion() {
+        let config = RoutingConfig {
+            standby: Some(StandbyConfig {
+                primary_sysid: Some(250),
+                primary_timeout_secs: 3,
+            }),
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let mut events = router.events.subscribe();
+        let (gcs, vehicle) = (ConnectionId::new_tcp(0), ConnectionId::new_uart(0));
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+
+        // Keeping up with the vehicle, but sending nothing
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(router.get_connection_by_sysid(1), Some(vehicle));
+        assert!(sent(&mut gcs_rx).is_empty());
+
+        // Not while the primary is heard from
+        router.primary_heard -= Duration::from_secs(10);
+        router.route_frame(gcs, heartbeat(250));
+        router.check_primary();
+        assert!(router.standby);
+
+        // Once it has been silent too long
+        router.primary_heard -= Duration::from_secs(4);
+        router.check_primary();
+        assert!(!router.standby);
+        assert!(published(&mut events)
+            .iter()
+            .any(|event| matches!(event, RouterEvent::Promoted { .. })));
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(sent(&mut gcs_rx), [messages::HEARTBEAT]);
+    }
+
+    #[test]
+    fn test_unrouted() {
+        let mut router = router(RoutingConfig::default());
+        let vehicle = ConnectionId::new_uart(0);
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+
+        // Nowhere to go: counted each time, logged once per interval
+        router.route_frame(vehicle, heartbeat(1));
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(router.metrics.get_stats().frames_unrouted, 2);
+        assert_eq!(router.unrouted_suppressed, 1);
+
+        // Somewhere to go
+        let _gcs_rx = connect(&mut router, ConnectionId::new_tcp(0), LinkConfig::default());
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(router.metrics.get_stats().frames_unrouted, 2);
+    }
+
+    #[test]
+    fn test_event_json() {
+        let mut router = router(RoutingConfig::default());
+        let mut events = router.events.subscribe();
+        let vehicle = ConnectionId::new_uart(0);
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        router.route_frame(vehicle, heartbeat(1));
+
+        // One flat object per event, tagged with what happened
+        let lines: Vec<String> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|stamped| {
+                assert!(stamped.time > 0.0);
+                serde_json::to_string(&StampedEvent {
+                    time: 1.5,
+                    ..stamped
+                })
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"time":1.5,"event":"connection_up","connection":"UART-0"}"#,
+                r#"{"time":1.5,"event":"vehicle_discovered","sysid":1,"connection":"UART-0"}"#,
+            ]
+        );
+
+        let degraded = StampedEvent {
+            time: 2.0,
+            event: RouterEvent::LinkDegraded {
+                connection: "UART-0".to_string(),
+                loss_percent: 12.5,
+                rssi: None,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&degraded).unwrap(),
+            r#"{"time":2.0,"event":"link_degraded","connection":"UART-0","loss_percent":12.5,"rssi":null}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_frames_dropped() {
+        let config = RoutingConfig {
+            max_router_age_ms: 100,
+            ..RoutingConfig::default()
+        };
+        let router = router(config);
+        let metrics = router.metrics.clone();
+        let (router_tx, router_rx) = router_channel(0, metrics.clone());
+        let (gcs, vehicle) = (ConnectionId::new_tcp(0), ConnectionId::new_uart(0));
+        let mut queues = Vec::new();
+        for conn_id in [gcs, vehicle] {
+            let (tx, rx) = message_channel();
+            queues.push(rx);
+            router_tx
+                .send(RouterMessage::NewConnection {
+                    conn_id,
+                    tx,
+                    link: Box::default(),
+                })
+                .unwrap();
+        }
+
+        // Waited too long for the router, then one that didn't
+        for (seq, waited) in [(0, Duration::from_millis(500)), (1, Duration::ZERO)] {
+            let frame = heartbeat(1).with_sequence(seq).unwrap();
+            router_tx
+                .send(RouterMessage::Frame {
+                    source: vehicle,
+                    frame,
+                    received: Instant::now() - waited,
+                })
+                .unwrap();
+        }
+        router_tx.send(RouterMessage::Shutdown).unwrap();
+        router.run(router_rx).await;
+
+        let data = queues[0].try_recv().unwrap();
+        assert_eq!(MavFrame::parse(&data).unwrap().0.sequence(), 1);
+        assert!(queues[0].try_recv().is_none());
+        assert_eq!(metrics.get_stats().frames_stale, 1);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_routes_queued() {
+        let router = router(RoutingConfig::default());
+        let (router_tx, router_rx) = router_channel(0, router.metrics.clone());
+        let (gcs, vehicle) = (ConnectionId::new_tcp(0), ConnectionId::new_uart(0));
+        let (tx, mut gcs_rx) = message_channel();
+        for (conn_id, tx) in [(gcs, tx), (vehicle, message_channel().0)] {
+            router_tx
+                .send(RouterMessage::NewConnection {
+                    conn_id,
+                    tx,
+                    link: Box::default(),
+                })
+                .unwrap();
+        }
+
+        // Already received when the shutdown comes, so still routed
+        for seq in 0..3 {
+            router_tx
+                .send(RouterMessage::Frame {
+                    source: vehicle,
+                    frame: heartbeat(1).with_sequence(seq).unwrap(),
+                    received: Instant::now(),
+                })
+                .unwrap();
+        }
+        router_tx.send(RouterMessage::Shutdown).unwrap();
+        let drained = router.run(router_rx).await;
+
+        let mut sequences = Vec::new();
+        while let Some(data) = gcs_rx.try_recv() {
+            sequences.push(MavFrame::parse(&data).unwrap().0.sequence());
+        }
+        assert_eq!(sequences, [0, 1, 2]);
+        assert_eq!(drained.len(), 2);
+        assert!(router_tx.is_closed());
+    }
+
+    #[test]
+    fn test_statustext_severity() {
+        use crate::config::Severity;
+        use crate::mavlink::decode::status_text;
+
+        let mut router = router(RoutingConfig::default());
+        let vehicle = ConnectionId::new_uart(0);
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let filtered_link = LinkConfig {
+            min_statustext_severity: Some(Severity::Warning),
+            ..LinkConfig::default()
+        };
+        let mut filtered = connect(&mut router, ConnectionId::new_tcp(0), filtered_link);
+        let mut unfiltered = connect(&mut router, ConnectionId::new_tcp(1), LinkConfig::default());
+
+        // ERROR, WARNING, INFO
+        for severity in [3, 4, 6] {
+            router.route_frame(vehicle, status_text(0, 1, 1, severity, "status"));
+        }
+
+        let severities = |rx: &mut MessageReceiver| {
+            std::iter::from_fn(|| rx.try_recv())
+                .filter_map(|data| StatusText::decode(&MavFrame::parse(&data).unwrap().0))
+                .map(|status| status.severity)
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(severities(&mut filtered), [3, 4]);
+        assert_eq!(severities(&mut unfiltered), [3, 4, 6]);
+        assert_eq!(router.metrics.get_stats().statustext_filtered, 1);
+
+        // Other messages aren't affected
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(sent(&mut filtered), [messages::HEARTBEAT]);
+    }
+
+    #[test]
+    fn test_queue_depth() {
+        let config = RoutingConfig {
+            adaptive_telemetry: Some(toml::from_str("queued_bytes = 4096").unwrap()),
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let vehicle = ConnectionId::new_uart(0);
+        let gcs = ConnectionId::new_tcp(0);
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let depths = |router: &mut Router| {
+            let (reply, mut rx) = oneshot::channel();
+            router.handle_query(RouterQuery::Queues { reply });
+            rx.try_recv()
+                .unwrap()
+                .into_iter()
+                .map(|queue| (queue.connection, queue.queued_bytes, queue.peak_bytes))
+                .collect::<Vec<_>>()
+        };
+
+        let len = heartbeat(1).as_bytes().len();
+        for _ in 0..3 {
+            router.route_frame(vehicle, heartbeat(1));
+        }
+        assert_eq!(
+            depths(&mut router),
+            [(gcs, 3 * len, 3 * len), (vehicle, 0, 0)]
+        );
+
+        // Taking from the queue lowers its depth, not its peak
+        gcs_rx.try_recv().unwrap();
+        assert_eq!(
+            depths(&mut router),
+            [(gcs, 2 * len, 3 * len), (vehicle, 0, 0)]
+        );
+
+        let (reply, mut rx) = oneshot::channel();
+        router.handle_query(RouterQuery::Queues { reply });
+        assert!(rx
+            .try_recv()
+            .unwrap()
+            .iter()
+            .all(|queue| queue.congested_above == Some(4096)));
+    }
+
+    #[test]
+    fn test_namespaced_command() {
+        let config = RoutingConfig {
+            sysid_namespace: vec![SysidNamespaceConfig {
+                from: ConnectionType::Uart,
+                to: ConnectionType::Tcp,
+                offset: 100,
+            }],
+            command_heartbeat_timeout_secs: 5,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (first, second) = (ConnectionId::new_uart(0), ConnectionId::new_uart(1));
+        let mut first_rx = connect(&mut router, first, LinkConfig::default());
+        let mut second_rx = connect(&mut router, second, LinkConfig::default());
+        let gcs = ConnectionId::new_tcp(0);
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        router.route_frame(first, heartbeat(5));
+        router.route_frame(second, heartbeat(6));
+
+        // The GCS sees the vehicles' sysids shifted...
+        let sysids: Vec<u8> = std::iter::from_fn(|| gcs_rx.try_recv())
+            .map(|data| MavFrame::parse(&data).unwrap().0.sys_id())
+            .collect();
+        assert_eq!(sysids, [105, 106]);
+
+        // ...and its commands for one reach that vehicle alone, unshifted
+        router.route_frame(gcs, command(105));
+        let received = first_rx.try_recv().unwrap();
+        assert_eq!(
+            MavFrame::parse(&received).unwrap().0.target_system(),
+            Some(5)
+        );
+        assert!(first_rx.try_recv().is_none());
+        assert!(second_rx.try_recv().is_none());
+        assert_eq!(router.metrics.get_stats().commands_interlocked, 0);
+    }
+
+    #[test]
+    fn test_out_of_namespace() {
+        let config = RoutingConfig {
+            sysid_namespace: vec![SysidNamespaceConfig {
+                from: ConnectionType::Uart,
+                to: ConnectionType::Tcp,
+                offset: 100,
+            }],
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let vehicle = ConnectionId::new_uart(0);
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let mut gcs_rx = connect(&mut router, ConnectionId::new_tcp(0), LinkConfig::default());
+
+        // Counted as what it is, not as backpressure
+        router.route_frame(vehicle, heartbeat(200));
+        router.route_frame(vehicle, heartbeat(200));
+        assert!(sent(&mut gcs_rx).is_empty());
+        let stats = router.metrics.get_stats();
+        assert_eq!(stats.frames_out_of_namespace, 2);
+        assert_eq!(stats.messages_dropped, 0);
+    }
+
+    #[test]
+    fn test_should_shed() {
+        let config = RoutingConfig {
+            max_queued_bytes: 1000,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let gcs = ConnectionId::new_tcp(0);
+        let _gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        router.connections[&gcs]
+            .tx
+            .send(Bytes::from(vec![0; 800]))
+            .unwrap();
+        let attitude = MavFrame::new_v2(0, 1, 1, messages::ATTITUDE, &[1; 28]).unwrap();
+
+        // Under the cap
+        assert!(!router.should_shed(&attitude));
+
+        // With a primary GCS connected, shedding starts at 75% of it, but
+        // never for heartbeats or targeted frames
+        router.primary_gcs = Some(gcs);
+        assert!(router.should_shed(&attitude));
+        assert!(!router.should_shed(&heartbeat(1)));
+        assert!(!router.should_shed(&command(1)));
+
+        // Or wherever it is configured to
+        router.config.primary_gcs_shed_percent = 100;
+        assert!(!router.should_shed(&attitude));
+        router.connections[&gcs]
+            .tx
+            .send(Bytes::from(vec![0; 201]))
+            .unwrap();
+        assert!(router.should_shed(&attitude));
+        router.primary_gcs = None;
+        assert!(router.should_shed(&attitude));
+    }
+
+    #[test]
+    fn test_primary_gcs() {
+        let config = RoutingConfig {
+            max_queued_bytes: 1000,
+            primary_gcs_sysid: Some(255),
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (vehicle, operator, observer, stalled) = (
+            ConnectionId::new_uart(0),
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_tcp(1),
+            ConnectionId::new_tcp(2),
+        );
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let mut operator_rx = connect(&mut router, operator, LinkConfig::default());
+        let mut observer_rx = connect(&mut router, observer, LinkConfig::default());
+        let _stalled_rx = connect(&mut router, stalled, LinkConfig::default());
+        router.connections[&stalled]
+            .tx
+            .send(Bytes::from(vec![0; 2000]))
+            .unwrap();
+        let attitude = MavFrame::new_v2(0, 1, 1, messages::ATTITUDE, &[1; 28]).unwrap();
+
+        // Over the cap with no primary, telemetry reaches nobody
+        router.route_frame(vehicle, attitude.clone());
+        assert!(sent(&mut operator_rx).is_empty());
+        assert!(sent(&mut observer_rx).is_empty());
+
+        // Once the operator's GCS is heard, it alone still gets it
+        router.route_frame(operator, heartbeat(255));
+        assert_eq!(router.primary_gcs, Some(operator));
+        sent(&mut observer_rx);
+        router.route_frame(vehicle, attitude.clone());
+        assert_eq!(sent(&mut operator_rx), [messages::ATTITUDE]);
+        assert!(sent(&mut observer_rx).is_empty());
+
+        // The primary moves to wherever the sysid is heard next
+        router.route_frame(observer, heartbeat(255));
+        assert_eq!(router.primary_gcs, Some(observer));
+        sent(&mut operator_rx);
+        router.route_frame(vehicle, attitude.clone());
+        assert!(sent(&mut operator_rx).is_empty());
+        assert_eq!(sent(&mut observer_rx), [messages::ATTITUDE]);
+
+        // And is gone with its connection
+        router.handle_disconnect(observer);
+        assert_eq!(router.primary_gcs, None);
+        router.route_frame(vehicle, attitude);
+        assert!(sent(&mut operator_rx).is_empty());
+    }
+
+    #[test]
+    fn test_relay_routing() {
+        let mut router = router(RoutingConfig::default());
+        let (gcs, vehicle, relay, udp) = (
+            ConnectionId::new_tcp(0),
+            ConnectionId::new_uart(0),
+            ConnectionId::new_tcp_client(0),
+            ConnectionId::new_udp(0),
+        );
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let mut vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let mut relay_rx = connect(&mut router, relay, LinkConfig::default());
+        let mut udp_rx = connect(&mut router, udp, LinkConfig::default());
+
+        // The vehicle's telemetry goes upstream, and commands come back
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(sent(&mut relay_rx), [messages::HEARTBEAT]);
+        assert_eq!(sent(&mut udp_rx), [messages::HEARTBEAT]);
+        assert_eq!(sent(&mut gcs_rx), [messages::HEARTBEAT]);
+        router.route_frame(relay, command(1));
+        assert_eq!(sent(&mut vehicle_rx), [messages::COMMAND_LONG]);
+        router.route_frame(udp, command(1));
+        assert_eq!(sent(&mut vehicle_rx), [messages::COMMAND_LONG]);
+        sent(&mut gcs_rx);
+
+        // Vehicles on the far side reach the local GCS, under strict
+        // separation too
+        router.config.strict_role_separation = true;
+        router.route_frame(relay, heartbeat(2));
+        assert_eq!(sent(&mut gcs_rx), [messages::HEARTBEAT]);
+        router.route_frame(vehicle, heartbeat(1));
+        assert_eq!(sent(&mut relay_rx), [messages::HEARTBEAT]);
+    }
+
+    #[test]
+    fn test_synthetic_heartbeats() {
+        let config = RoutingConfig {
+            synthetic_heartbeat_secs: 10,
+            ..RoutingConfig::default()
+        };
+        let mut router = router(config);
+        let (gcs, vehicle) = (ConnectionId::new_tcp(0), ConnectionId::new_uart(0));
+        let mut gcs_rx = connect(&mut router, gcs, LinkConfig::default());
+        let _vehicle_rx = connect(&mut router, vehicle, LinkConfig::default());
+        let last_heard = |router: &mut Router, ago| {
+            router
+                .last_frames
+                .get_mut(&(1, messages::HEARTBEAT))
+                .unwrap()
+                .received_at = Instant::now() - ago;
+        };
+        let synthetic = |rx: &mut MessageReceiver| -> Vec<u8> {
+            std::iter::from_fn(|| rx.try_recv())
+                .map(|data| MavFrame::parse(&data).unwrap().0.sequence())
+                .collect()
+        };
+        router.route_frame(vehicle, heartbeat(1).with_sequence(250).unwrap());
+        sent(&mut gcs_rx);
+
+        // Nothing while the vehicle's own are still coming
+        router.send_synthetic_heartbeats();
+        assert!(sent(&mut gcs_rx).is_empty());
+
+        // Then one per tick, each with the next sequence number, however
+        // long apart the ticks are
+        last_heard(&mut router, Duration::from_secs(3));
+        for _ in 0..3 {
+            router.send_synthetic_heartbeats();
+        }
+        last_heard(&mut router, Duration::from_millis(5500));
+        for _ in 0..3 {
+            router.send_synthetic_heartbeats();
+        }
+        assert_eq!(synthetic(&mut gcs_rx), [251, 252, 253, 254, 255, 0]);
+
+        // Until the grace period is over
+        last_heard(&mut router, Duration::from_secs(13));
+        router.send_synthetic_heartbeats();
+        assert!(sent(&mut gcs_rx).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::config::SigningConfig;
+use crate::mavlink::MavFrame;
+use ring::digest::{digest, SHA256};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Signature timestamps count from 2015-01-01 00:00:00 UTC
+const SIGNING_EPOCH: Duration = Duration::from_secs(1_420_070_400);
+
+/// How far behind the clock a signature timestamp may be (10 µs units)
+const MAX_TIMESTAMP_AGE: u64 = 60 * 100_000;
+
+/// How a frame arriving on a signed link checked out
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verified<'a> {
+    /// Signed with this valid key
+    Key(&'a str),
+    Unsigned,
+    /// Signed, but not with any key valid now
+    BadSignature,
+    /// Validly signed, but not newer than the last frame of its stream, or
+    /// too far behind the clock
+    Replayed,
+}
+
+#[derive(Debug)]
+struct Key {
+    id: String,
+    secret: [u8; 32],
+    valid_from: Option<u64>,
+    valid_until: Option<u64>,
+}
+
+impl Key {
+    fn valid_at(&self, unix_secs: u64) -> bool {
+        self.valid_from.is_none_or(|from| unix_secs >= from)
+            && self.valid_until.is_none_or(|until| unix_secs < until)
+    }
+}
+
+/// Keys and signing state for one link. Outgoing frames are signed with the
+/// newest valid key; incoming ones may use any valid key, but must be newer
+/// than the last of their stream
+#[derive(Debug)]
+pub struct LinkSigning {
+    keys: Vec<Key>,
+    require: bool,
+    sign_outgoing: bool,
+    link_id: u8,
+    /// Timestamp of the last frame signed, which the next must exceed
+    last_timestamp: u64,
+    /// Timestamp of the last frame accepted from each (sysid, compid,
+    /// link id), which the next must exceed
+    received: HashMap<(u8, u8, u8), u64>,
+    /// Frames from the link rejected so far
+    rejected: u64,
+}
+
+impl LinkSigning {
+    pub fn new(config: &SigningConfig) -> Self {
+        Self {
+            keys: config
+                .keys
+                .iter()
+                .map(|key| Key {
+                    id: key.id.clone(),
+                    secret: secret_key(&key.secret),
+                    valid_from: key.valid_from,
+                    valid_until: key.valid_until,
+                })
+                .collect(),
+            require: config.require,
+            sign_outgoing: config.sign_outgoing,
+            link_id: config.link_id,
+            last_timestamp: 0,
+            received: HashMap::new(),
+            rejected: 0,
+        }
+    }
+
+    /// Count a frame rejected for its signature; returns whether it was the
+    /// link's first
+    pub fn record_rejected(&mut self) -> bool {
+        self.rejected += 1;
+        self.rejected == 1
+    }
+
+    /// Whether frames from the link must be signed
+    pub fn required(&self) -> bool {
+        self.require
+    }
+
+    /// Id of the key frames are signed with at `now`, if any is valid
+    pub fn active(&self, now: SystemTime) -> Option<&str> {
+        let secs = unix_secs(now);
+        self.keys
+            .iter()
+            .filter(|key| key.valid_at(secs))
+            .max_by_key(|key| key.valid_from.unwrap_or(0))
+            .map(|key| key.id.as_str())
+    }
+
+    pub fn verify(&mut self, frame: &MavFrame, now: SystemTime) -> Verified<'_> {
+        let Some(signature) = frame.signature() else {
+            return Verified::Unsigned;
+        };
+        let secs = unix_secs(now);
+        let Some(key) = self
+            .keys
+            .iter()
+            .filter(|key| key.valid_at(secs))
+            .find(|key| frame.signature_valid(&key.secret) == Some(true))
+        else {
+            return Verified::BadSignature;
+        };
+
+        let mut bytes = [0u8; 8];
+        bytes[..6].copy_from_slice(&signature[1..7]);
+        let timestamp = u64::from_le_bytes(bytes);
+        let stream = (frame.sys_id(), frame.comp_id(), signature[0]);
+        if self
+            .received
+            .get(&stream)
+            .is_some_and(|&last| timestamp <= last)
+            || timestamp + MAX_TIMESTAMP_AGE < signing_timestamp(now)
+        {
+            return Verified::Replayed;
+        }
+        self.received.insert(stream, timestamp);
+        Verified::Key(&key.id)
+    }
+
+    /// The frame signed with the active key, or `None` to send it as it is:
+    /// the link doesn't sign, no key is valid, or it is MAVLink 1
+    pub fn sign(&mut self, frame: &MavFrame, now: SystemTime) -> Option<MavFrame> {
+        if !self.sign_outgoing {
+            return None;
+        }
+        let id = self.active(now)?;
+        let key = self.keys.iter().find(|key| key.id == id)?;
+
+        // Each frame needs a later timestamp than the last
+        let timestamp = signing_timestamp(now).max(self.last_timestamp + 1);
+        let signed = frame.signed(&key.secret, self.link_id, timestamp)?;
+        self.last_timestamp = timestamp;
+        Some(signed)
+    }
+}
+
+/// 64 hex digits are the key itself; anything else is a passphrase
+fn secret_key(secret: &str) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    let hex: Option<Vec<u8>> = (secret.len() == 64)
+        .then(|| {
+            (0..32)
+                .map(|i| u8::from_str_radix(secret.get(i * 2..i * 2 + 2)?, 16).ok())
+                .collect()
+        })
+        .flatten();
+    match hex {
+        Some(bytes) => key.copy_from_slice(&bytes),
+        None => key.copy_from_slice(digest(&SHA256, secret.as_bytes()).as_ref()),
+    }
+    key
+}
+
+/// `now` in 10 µs units since the signing epoch
+fn signing_timestamp(now: SystemTime) -> u64 {
+    let since_epoch = now
+        .duration_since(UNIX_EPOCH + SIGNING_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_micros() / 10) as u64
+}
+
+fn unix_secs(now: SystemTime) -> u64 {
+    now.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SigningKeyConfig;
+    use crate::mavlink::messages;
+
+    fn key(id: &str, valid_from: Option<u64>, valid_until: Option<u64>) -> SigningKeyConfig {
+        SigningKeyConfig {
+            id: id.to_string(),
+            secret: format!("{} passphrase", id),
+            valid_from,
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn test_rotation_window() {
+        // Old key retiring at 2000, new key starting at 1000: both are
+        // accepted in between, and the new one signs from when it starts
+        let config = SigningConfig {
+            keys: vec![key("old", None, Some(2000)), key("new", Some(1000), None)],
+            require: true,
+            sign_outgoing: true,
+            link_id: 1,
+        };
+        let mut signing = LinkSigning::new(&config);
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let heartbeat = MavFrame::new_v2(0, 1, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+
+        assert_eq!(signing.active(at(500)), Some("old"));
+        assert_eq!(signing.active(at(1500)), Some("new"));
+        assert_eq!(signing.verify(&heartbeat, at(500)), Verified::Unsigned);
+
+        // Each check on a fresh receiver, which hasn't seen the frame yet
+        let verify = |frame: &MavFrame, secs| {
+            let mut receiver = LinkSigning::new(&config);
+            let verified = receiver.verify(frame, at(secs));
+            format!("{:?}", verified)
+        };
+        let by_old = signing.sign(&heartbeat, at(500)).unwrap();
+        assert_eq!(verify(&by_old, 500), "Key(\"old\")");
+        assert_eq!(verify(&by_old, 2500), "BadSignature");
+
+        let by_new = signing.sign(&heartbeat, at(1500)).unwrap();
+        assert_eq!(verify(&by_new, 1500), "Key(\"new\")");
+        assert_eq!(verify(&by_new, 500), "BadSignature");
+        assert_eq!(by_new.msg_id(), messages::HEARTBEAT);
+        assert_eq!(by_new.crc_valid(), Some(true));
+
+        // Timestamps keep increasing even if the clock doesn't
+        let timestamp = |frame: &MavFrame| {
+            let mut bytes = [0u8; 8];
+            bytes[..6].copy_from_slice(&frame.signature().unwrap()[1..7]);
+            u64::from_le_bytes(bytes)
+        };
+        let again = signing.sign(&heartbeat, at(1500)).unwrap();
+        assert_eq!(timestamp(&again), timestamp(&by_new) + 1);
+    }
+
+    #[test]
+    fn test_replay() {
+        let config = SigningConfig {
+            keys: vec![key("only", None, None)],
+            require: true,
+            sign_outgoing: true,
+            link_id: 1,
+        };
+        let mut sender = LinkSigning::new(&config);
+        let mut receiver = LinkSigning::new(&config);
+        let at = |secs| UNIX_EPOCH + SIGNING_EPOCH + Duration::from_secs(secs);
+        let heartbeat =
+            |sysid| MavFrame::new_v2(0, sysid, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+
+        let first = sender.sign(&heartbeat(1), at(1000)).unwrap();
+        let second = sender.sign(&heartbeat(1), at(1001)).unwrap();
+        assert_eq!(receiver.verify(&second, at(1001)), Verified::Key("only"));
+        // Played back, or older than what the stream has already sent
+        assert_eq!(receiver.verify(&second, at(1001)), Verified::Replayed);
+        assert_eq!(receiver.verify(&first, at(1001)), Verified::Replayed);
+        // Other systems have their own streams
+        let other = sender.sign(&heartbeat(2), at(1000)).unwrap();
+        assert_eq!(receiver.verify(&other, at(1001)), Verified::Key("only"));
+
+        // A frame never seen, but from over a minute ago
+        let old = sender.sign(&heartbeat(3), at(1002)).unwrap();
+        assert_eq!(receiver.verify(&old, at(1100)), Verified::Replayed);
+    }
+
+    #[test]
+    fn test_hex_secret() {
+        let hex = "00".repeat(31) + "ff";
+        assert_eq!(secret_key(&hex)[31], 0xff);
+        assert_ne!(secret_key("not hex")[..], [0u8; 32][..]);
+    }
+}
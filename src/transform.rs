@@ -0,0 +1,262 @@
+use crate::config::{LinkConfig, SysidNamespaceConfig};
+use crate::connection::{ConnectionRole, ConnectionType};
+use crate::mavlink::messages::MessageClass;
+use crate::mavlink::MavFrame;
+use std::collections::HashSet;
+use tracing::{debug, warn};
+
+/// What to do with a frame on an edge
+#[derive(Debug)]
+pub enum Rewrite {
+    /// Send it as it is
+    Unchanged,
+    /// Send this rewritten copy instead
+    Rewritten(MavFrame),
+    /// It can't be moved into its namespace, so it can't be sent
+    OutOfNamespace,
+}
+
+/// Frame rewrites applied on a routing edge (source type -> destination), plus
+/// any egress rewrites configured on the destination connection
+pub struct EdgeTransforms {
+    namespaces: Vec<SysidNamespaceConfig>,
+    /// Vehicle that broadcast commands are narrowed to
+    broadcast_command_target: Option<u8>,
+    /// Sysids already warned about for not fitting their namespace
+    out_of_range: HashSet<u8>,
+}
+
+impl EdgeTransforms {
+    pub fn new(
+        namespaces: Vec<SysidNamespaceConfig>,
+        broadcast_command_target: Option<u8>,
+    ) -> Self {
+        Self {
+            namespaces,
+            broadcast_command_target,
+            out_of_range: HashSet::new(),
+        }
+    }
+
+    /// A `target_system` arriving from `src_type`, mapped back to the sysid
+    /// the vehicle is known by, as the return path of `apply` would
+    pub fn local_sysid(&self, src_type: ConnectionType, sysid: u8) -> u8 {
+        self.namespaces
+            .iter()
+            .filter(|ns| src_type == ns.to)
+            .fold(sysid, |sysid, ns| {
+                if sysid > ns.offset {
+                    sysid - ns.offset
+                } else {
+                    sysid
+                }
+            })
+    }
+
+    /// Rewrite a frame for this edge. A frame whose sysid would be shifted
+    /// past 255, or that can't be resealed with a new one, mustn't go out
+    /// under a sysid from outside its namespace; it is turned away, with a
+    /// warning once per sysid.
+    pub fn apply(
+        &mut self,
+        src_type: ConnectionType,
+        dst_type: ConnectionType,
+        dst_link: &LinkConfig,
+        frame: &MavFrame,
+    ) -> Rewrite {
+        let mut rewritten: Option<MavFrame> = None;
+
+        for ns in &self.namespaces {
+            let current = rewritten.as_ref().unwrap_or(frame);
+
+            if src_type == ns.from && dst_type == ns.to {
+                // Outbound: shift the source sysid into the reserved range
+                let shifted = current.sys_id().checked_add(ns.offset);
+                match shifted.and_then(|sysid| current.with_sys_id(sysid)) {
+                    Some(next) => rewritten = Some(next),
+                    None => {
+                        if self.out_of_range.insert(frame.sys_id()) {
+                            warn!(
+                                "NAMESPACE: sysid {} can't be moved into its namespace on its way from {:?} to {:?} ({}); dropping its frames",
+                                frame.sys_id(),
+                                src_type,
+                                dst_type,
+                                match shifted {
+                                    Some(_) => "frame can't be resealed",
+                                    None => "shifted past 255",
+                                }
+                            );
+                        }
+                        return Rewrite::OutOfNamespace;
+                    }
+                }
+            } else if src_type == ns.to && dst_type == ns.from {
+                // Return path: map target_system back into the local range
+                if let Some(next) = current
+                    .target_system()
+                    .filter(|&target| target > ns.offset)
+                    .and_then(|target| current.with_target_system(target - ns.offset))
+                {
+                    rewritten = Some(next);
+                }
+            }
+        }
+
+        // Scope a command meant for every vehicle to the one configured
+        if let Some(target) = self.broadcast_command_target {
+            let current = rewritten.as_ref().unwrap_or(frame);
+            if dst_type.role() == ConnectionRole::Vehicle
+                && current.message_class() == MessageClass::Command
+                && current.target_system() == Some(0)
+            {
+                if let Some(next) = current.with_target_system(target) {
+                    debug!(
+                        "Broadcast command (msgid={}) narrowed to sysid {}",
+                        current.msg_id(),
+                        target
+                    );
+                    rewritten = Some(next);
+                }
+            }
+        }
+
+        // Present a single commanding identity to the vehicle
+        if let Some(gcs_sysid) = dst_link.gcs_sysid {
+            let current = rewritten.as_ref().unwrap_or(frame);
+            if dst_type.role() == ConnectionRole::Vehicle
+                && current.message_class() == MessageClass::Command
+                && current.sys_id() != gcs_sysid
+            {
+                if let Some(next) = current.with_sys_id(gcs_sysid) {
+                    rewritten = Some(next);
+                }
+            }
+        }
+
+        match rewritten {
+            Some(frame) => Rewrite::Rewritten(frame),
+            None => Rewrite::Unchanged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mavlink::decode::command_long;
+    use crate::mavlink::messages;
+
+    /// The frame to send instead, if any
+    fn rewritten(rewrite: Rewrite) -> Option<MavFrame> {
+        match rewrite {
+            Rewrite::Unchanged => None,
+            Rewrite::Rewritten(frame) => Some(frame),
+            Rewrite::OutOfNamespace => panic!("frame dropped"),
+        }
+    }
+
+    fn namespace() -> SysidNamespaceConfig {
+        SysidNamespaceConfig {
+            from: ConnectionType::Uart,
+            to: ConnectionType::Tcp,
+            offset: 100,
+        }
+    }
+
+    #[test]
+    fn test_broadcast_command_target() {
+        let mut transforms = EdgeTransforms::new(Vec::new(), Some(3));
+        let link = LinkConfig::default();
+        let command = |target| command_long(0, (255, 190), (target, 1), 400, [0.0; 7]);
+        let mut apply = |dst_type, frame: &MavFrame| {
+            rewritten(transforms.apply(ConnectionType::Tcp, dst_type, &link, frame))
+        };
+
+        // Broadcast towards a vehicle link is narrowed, with a valid checksum
+        let narrowed = apply(ConnectionType::Uart, &command(0)).unwrap();
+        assert_eq!(narrowed.target_system(), Some(3));
+        assert_eq!(narrowed.crc_valid(), Some(true));
+
+        // Already targeted, or towards a ground station: left alone
+        assert!(apply(ConnectionType::Uart, &command(2)).is_none());
+        assert!(apply(ConnectionType::Tcp, &command(0)).is_none());
+    }
+
+    #[test]
+    fn test_gcs_sysid() {
+        let mut transforms = EdgeTransforms::new(Vec::new(), None);
+        let link = LinkConfig {
+            gcs_sysid: Some(255),
+            ..LinkConfig::default()
+        };
+        let mut apply = |dst_type, frame: &MavFrame| {
+            rewritten(transforms.apply(ConnectionType::Tcp, dst_type, &link, frame))
+        };
+        let command = command_long(0, (254, 190), (1, 1), 400, [0.0; 7]);
+
+        // Commands to a vehicle take on the GCS sysid, checksum and all
+        let rewritten = apply(ConnectionType::Uart, &command).unwrap();
+        assert_eq!(rewritten.sys_id(), 255);
+        assert_eq!(rewritten.crc_valid(), Some(true));
+
+        // Not towards a ground station, nor for targeted replies
+        assert!(apply(ConnectionType::Tcp, &command).is_none());
+        let mut payload = [0u8; 10];
+        payload[8] = 1;
+        let ack = MavFrame::new_v2(0, 254, 190, messages::COMMAND_ACK, &payload).unwrap();
+        assert_eq!(ack.target_system(), Some(1));
+        assert!(apply(ConnectionType::Uart, &ack).is_none());
+    }
+
+    #[test]
+    fn test_namespace() {
+        let mut transforms = EdgeTransforms::new(vec![namespace()], None);
+        let link = LinkConfig::default();
+        let heartbeat = MavFrame::new_v2(0, 5, 1, messages::HEARTBEAT, &[1; 9]).unwrap();
+
+        // Out to the ground station under the shifted sysid, resealed
+        let (uart, tcp) = (ConnectionType::Uart, ConnectionType::Tcp);
+        let shifted = rewritten(transforms.apply(uart, tcp, &link, &heartbeat)).unwrap();
+        assert_eq!(shifted.sys_id(), 105);
+        assert_eq!(shifted.payload(), heartbeat.payload());
+        assert_eq!(shifted.crc_valid(), Some(true));
+
+        // Commands for it come back aimed at its own sysid
+        let command = command_long(0, (255, 190), (105, 1), 400, [0.0; 7]);
+        let back = rewritten(transforms.apply(tcp, uart, &link, &command)).unwrap();
+        assert_eq!(back.target_system(), Some(5));
+        assert_eq!(back.crc_valid(), Some(true));
+        assert_eq!(transforms.local_sysid(tcp, 105), 5);
+
+        // Nothing to undo for targets below the range, or other edges
+        let local = command_long(0, (255, 190), (5, 1), 400, [0.0; 7]);
+        assert!(rewritten(transforms.apply(tcp, uart, &link, &local)).is_none());
+        let udp = ConnectionType::Udp;
+        assert!(rewritten(transforms.apply(udp, tcp, &link, &heartbeat)).is_none());
+        assert_eq!(transforms.local_sysid(uart, 105), 105);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut transforms = EdgeTransforms::new(vec![namespace()], None);
+        let link = LinkConfig::default();
+        let mut apply = |src_type, dst_type, sysid| {
+            let heartbeat = MavFrame::new_v2(0, sysid, 1, messages::HEARTBEAT, &[0; 9]).unwrap();
+            transforms.apply(src_type, dst_type, &link, &heartbeat)
+        };
+
+        assert!(matches!(
+            apply(ConnectionType::Uart, ConnectionType::Tcp, 155),
+            Rewrite::Rewritten(_)
+        ));
+        assert!(matches!(
+            apply(ConnectionType::Uart, ConnectionType::Tcp, 156),
+            Rewrite::OutOfNamespace
+        ));
+        // Only the shifted direction is checked
+        assert!(matches!(
+            apply(ConnectionType::Tcp, ConnectionType::Uart, 156),
+            Rewrite::Unchanged
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::TsdbConfig;
+use crate::decoded_log::{fields, DECODED};
+use crate::mavlink::{messages, MavFrame};
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Most line-protocol bytes per UDP datagram
+const UDP_PAYLOAD_BYTES: usize = 1400;
+
+/// Points kept while the endpoint can't be reached; older ones go first
+const MAX_PENDING_LINES: usize = 10_000;
+
+/// Limit on each HTTP write
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Router-side handle: passes on frames of the exported messages
+#[derive(Debug, Clone)]
+pub struct TsdbExporter {
+    messages: Arc<HashSet<u32>>,
+    tx: mpsc::UnboundedSender<(u64, MavFrame)>,
+}
+
+impl TsdbExporter {
+    /// Start the exporter for `[tsdb]`. Returns `None` when disabled, and an
+    /// error for an endpoint it can't write to or messages there is no
+    /// decoder for.
+    pub fn start(config: &TsdbConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        for &msg_id in &config.messages {
+            if !DECODED.contains(&msg_id) {
+                anyhow::bail!("tsdb: no decoder for message id {}", msg_id);
+            }
+        }
+        let mut sink = Sink::new(config)?;
+        info!(
+            "Time-series export: messages {:?} to {}",
+            config.messages, config.endpoint
+        );
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<(u64, MavFrame)>();
+        let flush_interval = Duration::from_millis(config.flush_interval_ms.max(10));
+        tokio::spawn(async move {
+            let mut pending = VecDeque::new();
+            let mut dropped = 0u64;
+            let mut failing = false;
+            let mut flush = interval(flush_interval);
+            flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    received = rx.recv() => {
+                        let Some((time_ns, frame)) = received else {
+                            break;
+                        };
+                        if let Some(line) = line(time_ns, &frame) {
+                            if pending.len() == MAX_PENDING_LINES {
+                                pending.pop_front();
+                                dropped += 1;
+                            }
+                            pending.push_back(line);
+                        }
+                    }
+                    _ = flush.tick() => {
+                        if pending.is_empty() {
+                            continue;
+                        }
+                        match sink.write(pending.make_contiguous()).await {
+                            Ok(()) => {
+                                if failing || dropped > 0 {
+                                    info!(
+                                        "Time-series export: writing again ({} points dropped meanwhile)",
+                                        dropped
+                                    );
+                                }
+                                pending.clear();
+                                dropped = 0;
+                                failing = false;
+                            }
+                            Err(e) => {
+                                if !failing {
+                                    warn!("Time-series export: write failed, retrying: {}", e);
+                                }
+                                failing = true;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Some(Self {
+            messages: Arc::new(config.messages.iter().copied().collect()),
+            tx,
+        }))
+    }
+
+    /// Export a received frame, if it is of one of the chosen messages
+    pub fn record(&self, frame: &MavFrame) {
+        if !self.messages.contains(&frame.msg_id()) {
+            return;
+        }
+        let time_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let _ = self.tx.send((time_ns, frame.clone()));
+    }
+}
+
+/// Where points are written
+enum Sink {
+    Udp {
+        socket: Option<UdpSocket>,
+        addr: String,
+    },
+    Http {
+        client: reqwest::Client,
+        url: String,
+        token: Option<String>,
+    },
+}
+
+impl Sink {
+    fn new(config: &TsdbConfig) -> anyhow::Result<Self> {
+        if let Some(addr) = config.endpoint.strip_prefix("udp://") {
+            return Ok(Sink::Udp {
+                socket: None,
+                addr: addr.trim_end_matches('/').to_string(),
+            });
+        }
+        if config.endpoint.starts_with("http://") || config.endpoint.starts_with("https://") {
+            return Ok(Sink::Http {
+                client: reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?,
+                url: config.endpoint.clone(),
+                token: config.token.clone(),
+            });
+        }
+        anyhow::bail!(
+            "tsdb: endpoint {} is neither udp:// nor http(s)://",
+            config.endpoint
+        )
+    }
+
+    async fn write(&mut self, lines: &[String]) -> anyhow::Result<()> {
+        match self {
+            Sink::Udp { socket, addr } => {
+                if socket.is_none() {
+                    *socket = Some(UdpSocket::bind("0.0.0.0:0").await?);
+                }
+                let socket = socket.as_ref().expect("bound above");
+                for datagram in batches(lines, UDP_PAYLOAD_BYTES) {
+                    socket.send_to(datagram.as_bytes(), addr.as_str()).await?;
+                }
+                Ok(())
+            }
+            Sink::Http { client, url, token } => {
+                let mut request = client.post(url.as_str()).body(lines.join("\n"));
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                request.send().await?.error_for_status()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A frame as a line-protocol point, `None` if it has no fields to write
+fn line(time_ns: u64, frame: &MavFrame) -> Option<String> {
+    let fields = fields(frame)?;
+    let name = messages::lookup(frame.msg_id()).map_or("?", |m| m.name);
+    let values: Vec<String> = fields
+        .iter()
+        .filter_map(|(field, value)| {
+            // Floats that aren't finite decode to null, which has no line
+            // protocol form
+            let Value::Number(n) = value else {
+                return None;
+            };
+            Some(match n.is_f64() {
+                true => format!("{}={}", field, n),
+                false => format!("{}={}i", field, n),
+            })
+        })
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{},sysid={},compid={} {} {}",
+        name,
+        frame.sys_id(),
+        frame.comp_id(),
+        values.join(","),
+        time_ns
+    ))
+}
+
+/// Lines joined into bodies of at most `max_bytes`, unless a line alone is
+/// longer
+fn batches(lines: &[String], max_bytes: usize) -> Vec<String> {
+    let mut batches = Vec::new();
+    let mut batch = String::new();
+    for line in lines {
+        if !batch.is_empty() && batch.len() + 1 + line.len() > max_bytes {
+            batches.push(std::mem::take(&mut batch));
+        }
+        if !batch.is_empty() {
+            batch.push('\n');
+        }
+        batch.push_str(line);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_protocol() {
+        let mut payload = vec![0u8; 28];
+        payload[4..8].copy_from_slice(&473_977_418i32.to_le_bytes());
+        payload[20..22].copy_from_slice(&(-150i16).to_le_bytes());
+        let frame = MavFrame::new_v2(0, 1, 1, messages::GLOBAL_POSITION_INT, &payload).unwrap();
+        assert_eq!(
+            line(1_700_000_000_000_000_000, &frame).unwrap(),
+            "GLOBAL_POSITION_INT,sysid=1,compid=1 time_boot_ms=0i,lat=473977418i,lon=0i,\
+             alt=0i,relative_alt=0i,vx=-150i,vy=0i,vz=0i,hdg=0i 1700000000000000000"
+        );
+
+        // Floats go without the integer suffix, and a NaN is left out
+        let mut payload = vec![0u8; 28];
+        payload[4..8].copy_from_slice(&0.5f32.to_le_bytes());
+        payload[8..12].copy_from_slice(&f32::NAN.to_le_bytes());
+        let frame = MavFrame::new_v2(0, 2, 1, messages::ATTITUDE, &payload).unwrap();
+        let line = line(5, &frame).unwrap();
+        assert!(line.starts_with("ATTITUDE,sysid=2,compid=1 time_boot_ms=0i,roll=0.5,yaw=0.0,"));
+        assert!(line.ends_with(" 5"));
+    }
+
+    #[test]
+    fn test_batches() {
+        let lines: Vec<String> = ["a".repeat(6), "b".repeat(3), "c".repeat(20)].into();
+        assert_eq!(
+            batches(&lines, 10),
+            vec!["aaaaaa\nbbb".to_string(), "c".repeat(20)]
+        );
+        assert!(batches(&[], 10).is_empty());
+    }
+}